@@ -0,0 +1,97 @@
+use crate::humanize;
+use crate::record::Record;
+use crate::table;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+/// The metadata keys naming a model's architecture, in the order they are tried: the
+/// modelspec standard, then kohya-ss training metadata, then the keys of GGUF and of
+/// Hugging Face configs.
+const ARCHITECTURE_KEYS: &[&str] =
+    &["modelspec.architecture", "ss_base_model_version", "general.architecture", "architecture", "model_type"];
+
+/// The group of files with none of [`ARCHITECTURE_KEYS`].
+const UNKNOWN: &str = "unknown";
+
+/// The files and bytes of one architecture.
+#[derive(Debug, Default, Serialize)]
+pub struct Group {
+    pub architecture: String,
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// Counts the files and bytes of each architecture, for `--by-architecture`.
+#[derive(Debug, Default)]
+pub struct Architectures {
+    groups: Mutex<BTreeMap<String, Group>>,
+}
+
+impl Architectures {
+    pub fn add(&self, record: &Record) {
+        let architecture = architecture(&record.metadata);
+        let mut groups = self.groups.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        let group = groups
+            .entry(architecture.clone())
+            .or_insert_with(|| Group { architecture, ..Group::default() });
+        group.files += 1;
+        group.bytes += record.size.unwrap_or(0);
+    }
+
+    /// The groups with the most bytes first.
+    pub fn groups(&self) -> Vec<Group> {
+        let groups = std::mem::take(&mut *self.groups.lock().unwrap_or_else(std::sync::PoisonError::into_inner));
+        let mut groups: Vec<Group> = groups.into_values().collect();
+        groups.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.architecture.cmp(&b.architecture)));
+        groups
+    }
+}
+
+/// A table of `groups` with a total line.
+pub fn render(groups: &[Group]) -> String {
+    let headers = ["architecture", "files", "size"].map(String::from);
+    let mut rows: Vec<Vec<String>> = groups
+        .iter()
+        .map(|group| vec![group.architecture.clone(), group.files.to_string(), humanize::bytes(group.bytes)])
+        .collect();
+    let files = groups.iter().map(|group| group.files).sum::<usize>();
+    let bytes = groups.iter().map(|group| group.bytes).sum();
+    rows.push(vec!["total".to_string(), files.to_string(), humanize::bytes(bytes)]);
+    table::render(&headers, &rows)
+}
+
+/// The value of the first of [`ARCHITECTURE_KEYS`] that `metadata` has as a non-empty string.
+fn architecture(metadata: &Value) -> String {
+    ARCHITECTURE_KEYS
+        .iter()
+        .filter_map(|key| metadata.get(*key)?.as_str())
+        .find(|value| !value.trim().is_empty())
+        .unwrap_or(UNKNOWN)
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn record(metadata: Value, size: u64) -> Record {
+        Record { metadata, size: Some(size), ..Record::default() }
+    }
+
+    #[test]
+    fn test_groups() {
+        let architectures = Architectures::default();
+        architectures.add(&record(json!({"modelspec.architecture": "stable-diffusion-xl-v1-base/lora"}), 100));
+        architectures.add(&record(json!({"ss_base_model_version": "sdxl_base_v1-0", "modelspec.architecture": ""}), 300));
+        architectures.add(&record(json!({"modelspec.architecture": "stable-diffusion-xl-v1-base/lora"}), 150));
+        architectures.add(&record(json!({}), 10));
+
+        let groups = architectures.groups();
+        let summary: Vec<(&str, usize, u64)> = groups.iter().map(|g| (g.architecture.as_str(), g.files, g.bytes)).collect();
+        assert_eq!(summary, [("sdxl_base_v1-0", 1, 300), ("stable-diffusion-xl-v1-base/lora", 2, 250), ("unknown", 1, 10)]);
+        assert!(render(&groups).ends_with("total                             4      560 B\n"), "{}", render(&groups));
+    }
+}
@@ -0,0 +1,189 @@
+use crate::header::MAX_HEADER_SIZE;
+use anyhow::{Context, bail};
+use std::io::{Cursor, Read, Seek};
+use std::path::{Path, PathBuf};
+
+/// Separates an archive from the name of a member in it, as in `outer.zip!model.safetensors`.
+const SEPARATOR: char = '!';
+
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    Zip,
+    Tar,
+}
+
+fn kind(name: &str) -> Option<Kind> {
+    let extension = Path::new(name).extension()?;
+    if extension.eq_ignore_ascii_case("zip") {
+        Some(Kind::Zip)
+    } else if extension.eq_ignore_ascii_case("tar") {
+        Some(Kind::Tar)
+    } else {
+        None
+    }
+}
+
+/// Whether `path` is an archive `--recurse-archives` opens.
+pub fn is_archive(path: &Path) -> bool {
+    kind(&path.to_string_lossy()).is_some()
+}
+
+/// A safetensors file found in an archive.
+#[derive(Debug)]
+pub struct Member {
+    /// The archive's path followed by the member's name in each archive it is nested in.
+    pub path: PathBuf,
+    /// The member's uncompressed size.
+    pub size: u64,
+    /// The header JSON, unparsed.
+    pub header: Vec<u8>,
+}
+
+/// How far `--recurse-archives` unpacks an archive.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    /// How many levels of archives in archives to open (`--archive-depth`).
+    pub depth: usize,
+    /// How many bytes may be unpacked from one archive (`--max-archive-expansion`).
+    pub max_expansion: u64,
+}
+
+/// The safetensors members of the archive at `path` and of the archives nested in it, up to
+/// `limits.depth` levels down. Only their headers are read, but a nested archive has to be
+/// unpacked into memory to be opened; an archive unpacking to more than
+/// `limits.max_expansion` bytes in total is taken for a zip bomb and fails.
+pub fn members(path: &Path, limits: Limits) -> anyhow::Result<Vec<Member>> {
+    let kind = kind(&path.to_string_lossy()).context("Not a .zip or .tar archive")?;
+    let file = std::fs::File::open(path).context("Failed to open archive")?;
+    let mut walk = Walk { limits, expanded: 0, members: Vec::new() };
+    walk.archive(kind, file, &path.display().to_string(), 0)?;
+    Ok(walk.members)
+}
+
+struct Walk {
+    limits: Limits,
+    expanded: u64,
+    members: Vec<Member>,
+}
+
+impl Walk {
+    fn archive<R: Read + Seek>(&mut self, kind: Kind, reader: R, path: &str, depth: usize) -> anyhow::Result<()> {
+        match kind {
+            Kind::Zip => {
+                let mut zip = zip::ZipArchive::new(reader).with_context(|| format!("Failed to read {path} as zip"))?;
+                for index in 0..zip.len() {
+                    let entry = zip.by_index(index).with_context(|| format!("Failed to read {path}"))?;
+                    if entry.is_file() {
+                        let name = entry.name().with_context(|| format!("Failed to read {path}"))?.into_owned();
+                        let size = entry.size();
+                        self.entry(entry, &format!("{path}{SEPARATOR}{name}"), size, depth)?;
+                    }
+                }
+            }
+            Kind::Tar => {
+                let mut tar = tar::Archive::new(reader);
+                for entry in tar.entries().with_context(|| format!("Failed to read {path} as tar"))? {
+                    let entry = entry.with_context(|| format!("Failed to read {path}"))?;
+                    if entry.header().entry_type().is_file() {
+                        let name = entry.path().with_context(|| format!("Failed to read {path}"))?.display().to_string();
+                        let size = entry.size();
+                        self.entry(entry, &format!("{path}{SEPARATOR}{name}"), size, depth)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn entry(&mut self, mut reader: impl Read, path: &str, size: u64, depth: usize) -> anyhow::Result<()> {
+        if Path::new(path).extension().is_some_and(|ext| ext == "safetensors") {
+            let header = read_header(&mut reader, size).with_context(|| format!("Failed to read {path}"))?;
+            self.expanded += 8 + header.len() as u64;
+            self.members.push(Member { path: PathBuf::from(path), size, header });
+        } else if let Some(kind) = kind(path) {
+            if depth == self.limits.depth {
+                log::info!("Skipping {path}: nested deeper than --archive-depth");
+                return Ok(());
+            }
+            // Sizes in the archive can lie, so what is actually unpacked is counted
+            let allowed = self.limits.max_expansion.saturating_sub(self.expanded);
+            let mut bytes = Vec::new();
+            reader.take(allowed.saturating_add(1)).read_to_end(&mut bytes).with_context(|| format!("Failed to read {path}"))?;
+            self.expanded += bytes.len() as u64;
+            if self.expanded > self.limits.max_expansion {
+                bail!("Unpacking {path} exceeds --max-archive-expansion of {} bytes", self.limits.max_expansion);
+            }
+            self.archive(kind, Cursor::new(bytes), path, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+/// The header bytes of a safetensors member of `size` bytes, with a little-endian length.
+fn read_header(reader: &mut impl Read, size: u64) -> anyhow::Result<Vec<u8>> {
+    let mut prefix = [0u8; 8];
+    reader.read_exact(&mut prefix).context("File is too small to contain a safetensors header")?;
+    let len = u64::from_le_bytes(prefix);
+    if len > MAX_HEADER_SIZE {
+        bail!("Header length {len} exceeds the maximum of {MAX_HEADER_SIZE} bytes");
+    }
+    if len > size.saturating_sub(8) {
+        bail!("Header length {len} exceeds the file size of {size} bytes");
+    }
+    let mut header = vec![0; usize::try_from(len)?];
+    reader.read_exact(&mut header).context("Failed to read header")?;
+    Ok(header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn safetensors(metadata: &str) -> Vec<u8> {
+        let header = format!(r#"{{"__metadata__":{{"name":"{metadata}"}}}}"#);
+        let mut bytes = (header.len() as u64).to_le_bytes().to_vec();
+        bytes.extend(header.as_bytes());
+        bytes
+    }
+
+    fn tar(files: &[(&str, Vec<u8>)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (name, data) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            builder.append_data(&mut header, name, data.as_slice()).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn zip(files: &[(&str, Vec<u8>)]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in files {
+            writer.start_file(*name, zip::write::SimpleFileOptions::default()).unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_nested_archives() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let inner = tar(&[("model.safetensors", safetensors("inner")), ("deeper.tar", tar(&[("deep.safetensors", safetensors("deep"))]))]);
+        let outer = temp_dir.path().join("outer.zip");
+        std::fs::write(&outer, zip(&[("a.safetensors", safetensors("a")), ("inner.tar", inner), ("notes.txt", b"hi".to_vec())]))?;
+
+        let limits = Limits { depth: 1, max_expansion: 1 << 20 };
+        let members = members(&outer, limits)?;
+        let paths: Vec<String> = members.iter().map(|member| member.path.display().to_string()).collect();
+        let outer = outer.display();
+        assert_eq!(paths, [format!("{outer}!a.safetensors"), format!("{outer}!inner.tar!model.safetensors")]);
+        assert_eq!(members[0].header, br#"{"__metadata__":{"name":"a"}}"#);
+
+        let limits = Limits { depth: 1, max_expansion: 100 };
+        let error = super::members(Path::new(&outer.to_string()), limits).unwrap_err();
+        assert!(format!("{error:#}").contains("exceeds --max-archive-expansion of 100 bytes"), "{error:#}");
+        Ok(())
+    }
+}
@@ -0,0 +1,156 @@
+use crate::Scan;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+/// The number of files `--autotune` starts with reading at once.
+pub const START: usize = 2;
+
+/// How long `--autotune` measures the throughput of a concurrency for.
+const WINDOW: Duration = Duration::from_secs(1);
+
+/// How much faster a higher concurrency has to be to count as better, so noise doesn't keep it
+/// climbing.
+const MIN_GAIN: f64 = 1.1;
+
+/// The share of the files finished in a window that may fail before `--autotune` backs off.
+const MAX_FAILED: f64 = 0.25;
+
+/// The heuristic of `--autotune`. The concurrency starts at [`START`] and doubles after each
+/// window in which it was at least [`MIN_GAIN`] times as fast as the best so far; once it isn't,
+/// it goes back to the best and stays there. A window in which more than [`MAX_FAILED`] of the
+/// files failed halves it, whether it has settled or not, as storage that is overwhelmed tends to
+/// fail rather than slow down. Windows in which no file finished are not judged.
+#[derive(Debug)]
+struct Tuner {
+    concurrency: usize,
+    max: usize,
+    /// The fastest concurrency so far, with its files per second.
+    best: Option<(usize, f64)>,
+    /// Whether the concurrency stopped climbing.
+    settled: bool,
+}
+
+impl Tuner {
+    fn new(max: usize) -> Self {
+        Self { concurrency: START.min(max), max, best: None, settled: false }
+    }
+
+    /// Judge a window of `elapsed` in which `finished` files were done and `failed` of them
+    /// failed, and return the concurrency for the next.
+    fn next(&mut self, finished: usize, failed: usize, elapsed: Duration) -> usize {
+        if finished == 0 {
+            return self.concurrency;
+        }
+        #[allow(clippy::cast_precision_loss)] // only a rate
+        let (rate, failed_share) = (finished as f64 / elapsed.as_secs_f64(), failed as f64 / finished as f64);
+        if failed_share > MAX_FAILED {
+            self.concurrency = (self.concurrency / 2).max(1);
+            self.best = Some((self.concurrency, 0.0));
+            self.settled = true;
+        } else if !self.settled {
+            match self.best {
+                Some((best, best_rate)) if rate < best_rate * MIN_GAIN => {
+                    self.concurrency = best;
+                    self.settled = true;
+                }
+                _ => {
+                    self.best = Some((self.concurrency, rate));
+                    self.settled = self.concurrency == self.max;
+                    self.concurrency = (self.concurrency * 2).min(self.max);
+                }
+            }
+        }
+        self.concurrency
+    }
+}
+
+/// Scales the number of files read at once for `--autotune`, by resizing the scan's open-file
+/// semaphore, which `--max-open-files` sizes, between 1 and that size.
+#[derive(Debug)]
+pub struct Autotune {
+    tuner: Mutex<Tuner>,
+    /// The permits the semaphore has now, which lag behind a lower concurrency until enough of
+    /// the files holding them are done.
+    permits: AtomicUsize,
+}
+
+impl Autotune {
+    pub fn new(max: usize) -> Self {
+        Self { tuner: Mutex::new(Tuner::new(max)), permits: AtomicUsize::new(max) }
+    }
+
+    /// The concurrency it settled on, or is at.
+    pub fn concurrency(&self) -> usize {
+        self.tuner.lock().unwrap_or_else(std::sync::PoisonError::into_inner).concurrency
+    }
+
+    /// Start tuning the files `scan` is about to read, if it has `--autotune`.
+    pub fn start(scan: &Arc<Scan>) -> Option<Tuning> {
+        let autotune = scan.autotune.as_ref()?;
+        autotune.resize(scan, autotune.concurrency());
+        let scan = Arc::clone(scan);
+        Some(Tuning(tokio::spawn(async move {
+            let Some(autotune) = &scan.autotune else { return };
+            let mut ticks = tokio::time::interval_at(tokio::time::Instant::now() + WINDOW, WINDOW);
+            let (mut finished, mut failed, mut last) = (scan.summary.total(), scan.summary.failed(), Instant::now());
+            loop {
+                ticks.tick().await;
+                let (now_finished, now_failed) = (scan.summary.total(), scan.summary.failed());
+                let mut tuner = autotune.tuner.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+                let concurrency = tuner.next(now_finished - finished, now_failed - failed, last.elapsed());
+                drop(tuner);
+                autotune.resize(&scan, concurrency);
+                log::debug!("Autotune: {} files in the last window, now {concurrency} at once", now_finished - finished);
+                (finished, failed, last) = (now_finished, now_failed, Instant::now());
+            }
+        })))
+    }
+
+    /// Move the permits of the semaphore towards `concurrency`. Only free permits can be taken
+    /// away, so the rest are taken at a later window.
+    fn resize(&self, scan: &Scan, concurrency: usize) {
+        let permits = self.permits.load(Ordering::Relaxed);
+        if concurrency > permits {
+            scan.open_files.add_permits(concurrency - permits);
+            self.permits.store(concurrency, Ordering::Relaxed);
+        } else if concurrency < permits {
+            let forgotten = scan.open_files.forget_permits(permits - concurrency);
+            self.permits.store(permits - forgotten, Ordering::Relaxed);
+        }
+    }
+}
+
+/// The task of [`Autotune::start`]. Stops when dropped, leaving the concurrency where it is.
+pub struct Tuning(JoinHandle<()>);
+
+impl Drop for Tuning {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tuner() {
+        let mut tuner = Tuner::new(64);
+        let second = Duration::from_secs(1);
+        // Faster as it climbs, until 16 is no better than 8
+        assert_eq!(tuner.next(10, 0, second), 4);
+        assert_eq!(tuner.next(0, 0, second), 4);
+        assert_eq!(tuner.next(20, 0, second), 8);
+        assert_eq!(tuner.next(40, 0, second), 16);
+        assert_eq!(tuner.next(42, 0, second), 8);
+        assert_eq!(tuner.next(80, 0, second), 8);
+        // Too many failures
+        assert_eq!(tuner.next(40, 20, second), 4);
+
+        let mut tuner = Tuner::new(3);
+        assert_eq!(tuner.next(10, 0, second), 3);
+        assert_eq!(tuner.next(30, 0, second), 3);
+    }
+}
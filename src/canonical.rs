@@ -0,0 +1,129 @@
+use serde_json::{Number, Value};
+use std::fmt::Write;
+
+/// Serialize `value` as canonical JSON in the style of RFC 8785 (JCS): object keys sorted by
+/// their UTF-16 code units, no whitespace, floats in the shortest form that round-trips as
+/// JavaScript prints them, and only the escapes JSON requires. The same value always gives the
+/// same bytes, so the output can be hashed or signed.
+///
+/// Integers are written exactly rather than through a double, so values beyond 2^53 survive.
+pub fn to_string(value: &Value) -> String {
+    let mut out = String::new();
+    write_value(&mut out, value);
+    out
+}
+
+fn write_value(out: &mut String, value: &Value) {
+    match value {
+        Value::Null => out.push_str("null"),
+        Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        Value::Number(n) => write_number(out, n),
+        Value::String(s) => write_string(out, s),
+        Value::Array(values) => {
+            out.push('[');
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_value(out, value);
+            }
+            out.push(']');
+        }
+        Value::Object(map) => {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.encode_utf16().cmp(b.encode_utf16()));
+            out.push('{');
+            for (i, (key, value)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_string(out, key);
+                out.push(':');
+                write_value(out, value);
+            }
+            out.push('}');
+        }
+    }
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\u{08}' => out.push_str("\\b"),
+            '\u{0C}' => out.push_str("\\f"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c < ' ' => {
+                let _ = write!(out, "\\u{:04x}", u32::from(c));
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn write_number(out: &mut String, n: &Number) {
+    if n.is_i64() || n.is_u64() {
+        let _ = write!(out, "{n}");
+    } else if let Some(f) = n.as_f64() {
+        out.push_str(&format_f64(f));
+    }
+}
+
+/// Format a finite double the way ECMAScript's `Number.prototype.toString` does.
+fn format_f64(f: f64) -> String {
+    if f == 0.0 {
+        return "0".to_string();
+    }
+    // `{:e}` gives the shortest round-tripping digits, such as `-1.2345e-7`
+    let scientific = format!("{:e}", f.abs());
+    let (mantissa, exponent) = scientific.split_once('e').expect("`{:e}` output has an exponent");
+    let digits: String = mantissa.chars().filter(char::is_ascii_digit).collect();
+    let exponent: i32 = exponent.parse().expect("`{:e}` exponent is an integer");
+    let k = i32::try_from(digits.len()).expect("a double has at most 17 digits");
+    let n = exponent + 1;
+
+    let sign = if f < 0.0 { "-" } else { "" };
+    let body = if k <= n && n <= 21 {
+        format!("{digits}{}", "0".repeat((n - k).unsigned_abs() as usize))
+    } else if 0 < n && n <= 21 {
+        let (int, frac) = digits.split_at(n.unsigned_abs() as usize);
+        format!("{int}.{frac}")
+    } else if -6 < n && n <= 0 {
+        format!("0.{}{digits}", "0".repeat(n.unsigned_abs() as usize))
+    } else {
+        let (first, rest) = digits.split_at(1);
+        let fraction = if rest.is_empty() { String::new() } else { format!(".{rest}") };
+        let exponent_sign = if n - 1 < 0 { "-" } else { "+" };
+        format!("{first}{fraction}e{exponent_sign}{}", (n - 1).unsigned_abs())
+    };
+    format!("{sign}{body}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_canonical_json() {
+        let value = json!({"b": [1.5, 1e21, 1e-7, 8.0, -0.000_001, 123_456_789.125], "a": "tab\tctl\u{1}é", "é": null, "aa": {"z": 1, "y": true}});
+        assert_eq!(
+            to_string(&value),
+            r#"{"a":"tab\tctl\u0001é","aa":{"y":true,"z":1},"b":[1.5,1e+21,1e-7,8,-0.000001,123456789.125],"é":null}"#
+        );
+    }
+
+    #[test]
+    fn test_format_f64() {
+        assert_eq!(format_f64(0.1), "0.1");
+        assert_eq!(format_f64(100.0), "100");
+        assert_eq!(format_f64(1e20), "100000000000000000000");
+        assert_eq!(format_f64(1.5e-10), "1.5e-10");
+        assert_eq!(format_f64(-2.5e25), "-2.5e+25");
+    }
+}
@@ -0,0 +1,93 @@
+use crate::output::LineEnding;
+use crate::record::Record;
+use anyhow::Context;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// One JSON object with the records keyed by their path, for `--merge-into`. An existing
+/// file is updated: the records of this run replace those of the same paths and the others
+/// are kept.
+#[derive(Debug)]
+pub struct Catalog {
+    path: PathBuf,
+    line_ending: LineEnding,
+    /// The records of earlier runs.
+    existing: Map<String, Value>,
+    /// The records of this run, by path.
+    added: Mutex<BTreeMap<String, Vec<Value>>>,
+}
+
+impl Catalog {
+    /// Load the catalog at `path`, which may not exist yet.
+    pub fn load(path: &Path, line_ending: LineEnding) -> anyhow::Result<Self> {
+        let existing = match crate::io::read_to_string(path) {
+            Ok(text) => match serde_json::from_str(&text).with_context(|| format!("Failed to parse {}", path.display()))? {
+                Value::Object(existing) => existing,
+                _ => anyhow::bail!("{} isn't a JSON object keyed by path", path.display()),
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Map::new(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        Ok(Self { path: path.to_path_buf(), line_ending, existing, added: Mutex::default() })
+    }
+
+    pub fn add(&self, record: &Record) -> anyhow::Result<()> {
+        let key = record.path.to_string_lossy().into_owned();
+        let value = serde_json::to_value(record)?;
+        self.added.lock().unwrap_or_else(std::sync::PoisonError::into_inner).entry(key).or_default().push(value);
+        Ok(())
+    }
+
+    /// Write the catalog, with its keys sorted. When several records of this run have the same
+    /// path, such as the same file reached through two inputs, the one whose JSON sorts first
+    /// is kept, so the result doesn't depend on which finished first.
+    pub fn write(&self) -> anyhow::Result<()> {
+        let mut catalog: BTreeMap<String, Value> = self.existing.clone().into_iter().collect();
+        let mut added = self.added.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        for (key, records) in added.iter_mut() {
+            if records.len() > 1 {
+                eprintln!("Warning: {key}: {} records with this path, keeping one", records.len());
+            }
+            let kept = records.iter().min_by_key(ToString::to_string).cloned().unwrap_or(Value::Null);
+            catalog.insert(key.clone(), kept);
+        }
+        let json = self.line_ending.apply(serde_json::to_string_pretty(&catalog)?);
+        crate::io::atomic_write(&self.path, json.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_catalog() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("catalog.json");
+        std::fs::write(&path, r#"{"/m/a.safetensors": {"old": true}, "/m/gone.safetensors": {}}"#)?;
+        let record = |path: &str, metadata| Record { path: path.into(), metadata, ..Record::default() };
+
+        let catalog = Catalog::load(&path, LineEnding::Lf)?;
+        catalog.add(&record("/m/b.safetensors", json!({"v": 2})))?;
+        catalog.add(&record("/m/a.safetensors", json!({"v": 1})))?;
+        catalog.add(&record("/m/b.safetensors", json!({"v": 1})))?;
+        catalog.write()?;
+        let written: Value = serde_json::from_str(&std::fs::read_to_string(&path)?)?;
+        assert_eq!(
+            written,
+            json!({
+                "/m/a.safetensors": {"path": "/m/a.safetensors", "metadata": {"v": 1}},
+                "/m/b.safetensors": {"path": "/m/b.safetensors", "metadata": {"v": 1}},
+                "/m/gone.safetensors": {},
+            })
+        );
+
+        std::fs::write(&path, "[]")?;
+        assert!(Catalog::load(&path, LineEnding::Lf).is_err());
+        Ok(())
+    }
+}
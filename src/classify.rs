@@ -0,0 +1,104 @@
+use crate::formats::TensorInfo;
+use schemars::JsonSchema;
+use serde::Serialize;
+use serde_json::Value;
+
+/// What a model file contains, as guessed by [`classify`] for `--classify`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    /// A low-rank adapter, such as a kohya-ss network
+    Lora,
+    /// A full model checkpoint
+    Checkpoint,
+    /// A standalone VAE
+    Vae,
+    /// A textual inversion embedding
+    Embedding,
+    /// None of the rules matched
+    Unknown,
+}
+
+/// Tensor names written by textual inversion trainers: A1111 (`string_to_param.*`),
+/// kohya-ss (`emb_params`) and SDXL embeddings (`clip_l`, `clip_g`).
+const EMBEDDING_TENSORS: &[&str] = &["emb_params", "clip_l", "clip_g"];
+
+/// Prefixes of the tensors of an autoencoder, on its own or inside a checkpoint.
+const VAE_PREFIXES: &[&str] = &["encoder.", "decoder.", "quant_conv.", "post_quant_conv."];
+
+/// Checkpoints have more tensors than this; the smallest SD 1.x VAE has about 250.
+const MIN_CHECKPOINT_TENSORS: usize = 500;
+
+/// Guess what a file is from its training metadata and tensors. The rules, in order:
+///
+/// 1. A low-rank adapter has an `ss_network_module` in its metadata, as kohya-ss sd-scripts writes, or
+///    tensors named like `lora_unet_*`, `*.lora_down.*`/`*.lora_up.*` or PEFT's `*.lora_A.*`/`*.lora_B.*`.
+/// 2. An embedding has only a few tensors, all with textual inversion names.
+/// 3. A VAE has only encoder, decoder and quantization convolution tensors.
+/// 4. A checkpoint has a diffusion model (`model.diffusion_model.*`), or at least
+///    `MIN_CHECKPOINT_TENSORS` tensors of some other architecture.
+pub fn classify(metadata: &Value, tensors: &[TensorInfo]) -> Kind {
+    if metadata.get("ss_network_module").is_some() || tensors.iter().any(|tensor| is_lora_tensor(&tensor.name)) {
+        return Kind::Lora;
+    }
+    if tensors.is_empty() {
+        return Kind::Unknown;
+    }
+    if tensors.len() <= 4 && tensors.iter().all(|tensor| is_embedding_tensor(&tensor.name)) {
+        return Kind::Embedding;
+    }
+    if tensors.iter().all(|tensor| VAE_PREFIXES.iter().any(|prefix| tensor.name.starts_with(prefix))) {
+        return Kind::Vae;
+    }
+    if tensors.iter().any(|tensor| tensor.name.starts_with("model.diffusion_model."))
+        || tensors.len() >= MIN_CHECKPOINT_TENSORS
+    {
+        return Kind::Checkpoint;
+    }
+    Kind::Unknown
+}
+
+fn is_lora_tensor(name: &str) -> bool {
+    name.starts_with("lora_")
+        || [".lora_down.", ".lora_up.", ".lora_A.", ".lora_B."].iter().any(|part| name.contains(part))
+}
+
+fn is_embedding_tensor(name: &str) -> bool {
+    EMBEDDING_TENSORS.contains(&name) || name.starts_with("string_to_param.")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn tensors(names: &[&str]) -> Vec<TensorInfo> {
+        names
+            .iter()
+            .map(|name| TensorInfo {
+                name: (*name).to_string(),
+                dtype: "F16".to_string(),
+                shape: vec![4, 4],
+                ..TensorInfo::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_classify() {
+        let none = json!({});
+        let kohya = json!({"ss_network_module": "networks.lora"});
+        assert_eq!(classify(&kohya, &[]), Kind::Lora);
+        assert_eq!(classify(&none, &tensors(&["lora_unet_down_blocks_0.lora_down.weight", "lora_unet_down_blocks_0.alpha"])), Kind::Lora);
+        assert_eq!(classify(&none, &tensors(&["base_model.model.q_proj.lora_A.weight"])), Kind::Lora);
+        assert_eq!(classify(&none, &tensors(&["emb_params"])), Kind::Embedding);
+        assert_eq!(classify(&none, &tensors(&["clip_l", "clip_g"])), Kind::Embedding);
+        assert_eq!(classify(&none, &tensors(&["encoder.conv_in.weight", "decoder.conv_out.weight", "quant_conv.bias"])), Kind::Vae);
+        let checkpoint = tensors(&["model.diffusion_model.input_blocks.0.0.weight", "first_stage_model.encoder.conv_in.weight"]);
+        assert_eq!(classify(&none, &checkpoint), Kind::Checkpoint);
+        let many: Vec<String> = (0..MIN_CHECKPOINT_TENSORS).map(|i| format!("layers.{i}.weight")).collect();
+        assert_eq!(classify(&none, &tensors(&many.iter().map(String::as_str).collect::<Vec<_>>())), Kind::Checkpoint);
+        assert_eq!(classify(&none, &tensors(&["layers.0.weight"])), Kind::Unknown);
+        assert_eq!(classify(&none, &[]), Kind::Unknown);
+    }
+}
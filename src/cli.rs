@@ -0,0 +1,1093 @@
+use crate::collisions::OnDuplicate;
+use crate::drift::{MatchBy, parse_epsilon, parse_match_by};
+use crate::extractor::{Extractor, parse_extractor_arg};
+use crate::formats::InputFormat;
+use crate::filter::{Condition, CountBound, parse_count_bound, parse_pointer_arg, parse_where_arg};
+use crate::header::{Endian, InvalidUtf8};
+use crate::normalize::{Coercion, parse_coerce_arg};
+use crate::output::{Compression, LineEnding, OutputFormat, PathSeparator};
+use crate::readahead::Readahead;
+use crate::sidecar::{MergeMode, PathTemplate, parse_path_template};
+use crate::walk::{Symlinks, WalkErrors};
+use crate::template::{Template, parse_template_arg};
+use clap::{Parser, Subcommand, ValueEnum};
+use std::num::{NonZeroU64, NonZeroUsize};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Extract metadata from .safetensors files in a target directory and its subdirectories.
+#[derive(Parser, Debug)]
+#[command(version, about, subcommand_negates_reqs = true)]
+#[allow(clippy::struct_excessive_bools)] // one per command-line flag
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// The safetensors files, directories to scan recursively or glob patterns to read, or `-`
+    /// to read a safetensors file from stdin. A file found through more than one is read once
+    #[arg(
+        value_name = "PATH",
+        required_unless_present_any = ["print_schema", "input_list"],
+        conflicts_with = "input_list"
+    )]
+    pub paths: Vec<String>,
+
+    /// Read the paths to process from FILE instead, one per line, each a file, directory or
+    /// glob pattern as PATH would be. Relative paths are relative to FILE's directory; blank
+    /// lines and lines starting with `#` are ignored
+    #[arg(long, value_name = "FILE")]
+    pub input_list: Option<PathBuf>,
+
+    /// Expand the PATHs, or the entries of the --input-list, concurrently rather than one after
+    /// the other
+    #[arg(long)]
+    pub parallel_glob: bool,
+
+    /// Don't print the summary line at the end of the run
+    #[arg(short, long, global = true)]
+    pub quiet: bool,
+
+    /// Print more detail, such as why files were skipped
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Write the output to FILE instead of stdout
+    #[arg(short, long, global = true, value_name = "FILE")]
+    pub output: Option<PathBuf>,
+
+    /// Compress the --output file [default: gzip if it ends in .gz, otherwise none]
+    #[arg(long, global = true, value_enum, value_name = "KIND", requires = "output")]
+    pub compress: Option<Compression>,
+
+    /// Add to the end of the --output file instead of replacing it
+    #[arg(long, global = true, requires = "output")]
+    pub append: bool,
+
+    /// Split the --output file into numbered files, such as manifest.0001.jsonl, of at most N
+    /// records, or of a size such as 64MiB
+    #[arg(
+        long,
+        global = true,
+        value_name = "N",
+        requires = "output",
+        conflicts_with = "append",
+        value_parser = crate::output::parse_split_size
+    )]
+    pub output_split_size: Option<crate::output::SplitSize>,
+
+    /// With --append, skip files that already have a record in the output file
+    #[arg(long, global = true, requires = "append")]
+    pub dedupe_paths: bool,
+
+    /// How to lay out the records
+    #[arg(long, value_enum, default_value_t = OutputFormat::Json)]
+    pub format: OutputFormat,
+
+    /// Print each record as canonical JSON on one line: sorted keys, no whitespace and fixed
+    /// number and string formatting, so the same metadata always gives the same bytes
+    #[arg(long, conflicts_with = "format")]
+    pub canonical_json: bool,
+
+    /// With --format parquet, write a column for each metadata key, named after it with a
+    /// metadata. prefix, rather than the metadata as one column of JSON
+    #[arg(long, global = true)]
+    pub explode_keys: bool,
+
+    /// Print one line per file formatted by TEMPLATE, such as `{path}\t{size}\t{metadata.modelspec.title}`,
+    /// instead of JSON. Fields are record fields, with dots for nested keys; `{{`, `}}`, `\t`
+    /// and `\n` are a literal brace, a tab and a newline
+    #[arg(long, value_name = "TEMPLATE", value_parser = parse_template_arg, conflicts_with_all = ["format", "canonical_json", "dedupe_paths"])]
+    pub output_template: Option<Template>,
+
+    /// What --output-template prints for fields a record doesn't have [default: nothing]
+    #[arg(long, value_name = "TEXT", requires = "output_template")]
+    pub missing_placeholder: Option<String>,
+
+    /// How to write the paths in records: `slash` uses forward slashes even on Windows, so
+    /// catalogs read the same everywhere. Files are still opened by their native paths
+    #[arg(long, global = true, value_enum, value_name = "STYLE", default_value_t = PathSeparator::Native, alias = "normalize-path-display")]
+    pub path_separator: PathSeparator,
+
+    /// Name files in warnings as their records do, relative to --manifest-relative-paths and
+    /// with the --path-separator, so a warning can be looked up in the output
+    #[arg(long, global = true)]
+    pub canonical_paths_in_errors: bool,
+
+    /// Size in bytes of the output buffer; records are written in batches of
+    /// this size instead of one write per record (0 disables buffering)
+    #[arg(long, global = true, value_name = "BYTES", default_value_t = 64 * 1024)]
+    pub buffer_size: usize,
+
+    /// Flush the output after every N records as well as when the buffer is full, so that a
+    /// reader of a pipe sees records sooner. 1 flushes each record
+    #[arg(long, global = true, value_name = "N", alias = "output-ndjson-buffered-flush")]
+    pub flush_every: Option<NonZeroU64>,
+
+    /// Print the SHA-256 of everything written to the output on stderr at the end, to check
+    /// that a catalog wasn't changed or that two runs wrote the same
+    #[arg(long, global = true, alias = "output-checksum")]
+    pub output_digest: bool,
+
+    /// Resolve relative input paths against DIR instead of the current directory
+    #[arg(long, global = true, value_name = "DIR")]
+    pub deref_relative_to: Option<PathBuf>,
+
+    /// Write the paths in records and manifests relative to --relative-to, so a catalog stays
+    /// valid when the collection moves. --compare-to and --dedupe-paths resolve them against
+    /// the same directory
+    #[arg(long, global = true, group = "relative")]
+    pub manifest_relative_paths: bool,
+
+    /// Include the path of each file relative to the input it was found under (`relpath`) along
+    /// with the absolute `path`, so a catalog is both portable and usable where it was made
+    #[arg(long, global = true, group = "relative", alias = "emit-relative-and-absolute")]
+    pub with_relpath: bool,
+
+    /// The directory --manifest-relative-paths writes paths relative to, by default the one
+    /// relative input paths are resolved against
+    #[arg(long, global = true, value_name = "DIR", requires = "manifest_relative_paths")]
+    pub relative_to: Option<PathBuf>,
+
+    /// What to do when the directory walk can't read a directory or entry, such as one without
+    /// permission: warn and count it in the summary, or abort
+    #[arg(long, global = true, value_enum, value_name = "MODE", default_value_t = WalkErrors::Warn)]
+    pub walk_errors: WalkErrors,
+
+    /// Don't descend into directories whose name, or path relative to the scanned directory,
+    /// matches GLOB, such as `optimizer_states`. May be repeated. Pruned directories are
+    /// reported with -v
+    #[arg(long, global = true, value_name = "GLOB")]
+    pub exclude_dir: Vec<glob::Pattern>,
+
+    /// Also scan hidden files and directories, whose name starts with `.` or which have the
+    /// hidden attribute on Windows. Skipped ones are reported with -v
+    #[arg(long, global = true)]
+    pub include_hidden: bool,
+
+    /// Which symlinks directory scans follow: all of them, only those whose target is inside
+    /// the scanned directory, or none. Links skipped by `internal` are reported with -v
+    #[arg(long, global = true, value_enum, value_name = "POLICY", default_value_t = Symlinks::All)]
+    pub symlinks: Symlinks,
+
+    /// Detect each file's format from its first bytes instead of trusting its extension.
+    /// Directory scans then also consider .bin, .pt, .pth and .ckpt files
+    #[arg(long, global = true)]
+    pub sniff: bool,
+
+    /// Read every file as FORMAT whatever its name, overriding --sniff as well as the
+    /// extensions that select --recurse-archives, --extractor and shard indexes. Directory
+    /// scans look for FORMAT's extensions only
+    #[arg(long = "as", alias = "input-format-override", global = true, value_enum, value_name = "FORMAT")]
+    pub input_format: Option<InputFormat>,
+
+    /// Tell the kernel how each file will be read before reading its header, which can speed
+    /// up scans of HDD arrays: `random` keeps it from reading ahead into the tensor data.
+    /// Only has an effect on Linux
+    #[arg(long, global = true, value_enum, value_name = "PATTERN", default_value_t = Readahead::Default)]
+    pub readahead: Readahead,
+
+    /// The byte order of the header length prefix: `auto` falls back to big-endian when the
+    /// little-endian length doesn't fit the file. With -v, prints which one each file used
+    #[arg(long, global = true, value_enum, value_name = "ORDER", default_value_t = Endian::Le)]
+    pub header_endian: Endian,
+
+    /// How to handle invalid UTF-8 in a header
+    #[arg(long, global = true, value_enum, value_name = "MODE", default_value_t = InvalidUtf8::Error)]
+    pub on_invalid_utf8: InvalidUtf8,
+
+    /// Accept headers with a UTF-8 byte order mark or whitespace before the JSON, with a warning
+    #[arg(long, global = true)]
+    pub lenient: bool,
+
+    /// Warn about metadata values that are JSON encoded twice, a string holding an escaped
+    /// JSON string, naming the affected keys
+    #[arg(long, global = true)]
+    pub detect_double_encoding: bool,
+
+    /// Like --detect-double-encoding, and unwrap such values one level in the output. The
+    /// files themselves are never changed
+    #[arg(long, global = true)]
+    pub fix_double_encoding: bool,
+
+    /// Fail files whose header has an object with the same key twice, which most JSON parsers
+    /// silently accept by keeping the last value
+    #[arg(long, global = true)]
+    pub fail_on_duplicate_keys: bool,
+
+    /// Warn about files whose header lists a tensor name twice, naming the tensors. Only the
+    /// last entry of such a tensor is read
+    #[arg(long, global = true)]
+    pub report_duplicated_tensor_names: bool,
+
+    /// Fail files whose __metadata__ has more than N keys, without reading the rest of them,
+    /// to bound the memory a malicious or broken header can take
+    #[arg(long, global = true, value_name = "N", default_value_t = crate::header::DEFAULT_MAX_METADATA_KEYS)]
+    pub max_metadata_keys: usize,
+
+    /// Fail files whose header is longer than BYTES, before reading it. This is what bounds
+    /// the header read from stdin, which has no size to check it against
+    #[arg(long, global = true, value_name = "BYTES", default_value_t = crate::header::MAX_HEADER_SIZE)]
+    pub max_header_bytes: u64,
+
+    /// On Ctrl-C, finish the files in progress instead of quitting at once; a second Ctrl-C
+    /// within 5 seconds quits without waiting, with exit status 131
+    #[arg(long, global = true)]
+    pub abort_on_signal_second: bool,
+
+    /// Skip files whose header runs past the end of the file if they were modified in the last
+    /// minute, as they are likely still being written, instead of failing them
+    #[arg(long, global = true)]
+    pub partial_ok: bool,
+
+    /// Fail files that break any rule of the safetensors spec, naming each rule and where it is
+    /// broken: dtypes, shapes, offsets, the layout of the data and string-only metadata
+    #[arg(long, global = true)]
+    pub strict_spec: bool,
+
+    /// Fail files with a tensor whose dtype isn't one of the safetensors dtypes, such as from a
+    /// newer or nonstandard writer, naming the tensors and their dtypes
+    #[arg(long, global = true)]
+    pub bail_on_unknown_dtype: bool,
+
+    /// Fail files with unused bytes between the data of two tensors, as some loaders require
+    /// the data to be contiguous, naming the size and place of each gap
+    #[arg(long, global = true, alias = "validate-offsets-contiguous")]
+    pub require_contiguous: bool,
+
+    /// Give files without metadata a record with empty metadata instead of skipping or failing
+    /// them: safetensors files without `__metadata__`, `torch.save` zip checkpoints and, with
+    /// --sniff, files of no known format
+    #[arg(long, global = true)]
+    pub metadata_only_if_present: bool,
+
+    /// Only read a file once its size and modification time have stayed the same for this
+    /// long, such as `500ms`, so that checkpoints aren't read while they are being written.
+    /// Files that are still changing after ten such waits are skipped
+    #[arg(long, global = true, value_name = "DURATION", value_parser = humantime::parse_duration)]
+    pub watch_debounce: Option<Duration>,
+
+    /// After the summary, list the files that couldn't be opened, such as for a lack of
+    /// permission or a broken symlink, with the kind of OS error
+    #[arg(long, global = true)]
+    pub report_unreadable: bool,
+
+    /// Also write a report of each file's outcome, in FORMAT, to --report-out, such as for
+    /// CI dashboards. The output is unchanged
+    #[arg(long, global = true, value_enum, value_name = "FORMAT", alias = "report-format", requires = "report_out")]
+    pub report: Option<RunReport>,
+
+    /// The file --report writes to
+    #[arg(long, global = true, value_name = "FILE", requires = "report")]
+    pub report_out: Option<PathBuf>,
+
+    /// After the scan, write a JSON object with its counts, errors by kind, bytes, duration
+    /// and concurrency to FILE, for the system running the scan to keep
+    #[arg(long, global = true, value_name = "FILE")]
+    pub stats_out: Option<PathBuf>,
+
+    /// Open .zip and .tar files and read the safetensors files in them, including those in
+    /// archives nested in the archive. Members are reported as `outer.zip!inner.tar!model.safetensors`
+    #[arg(long, global = true)]
+    pub recurse_archives: bool,
+
+    /// With --recurse-archives, how many levels of archives inside archives to open
+    #[arg(long, global = true, value_name = "N", default_value_t = 3)]
+    pub archive_depth: usize,
+
+    /// With --recurse-archives, fail an archive that unpacks to more than BYTES in total, as a
+    /// guard against zip bombs
+    #[arg(long, global = true, value_name = "BYTES", default_value_t = 4 << 30)]
+    pub max_archive_expansion: u64,
+
+    /// Stop starting files once those started add up to BYTES, to bound the time a scan with
+    /// --hash takes. The files in progress are finished
+    #[arg(long, global = true, value_name = "BYTES")]
+    pub max_total_bytes: Option<u64>,
+
+    /// Maximum number of files open at once [default: half the soft open-file limit]
+    #[arg(long, global = true, value_name = "N")]
+    pub max_open_files: Option<NonZeroUsize>,
+
+    /// Find the number of files to read at once instead of opening up to --max-open-files: start
+    /// with 2 and double it while the files per second go up, going back to the fastest once
+    /// they don't, and halve it when many files fail. The number settled on is printed at the end
+    #[arg(long, global = true, alias = "concurrency-autotune", conflicts_with = "parallel_hash")]
+    pub autotune: bool,
+
+    /// Read each header in a single blocking task on a pool of at most N threads, instead of
+    /// one async step at a time, so slow filesystems don't hold up the rest of the scan
+    #[arg(long, global = true, value_name = "N")]
+    pub threads_blocking: Option<NonZeroUsize>,
+
+    /// Whether --max-open-files is shared by all files or also limited per directory, so that
+    /// one large directory doesn't hold up the others
+    #[arg(long, global = true, value_enum, value_name = "SCOPE", default_value_t = ConcurrencyScope::Global)]
+    pub concurrency_scope: ConcurrencyScope,
+
+    /// With --concurrency-scope per-directory, how many files of one directory are read at once
+    #[arg(long, global = true, value_name = "N", default_value_t = NonZeroUsize::new(4).unwrap())]
+    pub workers_per_directory: NonZeroUsize,
+
+    /// Read at most N files at once from each disk, told apart by mount point, so HDDs aren't
+    /// thrashed while files on other disks are read in parallel
+    #[arg(long, global = true, value_name = "N")]
+    pub workers_per_disk: Option<NonZeroUsize>,
+
+    /// Include each file's modification time in its record, as an RFC 3339 timestamp
+    /// (`modified`) and in seconds since the Unix epoch (`modified_epoch`)
+    #[arg(long, global = true)]
+    pub with_mtime: bool,
+
+    /// Include a stable ID for each record (`id`): the SHA-256 of its path, which unlike --hash
+    /// doesn't change when the file is rewritten
+    #[arg(long, global = true, alias = "emit-path-hash")]
+    pub with_id: bool,
+
+    /// Include the version of the record layout (`schema_version`), so a reader of a long-lived
+    /// catalog can tell which fields to expect
+    #[arg(long, global = true, alias = "json-lines-schema-version")]
+    pub with_schema_version: bool,
+
+    /// Include the input each file was found through (`source`), as given: the directory, glob
+    /// pattern or file, or the --input-list entry, for telling apart the sources of one catalog
+    #[arg(long, global = true, alias = "record-source-argument")]
+    pub with_source: bool,
+
+    /// Include each file's size in bytes in its record (`size`)
+    #[arg(long, global = true)]
+    pub with_size: bool,
+
+    /// Write the numbers the tool computes (`size`, `modified_epoch`, `parameters`, `tensors` and the
+    /// values of `shard_sizes`) as strings, so consumers reading JSON numbers as doubles keep
+    /// them exact. Numbers in the metadata are left as they are
+    #[arg(long, global = true)]
+    pub json_numbers_as_strings: bool,
+
+    /// Leave out the fields and metadata keys whose value is null, an empty string, array or
+    /// object, at any depth, for leaner records
+    #[arg(long, global = true)]
+    pub compact_empty: bool,
+
+    /// Order the keys of the JSON records, and the tensors of --tensors, naturally, with numbers
+    /// in them by value: `block_2` before `block_10`
+    #[arg(long, global = true, alias = "sort-metadata-numeric-keys")]
+    pub natural_key_order: bool,
+
+    /// Convert the string values of known numeric and boolean fields (such as `ss_network_dim`
+    /// or `ss_cache_latents`) to JSON numbers and booleans
+    #[arg(long, global = true)]
+    pub normalize_metadata: bool,
+
+    /// Trim the string values of the metadata and normalize the whitespace in them, before
+    /// --where and the reports see them. The files themselves are never changed
+    #[arg(long, global = true)]
+    pub canonicalize_metadata_strings: bool,
+
+    /// Override how --normalize-metadata treats KEY; TYPE is number, bool or string (left as is).
+    /// Can be repeated
+    #[arg(long, global = true, value_name = "KEY=TYPE", value_parser = parse_coerce_arg)]
+    pub coerce: Vec<(String, Coercion)>,
+
+    /// Include the SHA-256 of each file's contents in its record (`sha256`). This reads
+    /// every file in full, where the metadata alone only needs the header
+    #[arg(long, global = true)]
+    pub hash: bool,
+
+    /// Size in bytes of the buffer files are read through by --hash. Larger buffers mean
+    /// fewer reads on fast storage, smaller ones less memory per hashing worker
+    #[arg(long, global = true, value_name = "BYTES", default_value_t = NonZeroUsize::new(crate::hash::DEFAULT_CHUNK_SIZE).unwrap())]
+    pub hash_chunk_size: NonZeroUsize,
+
+    /// With --hash, read headers and hash files in separate stages, so one file's header
+    /// is read while another is hashed
+    #[arg(long, global = true, requires = "hash")]
+    pub parallel_hash: bool,
+
+    /// Write the records in the order the files were found, while still processing files
+    /// concurrently
+    #[arg(long, global = true, conflicts_with = "parallel_hash")]
+    pub preserve_order: bool,
+
+    /// With --preserve-order, the most files processed ahead of the first one not yet written,
+    /// which bounds the records held in memory
+    #[arg(long, global = true, value_name = "N", default_value_t = NonZeroUsize::new(1000).unwrap())]
+    pub reorder_buffer: NonZeroUsize,
+
+    /// With --parallel-hash, how many records may wait between the two stages
+    #[arg(long, global = true, value_name = "N", default_value = "16")]
+    pub pipeline_depth: NonZeroUsize,
+
+    /// Read the headers of up to N files ahead of the one being processed, to hide the latency
+    /// of slow storage. 0 turns this off
+    #[arg(long, global = true, value_name = "N", default_value_t = crate::prefetch::DEFAULT_AHEAD)]
+    pub prefetch: usize,
+
+    /// Sign each file's metadata with the Ed25519 key given by --key, writing the signature
+    /// to `<file>.metadata.sig` and into the record (`signature`)
+    #[arg(long, global = true, requires = "key")]
+    pub sign: bool,
+
+    /// The Ed25519 private key for --sign, as PKCS#8 PEM or the 32-byte seed in hex
+    #[arg(long, global = true, value_name = "FILE")]
+    pub key: Option<PathBuf>,
+
+    /// Include each file's number of parameters in its record (`parameters`), counted from
+    /// SOURCE: `shapes` multiplies out the tensor shapes in the header
+    #[arg(long, global = true, value_enum, value_name = "SOURCE")]
+    pub count_parameters_from: Option<ParameterSource>,
+
+    /// Fail the files whose parameter count, counted from the tensor shapes, is outside BOUND:
+    /// `<=7B`, `<7B`, `>=350M`, `>350M`, a range such as `1B..7B`, or an exact count. The scan
+    /// exits with an error if any file is outside it
+    #[arg(long, global = true, value_name = "BOUND", value_parser = parse_count_bound)]
+    pub assert_param_count: Option<CountBound>,
+
+    /// Exit with an error if no files matched, instead of silently doing nothing
+    #[arg(long, global = true)]
+    pub fail_on_empty: bool,
+
+    /// Instead of printing the records, compare them with those in BASELINE, a file written by
+    /// an earlier run or a manifest.json, and report the files added, removed and changed
+    #[arg(long, global = true, value_name = "BASELINE")]
+    pub compare_to: Option<PathBuf>,
+
+    /// How --compare-to reports the differences
+    #[arg(long, global = true, value_enum, value_name = "FORMAT", default_value_t = DriftFormat::Text, requires = "compare_to")]
+    pub drift_format: DriftFormat,
+
+    /// Count metadata values that are numbers, or strings that parse as numbers, within E of
+    /// each other as unchanged in --compare-to, such as `1e-4` and `0.0001`
+    #[arg(long, global = true, value_name = "E", value_parser = parse_epsilon, requires = "compare_to")]
+    pub numeric_epsilon: Option<f64>,
+
+    /// Pair the files of --compare-to that aren't at the same path in both scans by their
+    /// `hash`, which needs --hash and a baseline with hashes, or by the value of a metadata key
+    /// (`key:NAME`), and report those that are the same but moved apart from the changes
+    #[arg(
+        long,
+        global = true,
+        value_name = "hash|key:NAME",
+        value_parser = parse_match_by,
+        requires = "compare_to",
+        alias = "compare-metadata-only"
+    )]
+    pub match_by: Option<MatchBy>,
+
+    /// Exit with an error if --compare-to found any differences
+    #[arg(long, global = true, requires = "compare_to")]
+    pub fail_on_drift: bool,
+
+    /// Instead of printing the records, print the size of each file's header and metadata,
+    /// largest metadata first, with the share of the file the metadata takes up
+    #[arg(long, global = true)]
+    pub metadata_size_report: bool,
+
+    /// Instead of printing the records, print the number of files and their total size for each
+    /// architecture, taken from `modelspec.architecture` or failing that `ss_base_model_version`,
+    /// `general.architecture`, `architecture` or `model_type`
+    #[arg(long, global = true, alias = "group-by-architecture")]
+    pub by_architecture: bool,
+
+    /// How --by-architecture is printed
+    #[arg(long, global = true, value_enum, value_name = "FORMAT", default_value_t = ReportFormat::Table, requires = "by_architecture")]
+    pub architecture_format: ReportFormat,
+
+    /// Instead of printing the records, hash every file and print how many have distinct
+    /// contents, their total and distinct size, and the bytes that removing the copies would
+    /// free. Renamed copies count as the same file
+    #[arg(long, global = true)]
+    pub count_distinct_files_by_hash: bool,
+
+    /// Instead of printing the records, print the groups of files whose metadata has the same
+    /// value for KEY, such as `modelspec.hash`. May be repeated for files that have the same
+    /// value for every KEY
+    #[arg(long, global = true, value_name = "KEY")]
+    pub dedupe_by: Vec<String>,
+
+    /// Which file of each --dedupe-by group to mark as the one to keep, the others as the ones
+    /// to remove
+    #[arg(long, global = true, value_enum, value_name = "WHICH", requires = "dedupe_by")]
+    pub keep: Option<Keep>,
+
+    /// Instead of printing the records, print each distinct value of the metadata key KEY once
+    /// with the number of files that have it, most frequent first. May be repeated
+    #[arg(long, global = true, value_name = "KEY", alias = "unique-metadata")]
+    pub distinct: Vec<String>,
+
+    /// Instead of printing the records, print how the values of the metadata key KEY are
+    /// distributed over the files, with the share of each; with --buckets, by numeric range.
+    /// May be repeated
+    #[arg(long, global = true, value_name = "KEY", alias = "metadata-value-histogram")]
+    pub histogram: Vec<String>,
+
+    /// Count the numeric values of --histogram in N ranges of equal width
+    #[arg(long, global = true, value_name = "N", requires = "histogram")]
+    pub buckets: Option<NonZeroUsize>,
+
+    /// Whether --histogram counts each value or numeric ranges
+    #[arg(long, global = true, value_enum, value_name = "MODE", default_value_t = HistogramMode::Auto)]
+    pub histogram_mode: HistogramMode,
+
+    /// Print the minimum, 50th, 90th and 99th percentile, maximum and mean of the numeric
+    /// values of the metadata key KEY with the summary, after the scan. May be repeated
+    #[arg(long, global = true, value_name = "KEY", alias = "summary-percentiles")]
+    pub percentiles: Vec<String>,
+
+    /// Instead of printing the records, sum the tag counts of `ss_tag_frequency` across the
+    /// files and print the N most common tags (50 if N isn't given), with the number of files
+    /// that have each
+    #[arg(
+        long,
+        global = true,
+        value_name = "N",
+        alias = "collect-tag-frequencies",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "50"
+    )]
+    pub aggregate_tag_frequencies: Option<usize>,
+
+    /// Instead of printing the records, write a manifest.json into each directory with the
+    /// records of the files directly in it
+    #[arg(long, global = true)]
+    pub group_output_by_dir: bool,
+
+    /// Instead of printing the records, merge them into FILE, one JSON object with each record
+    /// under its path. An existing FILE is updated, keeping the records of other paths
+    #[arg(long, global = true, value_name = "FILE", conflicts_with = "group_output_by_dir")]
+    pub merge_into: Option<PathBuf>,
+
+    /// Keep the records in the state FILE with the size and modification time of each file,
+    /// and on later runs parse only the files that are new or changed, keeping the records of
+    /// the others and dropping those of files no longer found. The records aren't printed
+    #[arg(
+        long,
+        global = true,
+        value_name = "FILE",
+        alias = "parse-only-changed",
+        conflicts_with_all = ["group_output_by_dir", "merge_into"]
+    )]
+    pub incremental: Option<PathBuf>,
+
+    /// Stop the scan with an error once the records held until its end, such as for
+    /// --merge-into, --group-shards or --compare-to, take more than BYTES, as estimated from
+    /// their JSON, instead of running out of memory
+    #[arg(long, global = true, value_name = "BYTES")]
+    pub max_memory: Option<u64>,
+
+    /// Read the `<stem>.json` already next to each file, such as one with hand-curated metadata,
+    /// and merge it into the record as set by --sidecar-merge-mode. The sidecar isn't overwritten
+    #[arg(long, global = true)]
+    pub merge_sidecar: bool,
+
+    /// Write each file's metadata to TEMPLATE instead of the `.json` next to it, such as
+    /// `out/{relpath}.meta.json`. Placeholders: {relpath}, the path relative to the input base
+    /// without extension, {stem}, {ext} and {hash}, which needs --hash
+    #[arg(long, global = true, alias = "output-per-file", value_name = "TEMPLATE", value_parser = parse_path_template)]
+    pub per_file_out: Option<PathTemplate>,
+
+    /// What to do when the `.json` of two files, or their --per-file-out paths, are the same
+    /// path. -v reports each such collision
+    #[arg(long, global = true, value_enum, value_name = "POLICY", default_value_t = OnDuplicate::Overwrite)]
+    pub on_duplicate_output_path: OnDuplicate,
+
+    /// How --merge-sidecar combines the sidecar with the extracted metadata
+    #[arg(long, global = true, value_enum, value_name = "MODE", default_value_t = MergeMode::Nested, requires = "merge_sidecar")]
+    pub sidecar_merge_mode: MergeMode,
+
+    /// Guess whether each file is a low-rank adapter, a checkpoint, a VAE or an embedding from
+    /// its tensor names and metadata, and add it to the record as `kind`
+    #[arg(long, global = true)]
+    pub classify: bool,
+
+    /// Guess which tool wrote each file, such as sd-scripts or diffusers, from the keys of its
+    /// metadata, and add it to the record as `producer` with how sure the guess is
+    #[arg(long, global = true, alias = "detect-metadata-encoding-format")]
+    pub identify_tool: bool,
+
+    /// List each file's tensors in its record (`tensor_list`) with their dtypes and shapes
+    #[arg(long, global = true)]
+    pub tensors: bool,
+
+    /// Include the number of tensors in each file's header in its record (`tensors`), and the
+    /// total in the summary line. Much shorter than the list of --tensors
+    #[arg(long, global = true)]
+    pub count_tensors: bool,
+
+    /// The order of the tensors listed by --tensors. `offset` also shows each tensor's
+    /// `byte_range` and its `gap_to_next`
+    #[arg(long, global = true, value_enum, value_name = "ORDER", default_value_t = TensorOrder::Name, requires = "tensors")]
+    pub sort_tensors_by: TensorOrder,
+
+    /// Only list the first N tensors with --tensors, in the --sort-tensors-by order, and the
+    /// number of the others as `tensors_not_listed`
+    #[arg(long, global = true, value_name = "N", requires = "tensors")]
+    pub first_n_tensors: Option<usize>,
+
+    /// Only list, and count the parameters of, the tensors whose name matches GLOB, such as
+    /// `*.attn.*`. May be repeated
+    #[arg(long, global = true, value_name = "GLOB")]
+    pub tensor_filter: Vec<glob::Pattern>,
+
+    /// Show the canonical name of each tensor listed by --tensors (`canonical_name`), from a
+    /// JSON object mapping names to canonical names. A key and name ending in `*` rename a prefix
+    #[arg(long, global = true, value_name = "FILE")]
+    pub tensor_name_map: Option<PathBuf>,
+
+    /// The line ending of the .json files written next to the models and of the manifests of
+    /// --group-output-by-dir
+    #[arg(long, global = true, value_enum, value_name = "ENDING", default_value_t = LineEnding::Lf, alias = "normalize-line-endings")]
+    pub line_ending: LineEnding,
+
+    /// Write the preview images embedded in the metadata, as base64 or data URIs, into DIR as
+    /// `<stem>.<key>.<ext>`
+    #[arg(long, global = true, value_name = "DIR")]
+    pub extract_thumbnails: Option<PathBuf>,
+
+    /// Write the base64-decoded value of KEY in the metadata of a single file to stdout as raw
+    /// bytes, instead of the record
+    #[arg(long, value_name = "KEY", alias = "extract-to-stdout-binary", conflicts_with = "probe_only")]
+    pub extract_key_binary: Option<String>,
+
+    /// Read and print the first K elements, 8 by default and at most 1024, of the tensor
+    /// NAME of a single file, decoded by its dtype. Unlike everything else, this reads tensor
+    /// data
+    #[arg(
+        long,
+        value_name = "NAME[:K]",
+        value_parser = crate::peek::parse_peek,
+        conflicts_with_all = ["probe_only", "extract_key_binary"]
+    )]
+    pub peek_tensor: Option<crate::peek::Peek>,
+
+    /// Only process files with at least one tensor of type DTYPE, such as BF16. May be
+    /// repeated, in which case a file needs tensors of each type
+    #[arg(long, global = true, value_name = "DTYPE")]
+    pub has_dtype: Vec<String>,
+
+    /// Only process files whose tensors are all of type DTYPE, such as F16
+    #[arg(long, global = true, value_name = "DTYPE")]
+    pub only_dtype: Option<String>,
+
+    /// Only process files whose tensor NAME has the shape SHAPE, the sizes of its dimensions
+    /// separated by commas with `*` for any size, as in `model.embed_tokens.weight=32000,*`. May be
+    /// repeated, in which case a file must match each
+    #[arg(
+        long,
+        global = true,
+        value_name = "NAME=SHAPE",
+        alias = "tensor-shape-filter",
+        value_parser = crate::filter::parse_tensor_shape
+    )]
+    pub tensor_shape: Vec<crate::filter::ShapeCondition>,
+
+    /// Only process files whose number of tensors is within BOUND: `<100`, `<=100`, `>2000`,
+    /// `>=2000`, an inclusive range such as `100..500`, or an exact count
+    #[arg(long, global = true, value_name = "BOUND", alias = "tensor-count-range", value_parser = parse_count_bound)]
+    pub tensor_count: Option<CountBound>,
+
+    /// When PATH is a shard index such as model.safetensors.index.json, read the shards it
+    /// lists instead and combine them into one record as --group-shards does
+    #[arg(long, global = true)]
+    pub follow_index_to_shards: bool,
+
+    /// With --follow-index-to-shards, compare the index's `metadata.total_size` with the tensor
+    /// data of the shards found, warning if they differ
+    #[arg(long, global = true, alias = "tensor-total-bytes-check", requires = "follow_index_to_shards")]
+    pub check_total_size: bool,
+
+    /// With --follow-index-to-shards, check that every tensor of the index's `weight_map` is in
+    /// the shard it names and that the shards have no tensors the map doesn't list, warning
+    /// with the ones missing or extra
+    #[arg(long, global = true, requires = "follow_index_to_shards")]
+    pub verify_against_index_weightmap: bool,
+
+    /// Emit one record per sharded model instead of one per shard, with summed sizes and
+    /// counts, the metadata the shards agree on and a list of the keys they disagree on
+    #[arg(long, global = true)]
+    pub group_shards: bool,
+
+    /// Write a record for each file that was skipped, with just its path and the reason in
+    /// `skipped`, so every file found is accounted for in the output
+    #[arg(long, global = true)]
+    pub emit_empty_record_for_skipped: bool,
+
+    /// Print only the files that failed, each with its error, instead of the records of the
+    /// files that succeeded. Exits with status 1 if any file failed
+    #[arg(long, global = true)]
+    pub only_errors: bool,
+
+    /// Add the value at the JSON Pointer POINTER in the metadata, such as
+    /// `/ss_dataset_dirs/0/name`, to each record's `pointers`, null if there is none. May be repeated
+    #[arg(long, alias = "metadata-json-pointer", global = true, value_name = "POINTER", value_parser = parse_pointer_arg)]
+    pub pointer: Vec<String>,
+
+    /// Keep only the metadata keys starting with PREFIX, such as `ss_` or `modelspec.`.
+    /// Can be repeated to keep the keys matching any of them
+    #[arg(long, alias = "metadata-prefix-filter", global = true, value_name = "PREFIX")]
+    pub prefix: Vec<String>,
+
+    /// With --prefix, remove the matching prefix from the keys that are kept
+    #[arg(long, global = true, requires = "prefix")]
+    pub strip_prefix: bool,
+
+    /// Add the named groups of REGEX, matched against each file name, to the metadata as
+    /// `fname.<group>`, such as `fname.step` for `-step(?<step>\d+)`
+    #[arg(long, global = true, value_name = "REGEX", value_parser = crate::filename::parse_filename_regex)]
+    pub parse_filename: Option<regex::Regex>,
+
+    /// Only emit files whose metadata has KEY, with the value VALUE if given. Can be repeated;
+    /// a file must satisfy every condition
+    #[arg(long = "where", global = true, value_name = "KEY[=VALUE]", value_parser = parse_where_arg)]
+    pub r#where: Vec<Condition>,
+
+    /// Only emit files whose metadata has the key NAME, whatever its value. Can be repeated
+    #[arg(long, global = true, value_name = "NAME", alias = "only-with-key")]
+    pub has_key: Vec<String>,
+
+    /// Only emit files whose metadata lacks the key NAME, such as `modelspec.license`. Can be
+    /// repeated
+    #[arg(long, global = true, value_name = "NAME", alias = "only-without-key")]
+    pub missing_key: Vec<String>,
+
+    /// Only emit files whose metadata satisfies EXPR, such as
+    /// `ss_network_dim >= 64 and has(modelspec.title) and not (producer == "kohya")`, made of
+    /// comparisons, regex matches (`KEY =~ "REGEX"`) and `has(KEY)` combined with and, or, not
+    /// and parentheses
+    #[arg(
+        long,
+        global = true,
+        value_name = "EXPR",
+        value_parser = crate::expression::parse_expression,
+        alias = "filter-expression"
+    )]
+    pub filter: Option<crate::expression::Expression>,
+
+    /// Exit like grep: 0 if any file was emitted, 1 if none was, 2 on errors
+    #[arg(long, global = true)]
+    pub emit_on_match_only: bool,
+
+    /// Stop after N files; the directory walk or glob expansion ends as soon as N files were found
+    #[arg(long, global = true, value_name = "N")]
+    pub limit: Option<usize>,
+
+    /// Find at most N files in each directory of the walk, so a preview spans the whole tree
+    /// instead of the first directory. -v lists the files skipped in each directory
+    #[arg(long, global = true, value_name = "N")]
+    pub limit_per_directory: Option<NonZeroUsize>,
+
+    /// Print at most N warnings about files that failed with the same error, but for their path,
+    /// and how many more there were at the end
+    #[arg(long, global = true, value_name = "N")]
+    pub error_sample: Option<usize>,
+
+    /// Extract the metadata of files ending in .EXT by running COMMAND, which must print JSON.
+    /// `{path}` in COMMAND is replaced by the file's path (appended if absent). Can be repeated
+    #[arg(long, global = true, value_name = "EXT=COMMAND", value_parser = parse_extractor_arg)]
+    pub extractor: Vec<Extractor>,
+
+    /// When stderr isn't a terminal, such as in CI logs, print a progress line every SECONDS
+    /// (or a duration such as `1m`), 30 if only `--progress-interval` is given
+    #[arg(
+        long,
+        global = true,
+        value_name = "SECONDS",
+        value_parser = crate::progress::parse_interval,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "30"
+    )]
+    pub progress_interval: Option<Duration>,
+
+    /// When stderr is a terminal, keep a progress bar of the `indicatif` TEMPLATE on its last
+    /// line, such as `{bar:30.cyan/blue} {pos}/{len} {msg}`, where pos counts the files done, msg
+    /// is the file started last and bytes the size of the files started. An invalid TEMPLATE
+    /// falls back to the default with a warning
+    #[arg(long, global = true, value_name = "TEMPLATE")]
+    pub progress_template: Option<String>,
+
+    /// Give up on a file after this long, such as `30s` or `1m`
+    #[arg(long, global = true, value_name = "DURATION", value_parser = humantime::parse_duration)]
+    pub timeout: Option<Duration>,
+
+    /// Stop starting files once the whole scan has run this long, such as `10m`, finish those
+    /// in progress and exit with status 124, to keep a scheduled job within its time
+    #[arg(long, global = true, value_name = "DURATION", value_parser = humantime::parse_duration)]
+    pub max_runtime: Option<Duration>,
+
+    /// Print to stderr how many read calls reading each header took and how many bytes they
+    /// returned, next to the file's size, to confirm only the header is read
+    #[arg(long, global = true)]
+    pub trace_reads: bool,
+
+    /// Give up on a file whose open and stat take longer than this, such as `5s`, and count it
+    /// as unreadable. Meant to be shorter than --timeout, to fail fast on dead mounts
+    #[arg(long, global = true, value_name = "DURATION", value_parser = humantime::parse_duration)]
+    pub open_read_timeout: Option<Duration>,
+
+    /// Read the data of the F16, BF16, F32 and F64 tensors of up to --nan-check-max-bytes and
+    /// list those with NaN or infinite values as `non_finite`, to catch a broken training run
+    #[arg(long, global = true)]
+    pub detect_nan_inf: bool,
+
+    /// The largest tensor --detect-nan-inf reads, in bytes
+    #[arg(
+        long,
+        global = true,
+        value_name = "BYTES",
+        default_value_t = crate::nan_inf::DEFAULT_MAX_BYTES,
+        requires = "detect_nan_inf"
+    )]
+    pub nan_check_max_bytes: u64,
+
+    /// When a sidecar, signature or thumbnail can't be written, such as in a read-only
+    /// directory, warn and still print the file's record. Without it the scan stops starting
+    /// files and fails
+    #[arg(long, global = true)]
+    pub continue_on_write_error: bool,
+
+    /// Only check that each file opens and starts with a plausible safetensors header, printing
+    /// OK or NOT-OK for it, without extracting the metadata
+    #[arg(long, conflicts_with = "print_header_raw")]
+    pub probe_only: bool,
+
+    /// Instead of reading any file, print how many files of each extension the inputs hold
+    /// and their total size, most common first, to see what a directory holds before a scan
+    #[arg(long, conflicts_with_all = ["probe_only", "extract_key_binary", "peek_tensor"])]
+    pub count_by_extension: bool,
+
+    /// Print the JSON Schema of the output records and exit
+    #[arg(long)]
+    pub print_schema: bool,
+
+    /// Print each file's header JSON exactly as stored, one header per line, instead of
+    /// extracting its metadata
+    #[arg(long)]
+    pub print_header_raw: bool,
+}
+
+impl Args {
+    /// The first file, directory or glob pattern to read, whether given to a subcommand or not.
+    #[must_use]
+    pub fn input(&self) -> &str {
+        self.inputs().first().copied().unwrap_or_default()
+    }
+
+    /// The files, directories and glob patterns to read, whether given to a subcommand or not.
+    #[must_use]
+    pub fn inputs(&self) -> Vec<&str> {
+        match &self.command {
+            Some(Command::Matrix(matrix)) => vec![&matrix.path],
+            Some(Command::KeyReport(report)) => vec![&report.path],
+            Some(Command::Inspect(inspect)) => vec![&inspect.path],
+            Some(Command::VerifySignature(verify)) => vec![&verify.path],
+            Some(Command::Repair(repair)) => vec![&repair.path],
+            Some(Command::Set(set)) => vec![&set.path],
+            Some(Command::Strip(strip)) => vec![&strip.path],
+            Some(Command::MergeFiles(merge)) => merge.paths.iter().map(String::as_str).collect(),
+            None => self.paths.iter().map(String::as_str).collect(),
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Show which metadata keys are present in which files
+    Matrix(MatrixArgs),
+    /// Show the types of value each metadata key holds, and in how many files
+    KeyReport(KeyReportArgs),
+    /// Read the metadata of the files, then query it interactively
+    Inspect(InspectArgs),
+    /// Check the `<file>.metadata.sig` signatures written by --sign
+    VerifySignature(VerifySignatureArgs),
+    /// Rewrite headers with stray padding or misaligned tensor data, leaving the data intact
+    Repair(RepairArgs),
+    /// Merge the keys of a JSON template into the metadata of the files, leaving the tensor
+    /// data intact
+    Set(SetArgs),
+    /// Remove the large metadata values of the files, keeping the small keys and the tensor data
+    Strip(StripArgs),
+    /// Merge the metadata of several files into one record, telling which keys they agree on
+    MergeFiles(MergeFilesArgs),
+}
+
+#[derive(clap::Args, Debug)]
+pub struct VerifySignatureArgs {
+    /// A safetensors file, a directory to scan recursively, or a glob pattern
+    pub path: String,
+
+    /// The Ed25519 public key, as SPKI PEM or 64 hex digits
+    #[arg(long, value_name = "FILE")]
+    pub public_key: PathBuf,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct RepairArgs {
+    /// A safetensors file, a directory to scan recursively, or a glob pattern
+    pub path: String,
+
+    /// Only list the files that would be rewritten
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct SetArgs {
+    /// A safetensors file, a directory to scan recursively, or a glob pattern
+    pub path: String,
+
+    /// A JSON object of the metadata keys to set. In string values, {stem}, {name}, {ext}
+    /// and {dir} are replaced by the file name without its extension, the file name, the
+    /// extension and the name of its directory
+    #[arg(long, value_name = "FILE", alias = "rewrite-metadata-from")]
+    pub set_from: PathBuf,
+
+    /// Only list the files that would be rewritten
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct StripArgs {
+    /// A safetensors file, a directory to scan recursively, or a glob pattern
+    pub path: String,
+
+    /// Remove the metadata values longer than BYTES, such as a huge `ss_tag_frequency`
+    #[arg(long, value_name = "BYTES", alias = "strip-large-values")]
+    pub strip_values_over: u64,
+
+    /// Only list the files that would be rewritten
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct MergeFilesArgs {
+    /// The files to merge, in order: safetensors files, directories to scan recursively, or
+    /// glob patterns
+    #[arg(required = true)]
+    pub paths: Vec<String>,
+
+    /// Which value the merged metadata keeps of a key the files conflict on
+    #[arg(long, value_enum, default_value_t = Prefer::First)]
+    pub prefer: Prefer,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct KeyReportArgs {
+    /// A safetensors file, a directory to scan recursively, or a glob pattern
+    pub path: String,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct InspectArgs {
+    /// A safetensors file, a directory to scan recursively, or a glob pattern
+    pub path: String,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct MatrixArgs {
+    /// A safetensors file, a directory to scan recursively, or a glob pattern
+    pub path: String,
+
+    /// How to render the matrix
+    #[arg(long, value_enum, default_value_t = MatrixFormat::Table)]
+    pub format: MatrixFormat,
+}
+
+/// Where `--count-parameters-from` gets the parameter count.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ParameterSource {
+    /// The product of each tensor's shape, summed over the tensors in the header
+    Shapes,
+}
+
+/// How the files being read at once are chosen (`--concurrency-scope`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ConcurrencyScope {
+    /// In the order they were found, up to --max-open-files
+    Global,
+    /// Also at most --workers-per-directory from each directory
+    PerDirectory,
+}
+
+/// Which of the files of a `--dedupe-by` group to keep.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Keep {
+    /// The one modified last
+    Newest,
+    /// The biggest one
+    Largest,
+}
+
+/// The order of `--tensors`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TensorOrder {
+    /// By name
+    Name,
+    /// As their data is laid out in the file
+    Offset,
+}
+
+/// How `--histogram` counts the values of a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum HistogramMode {
+    /// Numeric ranges with --buckets if every value is a number, each value otherwise
+    Auto,
+    /// Each value
+    Categorical,
+    /// Numeric ranges, of --buckets or 10, with the values that aren't numbers counted apart
+    Numeric,
+}
+
+/// How a report such as `--by-architecture` is printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ReportFormat {
+    /// Aligned columns
+    Table,
+    /// A JSON array with an object for each row
+    Json,
+}
+
+/// The report `--report` writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum RunReport {
+    /// `JUnit` XML, with a test case for each file that fails with the file's error
+    Junit,
+}
+
+/// How `--compare-to` reports the differences.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DriftFormat {
+    /// One line per file that changed, then a count of each kind of change
+    Text,
+    /// A JSON object with `added`, `removed` and `changed` lists
+    Json,
+}
+
+/// Which value of a conflicting key `merge-files` keeps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Prefer {
+    /// That of the first file with the key
+    First,
+    /// That of the last file with the key
+    Last,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum MatrixFormat {
+    /// Aligned columns with ✓/✗ cells
+    Table,
+    /// Comma-separated values with 1/0 cells
+    Csv,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cli_definition() {
+        <Args as clap::CommandFactory>::command().debug_assert();
+    }
+}
@@ -0,0 +1,110 @@
+use crate::io::WriteError;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// What to do when the outputs of two files, such as the sidecars of `a.safetensors` and
+/// `a.gguf` or two `--per-file-out` paths, would be written to the same path
+/// (`--on-duplicate-output-path`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OnDuplicate {
+    /// Write over the earlier output, so the last file wins
+    #[default]
+    Overwrite,
+    /// Fail the file as an output that can't be written, which stops the scan unless
+    /// --continue-on-write-error is given
+    Error,
+    /// Write to the path with `-1`, `-2` and so on added to its name
+    Suffix,
+}
+
+/// The output paths written during the scan and the files they were written for, to catch
+/// two files writing the same one.
+#[derive(Debug)]
+pub struct OutputPaths {
+    policy: OnDuplicate,
+    /// Report each collision and what was done about it.
+    verbose: bool,
+    claimed: Mutex<HashMap<PathBuf, PathBuf>>,
+}
+
+impl OutputPaths {
+    pub fn new(policy: OnDuplicate, verbose: bool) -> Self {
+        Self { policy, verbose, claimed: Mutex::default() }
+    }
+
+    /// The path to write the output of `input` to, where it would go to `out`: `out` itself
+    /// unless another file's output already went there, in which case the policy decides.
+    /// With `error` that is a [`WriteError`].
+    pub fn claim(&self, out: PathBuf, input: &Path) -> anyhow::Result<PathBuf> {
+        if self.policy == OnDuplicate::Overwrite && !self.verbose {
+            return Ok(out);
+        }
+        let mut claimed = self.claimed.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        let Some(other) = claimed.get(&out).filter(|other| *other != input).cloned() else {
+            claimed.insert(out.clone(), input.to_path_buf());
+            return Ok(out);
+        };
+        let (shown, other) = (out.display(), other.display());
+        match self.policy {
+            OnDuplicate::Overwrite => {
+                eprintln!("{}: {shown} is also the output of {other}; overwriting it", input.display());
+                claimed.insert(out.clone(), input.to_path_buf());
+                Ok(out)
+            }
+            OnDuplicate::Error => {
+                let source = anyhow::anyhow!("It is also the output of {other}");
+                Err(anyhow::Error::new(WriteError::new(out.clone(), source)))
+            }
+            OnDuplicate::Suffix => {
+                let mut n = 1;
+                while claimed.contains_key(&suffixed(&out, n)) {
+                    n += 1;
+                }
+                let suffixed = suffixed(&out, n);
+                if self.verbose {
+                    eprintln!("{}: {shown} is also the output of {other}; writing {}", input.display(), suffixed.display());
+                }
+                claimed.insert(suffixed.clone(), input.to_path_buf());
+                Ok(suffixed)
+            }
+        }
+    }
+}
+
+/// `path` with `-n` added to its name, before the extension: `a.json` becomes `a-1.json`.
+fn suffixed(path: &Path, n: usize) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    match path.extension() {
+        Some(extension) => path.with_file_name(format!("{stem}-{n}.{}", extension.to_string_lossy())),
+        None => path.with_file_name(format!("{stem}-{n}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_claim() {
+        let (a, b, c) = (Path::new("m/a.safetensors"), Path::new("m/a.gguf"), Path::new("m/a.ckpt"));
+        let out = PathBuf::from("m/a.json");
+
+        let paths = OutputPaths::new(OnDuplicate::Overwrite, true);
+        assert_eq!(paths.claim(out.clone(), a).unwrap(), out);
+        assert_eq!(paths.claim(out.clone(), b).unwrap(), out);
+
+        let paths = OutputPaths::new(OnDuplicate::Error, false);
+        assert_eq!(paths.claim(out.clone(), a).unwrap(), out);
+        // The same file writing again isn't a collision
+        assert_eq!(paths.claim(out.clone(), a).unwrap(), out);
+        let err = paths.claim(out.clone(), b).unwrap_err();
+        assert!(err.is::<WriteError>());
+        assert_eq!(format!("{err:#}"), "Failed to write m/a.json: It is also the output of m/a.safetensors");
+
+        let paths = OutputPaths::new(OnDuplicate::Suffix, false);
+        assert_eq!(paths.claim(out.clone(), a).unwrap(), out);
+        assert_eq!(paths.claim(out.clone(), b).unwrap(), PathBuf::from("m/a-1.json"));
+        assert_eq!(paths.claim(out.clone(), c).unwrap(), PathBuf::from("m/a-2.json"));
+    }
+}
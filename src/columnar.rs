@@ -0,0 +1,267 @@
+use arrow_array::builder::{BooleanBuilder, Float64Builder, Int64Builder, StringBuilder};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// How many records make up a row group, which is as many as are held in memory at once.
+const ROW_GROUP_ROWS: usize = 8192;
+
+/// The type of a column, from the JSON values it holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Boolean,
+    Int64,
+    Float64,
+    /// Strings, and the compact JSON of arrays and objects.
+    Utf8,
+}
+
+impl Kind {
+    fn of(value: &Value) -> Option<Self> {
+        match value {
+            Value::Null => None,
+            Value::Bool(_) => Some(Self::Boolean),
+            Value::Number(number) if number.is_i64() => Some(Self::Int64),
+            Value::Number(_) => Some(Self::Float64),
+            Value::String(_) | Value::Array(_) | Value::Object(_) => Some(Self::Utf8),
+        }
+    }
+
+    fn data_type(self) -> DataType {
+        match self {
+            Self::Boolean => DataType::Boolean,
+            Self::Int64 => DataType::Int64,
+            Self::Float64 => DataType::Float64,
+            Self::Utf8 => DataType::Utf8,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Column {
+    name: String,
+    /// `None` while the column has only had nulls.
+    kind: Option<Kind>,
+    /// Whether it had values of types that don't widen into one another, such as numbers and
+    /// strings, which are then written as strings.
+    mixed: bool,
+}
+
+/// The columns of the records, in the order they were first seen, those of one record by name.
+#[derive(Debug, Default)]
+struct Columns {
+    columns: Vec<Column>,
+    index: HashMap<String, usize>,
+}
+
+impl Columns {
+    fn see(&mut self, name: &str, value: &Value) {
+        let i = *self.index.entry(name.to_string()).or_insert_with(|| {
+            self.columns.push(Column { name: name.to_string(), kind: None, mixed: false });
+            self.columns.len() - 1
+        });
+        let column = &mut self.columns[i];
+        column.kind = match (column.kind, Kind::of(value)) {
+            (kind, None) | (None, kind) => kind,
+            (Some(Kind::Int64), Some(Kind::Float64)) | (Some(Kind::Float64), Some(Kind::Int64)) => Some(Kind::Float64),
+            (Some(kind), Some(other)) if kind == other => Some(kind),
+            (Some(_), Some(_)) => {
+                column.mixed = true;
+                Some(Kind::Utf8)
+            }
+        };
+    }
+
+    /// The schema of the columns, a column with only nulls being a string column. Without
+    /// any records there are still the `path` and `metadata` columns every record has.
+    fn schema(&self) -> Schema {
+        if self.columns.is_empty() {
+            return Schema::new(["path", "metadata"].map(|name| Field::new(name, DataType::Utf8, true)).to_vec());
+        }
+        let fields: Vec<Field> = self
+            .columns
+            .iter()
+            .map(|column| Field::new(&column.name, column.kind.unwrap_or(Kind::Utf8).data_type(), true))
+            .collect();
+        Schema::new(fields)
+    }
+}
+
+/// The `rows` as a batch of `schema`, each value converted to its column's type, which
+/// every value was seen to fit.
+fn batch(schema: &Arc<Schema>, rows: &[Map<String, Value>]) -> anyhow::Result<RecordBatch> {
+    let arrays: Vec<ArrayRef> = schema
+        .fields()
+        .iter()
+        .map(|field| {
+            let values = rows.iter().map(|row| row.get(field.name()).filter(|value| !value.is_null()));
+            let array: ArrayRef = match field.data_type() {
+                DataType::Boolean => {
+                    let mut builder = BooleanBuilder::with_capacity(rows.len());
+                    values.for_each(|value| builder.append_option(value.and_then(Value::as_bool)));
+                    Arc::new(builder.finish())
+                }
+                DataType::Int64 => {
+                    let mut builder = Int64Builder::with_capacity(rows.len());
+                    values.for_each(|value| builder.append_option(value.and_then(Value::as_i64)));
+                    Arc::new(builder.finish())
+                }
+                DataType::Float64 => {
+                    let mut builder = Float64Builder::with_capacity(rows.len());
+                    values.for_each(|value| builder.append_option(value.and_then(Value::as_f64)));
+                    Arc::new(builder.finish())
+                }
+                _ => {
+                    let mut builder = StringBuilder::new();
+                    values.for_each(|value| match value {
+                        Some(Value::String(text)) => builder.append_value(text),
+                        Some(value) => builder.append_value(value.to_string()),
+                        None => builder.append_null(),
+                    });
+                    Arc::new(builder.finish())
+                }
+            };
+            array
+        })
+        .collect();
+    Ok(RecordBatch::try_new(Arc::clone(schema), arrays)?)
+}
+
+/// A `--format parquet` output: the records are spooled to a file of JSON lines next to it,
+/// which is turned into the Parquet file when the output is finished, as the columns are
+/// only known once every record is.
+#[derive(Debug)]
+pub struct Parquet {
+    pub spool: PathBuf,
+    pub path: PathBuf,
+    /// `--explode-keys`: a column for each metadata key rather than one of JSON.
+    pub explode_keys: bool,
+}
+
+impl Parquet {
+    /// Write the Parquet file from the spool in two passes over it, one to find the columns
+    /// and their types and one to write the row groups, and remove the spool. A column whose
+    /// values have types that don't widen into one another is written as strings, with a
+    /// warning. The file is replaced atomically, so a failure leaves the one before.
+    pub fn write(&self) -> anyhow::Result<()> {
+        let result = self.convert();
+        let _ = std::fs::remove_file(&self.spool);
+        result
+    }
+
+    fn convert(&self) -> anyhow::Result<()> {
+        let mut columns = Columns::default();
+        for row in self.rows()? {
+            for (name, value) in &row? {
+                columns.see(name, value);
+            }
+        }
+        for column in columns.columns.iter().filter(|column| column.mixed) {
+            eprintln!(
+                "Warning: {}: The column {} has values of different types, so they are written as strings",
+                self.path.display(),
+                column.name
+            );
+        }
+        let schema = Arc::new(columns.schema());
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .set_max_row_group_row_count(Some(ROW_GROUP_ROWS))
+            .build();
+        crate::io::atomic_write_with(&self.path, |file| {
+            let mut writer = ArrowWriter::try_new(file, Arc::clone(&schema), Some(properties))?;
+            let mut rows = Vec::with_capacity(ROW_GROUP_ROWS);
+            for row in self.rows()? {
+                rows.push(row?);
+                if rows.len() == ROW_GROUP_ROWS {
+                    writer.write(&batch(&schema, &rows)?)?;
+                    rows.clear();
+                }
+            }
+            if !rows.is_empty() {
+                writer.write(&batch(&schema, &rows)?)?;
+            }
+            writer.close()?;
+            Ok(())
+        })
+    }
+
+    /// The records of the spool as the fields of their rows.
+    fn rows(&self) -> anyhow::Result<impl Iterator<Item = anyhow::Result<Map<String, Value>>>> {
+        let explode_keys = self.explode_keys;
+        let spool = BufReader::new(File::open(&self.spool)?);
+        Ok(spool.lines().map(move |line| Ok(fields(serde_json::from_str(&line?)?, explode_keys))))
+    }
+}
+
+/// The fields of a record: its own, with the metadata as one field or, with `explode_keys`, a
+/// `metadata.KEY` field for each of its keys.
+fn fields(record: Value, explode_keys: bool) -> Map<String, Value> {
+    let Value::Object(record) = record else { return Map::new() };
+    let mut fields = Map::new();
+    for (name, value) in record {
+        match value {
+            Value::Object(metadata) if explode_keys && name == "metadata" => {
+                fields.extend(metadata.into_iter().map(|(key, value)| (format!("metadata.{key}"), value)));
+            }
+            value => {
+                fields.insert(name, value);
+            }
+        }
+    }
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::Array;
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{Float64Type, Int64Type};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use serde_json::json;
+
+    #[test]
+    fn test_write() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let records = [
+            json!({"path": "a", "size": 1, "metadata": {"ss_epoch": 1, "ss_lr": 0.5, "title": "a"}}),
+            json!({"path": "b", "size": 2, "metadata": {"ss_epoch": "2", "ss_lr": 1, "tags": ["x"]}}),
+        ];
+        let read = |explode_keys| -> anyhow::Result<RecordBatch> {
+            let spool = temp_dir.path().join("spool.ndjson");
+            std::fs::write(&spool, records.iter().map(|record| record.to_string() + "\n").collect::<String>())?;
+            let parquet = Parquet { spool: spool.clone(), path: temp_dir.path().join("out.parquet"), explode_keys };
+            parquet.write()?;
+            assert!(!spool.exists());
+            let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(&parquet.path)?)?.build()?;
+            Ok(reader.collect::<Result<Vec<_>, _>>()?.remove(0))
+        };
+
+        let batch = read(false)?;
+        let names: Vec<&str> = batch.schema_ref().fields().iter().map(|field| field.name().as_str()).collect();
+        assert_eq!(names, ["metadata", "path", "size"]);
+        assert_eq!(batch.column(2).as_primitive::<Int64Type>().values(), &[1, 2]);
+        let metadata = batch.column(0).as_string::<i32>();
+        assert_eq!(serde_json::from_str::<Value>(metadata.value(0))?, records[0]["metadata"]);
+
+        // Mixed numbers and strings become strings, integers and floats floats
+        let batch = read(true)?;
+        let column = |name| batch.column_by_name(name).unwrap();
+        let epochs = column("metadata.ss_epoch").as_string::<i32>();
+        assert_eq!((epochs.value(0), epochs.value(1)), ("1", "2"));
+        assert_eq!(column("metadata.ss_lr").as_primitive::<Float64Type>().values(), &[0.5, 1.0]);
+        let titles = column("metadata.title").as_string::<i32>();
+        assert!(titles.is_null(1));
+        assert_eq!(column("metadata.tags").as_string::<i32>().value(1), r#"["x"]"#);
+        Ok(())
+    }
+}
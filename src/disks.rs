@@ -0,0 +1,69 @@
+use crate::groups::GroupLimits;
+use std::path::{Component, Path, PathBuf};
+use tokio::sync::OwnedSemaphorePermit;
+
+/// Identifies the disk a file is on: the device ID on Unix, which is the same for every file
+/// under one mount point, and the path's root (such as a drive letter) elsewhere.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Disk {
+    #[cfg_attr(not(unix), allow(dead_code))]
+    Device(u64),
+    Root(PathBuf),
+}
+
+fn disk_of(path: &Path) -> Disk {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        if let Ok(metadata) = std::fs::metadata(path) {
+            return Disk::Device(metadata.dev());
+        }
+    }
+    let root = path.components().take_while(|c| matches!(c, Component::Prefix(_) | Component::RootDir));
+    Disk::Root(root.collect())
+}
+
+/// Limits how many files on the same disk are read at once (`--workers-per-disk`), so that
+/// an HDD isn't thrashed by many concurrent reads while files on other disks proceed.
+#[derive(Debug)]
+pub struct DiskLimits {
+    disks: GroupLimits<Disk>,
+}
+
+impl DiskLimits {
+    pub fn new(per_disk: usize) -> Self {
+        Self { disks: GroupLimits::new(per_disk) }
+    }
+
+    /// Wait for a slot on the disk holding `path`.
+    pub async fn acquire(&self, path: &Path) -> anyhow::Result<OwnedSemaphorePermit> {
+        self.disks.acquire(disk_of(path)).await
+    }
+
+    /// A slot on the disk holding `path`, if one is free.
+    pub fn try_acquire(&self, path: &Path) -> Option<OwnedSemaphorePermit> {
+        self.disks.try_acquire(disk_of(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_disk_limits() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let a = temp_dir.path().join("a");
+        let b = temp_dir.path().join("b");
+        std::fs::write(&a, "")?;
+        std::fs::write(&b, "")?;
+
+        let limits = DiskLimits::new(1);
+        let permit = limits.acquire(&a).await?;
+        // Same disk, so the only slot is taken
+        assert!(limits.disks.semaphore(disk_of(&b)).try_acquire_owned().is_err());
+        drop(permit);
+        assert!(limits.disks.semaphore(disk_of(&b)).try_acquire_owned().is_ok());
+        Ok(())
+    }
+}
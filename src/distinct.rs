@@ -0,0 +1,79 @@
+use crate::table;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Shown for the files that don't have the key.
+const NOT_SET: &str = "(not set)";
+
+/// Tallies the values that metadata keys take across the scanned files, for `--distinct`.
+#[derive(Debug)]
+pub struct Distinct {
+    keys: Vec<String>,
+    /// For each key, how many files have each value.
+    counts: Mutex<Vec<HashMap<String, usize>>>,
+}
+
+impl Distinct {
+    pub fn new(keys: Vec<String>) -> Self {
+        let counts = Mutex::new(vec![HashMap::new(); keys.len()]);
+        Self { keys, counts }
+    }
+
+    /// Count the values of one file's metadata. Strings are counted as they are, other values
+    /// as compact JSON.
+    pub fn add(&self, metadata: &Value) {
+        let mut counts = self.counts.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        for (key, counts) in self.keys.iter().zip(counts.iter_mut()) {
+            let value = match metadata.get(key) {
+                None => NOT_SET.to_string(),
+                Some(Value::String(s)) => s.clone(),
+                Some(value) => value.to_string(),
+            };
+            *counts.entry(value).or_default() += 1;
+        }
+    }
+
+    /// A table per key of its values and the number of files with each, most frequent first.
+    pub fn report(&self) -> String {
+        let counts = self.counts.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        let sections: Vec<String> = self
+            .keys
+            .iter()
+            .zip(counts.iter())
+            .map(|(key, counts)| {
+                let mut values: Vec<(&String, &usize)> = counts.iter().collect();
+                values.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+                let rows: Vec<Vec<String>> =
+                    values.into_iter().map(|(value, count)| vec![count.to_string(), value.clone()]).collect();
+                table::render(&["files".to_string(), key.clone()], &rows)
+            })
+            .collect();
+        sections.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_distinct_report() {
+        let distinct = Distinct::new(vec!["modelspec.architecture".to_string(), "ss_network_dim".to_string()]);
+        distinct.add(&json!({"modelspec.architecture": "sdxl/lora", "ss_network_dim": 16}));
+        distinct.add(&json!({"modelspec.architecture": "flux/lora", "ss_network_dim": 16}));
+        distinct.add(&json!({"modelspec.architecture": "sdxl/lora"}));
+
+        assert_eq!(
+            distinct.report(),
+            "files  modelspec.architecture\n\
+             2      sdxl/lora\n\
+             1      flux/lora\n\
+             \n\
+             files  ss_network_dim\n\
+             2      16\n\
+             1      (not set)\n"
+        );
+    }
+}
@@ -0,0 +1,340 @@
+use crate::record::Record;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// What is compared of each file.
+#[derive(Debug, Clone, PartialEq)]
+struct Snapshot {
+    metadata: Value,
+    sha256: Option<String>,
+}
+
+/// How `--compare-to` pairs the files that aren't at the same path in both scans
+/// (`--match-by`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MatchBy {
+    /// By their SHA-256, which both scans must have computed.
+    Hash,
+    /// By the value of a metadata key, such as `modelspec.hash`.
+    Key(String),
+}
+
+impl std::fmt::Display for MatchBy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Hash => f.write_str("sha256"),
+            Self::Key(key) => f.write_str(key),
+        }
+    }
+}
+
+/// Parse `--match-by`: `hash` or `key:NAME`.
+pub fn parse_match_by(arg: &str) -> Result<MatchBy, String> {
+    match arg.split_once(':') {
+        None if arg == "hash" => Ok(MatchBy::Hash),
+        Some(("key", key)) if !key.is_empty() => Ok(MatchBy::Key(key.to_string())),
+        _ => Err(format!("expected `hash` or `key:NAME`, got `{arg}`")),
+    }
+}
+
+impl Snapshot {
+    /// What identifies the file under `match_by`, if it has it.
+    fn identity(&self, match_by: &MatchBy) -> Option<String> {
+        match match_by {
+            MatchBy::Hash => self.sha256.clone(),
+            MatchBy::Key(key) => self.metadata.get(key).map(Value::to_string),
+        }
+    }
+
+    fn of(record: &Value) -> Option<(PathBuf, Self)> {
+        let path = record.get("path")?.as_str()?;
+        let metadata = record.get("metadata").cloned().unwrap_or(Value::Null);
+        let sha256 = record.get("sha256").and_then(Value::as_str).map(str::to_string);
+        Some((PathBuf::from(path), Self { metadata, sha256 }))
+    }
+}
+
+/// Compares the records of a scan with those of an earlier one (`--compare-to`).
+#[derive(Debug)]
+pub struct Drift {
+    baseline: BTreeMap<PathBuf, Snapshot>,
+    current: Mutex<BTreeMap<PathBuf, Snapshot>>,
+    /// How far apart two numbers may be and still count as the same (`--numeric-epsilon`).
+    epsilon: Option<f64>,
+    match_by: Option<MatchBy>,
+}
+
+impl Drift {
+    /// Compare against `records`, as read from a saved output file or manifest, taking values
+    /// that are numbers within `epsilon` of each other as unchanged.
+    pub fn new(records: &[Value], epsilon: Option<f64>) -> Self {
+        let baseline = records.iter().filter_map(Snapshot::of).collect();
+        Self { baseline, current: Mutex::default(), epsilon, match_by: None }
+    }
+
+    /// Pair the files that were added and removed by `match_by`, as moves (`--match-by`).
+    pub fn match_by(mut self, match_by: Option<MatchBy>) -> Self {
+        self.match_by = match_by;
+        self
+    }
+
+    pub fn add(&self, record: &Record) {
+        let snapshot = Snapshot { metadata: record.metadata.clone(), sha256: record.sha256.clone() };
+        let mut current = self.current.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        current.insert(record.path.clone(), snapshot);
+    }
+
+    /// The files added, removed and changed since the baseline, each sorted by path. With
+    /// `--match-by`, an added and a removed file with the same identity are one file that moved.
+    pub fn diff(&self) -> Diff {
+        let current = self.current.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        let mut diff = Diff { numeric_epsilon: self.epsilon, matched_by: self.match_by.clone(), ..Diff::default() };
+        let mut removed: Vec<&PathBuf> = self.baseline.keys().filter(|path| !current.contains_key(*path)).collect();
+        for (path, now) in current.iter() {
+            if let Some(then) = self.baseline.get(path) {
+                diff.changed.extend(self.change(path, then, now));
+            } else if let Some(from) = self.moved_from(now, &mut removed) {
+                match self.change(path, &self.baseline[from], now) {
+                    Some(change) => diff.changed.push(Change { moved_from: Some(from.clone()), ..change }),
+                    None => diff.moved.push(Move { from: from.clone(), to: path.clone() }),
+                }
+            } else {
+                diff.added.push(path.clone());
+            }
+        }
+        diff.removed = removed.into_iter().cloned().collect();
+        diff
+    }
+
+    /// The change of the file at `path` since the baseline, if there is one.
+    fn change(&self, path: &Path, then: &Snapshot, now: &Snapshot) -> Option<Change> {
+        let keys = changed_keys(&then.metadata, &now.metadata, self.epsilon);
+        // A hash can only be compared if both scans computed one
+        let sha256 = matches!((&then.sha256, &now.sha256), (Some(then), Some(now)) if then != now);
+        (!keys.is_empty() || sha256).then(|| Change { path: path.to_path_buf(), moved_from: None, keys, sha256 })
+    }
+
+    /// Take the first of the `removed` files with the identity of `now` out of them.
+    fn moved_from<'a>(&self, now: &Snapshot, removed: &mut Vec<&'a PathBuf>) -> Option<&'a PathBuf> {
+        let identity = now.identity(self.match_by.as_ref()?)?;
+        let match_by = self.match_by.as_ref()?;
+        let i = removed.iter().position(|path| self.baseline[*path].identity(match_by).as_ref() == Some(&identity))?;
+        Some(removed.remove(i))
+    }
+}
+
+/// The top-level metadata keys whose values differ, including keys only one side has.
+fn changed_keys(then: &Value, now: &Value, epsilon: Option<f64>) -> Vec<String> {
+    let (Value::Object(then), Value::Object(now)) = (then, now) else {
+        return if then == now { Vec::new() } else { vec![String::new()] };
+    };
+    let same = |key: &String| match (then.get(key), now.get(key)) {
+        (Some(then), Some(now)) => same_value(then, now, epsilon),
+        (then, now) => then == now,
+    };
+    let mut keys: Vec<String> = then.keys().chain(now.keys()).filter(|key| !same(key)).cloned().collect();
+    keys.sort();
+    keys.dedup();
+    keys
+}
+
+/// Parse `--numeric-epsilon`: a number that isn't negative, such as `1e-9`.
+pub fn parse_epsilon(arg: &str) -> Result<f64, String> {
+    match arg.parse::<f64>() {
+        Ok(epsilon) if epsilon.is_finite() && epsilon >= 0.0 => Ok(epsilon),
+        _ => Err(format!("expected a number of at least 0, got `{arg}`")),
+    }
+}
+
+/// Whether two metadata values are the same. With an `epsilon`, two values that are both
+/// numbers, or strings such as `1e-4` that parse as one, only need to be within it of each
+/// other, so a learning rate written out with another precision isn't a change.
+fn same_value(then: &Value, now: &Value, epsilon: Option<f64>) -> bool {
+    let number = |value: &Value| match value {
+        Value::Number(number) => number.as_f64(),
+        Value::String(text) => text.trim().parse::<f64>().ok().filter(|number| number.is_finite()),
+        _ => None,
+    };
+    match (epsilon, number(then), number(now)) {
+        (Some(epsilon), Some(then), Some(now)) => (then - now).abs() <= epsilon,
+        _ => then == now,
+    }
+}
+
+/// The differences between a scan and its baseline.
+#[derive(Debug, Default, Serialize)]
+pub struct Diff {
+    /// The `--numeric-epsilon` the values were compared with.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub numeric_epsilon: Option<f64>,
+    /// What `--match-by` paired the files that moved by.
+    #[serde(skip_serializing_if = "Option::is_none", serialize_with = "serialize_display")]
+    pub matched_by: Option<MatchBy>,
+    pub added: Vec<PathBuf>,
+    pub removed: Vec<PathBuf>,
+    pub changed: Vec<Change>,
+    /// The files that only moved, with `--match-by`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub moved: Vec<Move>,
+}
+
+#[allow(clippy::ref_option)] // The signature serde calls
+fn serialize_display<S: serde::Serializer>(value: &Option<MatchBy>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(value.as_ref().expect("skipped when None"))
+}
+
+/// A file in both scans whose metadata or hash changed.
+#[derive(Debug, Serialize)]
+pub struct Change {
+    pub path: PathBuf,
+    /// Where the file was in the baseline, if it moved (`--match-by`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub moved_from: Option<PathBuf>,
+    /// The metadata keys that were added, removed or given a different value.
+    pub keys: Vec<String>,
+    pub sha256: bool,
+}
+
+/// A file that is the same as in the baseline but at another path.
+#[derive(Debug, Serialize)]
+pub struct Move {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+impl Diff {
+    /// Whether nothing changed. Files that only moved don't count.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// One line per file, such as `changed  a.safetensors: ss_epoch, sha256`, then a count of each,
+    /// after lines with the `--numeric-epsilon` and `--match-by` if they are given.
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        if let Some(epsilon) = self.numeric_epsilon {
+            let _ = writeln!(text, "Numbers within {epsilon} of each other are unchanged");
+        }
+        if let Some(match_by) = &self.matched_by {
+            let _ = writeln!(text, "Files at another path are matched by {match_by}");
+        }
+        let mut line = |label: &str, path: &Path, detail: &str| {
+            let _ = writeln!(text, "{label:<8} {}{detail}", path.display());
+        };
+        for path in &self.added {
+            line("added", path, "");
+        }
+        for path in &self.removed {
+            line("removed", path, "");
+        }
+        for change in &self.changed {
+            let mut what: Vec<&str> = change.keys.iter().map(String::as_str).collect();
+            if change.sha256 {
+                what.push("sha256");
+            }
+            let from = change.moved_from.as_ref().map(|from| format!(" (moved from {})", from.display()));
+            let from = from.unwrap_or_default();
+            line("changed", &change.path, &format!("{from}: {}", what.join(", ")));
+        }
+        for moved in &self.moved {
+            line("moved", &moved.from, &format!(" -> {}", moved.to.display()));
+        }
+        let (added, removed, changed) = (self.added.len(), self.removed.len(), self.changed.len());
+        let _ = write!(text, "{added} added, {removed} removed, {changed} changed");
+        if self.matched_by.is_some() {
+            let _ = write!(text, ", {} moved", self.moved.len());
+        }
+        text.push('\n');
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_diff() {
+        let baseline = [
+            json!({"path": "a.safetensors", "metadata": {"ss_epoch": "1", "title": "a"}, "sha256": "00"}),
+            json!({"path": "b.safetensors", "metadata": {}, "sha256": "00"}),
+            json!({"path": "gone.safetensors", "metadata": {}}),
+        ];
+        let drift = Drift::new(&baseline, None);
+        let record = |path: &str, metadata: Value, sha256: Option<&str>| Record {
+            path: PathBuf::from(path),
+            metadata,
+            sha256: sha256.map(str::to_string),
+            ..Record::default()
+        };
+        drift.add(&record("a.safetensors", json!({"ss_epoch": "2", "title": "a", "new": 1}), None));
+        drift.add(&record("b.safetensors", json!({}), Some("11")));
+        drift.add(&record("c.safetensors", json!({}), None));
+
+        let diff = drift.diff();
+        assert!(!diff.is_empty());
+        assert_eq!(
+            diff.text(),
+            "added    c.safetensors\n\
+             removed  gone.safetensors\n\
+             changed  a.safetensors: new, ss_epoch\n\
+             changed  b.safetensors: sha256\n\
+             1 added, 1 removed, 2 changed\n"
+        );
+
+        let unchanged = Drift::new(&baseline[1..2], None);
+        unchanged.add(&record("b.safetensors", json!({}), None));
+        assert!(unchanged.diff().is_empty());
+    }
+
+    #[test]
+    fn test_match_by() {
+        assert_eq!(parse_match_by("hash"), Ok(MatchBy::Hash));
+        assert_eq!(parse_match_by("key:modelspec.hash"), Ok(MatchBy::Key("modelspec.hash".to_string())));
+        assert!(parse_match_by("key:").is_err());
+        assert!(parse_match_by("path").is_err());
+
+        let baseline = [
+            json!({"path": "old/a", "metadata": {"id": "1", "title": "a"}}),
+            json!({"path": "old/b", "metadata": {"id": "2", "title": "b"}}),
+            json!({"path": "gone", "metadata": {"id": "3"}}),
+        ];
+        let drift = Drift::new(&baseline, None).match_by(Some(MatchBy::Key("id".to_string())));
+        let add = |path: &str, metadata| drift.add(&Record { path: PathBuf::from(path), metadata, ..Record::default() });
+        add("new/a", json!({"id": "1", "title": "a"}));
+        add("new/b", json!({"id": "2", "title": "b2"}));
+        add("c", json!({"id": "4"}));
+        let diff = drift.diff();
+        assert_eq!(
+            diff.text(),
+            "Files at another path are matched by id\n\
+             added    c\n\
+             removed  gone\n\
+             changed  new/b (moved from old/b): title\n\
+             moved    old/a -> new/a\n\
+             1 added, 1 removed, 1 changed, 1 moved\n"
+        );
+    }
+
+    #[test]
+    fn test_numeric_epsilon() {
+        let baseline = [json!({"path": "a", "metadata": {"ss_learning_rate": "0.0001", "ss_seed": 42, "title": "1"}})];
+        let drift = Drift::new(&baseline, Some(1e-9));
+        let metadata = json!({"ss_learning_rate": "1e-4", "ss_seed": 42.000_000_000_1, "title": "1.0"});
+        drift.add(&Record { path: PathBuf::from("a"), metadata, ..Record::default() });
+        let diff = drift.diff();
+        assert!(diff.is_empty());
+        let header = "Numbers within 0.000000001 of each other are unchanged\n";
+        assert_eq!(diff.text(), format!("{header}0 added, 0 removed, 0 changed\n"));
+
+        let drift = Drift::new(&baseline, Some(1e-9));
+        let metadata = json!({"ss_learning_rate": "2e-4", "ss_seed": 42, "title": "one"});
+        drift.add(&Record { path: PathBuf::from("a"), metadata, ..Record::default() });
+        assert_eq!(drift.diff().changed[0].keys, ["ss_learning_rate", "title"]);
+    }
+}
@@ -0,0 +1,160 @@
+use crate::cli::Keep;
+use crate::humanize;
+use crate::record::Record;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// Counts the files with the same contents, by their SHA-256, for
+/// `--count-distinct-files-by-hash`.
+#[derive(Debug, Default)]
+pub struct Duplicates {
+    /// The size and number of files of each digest.
+    digests: Mutex<HashMap<String, (u64, usize)>>,
+}
+
+impl Duplicates {
+    /// Count a record hashed by `--hash`. Records without a digest, such as archive members,
+    /// are counted as distinct.
+    pub fn add(&self, record: &Record) {
+        let key = record.sha256.clone().unwrap_or_else(|| format!("path:{}", record.path.display()));
+        let mut digests = self.digests.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        let entry = digests.entry(key).or_insert((record.size.unwrap_or(0), 0));
+        entry.1 += 1;
+    }
+
+    /// How many files and distinct contents there are, their bytes, and how many bytes removing
+    /// all but one copy of each would free.
+    pub fn report(&self) -> String {
+        let digests = self.digests.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        let files = digests.values().map(|(_, files)| files).sum::<usize>();
+        let total = digests.values().map(|(size, files)| size * *files as u64).sum::<u64>();
+        let unique = digests.values().map(|(size, _)| size).sum::<u64>();
+        let duplicated = digests.values().filter(|(_, files)| *files > 1).count();
+        format!(
+            "{} distinct of {files} files ({duplicated} with copies)\n{} in total, {} distinct, {} reclaimable\n",
+            digests.len(),
+            humanize::bytes(total),
+            humanize::bytes(unique),
+            humanize::bytes(total - unique),
+        )
+    }
+}
+
+/// A file of a [`ByMetadata`] group.
+#[derive(Debug)]
+struct Copy {
+    path: PathBuf,
+    size: u64,
+    modified: Option<u64>,
+}
+
+/// Groups the files whose metadata has the same values for a set of keys, such as
+/// `modelspec.hash`, for `--dedupe-by`. This finds copies of a model that aren't byte for
+/// byte the same, such as re-exports with another tensor layout.
+#[derive(Debug)]
+pub struct ByMetadata {
+    keys: Vec<String>,
+    keep: Option<Keep>,
+    /// The files of each group, by their values of the keys as JSON.
+    groups: Mutex<BTreeMap<Vec<String>, Vec<Copy>>>,
+}
+
+impl ByMetadata {
+    pub fn new(keys: Vec<String>, keep: Option<Keep>) -> Self {
+        Self { keys, keep, groups: Mutex::default() }
+    }
+
+    /// Add a record with its size and modification time. A file without one of the keys
+    /// isn't the copy of anything.
+    pub fn add(&self, record: &Record) {
+        let Some(values) = self.keys.iter().map(|key| record.metadata.get(key).map(Value::to_string)).collect() else {
+            return;
+        };
+        let copy = Copy { path: record.path.clone(), size: record.size.unwrap_or(0), modified: record.modified_epoch };
+        self.groups.lock().unwrap_or_else(std::sync::PoisonError::into_inner).entry(values).or_default().push(copy);
+    }
+
+    /// Each group of more than one file, headed by the values that define it, with the file
+    /// that `--keep` selects marked, and a total line.
+    pub fn report(&self) -> String {
+        let mut groups = self.groups.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        let mut report = String::new();
+        let (mut duplicated, mut redundant) = (0, 0);
+        for (values, copies) in groups.iter_mut().filter(|(_, copies)| copies.len() > 1) {
+            copies.sort_by(|a, b| a.path.cmp(&b.path));
+            // The first of equals, so the choice is the same every run
+            let first_max = |rank: fn(&Copy) -> u64| copies.iter().enumerate().rev().max_by_key(|(_, copy)| rank(copy)).map(|(i, _)| i);
+            let kept = match self.keep {
+                Some(Keep::Newest) => first_max(|copy| copy.modified.unwrap_or(0)),
+                Some(Keep::Largest) => first_max(|copy| copy.size),
+                None => None,
+            };
+            let defined: Vec<String> = self.keys.iter().zip(values).map(|(key, value)| format!("{key}={value}")).collect();
+            let _ = writeln!(report, "{} ({} files)", defined.join(", "), copies.len());
+            for (i, copy) in copies.iter().enumerate() {
+                let label = match kept {
+                    Some(kept) if kept == i => "keep  ",
+                    Some(_) => "remove",
+                    None => "",
+                };
+                let _ = writeln!(report, "  {label}{}{}", if label.is_empty() { "" } else { " " }, copy.path.display());
+            }
+            duplicated += 1;
+            redundant += copies.len() - 1;
+        }
+        let _ = writeln!(report, "{duplicated} models with more than one file, {redundant} redundant files");
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(path: &str, sha256: Option<&str>, size: u64) -> Record {
+        Record { path: path.into(), sha256: sha256.map(String::from), size: Some(size), ..Record::default() }
+    }
+
+    #[test]
+    fn test_by_metadata() {
+        let by_metadata = ByMetadata::new(vec!["modelspec.hash".to_string(), "ss_epoch".to_string()], Some(Keep::Newest));
+        let add = |path: &str, metadata: Value, modified| {
+            by_metadata.add(&Record { path: path.into(), metadata, modified_epoch: Some(modified), ..Record::default() });
+        };
+        add("b", serde_json::json!({"modelspec.hash": "0x1", "ss_epoch": "2"}), 20);
+        add("a", serde_json::json!({"modelspec.hash": "0x1", "ss_epoch": "2"}), 10);
+        add("c", serde_json::json!({"modelspec.hash": "0x1", "ss_epoch": "3"}), 30);
+        add("d", serde_json::json!({"modelspec.hash": "0x1"}), 40);
+        assert_eq!(
+            by_metadata.report(),
+            concat!(
+                "modelspec.hash=\"0x1\", ss_epoch=\"2\" (2 files)\n",
+                "  remove a\n",
+                "  keep   b\n",
+                "1 models with more than one file, 1 redundant files\n"
+            )
+        );
+    }
+
+    #[test]
+    fn test_report() {
+        let duplicates = Duplicates::default();
+        duplicates.add(&record("a", Some("1"), 1000));
+        duplicates.add(&record("copy of a", Some("1"), 1000));
+        duplicates.add(&record("renamed a", Some("1"), 1000));
+        duplicates.add(&record("b", Some("2"), 500));
+        duplicates.add(&record("archive.zip!c", None, 100));
+        assert_eq!(
+            duplicates.report(),
+            format!(
+                "3 distinct of 5 files (1 with copies)\n{} in total, {} distinct, {} reclaimable\n",
+                humanize::bytes(3600),
+                humanize::bytes(1600),
+                humanize::bytes(2000)
+            )
+        );
+    }
+}
@@ -0,0 +1,67 @@
+use serde_json::Value;
+
+/// Find the values in `metadata` that are JSON encoded twice: strings that, after the usual
+/// decoding of nested JSON, still hold a JSON string literal such as `"\"{\\\"a\\\": 1}\""`.
+/// Returns their keys as dotted paths. With `fix`, each is unwrapped one level, and decoded
+/// further like any other nested JSON.
+pub fn double_encoded(metadata: &mut Value, fix: bool) -> Vec<String> {
+    let mut keys = Vec::new();
+    visit(metadata, "", fix, &mut keys);
+    keys
+}
+
+fn visit(value: &mut Value, path: &str, fix: bool, keys: &mut Vec<String>) {
+    let child = |key: &str| if path.is_empty() { key.to_string() } else { format!("{path}.{key}") };
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                visit(value, &child(key), fix, keys);
+            }
+        }
+        Value::Array(values) => {
+            for (i, value) in values.iter_mut().enumerate() {
+                visit(value, &child(&i.to_string()), fix, keys);
+            }
+        }
+        Value::String(s) => {
+            let trimmed = s.trim();
+            if trimmed.len() < 2 || !trimmed.starts_with('"') || !trimmed.ends_with('"') {
+                return;
+            }
+            let Ok(inner) = serde_json::from_str::<String>(trimmed) else { return };
+            keys.push(path.to_string());
+            if fix {
+                *value = dset::metadata::decode_json_strings(Value::String(inner));
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_double_encoded() {
+        let header = json!({"__metadata__": {
+            "ss_tag_frequency": serde_json::to_string(&serde_json::to_string(&json!({"a": 1})).unwrap()).unwrap(),
+            "ss_output_name": "\"quoted\"",
+            "ss_network_dim": "16",
+            "ss_comment": "\"unbalanced",
+        }});
+        let original = dset::metadata::extract_training_metadata(&header);
+
+        let mut metadata = original.clone();
+        assert_eq!(double_encoded(&mut metadata, false), ["ss_output_name", "ss_tag_frequency"]);
+        assert_eq!(metadata, original);
+
+        assert_eq!(double_encoded(&mut metadata, true).len(), 2);
+        assert_eq!(metadata["ss_tag_frequency"], json!({"a": 1}));
+        assert_eq!(metadata["ss_output_name"], "quoted");
+        assert_eq!(metadata["ss_network_dim"], "16");
+        assert_eq!(metadata["ss_comment"], "\"unbalanced");
+        assert!(double_encoded(&mut metadata, false).is_empty());
+    }
+}
@@ -0,0 +1,61 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Stands in for the path of the file in the messages counted by [`ErrorSample`].
+const PATH: &str = "<path>";
+
+/// Limits the warnings about failed files to a few of each kind, for `--error-sample`, so a
+/// directory that fails as a whole, such as one that can't be read, doesn't flood stderr.
+/// Messages are of the same kind if they are the same but for the path of their file.
+#[derive(Debug)]
+pub struct ErrorSample {
+    limit: usize,
+    /// How many times each message was seen, with the path replaced by [`PATH`].
+    counts: Mutex<BTreeMap<String, usize>>,
+}
+
+impl ErrorSample {
+    pub fn new(limit: usize) -> Self {
+        Self { limit, counts: Mutex::default() }
+    }
+
+    /// Count the warning `message` about `path`, and whether it is to be printed: the first
+    /// `limit` of each kind are.
+    pub fn admit(&self, path: &Path, message: &str) -> bool {
+        let key = message.replace(&path.display().to_string(), PATH);
+        let mut counts = self.counts.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        let count = counts.entry(key).or_default();
+        *count += 1;
+        *count <= self.limit
+    }
+
+    /// A line for each kind of message that was seen more than `limit` times, with how many
+    /// weren't printed. Empty if none were left out.
+    pub fn report(&self) -> String {
+        let counts = self.counts.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        let mut report = String::new();
+        for (message, count) in counts.iter().filter(|(_, count)| **count > self.limit) {
+            let _ = writeln!(report, "{message} (+{} more like this)", count - self.limit);
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_sample() {
+        let sample = ErrorSample::new(2);
+        let warn = |path: &str, error: &str| sample.admit(Path::new(path), &format!("Warning: {path}: {error}"));
+        assert!(warn("/m/a.safetensors", "Permission denied"));
+        assert!(warn("/m/b.safetensors", "Permission denied"));
+        assert!(!warn("/m/c.safetensors", "Permission denied"));
+        assert!(!warn("/m/d.safetensors", "Permission denied"));
+        assert!(warn("/m/e.safetensors", "Header too large"));
+        assert_eq!(sample.report(), "Warning: <path>: Permission denied (+2 more like this)\n");
+    }
+}
@@ -0,0 +1,387 @@
+use regex::Regex;
+use serde_json::Value;
+use std::cmp::Ordering;
+
+/// A `--filter` expression on the metadata, such as
+/// `ss_network_dim >= 64 and has(modelspec.title) and not (producer == "kohya")`.
+///
+/// Conditions are comparisons of a key with a value (`==`, `!=`, `<`, `<=`, `>`, `>=`), regex
+/// matches (`KEY =~ "REGEX"`) and `has(KEY)`, combined with `and`, `or`, `not` and
+/// parentheses. `not` binds tightest and `or` loosest.
+#[derive(Debug, Clone)]
+pub struct Expression {
+    root: Node,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    Not(Box<Node>),
+    Has(String),
+    Compare { key: String, op: Op, value: Literal },
+    Matches { key: String, regex: Regex },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Op {
+    fn holds(self, ordering: Ordering) -> bool {
+        match self {
+            Self::Eq => ordering.is_eq(),
+            Self::Ne => ordering.is_ne(),
+            Self::Lt => ordering.is_lt(),
+            Self::Le => ordering.is_le(),
+            Self::Gt => ordering.is_gt(),
+            Self::Ge => ordering.is_ge(),
+        }
+    }
+}
+
+/// The value a key is compared with: a quoted string, or a bare word, which is a number, `true`,
+/// `false` or `null` if it parses as one and otherwise a string.
+#[derive(Debug, Clone)]
+struct Literal {
+    text: String,
+    value: Value,
+}
+
+impl Expression {
+    /// Whether `metadata` satisfies the expression. Comparisons and matches with a key the
+    /// metadata doesn't have are false, so `not` is how to include such files.
+    pub fn matches(&self, metadata: &Value) -> bool {
+        self.root.matches(metadata)
+    }
+}
+
+impl Node {
+    fn matches(&self, metadata: &Value) -> bool {
+        match self {
+            Self::And(left, right) => left.matches(metadata) && right.matches(metadata),
+            Self::Or(left, right) => left.matches(metadata) || right.matches(metadata),
+            Self::Not(node) => !node.matches(metadata),
+            Self::Has(key) => metadata.get(key).is_some(),
+            Self::Compare { key, op, value } => match (metadata.get(key), op) {
+                (None, _) => false,
+                (Some(actual), Op::Eq) => equals(actual, value),
+                (Some(actual), Op::Ne) => !equals(actual, value),
+                (Some(actual), op) => order(actual, value).is_some_and(|ordering| op.holds(ordering)),
+            },
+            Self::Matches { key, regex } => match metadata.get(key) {
+                Some(Value::String(actual)) => regex.is_match(actual),
+                None | Some(Value::Null) => false,
+                Some(actual) => regex.is_match(&actual.to_string()),
+            },
+        }
+    }
+}
+
+/// Whether `actual` equals `literal`: as numbers if both are, numbers stored as strings such as
+/// `"64"` included; as text if `actual` is a string, as `--where` compares; or else as JSON.
+fn equals(actual: &Value, literal: &Literal) -> bool {
+    match (number(actual), literal.value.as_f64(), actual) {
+        (Some(actual), Some(expected), _) => actual.partial_cmp(&expected) == Some(Ordering::Equal),
+        (_, _, Value::String(actual)) => *actual == literal.text,
+        (_, _, actual) => *actual == literal.value,
+    }
+}
+
+/// How `actual` orders against `literal`: as numbers if both are, as for [`equals`], or as text
+/// if both are strings. `None` if they don't order, such as a number and a word.
+fn order(actual: &Value, literal: &Literal) -> Option<Ordering> {
+    match (number(actual), literal.value.as_f64(), actual) {
+        (Some(actual), Some(expected), _) => actual.partial_cmp(&expected),
+        (_, _, Value::String(actual)) if literal.value.is_string() => Some(actual.as_str().cmp(&literal.text)),
+        _ => None,
+    }
+}
+
+/// The number `value` holds, as a number or a string such as `"1e-4"`.
+fn number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(number) => number.as_f64(),
+        Value::String(text) => text.trim().parse::<f64>().ok().filter(|number| number.is_finite()),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    Op(Op),
+    Match,
+    /// A bare word: a key, a keyword or an unquoted value.
+    Word(String),
+    Quoted(String),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Open => write!(f, "`(`"),
+            Self::Close => write!(f, "`)`"),
+            Self::Op(op) => {
+                let op = match op {
+                    Op::Eq => "==",
+                    Op::Ne => "!=",
+                    Op::Lt => "<",
+                    Op::Le => "<=",
+                    Op::Gt => ">",
+                    Op::Ge => ">=",
+                };
+                write!(f, "`{op}`")
+            }
+            Self::Match => write!(f, "`=~`"),
+            Self::Word(word) => write!(f, "`{word}`"),
+            Self::Quoted(text) => write!(f, "the string {text:?}"),
+        }
+    }
+}
+
+/// The characters that end a bare word.
+const SPECIAL: &[char] = &['(', ')', '"', '\'', '=', '!', '<', '>'];
+
+/// Split `arg` into tokens, each with the position, counted in characters from 1, it starts at.
+fn tokenize(arg: &str) -> Result<Vec<(usize, Token)>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = arg.chars().enumerate().peekable();
+    while let Some((i, c)) = chars.next() {
+        let position = i + 1;
+        let mut next_is = |expected| chars.next_if(|&(_, c)| c == expected).is_some();
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '=' if next_is('=') => Token::Op(Op::Eq),
+            '=' if next_is('~') => Token::Match,
+            '!' if next_is('=') => Token::Op(Op::Ne),
+            '<' if next_is('=') => Token::Op(Op::Le),
+            '>' if next_is('=') => Token::Op(Op::Ge),
+            '<' => Token::Op(Op::Lt),
+            '>' => Token::Op(Op::Gt),
+            '"' | '\'' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, escaped)) => text.push(escaped),
+                            None => return Err(format!("unterminated string at position {position}")),
+                        },
+                        Some((_, end)) if end == c => break,
+                        Some((_, other)) => text.push(other),
+                        None => return Err(format!("unterminated string at position {position}")),
+                    }
+                }
+                Token::Quoted(text)
+            }
+            '=' | '!' => return Err(format!("unexpected `{c}` at position {position}, did you mean `{c}=`?")),
+            c => {
+                let mut word = c.to_string();
+                while let Some((_, c)) = chars.next_if(|&(_, c)| !c.is_whitespace() && !SPECIAL.contains(&c)) {
+                    word.push(c);
+                }
+                Token::Word(word)
+            }
+        };
+        tokens.push((position, token));
+    }
+    Ok(tokens)
+}
+
+/// A recursive descent parser over the tokens of an expression.
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+    /// The position after the last character, where an incomplete expression ends.
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(_, token)| token)
+    }
+
+    fn peek_word(&self, word: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(next)) if next == word)
+    }
+
+    /// The position of the next token, or the end if there is none.
+    fn position(&self) -> usize {
+        self.tokens.get(self.next).map_or(self.end, |(position, _)| *position)
+    }
+
+    fn take(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.next).map(|(_, token)| token.clone());
+        self.next += 1;
+        token
+    }
+
+    /// An error for `expected` not being next, naming what is instead.
+    fn expected(&self, expected: &str) -> String {
+        match self.peek() {
+            Some(token) => format!("expected {expected} at position {}, found {token}", self.position()),
+            None => format!("expected {expected} at position {}, found the end of the expression", self.end),
+        }
+    }
+
+    fn expect(&mut self, token: &Token, expected: &str) -> Result<(), String> {
+        if self.peek() == Some(token) {
+            self.next += 1;
+            return Ok(());
+        }
+        Err(self.expected(expected))
+    }
+
+    fn or(&mut self) -> Result<Node, String> {
+        let mut node = self.and()?;
+        while self.peek_word("or") {
+            self.next += 1;
+            node = Node::Or(Box::new(node), Box::new(self.and()?));
+        }
+        Ok(node)
+    }
+
+    fn and(&mut self) -> Result<Node, String> {
+        let mut node = self.not()?;
+        while self.peek_word("and") {
+            self.next += 1;
+            node = Node::And(Box::new(node), Box::new(self.not()?));
+        }
+        Ok(node)
+    }
+
+    fn not(&mut self) -> Result<Node, String> {
+        if self.peek_word("not") {
+            self.next += 1;
+            return Ok(Node::Not(Box::new(self.not()?)));
+        }
+        self.condition()
+    }
+
+    fn condition(&mut self) -> Result<Node, String> {
+        if self.peek() == Some(&Token::Open) {
+            self.next += 1;
+            let node = self.or()?;
+            self.expect(&Token::Close, "`)`")?;
+            return Ok(node);
+        }
+        let key = self.key()?;
+        if key == "has" && self.peek() == Some(&Token::Open) {
+            self.next += 1;
+            let key = self.key()?;
+            self.expect(&Token::Close, "`)`")?;
+            return Ok(Node::Has(key));
+        }
+        match self.take() {
+            Some(Token::Op(op)) => Ok(Node::Compare { key, op, value: self.literal()? }),
+            Some(Token::Match) => {
+                let pattern_position = self.position();
+                let pattern = self.literal()?.text;
+                let regex = Regex::new(&pattern)
+                    .map_err(|e| format!("invalid regex at position {pattern_position}: {e}"))?;
+                Ok(Node::Matches { key, regex })
+            }
+            _ => {
+                self.next -= 1;
+                Err(self.expected("a comparison such as `==` or `=~` after the key"))
+            }
+        }
+    }
+
+    /// A key, bare or quoted for those with spaces or special characters.
+    fn key(&mut self) -> Result<String, String> {
+        match self.peek() {
+            Some(Token::Word(word)) if !["and", "or", "not"].contains(&word.as_str()) => {}
+            Some(Token::Quoted(_)) => {}
+            _ => return Err(self.expected("a key")),
+        }
+        match self.take() {
+            Some(Token::Word(key) | Token::Quoted(key)) => Ok(key),
+            _ => unreachable!("checked above"),
+        }
+    }
+
+    fn literal(&mut self) -> Result<Literal, String> {
+        match self.peek() {
+            Some(Token::Quoted(text)) => {
+                let text = text.clone();
+                self.next += 1;
+                Ok(Literal { value: Value::String(text.clone()), text })
+            }
+            Some(Token::Word(word)) if !["and", "or", "not"].contains(&word.as_str()) => {
+                let text = word.clone();
+                self.next += 1;
+                let value = serde_json::from_str(&text).unwrap_or_else(|_| Value::String(text.clone()));
+                Ok(Literal { text, value })
+            }
+            _ => Err(self.expected("a value")),
+        }
+    }
+}
+
+/// Parse a `--filter` argument. Errors name the position, counted in characters from 1, where
+/// the expression stops making sense.
+pub fn parse_expression(arg: &str) -> Result<Expression, String> {
+    let tokens = tokenize(arg)?;
+    let mut parser = Parser { tokens, next: 0, end: arg.chars().count() + 1 };
+    let root = parser.or()?;
+    if parser.peek().is_some() {
+        return Err(parser.expected("`and`, `or` or the end of the expression"));
+    }
+    Ok(Expression { root })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_expressions() {
+        let metadata = json!({
+            "ss_network_dim": "64",
+            "ss_epoch": 10,
+            "modelspec.title": "Cats",
+            "producer": "kohya",
+            "ss_base_model_version": "sdxl_base_v1-0",
+            "ss_v2": false,
+        });
+        let matches = |arg: &str| parse_expression(arg).unwrap().matches(&metadata);
+        assert!(matches(r#"ss_network_dim >= 64 and has(modelspec.title) and not (producer == "ai-toolkit")"#));
+        assert!(!matches(r#"ss_network_dim >= 64 and has(modelspec.title) and not (producer == "kohya")"#));
+        assert!(matches("ss_epoch == 10.0 and ss_epoch < 11 and ss_network_dim != 32"));
+        assert!(matches("ss_v2 == false and producer == kohya"));
+        assert!(matches(r#"ss_base_model_version =~ "^sdxl" or missing == 1"#));
+        assert!(matches("not ss_base_model_version =~ '^sd_?1' and modelspec.title > Birds"));
+        // `and` binds tighter than `or`
+        assert!(matches("ss_epoch == 1 and ss_epoch == 2 or has(producer)"));
+        assert!(!matches("ss_epoch == 1 and (ss_epoch == 2 or has(producer))"));
+        // A key the file lacks fails every comparison
+        assert!(!matches("missing != 1"));
+        assert!(matches("not missing == 1"));
+
+        let err = |arg: &str| parse_expression(arg).unwrap_err();
+        assert_eq!(err("ss_epoch >= 1 and"), "expected a key at position 18, found the end of the expression");
+        assert_eq!(err("(ss_epoch >= 1"), "expected `)` at position 15, found the end of the expression");
+        assert_eq!(
+            err("ss_epoch 1"),
+            "expected a comparison such as `==` or `=~` after the key at position 10, found `1`"
+        );
+        assert_eq!(
+            err("has(x) has(y)"),
+            "expected `and`, `or` or the end of the expression at position 8, found `has`"
+        );
+        assert_eq!(err("x = 1"), "unexpected `=` at position 3, did you mean `==`?");
+        assert_eq!(err(r#"x == "1"#), "unterminated string at position 6");
+        assert!(err("x =~ '('").starts_with("invalid regex at position 6"));
+    }
+}
@@ -0,0 +1,89 @@
+use crate::Scan;
+use crate::humanize;
+use crate::summary::Outcome;
+use crate::table;
+use futures::StreamExt;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Print how many files of each extension there are, and their total size, most common
+/// first (`--count-by-extension`). Only the files' sizes are read, so this is a quick first
+/// look at a directory that shows which formats a scan should include.
+pub async fn run(scan: &Scan, files: Vec<PathBuf>) -> anyhow::Result<()> {
+    let mut sizes = futures::stream::iter(files)
+        .map(|path| async move {
+            let size = tokio::fs::metadata(&path).await.map(|metadata| metadata.len());
+            (path, size)
+        })
+        .buffer_unordered(scan.open_files.available_permits().max(1));
+
+    let mut counts = Counts::default();
+    while let Some((path, size)) = sizes.next().await {
+        match size {
+            Ok(size) => {
+                counts.add(&path, size);
+                scan.summary.record(Outcome::Processed);
+            }
+            Err(e) => {
+                eprintln!("Warning: {}: {e}", path.display());
+                scan.summary.record(Outcome::Failed);
+            }
+        }
+    }
+    scan.output.write_text(&counts.report())?;
+    Ok(())
+}
+
+/// The number of files and bytes of each extension.
+#[derive(Debug, Default)]
+struct Counts(HashMap<String, (usize, u64)>);
+
+impl Counts {
+    /// Count a file under its lowercased extension, or `(none)` if it has none.
+    fn add(&mut self, path: &Path, size: u64) {
+        let extension = path
+            .extension()
+            .map_or_else(|| "(none)".to_string(), |extension| format!(".{}", extension.to_string_lossy().to_lowercase()));
+        let entry = self.0.entry(extension).or_default();
+        entry.0 += 1;
+        entry.1 += size;
+    }
+
+    /// A table of the extensions, the most files first, and a line with the totals.
+    fn report(&self) -> String {
+        let mut counts: Vec<_> = self.0.iter().collect();
+        counts.sort_by(|a, b| b.1.0.cmp(&a.1.0).then_with(|| b.1.1.cmp(&a.1.1)).then_with(|| a.0.cmp(b.0)));
+        let headers = ["extension", "files", "size"].map(String::from);
+        let mut rows: Vec<Vec<String>> = counts
+            .iter()
+            .map(|(extension, (files, size))| vec![(*extension).clone(), files.to_string(), humanize::bytes(*size)])
+            .collect();
+        let files = counts.iter().map(|(_, (files, _))| files).sum::<usize>();
+        let size = counts.iter().map(|(_, (_, size))| size).sum::<u64>();
+        rows.push(vec!["total".to_string(), files.to_string(), humanize::bytes(size)]);
+        table::render(&headers, &rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let mut counts = Counts::default();
+        counts.add(Path::new("a.safetensors"), 1000);
+        counts.add(Path::new("b.SAFETENSORS"), 1000);
+        counts.add(Path::new("c.gguf"), 3000);
+        counts.add(Path::new("README"), 10);
+        counts.add(Path::new("d.ckpt"), 10);
+        let text = counts.report();
+        let lines: Vec<Vec<&str>> = text.lines().map(|line| line.split("  ").map(str::trim).filter(|cell| !cell.is_empty()).collect()).collect();
+        assert_eq!(lines[0], ["extension", "files", "size"]);
+        assert_eq!(lines[1][..2], [".safetensors", "2"]);
+        assert_eq!(lines[2][..2], [".gguf", "1"]);
+        assert_eq!(lines[3][..2], ["(none)", "1"]);
+        assert_eq!(lines[4][..2], [".ckpt", "1"]);
+        assert_eq!(lines[5], ["total", "5", humanize::bytes(5020).as_str()]);
+    }
+}
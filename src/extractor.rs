@@ -0,0 +1,86 @@
+use anyhow::Context;
+use serde_json::Value;
+use std::path::Path;
+use std::process::Stdio;
+use tokio::process::Command;
+
+/// An external command that extracts the metadata of files with a given extension
+/// (`--extractor EXT=COMMAND`), for formats without native support.
+#[derive(Debug, Clone)]
+pub struct Extractor {
+    /// Without the leading dot.
+    extension: String,
+    program: String,
+    /// May contain `{path}`, which is replaced by the file's path.
+    args: Vec<String>,
+}
+
+impl Extractor {
+    pub fn extension(&self) -> &str {
+        &self.extension
+    }
+
+    pub fn matches(&self, path: &Path) -> bool {
+        path.extension().is_some_and(|ext| ext == self.extension.as_str())
+    }
+
+    /// Run the command for `path` and parse its stdout as JSON. A nonzero exit status is an
+    /// error carrying the command's stderr. The child is killed if the future is dropped, so
+    /// `--timeout` doesn't leave it running.
+    pub async fn run(&self, path: &Path) -> anyhow::Result<Value> {
+        let path_str = path.to_string_lossy();
+        let mut args: Vec<String> = self.args.iter().map(|arg| arg.replace("{path}", &path_str)).collect();
+        if !self.args.iter().any(|arg| arg.contains("{path}")) {
+            args.push(path_str.into_owned());
+        }
+        let output = Command::new(&self.program)
+            .args(&args)
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .output()
+            .await
+            .with_context(|| format!("Failed to run extractor `{}`", self.program))?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            anyhow::bail!("Extractor `{}` failed ({}): {}", self.program, output.status, stderr.trim());
+        }
+        serde_json::from_slice(&output.stdout)
+            .with_context(|| format!("Extractor `{}` didn't print valid JSON", self.program))
+    }
+}
+
+/// Parse an `--extractor EXT=COMMAND` argument such as `.custom=mytool --json {path}`. The
+/// command is split on whitespace; if no argument contains `{path}`, the path is appended.
+pub fn parse_extractor_arg(arg: &str) -> Result<Extractor, String> {
+    let (extension, command) = arg.split_once('=').ok_or_else(|| format!("expected EXT=COMMAND, got `{arg}`"))?;
+    let extension = extension.trim().trim_start_matches('.');
+    if extension.is_empty() {
+        return Err(format!("missing extension in `{arg}`"));
+    }
+    let mut words = command.split_whitespace().map(str::to_string);
+    let program = words.next().ok_or_else(|| format!("missing command in `{arg}`"))?;
+    Ok(Extractor { extension: extension.to_string(), program, args: words.collect() })
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_extractor_output_and_failure() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("model.custom");
+        std::fs::write(&path, r#"{"arch": "custom"}"#)?;
+
+        let cat = parse_extractor_arg(".custom=cat {path}").unwrap();
+        assert!(cat.matches(&path));
+        assert_eq!(cat.run(&path).await?, json!({"arch": "custom"}));
+
+        let failing = parse_extractor_arg("custom=false").unwrap();
+        assert!(failing.run(&path).await.is_err());
+        assert!(parse_extractor_arg(".custom=").is_err());
+        assert!(parse_extractor_arg("=cat").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,52 @@
+use regex::Regex;
+use serde_json::{Map, Value};
+use std::path::Path;
+
+/// What the metadata keys taken from file names start with, as in `fname.step`.
+pub const PREFIX: &str = "fname.";
+
+/// Parse `--parse-filename`: a regex with at least one named group, such as `-step(?<step>\d+)`.
+pub fn parse_filename_regex(arg: &str) -> Result<Regex, String> {
+    let regex = Regex::new(arg).map_err(|e| e.to_string())?;
+    if regex.capture_names().flatten().next().is_none() {
+        return Err("the regex has no named group, such as (?<step>\\d+), to name the values".to_string());
+    }
+    Ok(regex)
+}
+
+/// Add the named groups of the first match of `regex` in the file name of `path` to
+/// `metadata` as strings, under [`PREFIX`] and the group's name. Nothing is added for a name
+/// that doesn't match, nor for a group that took no part in the match.
+pub fn add_captures(regex: &Regex, path: &Path, metadata: &mut Map<String, Value>) {
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else { return };
+    let Some(captures) = regex.captures(name) else { return };
+    for group in regex.capture_names().flatten() {
+        if let Some(value) = captures.name(group) {
+            metadata.insert(format!("{PREFIX}{group}"), value.as_str().into());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_add_captures() {
+        let regex = parse_filename_regex(r"-(?<version>v\d+)-(?<precision>fp\d+)(?:-step(?<step>\d+))?").unwrap();
+        let captures = |path: &str| {
+            let mut metadata = Map::new();
+            add_captures(&regex, Path::new(path), &mut metadata);
+            Value::Object(metadata)
+        };
+        assert_eq!(
+            captures("runs/model-v2-fp16-step12000.safetensors"),
+            json!({"fname.version": "v2", "fname.precision": "fp16", "fname.step": "12000"})
+        );
+        assert_eq!(captures("model-v3-fp32.safetensors"), json!({"fname.version": "v3", "fname.precision": "fp32"}));
+        assert_eq!(captures("model.safetensors"), json!({}));
+        assert!(parse_filename_regex(r"step\d+").is_err());
+        assert!(parse_filename_regex(r"(?<step>").is_err());
+    }
+}
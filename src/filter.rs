@@ -0,0 +1,224 @@
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+/// Keep only the metadata keys starting with one of `prefixes` (`--prefix`). With `strip`, the
+/// longest matching prefix is removed from each kept key.
+pub fn filter_prefixes(metadata: &mut Map<String, Value>, prefixes: &[String], strip: bool) {
+    let kept = std::mem::take(metadata).into_iter().filter_map(|(key, value)| {
+        let prefix = prefixes.iter().filter(|prefix| key.starts_with(prefix.as_str())).max_by_key(|prefix| prefix.len())?;
+        let key = if strip { key[prefix.len()..].to_string() } else { key };
+        Some((key, value))
+    });
+    metadata.extend(kept);
+}
+
+/// A `--where` condition on a metadata key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    key: String,
+    /// Without a value the key only has to be present.
+    value: Option<String>,
+}
+
+impl Condition {
+    /// Whether `metadata` satisfies the condition. Strings are compared as they are, other
+    /// values with VALUE parsed as JSON, so `ss_network_dim=16` matches both `"16"` and `16`.
+    pub fn matches(&self, metadata: &Value) -> bool {
+        let Some(actual) = metadata.get(&self.key) else { return false };
+        match (&self.value, actual) {
+            (None, _) => true,
+            (Some(expected), Value::String(actual)) => actual == expected,
+            (Some(expected), actual) => serde_json::from_str::<Value>(expected).is_ok_and(|expected| expected == *actual),
+        }
+    }
+}
+
+/// The values at the RFC 6901 JSON Pointers of `--pointer`, such as `/ss_dataset_dirs/0/name`,
+/// in the decoded metadata, by pointer. Pointers to nothing yield null.
+pub fn evaluate_pointers(metadata: &Value, pointers: &[String]) -> BTreeMap<String, Value> {
+    pointers
+        .iter()
+        .map(|pointer| (pointer.clone(), metadata.pointer(pointer).cloned().unwrap_or(Value::Null)))
+        .collect()
+}
+
+/// Parse a `--pointer` argument, which is empty for the whole metadata or starts with `/`.
+pub fn parse_pointer_arg(arg: &str) -> Result<String, String> {
+    if arg.is_empty() || arg.starts_with('/') {
+        Ok(arg.to_string())
+    } else {
+        Err(format!("a JSON Pointer starts with `/`, as in `/{arg}`"))
+    }
+}
+
+/// A range of counts, with both ends inclusive: the parameter counts `--assert-param-count`
+/// allows, or the tensor counts of `--tensor-count`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CountBound {
+    min: u128,
+    max: u128,
+    /// As given, for messages.
+    text: String,
+}
+
+impl CountBound {
+    pub fn contains(&self, count: u128) -> bool {
+        (self.min..=self.max).contains(&count)
+    }
+}
+
+impl std::fmt::Display for CountBound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+/// Parse an `--assert-param-count` or `--tensor-count` argument: `<=7B`, `<7B`, `>=1B`, `>1B`,
+/// a range such as `1B..7B`, or a count the file must have exactly.
+pub fn parse_count_bound(arg: &str) -> Result<CountBound, String> {
+    let count = crate::humanize::parse_count;
+    let (min, max) = if let Some(max) = arg.strip_prefix("<=") {
+        (0, count(max)?)
+    } else if let Some(max) = arg.strip_prefix('<') {
+        (0, count(max)?.checked_sub(1).ok_or("nothing is below 0")?)
+    } else if let Some(min) = arg.strip_prefix(">=") {
+        (count(min)?, u128::MAX)
+    } else if let Some(min) = arg.strip_prefix('>') {
+        (count(min)?.saturating_add(1), u128::MAX)
+    } else if let Some((min, max)) = arg.split_once("..") {
+        (count(min)?, count(max)?)
+    } else {
+        let exact = count(arg.strip_prefix('=').unwrap_or(arg))?;
+        (exact, exact)
+    };
+    if min > max {
+        return Err(format!("`{arg}` is an empty range"));
+    }
+    Ok(CountBound { min, max, text: arg.to_string() })
+}
+
+/// A tensor and the shape it must have for `--tensor-shape`, with `None` for a `*` dimension
+/// that may be anything.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShapeCondition {
+    pub name: String,
+    dims: Vec<Option<u64>>,
+    /// The shape as given, for messages.
+    text: String,
+}
+
+impl ShapeCondition {
+    pub fn matches(&self, shape: &[u64]) -> bool {
+        shape.len() == self.dims.len()
+            && self.dims.iter().zip(shape).all(|(dim, size)| dim.is_none_or(|dim| dim == *size))
+    }
+}
+
+impl std::fmt::Display for ShapeCondition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+/// Parse a `--tensor-shape NAME=SHAPE` argument, where SHAPE is the sizes of the dimensions
+/// separated by commas, each of them a number or `*`.
+pub fn parse_tensor_shape(arg: &str) -> Result<ShapeCondition, String> {
+    let Some((name, shape)) = arg.rsplit_once('=').filter(|(name, _)| !name.is_empty()) else {
+        return Err(format!("expected NAME=SHAPE, as in `model.embed_tokens.weight=32000,4096`, not `{arg}`"));
+    };
+    let dims = shape
+        .split(',')
+        .map(|dim| match dim.trim() {
+            "*" => Ok(None),
+            dim => dim.parse().map(Some).map_err(|_| format!("`{dim}` isn't a dimension size or `*`")),
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(ShapeCondition { name: name.to_string(), dims, text: shape.to_string() })
+}
+
+/// Parse a `--where KEY=VALUE` or `--where KEY` argument.
+pub fn parse_where_arg(arg: &str) -> Result<Condition, String> {
+    let (key, value) = match arg.split_once('=') {
+        Some((key, value)) => (key, Some(value.to_string())),
+        None => (arg, None),
+    };
+    if key.is_empty() {
+        return Err(format!("missing key in `{arg}`"));
+    }
+    Ok(Condition { key: key.to_string(), value })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_tensor_shape() {
+        let condition = parse_tensor_shape("model.embed_tokens.weight=32000,*").unwrap();
+        assert_eq!(condition.name, "model.embed_tokens.weight");
+        assert!(condition.matches(&[32000, 4096]));
+        assert!(condition.matches(&[32000, 2048]));
+        assert!(!condition.matches(&[32001, 4096]));
+        assert!(!condition.matches(&[32000]));
+        assert!(parse_tensor_shape("w=1,x").is_err());
+        assert!(parse_tensor_shape("w=").is_err());
+        assert!(parse_tensor_shape("32000,4096").is_err());
+    }
+
+    #[test]
+    fn test_filter_prefixes() {
+        let metadata = json!({"ss_network_dim": "16", "modelspec.title": "x", "format": "pt", "ss_v2": "False"});
+        let Value::Object(metadata) = metadata else { unreachable!() };
+        let prefixes = ["ss_".to_string(), "modelspec.".to_string()];
+
+        let mut kept = metadata.clone();
+        filter_prefixes(&mut kept, &prefixes[..1], false);
+        assert_eq!(Value::Object(kept), json!({"ss_network_dim": "16", "ss_v2": "False"}));
+
+        let mut stripped = metadata;
+        filter_prefixes(&mut stripped, &prefixes, true);
+        assert_eq!(Value::Object(stripped), json!({"network_dim": "16", "title": "x", "v2": "False"}));
+    }
+
+    #[test]
+    fn test_evaluate_pointers() {
+        let metadata = json!({"ss_dataset_dirs": [{"name": "cats", "n_repeats": 10}], "a/b": {"~x": 1}});
+        let pointers = ["/ss_dataset_dirs/0/name", "/ss_dataset_dirs/1/name", "/a~1b/~0x"].map(String::from);
+        assert_eq!(
+            evaluate_pointers(&metadata, &pointers),
+            BTreeMap::from([
+                ("/ss_dataset_dirs/0/name".to_string(), json!("cats")),
+                ("/ss_dataset_dirs/1/name".to_string(), Value::Null),
+                ("/a~1b/~0x".to_string(), json!(1)),
+            ])
+        );
+        assert!(parse_pointer_arg("ss_dataset_dirs").is_err());
+    }
+
+    #[test]
+    fn test_count_bounds() {
+        let bound = parse_count_bound("<=7B").unwrap();
+        assert!(bound.contains(7_000_000_000) && !bound.contains(7_000_000_001));
+        assert!(!parse_count_bound("<7B").unwrap().contains(7_000_000_000));
+        assert!(!parse_count_bound(">1B").unwrap().contains(1_000_000_000));
+        let range = parse_count_bound("350M..1.5B").unwrap();
+        assert!(range.contains(350_000_000) && range.contains(1_500_000_000) && !range.contains(349_999_999));
+        assert!(parse_count_bound("=6").unwrap().contains(6));
+        assert!(parse_count_bound("7B..1B").is_err());
+        assert!(parse_count_bound("<=lots").is_err());
+    }
+
+    #[test]
+    fn test_where_conditions() {
+        let metadata = json!({"ss_network_dim": 16, "ss_base_model_version": "sdxl_base_v1-0", "empty": ""});
+        let matches = |arg: &str| parse_where_arg(arg).unwrap().matches(&metadata);
+        assert!(matches("ss_network_dim=16"));
+        assert!(!matches("ss_network_dim=32"));
+        assert!(matches("ss_base_model_version=sdxl_base_v1-0"));
+        assert!(matches("empty="));
+        assert!(matches("empty"));
+        assert!(!matches("ss_output_name"));
+        assert!(parse_where_arg("=16").is_err());
+    }
+}
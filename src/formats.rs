@@ -0,0 +1,294 @@
+use crate::header::{self, Header, MAX_HEADER_SIZE, RawHeader, ReadOptions};
+use futures::future::BoxFuture;
+use serde_json::Value;
+use std::path::Path;
+use tokio::fs::File;
+use tokio::io::AsyncReadExt;
+
+/// How many bytes of a file are given to [`Format::sniff`].
+const SNIFF_LEN: u64 = 9;
+
+/// A tensor as listed in a file's header.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, schemars::JsonSchema)]
+pub struct TensorInfo {
+    pub name: String,
+    pub dtype: String,
+    pub shape: Vec<u64>,
+    /// The name given to it by `--tensor-name-map`, if a rule there matches.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub canonical_name: Option<String>,
+    /// Where its data starts and ends, relative to the end of the header.
+    #[serde(skip)]
+    pub data_offsets: Option<[u64; 2]>,
+    /// [`TensorInfo::data_offsets`], shown with `--sort-tensors-by offset`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub byte_range: Option<[u64; 2]>,
+    /// The bytes between the end of its data and the start of the next tensor's, negative if
+    /// they overlap. Shown with `--sort-tensors-by offset`, for all but the last tensor.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gap_to_next: Option<i64>,
+}
+
+/// Put `tensors` in the order of their data in the file and fill in their byte ranges and the
+/// gaps between them. Tensors without offsets go last.
+pub fn sort_by_offset(tensors: &mut [TensorInfo]) {
+    tensors.sort_by_key(|tensor| tensor.data_offsets.map_or((1, 0), |[start, _]| (0, start)));
+    for i in 0..tensors.len() {
+        let next_start = tensors.get(i + 1).and_then(|next| next.data_offsets).map(|[start, _]| start);
+        let tensor = &mut tensors[i];
+        tensor.byte_range = tensor.data_offsets;
+        tensor.gap_to_next = tensor
+            .data_offsets
+            .zip(next_start)
+            .map(|([_, end], next_start)| i128::from(next_start) - i128::from(end))
+            .and_then(|gap| i64::try_from(gap).ok());
+    }
+}
+
+/// A model file format. Every format is registered in [`FORMATS`], through which files are
+/// dispatched: by their first bytes with `--sniff`, by their extension otherwise.
+pub trait Format: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// The extensions, without the dot, that files of this format usually have.
+    fn extensions(&self) -> &'static [&'static str];
+
+    /// Whether the first bytes of a file (up to [`SNIFF_LEN`]) identify it as this format.
+    fn sniff(&self, prefix: &[u8], file_len: u64) -> bool;
+
+    /// Read a file's header without parsing it, for `--print-header-raw`.
+    fn read_raw_header<'a>(&'a self, path: &'a Path, options: &'a ReadOptions) -> BoxFuture<'a, anyhow::Result<RawHeader>>;
+
+    /// Read and parse a file's header, which holds its metadata.
+    fn extract_metadata<'a>(&'a self, path: &'a Path, options: &'a ReadOptions) -> BoxFuture<'a, anyhow::Result<Header>>;
+
+    /// The tensors described by a header read by [`Format::extract_metadata`].
+    fn list_tensors(&self, header: &Header) -> Vec<TensorInfo>;
+
+    /// Whether files of this format can hold metadata at all.
+    fn has_metadata(&self) -> bool {
+        true
+    }
+}
+
+pub struct Safetensors;
+
+impl Format for Safetensors {
+    fn name(&self) -> &'static str {
+        "safetensors"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["safetensors"]
+    }
+
+    /// A safetensors file starts with a little-endian `u64` header length that fits in the
+    /// file, followed by the `{` opening the header.
+    fn sniff(&self, prefix: &[u8], file_len: u64) -> bool {
+        let Some(len) = prefix.get(..8).and_then(|bytes| bytes.try_into().ok()).map(u64::from_le_bytes) else {
+            return false;
+        };
+        let plausible = (2..=MAX_HEADER_SIZE).contains(&len) && len <= file_len.saturating_sub(8);
+        plausible && prefix.get(8) == Some(&b'{')
+    }
+
+    fn read_raw_header<'a>(&'a self, path: &'a Path, options: &'a ReadOptions) -> BoxFuture<'a, anyhow::Result<RawHeader>> {
+        Box::pin(header::read_raw_header(path, options))
+    }
+
+    fn extract_metadata<'a>(&'a self, path: &'a Path, options: &'a ReadOptions) -> BoxFuture<'a, anyhow::Result<Header>> {
+        Box::pin(header::read_header(path, options))
+    }
+
+    fn list_tensors(&self, header: &Header) -> Vec<TensorInfo> {
+        let Some(entries) = header.json.as_object() else { return Vec::new() };
+        entries
+            .iter()
+            .filter(|(name, _)| *name != "__metadata__")
+            .map(|(name, tensor)| TensorInfo {
+                name: name.clone(),
+                dtype: tensor.get("dtype").and_then(Value::as_str).unwrap_or_default().to_string(),
+                shape: tensor
+                    .get("shape")
+                    .and_then(Value::as_array)
+                    .map(|shape| shape.iter().filter_map(Value::as_u64).collect())
+                    .unwrap_or_default(),
+                canonical_name: None,
+                data_offsets: tensor
+                    .get("data_offsets")
+                    .and_then(Value::as_array)
+                    .and_then(|offsets| Some([offsets.first()?.as_u64()?, offsets.get(1)?.as_u64()?])),
+                byte_range: None,
+                gap_to_next: None,
+            })
+            .collect()
+    }
+}
+
+/// A zip archive written by `torch.save`. Recognized so that it can be reported as what it is,
+/// but it has no metadata to extract.
+pub struct TorchZip;
+
+impl TorchZip {
+    fn no_metadata<T>() -> anyhow::Result<T> {
+        anyhow::bail!("File is a PyTorch zip checkpoint, which has no safetensors metadata")
+    }
+}
+
+impl Format for TorchZip {
+    fn name(&self) -> &'static str {
+        "torch-zip"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["bin", "pt", "pth", "ckpt"]
+    }
+
+    fn sniff(&self, prefix: &[u8], _file_len: u64) -> bool {
+        prefix.starts_with(b"PK\x03\x04")
+    }
+
+    fn read_raw_header<'a>(&'a self, _path: &'a Path, _options: &'a ReadOptions) -> BoxFuture<'a, anyhow::Result<RawHeader>> {
+        Box::pin(async { Self::no_metadata() })
+    }
+
+    fn extract_metadata<'a>(&'a self, _path: &'a Path, _options: &'a ReadOptions) -> BoxFuture<'a, anyhow::Result<Header>> {
+        Box::pin(async { Self::no_metadata() })
+    }
+
+    fn list_tensors(&self, _header: &Header) -> Vec<TensorInfo> {
+        Vec::new()
+    }
+
+    fn has_metadata(&self) -> bool {
+        false
+    }
+}
+
+/// A format `--as` can force every file to be read as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum InputFormat {
+    /// safetensors
+    Safetensors,
+    /// A zip checkpoint written by `torch.save`, which has no metadata
+    TorchZip,
+}
+
+impl InputFormat {
+    pub fn format(self) -> &'static dyn Format {
+        match self {
+            InputFormat::Safetensors => &Safetensors,
+            InputFormat::TorchZip => &TorchZip,
+        }
+    }
+}
+
+/// Every supported format, in the order they are sniffed.
+pub static FORMATS: &[&dyn Format] = &[&Safetensors, &TorchZip];
+
+/// The extensions of all formats, which directory scans consider with `--sniff`, since
+/// weights downloaded under these names are regularly safetensors in disguise.
+pub fn sniffed_extensions() -> Vec<&'static str> {
+    FORMATS.iter().flat_map(|format| format.extensions().iter().copied()).collect()
+}
+
+/// The format implied by a file's extension.
+pub fn by_extension(path: &Path) -> Option<&'static dyn Format> {
+    let extension = path.extension()?.to_str()?;
+    FORMATS.iter().copied().find(|format| format.extensions().contains(&extension))
+}
+
+/// Identify a format from the first bytes of a file, or `None` if they aren't conclusive.
+pub fn by_content(prefix: &[u8], file_len: u64) -> Option<&'static dyn Format> {
+    FORMATS.iter().copied().find(|format| format.sniff(prefix, file_len))
+}
+
+/// Sniff a file's content, falling back to its extension when sniffing is inconclusive.
+pub async fn detect(path: &Path) -> anyhow::Result<Option<&'static dyn Format>> {
+    let mut file = File::open(path).await?;
+    let file_len = file.metadata().await?.len();
+    let mut prefix = Vec::with_capacity(usize::try_from(SNIFF_LEN)?);
+    (&mut file).take(SNIFF_LEN).read_to_end(&mut prefix).await?;
+    Ok(by_content(&prefix, file_len).or_else(|| by_extension(path)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn safetensors_prefix(header: &str) -> Vec<u8> {
+        let mut bytes = (header.len() as u64).to_le_bytes().to_vec();
+        bytes.extend(header.as_bytes());
+        bytes
+    }
+
+    fn name(format: Option<&dyn Format>) -> Option<&'static str> {
+        format.map(Format::name)
+    }
+
+    #[test]
+    fn test_sniff() {
+        let bytes = safetensors_prefix("{}");
+        assert_eq!(name(by_content(&bytes, 10)), Some("safetensors"));
+        assert_eq!(name(by_content(b"PK\x03\x04rest", 100)), Some("torch-zip"));
+        // The declared header doesn't fit in the file
+        assert_eq!(name(by_content(&bytes, 9)), None);
+        // Legacy pickle checkpoints are neither
+        assert_eq!(name(by_content(b"\x80\x02\x8a\x0al\xfc\x9cF\xf9", 100)), None);
+    }
+
+    #[tokio::test]
+    async fn test_detect_misnamed_file() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let bin = temp_dir.path().join("model.bin");
+        std::fs::write(&bin, safetensors_prefix("{}"))?;
+        let safetensors = temp_dir.path().join("model.safetensors");
+        std::fs::write(&safetensors, b"PK\x03\x04rest")?;
+        let garbage = temp_dir.path().join("other.safetensors");
+        std::fs::write(&garbage, b"garbage")?;
+
+        assert_eq!(name(detect(&bin).await?), Some("safetensors"));
+        assert_eq!(name(detect(&safetensors).await?), Some("torch-zip"));
+        assert_eq!(name(detect(&garbage).await?), Some("safetensors"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_safetensors_list_tensors() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let header = serde_json::json!({
+            "__metadata__": {},
+            "lora_unet.alpha": {"dtype": "F16", "shape": [], "data_offsets": [0, 2]},
+            "lora_unet.down.weight": {"dtype": "F16", "shape": [4, 320], "data_offsets": [2, 2562]},
+        });
+        let path = crate::test_util::write_safetensors(temp_dir.path(), "a.safetensors", &header);
+
+        let header = Safetensors.extract_metadata(&path, &ReadOptions::default()).await?;
+        let tensors = Safetensors.list_tensors(&header);
+        assert_eq!(tensors.len(), 2);
+        assert_eq!(
+            tensors[1],
+            TensorInfo {
+                name: "lora_unet.down.weight".to_string(),
+                dtype: "F16".to_string(),
+                shape: vec![4, 320],
+                data_offsets: Some([2, 2562]),
+                ..TensorInfo::default()
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_sort_by_offset() {
+        let tensor = |name: &str, data_offsets| TensorInfo { name: name.to_string(), data_offsets, ..TensorInfo::default() };
+        let mut tensors =
+            [tensor("a", Some([100, 200])), tensor("b", Some([0, 64])), tensor("c", None), tensor("d", Some([50, 100]))];
+        sort_by_offset(&mut tensors);
+        let layout: Vec<_> = tensors.iter().map(|tensor| (tensor.name.as_str(), tensor.byte_range, tensor.gap_to_next)).collect();
+        assert_eq!(
+            layout,
+            [("b", Some([0, 64]), Some(-14)), ("d", Some([50, 100]), Some(0)), ("a", Some([100, 200]), None), ("c", None, None)]
+        );
+    }
+}
@@ -0,0 +1,51 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// A semaphore for each group of files, created when the group is first seen, so that at most
+/// `per_group` files of one group are worked on at once while other groups proceed.
+///
+/// A slot must be taken before the `--max-open-files` permit: the tasks of a full group then
+/// wait here instead of queueing for the shared permits ahead of the other groups.
+#[derive(Debug)]
+pub struct GroupLimits<K> {
+    per_group: usize,
+    groups: Mutex<HashMap<K, Arc<Semaphore>>>,
+}
+
+impl<K: Eq + Hash> GroupLimits<K> {
+    pub fn new(per_group: usize) -> Self {
+        Self { per_group, groups: Mutex::default() }
+    }
+
+    pub fn semaphore(&self, group: K) -> Arc<Semaphore> {
+        let mut groups = self.groups.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        Arc::clone(groups.entry(group).or_insert_with(|| Arc::new(Semaphore::new(self.per_group))))
+    }
+
+    /// Wait for a slot in `group`.
+    pub async fn acquire(&self, group: K) -> anyhow::Result<OwnedSemaphorePermit> {
+        Ok(self.semaphore(group).acquire_owned().await?)
+    }
+
+    /// A slot in `group`, if one is free.
+    pub fn try_acquire(&self, group: K) -> Option<OwnedSemaphorePermit> {
+        self.semaphore(group).try_acquire_owned().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_group_limits() -> anyhow::Result<()> {
+        let limits = GroupLimits::new(2);
+        let _first = limits.acquire("a").await?;
+        let _second = limits.acquire("a").await?;
+        assert!(limits.semaphore("a").try_acquire_owned().is_err());
+        assert!(limits.semaphore("b").try_acquire_owned().is_ok());
+        Ok(())
+    }
+}
@@ -0,0 +1,93 @@
+use anyhow::Context;
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use std::path::Path;
+
+/// Default size of the buffer the file is streamed through (`--hash-chunk-size`).
+pub const DEFAULT_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// The SHA-256 of the file's contents as lowercase hex, read `chunk_size` bytes at a time. The
+/// file is streamed on a blocking thread, since hashing is CPU-bound and would otherwise stall
+/// the runtime.
+pub async fn sha256_file(path: &Path, chunk_size: usize) -> anyhow::Result<String> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let mut file = std::fs::File::open(&path).context("Failed to open file for hashing")?;
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0; chunk_size];
+        loop {
+            let read = file.read(&mut buffer).context("Failed to read file for hashing")?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+        }
+        Ok(format!("{:x}", hasher.finalize()))
+    })
+    .await?
+}
+
+/// A stable ID for the file at `path` (`--with-id`): the SHA-256 of the path as lowercase hex.
+/// Unlike [`sha256_file`] it stays the same when the file is rewritten, and changes when the
+/// file is moved or renamed.
+pub fn path_id(path: &Path) -> String {
+    format!("{:x}", Sha256::digest(path.to_string_lossy().as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_id() {
+        let id = path_id(Path::new("abc"));
+        assert_eq!(id, "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_ne!(path_id(Path::new("/models/abc")), id);
+    }
+
+    #[tokio::test]
+    async fn test_sha256_file() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("abc");
+        std::fs::write(&path, "abc")?;
+        assert_eq!(
+            sha256_file(&path, DEFAULT_CHUNK_SIZE).await?,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sha256_file_chunk_sizes() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("small");
+        let contents: Vec<u8> = (0..10_000).map(|i: u32| (i % 251) as u8).collect();
+        std::fs::write(&path, &contents)?;
+
+        // Chunks that divide the file, that don't, and one larger than it
+        let expected = format!("{:x}", Sha256::digest(&contents));
+        for chunk_size in [1, 7, 4096, 10_000, 64 * 1024] {
+            assert_eq!(sha256_file(&path, chunk_size).await?, expected, "chunk size {chunk_size}");
+        }
+        Ok(())
+    }
+
+    /// How long a 16 MiB file takes to hash at each chunk size, to tune `--hash-chunk-size`.
+    /// Run with `cargo test --release -- --ignored --nocapture bench_sha256_file`.
+    #[tokio::test]
+    #[ignore = "a benchmark, which allocates 80 MiB"]
+    async fn bench_sha256_file() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("large");
+        let contents: Vec<u8> = (0..16 * 1024 * 1024).map(|i: u32| (i % 251) as u8).collect();
+        std::fs::write(&path, contents)?;
+
+        let expected = sha256_file(&path, DEFAULT_CHUNK_SIZE).await?;
+        for chunk_size in [4096, 64 * 1024, 8 * 1024 * 1024, 64 * 1024 * 1024] {
+            let started = std::time::Instant::now();
+            assert_eq!(sha256_file(&path, chunk_size).await?, expected);
+            eprintln!("chunk size {chunk_size}: {:?}", started.elapsed());
+        }
+        Ok(())
+    }
+}
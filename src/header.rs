@@ -0,0 +1,891 @@
+use crate::readahead::{self, Readahead};
+use crate::trace::Traced;
+use anyhow::{Context, bail};
+use serde::de::{self, DeserializeSeed, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fmt;
+use std::io::{self, Read};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Headers declaring more bytes than this are rejected before allocating,
+/// the same limit the `safetensors` crate applies.
+pub const MAX_HEADER_SIZE: u64 = 100_000_000;
+
+/// The default of `--max-metadata-keys`, far above the few hundred keys of real metadata.
+pub const DEFAULT_MAX_METADATA_KEYS: usize = 100_000;
+
+/// Marks where invalid UTF-8 was cut out of a header, so the entries containing it can be
+/// found after parsing. A noncharacter, which legitimate metadata has no reason to contain.
+const INVALID_UTF8_MARK: char = '\u{FFFF}';
+
+/// What to do with invalid UTF-8 in a header, which the safetensors spec forbids.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum InvalidUtf8 {
+    /// Decode lossily, substituting U+FFFD for invalid sequences
+    Replace,
+    /// Fail the file
+    #[default]
+    Error,
+    /// Drop the metadata keys and tensors whose key or value contains an invalid sequence
+    SkipKey,
+}
+
+/// The byte order of the header length prefix, which the safetensors spec says is little-endian.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Endian {
+    /// Little-endian, as the spec says
+    #[default]
+    Le,
+    /// Big-endian, as some third-party tools wrongly write it
+    Be,
+    /// Little-endian unless that length can't be right for the file and big-endian can
+    Auto,
+}
+
+impl fmt::Display for Endian {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Le => "little-endian",
+            Self::Be => "big-endian",
+            Self::Auto => "auto",
+        })
+    }
+}
+
+/// How headers are read and parsed.
+#[derive(Debug, Clone, Default)]
+#[allow(clippy::struct_excessive_bools)] // they mirror command-line flags
+pub struct ReadOptions {
+    pub on_invalid_utf8: InvalidUtf8,
+    /// Tolerate a UTF-8 byte order mark and whitespace around the header JSON (`--lenient`).
+    pub lenient: bool,
+    /// Reject headers with an object that has the same key twice (`--fail-on-duplicate-keys`).
+    pub fail_on_duplicate_keys: bool,
+    /// Warn about tensors named more than once (`--report-duplicated-tensor-names`).
+    pub report_duplicate_tensors: bool,
+    /// How to read the header length (`--header-endian`).
+    pub endian: Endian,
+    /// The access pattern to announce before reading (`--readahead`).
+    pub readahead: Readahead,
+    /// Read each header with blocking calls in one blocking task (`--threads-blocking`).
+    pub blocking: bool,
+    /// How long opening and stat'ing a file may take (`--open-read-timeout`).
+    pub open_timeout: Option<Duration>,
+    /// Print the number of reads and bytes read for each header (`--trace-reads`).
+    pub trace: bool,
+    /// Fail headers whose `__metadata__` has more keys (`--max-metadata-keys`).
+    pub max_metadata_keys: Option<usize>,
+    /// Fail headers longer than this, by default [`MAX_HEADER_SIZE`] (`--max-header-bytes`).
+    pub max_header_bytes: Option<u64>,
+}
+
+/// The unparsed header bytes of a safetensors file.
+#[derive(Debug)]
+pub struct RawHeader {
+    pub bytes: Vec<u8>,
+    /// The byte order the length prefix was read in, never `Auto`.
+    pub endian: Endian,
+    /// The file's metadata from the `stat` done while reading, so size and times are
+    /// available without touching the file again.
+    pub stat: std::fs::Metadata,
+}
+
+/// A header that declares more bytes than the file has, as happens while the file is still
+/// being written but also when it was cut short.
+#[derive(Debug)]
+pub struct Truncated {
+    pub len: u64,
+    pub file_len: u64,
+    pub modified: Option<SystemTime>,
+}
+
+impl fmt::Display for Truncated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Header length {} exceeds the file size of {} bytes", self.len, self.file_len)
+    }
+}
+
+impl std::error::Error for Truncated {}
+
+/// A file that couldn't be opened or stat'ed at all, which is a permission or filesystem
+/// problem rather than one with its data.
+#[derive(Debug)]
+pub struct Unreadable {
+    context: &'static str,
+    pub source: io::Error,
+}
+
+impl Unreadable {
+    fn new(context: &'static str) -> impl FnOnce(io::Error) -> Self {
+        move |source| Self { context, source }
+    }
+}
+
+impl fmt::Display for Unreadable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.context)
+    }
+}
+
+impl std::error::Error for Unreadable {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// The JSON header of a safetensors file.
+#[derive(Debug)]
+pub struct Header {
+    pub json: Value,
+    /// The length of the header JSON in bytes, as given before it.
+    pub len: u64,
+    /// Deviations from the spec that were tolerated while reading the header.
+    pub warnings: Vec<String>,
+    /// The byte order the length prefix was read in, never `Auto`.
+    pub endian: Endian,
+    pub stat: std::fs::Metadata,
+}
+
+impl Header {
+    /// The training metadata stored under `__metadata__`, with nested JSON strings decoded.
+    pub fn metadata(&self) -> Value {
+        dset::metadata::extract_training_metadata(&self.json)
+    }
+
+    /// The number of parameters, summed over the shapes of the tensors whose name satisfies
+    /// `include`. Accumulates in `u128` so multi-billion parameter models can't overflow;
+    /// entries without a valid shape count as zero.
+    pub fn parameter_count(&self, include: impl Fn(&str) -> bool) -> u128 {
+        let Some(entries) = self.json.as_object() else { return 0 };
+        entries
+            .iter()
+            .filter(|(key, _)| *key != "__metadata__" && include(key))
+            .filter_map(|(_, tensor)| tensor.get("shape")?.as_array())
+            .filter_map(|shape| {
+                shape.iter().try_fold(1u128, |product, dim| product.checked_mul(u128::from(dim.as_u64()?)))
+            })
+            .fold(0u128, u128::saturating_add)
+    }
+}
+
+/// Read the raw header bytes of a safetensors file: the `u64` length prefix is checked against
+/// the file size and those bytes returned unparsed. The tensor data is never read.
+///
+/// Each step runs on tokio's blocking pool by itself, unless `options.blocking` has all of
+/// them done in a single blocking task.
+pub async fn read_raw_header(path: &Path, options: &ReadOptions) -> anyhow::Result<RawHeader> {
+    if options.blocking {
+        let (path, options) = (path.to_path_buf(), options.clone());
+        if options.open_timeout.is_none() {
+            return tokio::task::spawn_blocking(move || read_raw_header_blocking(&path, &options)).await?;
+        }
+        let (file, stat) = open(&path, &options).await?;
+        return tokio::task::spawn_blocking(move || read_opened_blocking(file, stat, &path, &options)).await?;
+    }
+    let (file, stat) = open(path, options).await?;
+    advise(&file, path, options.readahead);
+    let mut file = Traced::new(File::from_std(file), path, stat.len(), options.trace);
+
+    let mut prefix = [0u8; 8];
+    file.read_exact(&mut prefix)
+        .await
+        .context("File is too small to contain a safetensors header")?;
+    let (len, endian) = header_len(prefix, &stat, options)?;
+
+    let mut bytes = vec![0; usize::try_from(len)?];
+    file.read_exact(&mut bytes).await.context("Failed to read header")?;
+    Ok(RawHeader { bytes, endian, stat })
+}
+
+/// Check that `path` starts like a safetensors file without reading its header: the length
+/// prefix must fit the file and the header start with `{`. For `--probe-only`.
+pub async fn probe(path: &Path, options: &ReadOptions) -> anyhow::Result<()> {
+    let (file, stat) = open(path, options).await?;
+    let mut file = File::from_std(file);
+    let mut prefix = [0u8; 8];
+    file.read_exact(&mut prefix)
+        .await
+        .context("File is too small to contain a safetensors header")?;
+    let (len, _) = header_len(prefix, &stat, options)?;
+    if len == 0 || file.read_u8().await.context("Failed to read header")? != b'{' {
+        bail!("Header doesn't start with {{");
+    }
+    Ok(())
+}
+
+/// Open and stat `path`. With `options.open_timeout` this is done in a blocking task that is
+/// given up on when the time is up, since both calls can hang on a dead network mount before
+/// any byte is read. The task is left to finish by itself, as a hung call can't be cancelled.
+async fn open(path: &Path, options: &ReadOptions) -> anyhow::Result<(std::fs::File, std::fs::Metadata)> {
+    let Some(limit) = options.open_timeout else {
+        let file = File::open(path).await.map_err(Unreadable::new("Failed to open file"))?;
+        let stat = file.metadata().await.map_err(Unreadable::new("Failed to stat file"))?;
+        return Ok((file.into_std().await, stat));
+    };
+    let owned = path.to_path_buf();
+    let task = tokio::task::spawn_blocking(move || -> anyhow::Result<_> {
+        let file = std::fs::File::open(&owned).map_err(Unreadable::new("Failed to open file"))?;
+        let stat = file.metadata().map_err(Unreadable::new("Failed to stat file"))?;
+        Ok((file, stat))
+    });
+    if let Ok(result) = tokio::time::timeout(limit, task).await {
+        return result?;
+    }
+    let message = format!("No response within --open-read-timeout {}", humantime::format_duration(limit));
+    Err(Unreadable::new("Failed to open file")(io::Error::new(io::ErrorKind::TimedOut, message)).into())
+}
+
+/// [`read_raw_header`] with blocking calls, for a blocking task.
+fn read_raw_header_blocking(path: &Path, options: &ReadOptions) -> anyhow::Result<RawHeader> {
+    let file = std::fs::File::open(path).map_err(Unreadable::new("Failed to open file"))?;
+    let stat = file.metadata().map_err(Unreadable::new("Failed to stat file"))?;
+    read_opened_blocking(file, stat, path, options)
+}
+
+/// The rest of [`read_raw_header_blocking`] once the file is open.
+fn read_opened_blocking(
+    file: std::fs::File,
+    stat: std::fs::Metadata,
+    path: &Path,
+    options: &ReadOptions,
+) -> anyhow::Result<RawHeader> {
+    advise(&file, path, options.readahead);
+    let mut file = Traced::new(file, path, stat.len(), options.trace);
+
+    let mut prefix = [0u8; 8];
+    file.read_exact(&mut prefix).context("File is too small to contain a safetensors header")?;
+    let (len, endian) = header_len(prefix, &stat, options)?;
+
+    let mut bytes = vec![0; usize::try_from(len)?];
+    file.read_exact(&mut bytes).context("Failed to read header")?;
+    Ok(RawHeader { bytes, endian, stat })
+}
+
+fn advise(file: &std::fs::File, path: &Path, advice: Readahead) {
+    if let Err(e) = readahead::advise(file, advice) {
+        // Some filesystems don't take advice, which is no reason to fail the file
+        log::debug!("Readahead advice for {} was refused: {e}", path.display());
+    }
+}
+
+/// The header length in the 8-byte `prefix` and the byte order it was read in, checked
+/// against the size of the file.
+fn header_len(prefix: [u8; 8], stat: &std::fs::Metadata, options: &ReadOptions) -> anyhow::Result<(u64, Endian)> {
+    let file_len = stat.len();
+    prefix_len(prefix, options, |len| {
+        if len > file_len.saturating_sub(8) {
+            return Err(Truncated { len, file_len, modified: stat.modified().ok() }.into());
+        }
+        Ok(len)
+    })
+}
+
+/// The header length in the 8-byte `prefix` in the byte order of `options` that passes the
+/// `--max-header-bytes` and `check`, and the byte order it was read in.
+fn prefix_len(
+    prefix: [u8; 8],
+    options: &ReadOptions,
+    check: impl Fn(u64) -> anyhow::Result<u64>,
+) -> anyhow::Result<(u64, Endian)> {
+    let max = options.max_header_bytes.unwrap_or(MAX_HEADER_SIZE);
+    let check = |len: u64| -> anyhow::Result<u64> {
+        if len > max {
+            bail!("Header length {len} exceeds the maximum of {max} bytes");
+        }
+        check(len)
+    };
+    Ok(match options.endian {
+        Endian::Le => (check(u64::from_le_bytes(prefix))?, Endian::Le),
+        Endian::Be => (check(u64::from_be_bytes(prefix))?, Endian::Be),
+        // Report the little-endian error if neither fits, since that's what the file should be
+        Endian::Auto => match check(u64::from_le_bytes(prefix)) {
+            Ok(len) => (len, Endian::Le),
+            Err(e) => (check(u64::from_be_bytes(prefix)).map_err(|_| e)?, Endian::Be),
+        },
+    })
+}
+
+/// Read the header of a safetensors stream that can't be stat'ed for its size, such as stdin,
+/// leaving `reader` at the start of the tensor data. With no file size to check the length
+/// prefix against, only the `--max-header-bytes` bounds what is allocated for the header.
+/// `stat` is that of wherever the stream comes from.
+pub async fn read_raw_stream(
+    reader: &mut (impl AsyncRead + Unpin),
+    stat: std::fs::Metadata,
+    options: &ReadOptions,
+) -> anyhow::Result<RawHeader> {
+    let mut prefix = [0u8; 8];
+    reader.read_exact(&mut prefix).await.context("Stream is too short to contain a safetensors header")?;
+    let (len, endian) = prefix_len(prefix, options, Ok)?;
+    let mut bytes = vec![0; usize::try_from(len)?];
+    reader.read_exact(&mut bytes).await.context("Stream ended inside the header")?;
+    Ok(RawHeader { bytes, endian, stat })
+}
+
+/// Read and parse the JSON header of a safetensors file.
+pub async fn read_header(path: &Path, options: &ReadOptions) -> anyhow::Result<Header> {
+    parse_header(read_raw_header(path, options).await?, options)
+}
+
+/// Parse header bytes read by [`read_raw_header`] or taken from elsewhere, such as an archive.
+pub fn parse_header(raw: RawHeader, options: &ReadOptions) -> anyhow::Result<Header> {
+    let RawHeader { bytes, endian, stat } = raw;
+    let len = bytes.len() as u64;
+    let mut warnings = Vec::new();
+    let text = match String::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(e) => {
+            let position = invalid_utf8_position(e.as_bytes(), e.utf8_error());
+            match options.on_invalid_utf8 {
+                InvalidUtf8::Error => bail!("Header is not valid UTF-8: {position}"),
+                InvalidUtf8::Replace => {
+                    warnings.push(format!("Replaced invalid UTF-8 in the header, the first {position}"));
+                    String::from_utf8_lossy(e.as_bytes()).into_owned()
+                }
+                InvalidUtf8::SkipKey => {
+                    warnings.push(format!("Found invalid UTF-8 in the header, the first {position}"));
+                    mark_invalid_utf8(e.as_bytes())
+                }
+            }
+        }
+    };
+
+    let mut json_text = text.as_str();
+    if options.lenient {
+        if let Some(rest) = json_text.strip_prefix('\u{FEFF}') {
+            warnings.push("Ignored a byte order mark before the header JSON".to_string());
+            json_text = rest;
+        }
+        // Trailing spaces are the spec's padding, so only leading whitespace is a deviation
+        if json_text.starts_with(char::is_whitespace) {
+            warnings.push("Ignored whitespace before the header JSON".to_string());
+        }
+        json_text = json_text.trim();
+    }
+    if options.fail_on_duplicate_keys {
+        let duplicates = duplicate_keys(json_text).context("Failed to parse header JSON")?;
+        if !duplicates.is_empty() {
+            bail!("Duplicate keys in the header: {}", duplicates.join(", "));
+        }
+    }
+    if options.report_duplicate_tensors {
+        let duplicates = duplicate_tensor_names(json_text).context("Failed to parse header JSON")?;
+        if !duplicates.is_empty() {
+            let duplicates = duplicates.join(", ");
+            warnings.push(format!("Tensors named more than once, of which only the last is read: {duplicates}"));
+        }
+    }
+    let mut json = parse_limited(json_text, options.max_metadata_keys).context("Failed to parse header JSON")?;
+    let Some(entries) = json.as_object_mut() else {
+        bail!("Header is not a JSON object");
+    };
+    if text.contains(INVALID_UTF8_MARK) {
+        let mut dropped = drop_marked(entries);
+        if let Some(Value::Object(metadata)) = entries.get_mut("__metadata__") {
+            dropped.extend(drop_marked(metadata).into_iter().map(|key| format!("__metadata__.{key}")));
+        }
+        if !dropped.is_empty() {
+            warnings.push(format!("Dropped entries with invalid UTF-8: {}", dropped.join(", ")));
+        }
+    }
+    Ok(Header { json, len, warnings, endian, stat })
+}
+
+/// Parse header JSON as `serde_json::from_str` does, but fail as soon as `__metadata__` has
+/// more than `max_keys` entries, before the rest of them are allocated.
+fn parse_limited(json: &str, max_keys: Option<usize>) -> serde_json::Result<Value> {
+    let mut deserializer = serde_json::Deserializer::from_str(json);
+    let value = LimitedValue { level: Level::Root, max_keys: max_keys.unwrap_or(usize::MAX) }.deserialize(&mut deserializer)?;
+    deserializer.end()?;
+    Ok(value)
+}
+
+/// Where in the header a [`LimitedValue`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Level {
+    Root,
+    Metadata,
+    Nested,
+}
+
+/// Deserializes a JSON value, failing on a `__metadata__` object with more than `max_keys`
+/// entries.
+#[derive(Debug, Clone, Copy)]
+struct LimitedValue {
+    level: Level,
+    max_keys: usize,
+}
+
+impl<'de> DeserializeSeed<'de> for LimitedValue {
+    type Value = Value;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for LimitedValue {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("any JSON value")
+    }
+
+    fn visit_bool<E: de::Error>(self, value: bool) -> Result<Value, E> {
+        Ok(Value::Bool(value))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Value, E> {
+        Ok(value.into())
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Value, E> {
+        Ok(value.into())
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<Value, E> {
+        Ok(value.into())
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Value, E> {
+        Ok(Value::String(value.to_string()))
+    }
+
+    fn visit_string<E: de::Error>(self, value: String) -> Result<Value, E> {
+        Ok(Value::String(value))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        let mut values = Vec::new();
+        while let Some(value) = seq.next_element_seed(Self { level: Level::Nested, ..self })? {
+            values.push(value);
+        }
+        Ok(Value::Array(values))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+        let mut entries = serde_json::Map::new();
+        while let Some(key) = map.next_key::<String>()? {
+            if self.level == Level::Metadata && entries.len() == self.max_keys {
+                return Err(de::Error::custom(format_args!(
+                    "__metadata__ has more than the --max-metadata-keys limit of {} keys",
+                    self.max_keys
+                )));
+            }
+            let level = if self.level == Level::Root && key == "__metadata__" { Level::Metadata } else { Level::Nested };
+            let value = map.next_value_seed(Self { level, ..self })?;
+            entries.insert(key, value);
+        }
+        Ok(Value::Object(entries))
+    }
+}
+
+/// The keys that occur more than once in the same object anywhere in `json`, as dotted paths
+/// such as `__metadata__.ss_network_dim`. Parsing into a `Value` would keep only the last of
+/// them, so the JSON is walked with a visitor that sees every key as it is read.
+fn duplicate_keys(json: &str) -> serde_json::Result<Vec<String>> {
+    let mut duplicates = Vec::new();
+    let mut deserializer = serde_json::Deserializer::from_str(json);
+    KeyWalker { path: String::new(), duplicates: &mut duplicates }.deserialize(&mut deserializer)?;
+    deserializer.end()?;
+    Ok(duplicates)
+}
+
+/// The tensor names that occur more than once at the top level of the header `json`, which
+/// [`duplicate_keys`] can't tell apart from nested keys since tensor names contain dots.
+fn duplicate_tensor_names(json: &str) -> serde_json::Result<Vec<String>> {
+    struct TopLevel;
+
+    impl<'de> Visitor<'de> for TopLevel {
+        type Value = Vec<String>;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("a JSON object")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Vec<String>, A::Error> {
+            let (mut seen, mut duplicates) = (HashSet::new(), Vec::new());
+            while let Some(key) = map.next_key::<String>()? {
+                map.next_value::<de::IgnoredAny>()?;
+                if key != "__metadata__" && !seen.insert(key.clone()) && !duplicates.contains(&key) {
+                    duplicates.push(key);
+                }
+            }
+            Ok(duplicates)
+        }
+    }
+
+    let mut deserializer = serde_json::Deserializer::from_str(json);
+    let duplicates = deserializer.deserialize_map(TopLevel)?;
+    deserializer.end()?;
+    Ok(duplicates)
+}
+
+/// Visits a JSON value, recording the duplicate keys of the objects in it.
+struct KeyWalker<'a> {
+    path: String,
+    duplicates: &'a mut Vec<String>,
+}
+
+impl KeyWalker<'_> {
+    fn child(path: &str, key: &str) -> String {
+        if path.is_empty() { key.to_string() } else { format!("{path}.{key}") }
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for KeyWalker<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for KeyWalker<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("any JSON value")
+    }
+
+    fn visit_bool<E: de::Error>(self, _: bool) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_i64<E: de::Error>(self, _: i64) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_u64<E: de::Error>(self, _: u64) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_f64<E: de::Error>(self, _: f64) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_str<E: de::Error>(self, _: &str) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        let KeyWalker { path, duplicates } = self;
+        for index in 0usize.. {
+            let element = KeyWalker { path: Self::child(&path, &index.to_string()), duplicates: &mut *duplicates };
+            if seq.next_element_seed(element)?.is_none() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        let KeyWalker { path, duplicates } = self;
+        let mut seen = HashSet::new();
+        while let Some(key) = map.next_key::<String>()? {
+            let child = Self::child(&path, &key);
+            if !seen.insert(key) && !duplicates.contains(&child) {
+                duplicates.push(child.clone());
+            }
+            map.next_value_seed(KeyWalker { path: child, duplicates: &mut *duplicates })?;
+        }
+        Ok(())
+    }
+}
+
+/// How many bytes on either side of an invalid UTF-8 sequence [`invalid_utf8_position`] shows.
+const UTF8_CONTEXT: usize = 8;
+
+/// Where the first invalid sequence in the header `bytes` is, both in the header and in the
+/// file, with a hex dump of the bytes around it and the sequence itself in brackets.
+fn invalid_utf8_position(bytes: &[u8], error: std::str::Utf8Error) -> String {
+    let start = error.valid_up_to();
+    // A sequence cut off by the end of the header has no length of its own
+    let end = start + error.error_len().unwrap_or(bytes.len() - start);
+    let hex = |bytes: &[u8]| bytes.iter().map(|byte| format!("{byte:02x}")).collect::<Vec<_>>().join(" ");
+    let before = &bytes[start.saturating_sub(UTF8_CONTEXT)..start];
+    let after = &bytes[end..(end + UTF8_CONTEXT).min(bytes.len())];
+    let dump = [hex(before), format!("[{}]", hex(&bytes[start..end])), hex(after)];
+    format!(
+        "invalid sequence at byte {start} of the header (byte {} of the file): {}",
+        start + 8,
+        dump.iter().filter(|part| !part.is_empty()).cloned().collect::<Vec<_>>().join(" ")
+    )
+}
+
+/// Decode `bytes`, replacing each invalid sequence with `INVALID_UTF8_MARK`.
+fn mark_invalid_utf8(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len());
+    for chunk in bytes.utf8_chunks() {
+        text.push_str(chunk.valid());
+        if !chunk.invalid().is_empty() {
+            text.push(INVALID_UTF8_MARK);
+        }
+    }
+    text
+}
+
+/// Remove the entries whose key or value contains `INVALID_UTF8_MARK`, returning their keys.
+/// `__metadata__` itself is only dropped if its own key is damaged; its entries are handled
+/// separately so that one bad value doesn't take all of the metadata with it.
+fn drop_marked(map: &mut serde_json::Map<String, Value>) -> Vec<String> {
+    fn contains_mark(value: &Value) -> bool {
+        match value {
+            Value::String(s) => s.contains(INVALID_UTF8_MARK),
+            Value::Array(values) => values.iter().any(contains_mark),
+            Value::Object(map) => map.iter().any(|(k, v)| k.contains(INVALID_UTF8_MARK) || contains_mark(v)),
+            _ => false,
+        }
+    }
+    let dropped: Vec<String> = map
+        .iter()
+        .filter(|(key, value)| {
+            key.contains(INVALID_UTF8_MARK) || (*key != "__metadata__" && contains_mark(value))
+        })
+        .map(|(key, _)| key.clone())
+        .collect();
+    for key in &dropped {
+        map.remove(key);
+    }
+    dropped.into_iter().map(|key| key.replace(INVALID_UTF8_MARK, "\u{FFFD}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_file(dir: &Path, name: &str, len: u64, header: &str) -> std::path::PathBuf {
+        let path = dir.join(name);
+        let mut bytes = len.to_le_bytes().to_vec();
+        bytes.extend(header.as_bytes());
+        std::fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn test_read_header_metadata() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let header = r#"{"__metadata__":{"ss_network_dim":"16","ss_tag_frequency":"{\"a\":1}"}}"#;
+        let path = write_file(temp_dir.path(), "a.safetensors", header.len() as u64, header);
+
+        let metadata = read_header(&path, &ReadOptions::default()).await?.metadata();
+        assert_eq!(metadata["ss_network_dim"], "16");
+        assert_eq!(metadata["ss_tag_frequency"]["a"], 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_header_length_exceeds_file() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = write_file(temp_dir.path(), "a.safetensors", 1000, "{}");
+
+        let err = read_header(&path, &ReadOptions::default()).await.unwrap_err();
+        assert!(err.to_string().contains("exceeds the file size"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_header_not_an_object() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = write_file(temp_dir.path(), "a.safetensors", 2, "[]");
+
+        assert!(read_header(&path, &ReadOptions::default()).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_header_invalid_utf8() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let header = b"{\"__metadata__\":{\"bad\":\"a\xffb\",\"good\":\"ok\"}}";
+        let mut bytes = (header.len() as u64).to_le_bytes().to_vec();
+        bytes.extend(header);
+        let path = temp_dir.path().join("a.safetensors");
+        std::fs::write(&path, bytes)?;
+        let options = |on_invalid_utf8| ReadOptions { on_invalid_utf8, ..ReadOptions::default() };
+
+        let err = read_header(&path, &options(InvalidUtf8::Error)).await.unwrap_err();
+        let position = concat!(
+            "invalid sequence at byte 25 of the header (byte 33 of the file): ",
+            "22 62 61 64 22 3a 22 61 [ff] 62 22 2c 22 67 6f 6f 64"
+        );
+        assert_eq!(format!("{err:#}"), format!("Header is not valid UTF-8: {position}"));
+
+        let header = read_header(&path, &options(InvalidUtf8::Replace)).await?;
+        assert_eq!(header.metadata()["bad"], "a\u{FFFD}b");
+        assert_eq!(header.warnings, vec![format!("Replaced invalid UTF-8 in the header, the first {position}")]);
+
+        let header = read_header(&path, &options(InvalidUtf8::SkipKey)).await?;
+        let metadata = header.metadata();
+        assert!(metadata.get("bad").is_none());
+        assert_eq!(metadata["good"], "ok");
+        assert_eq!(
+            header.warnings,
+            vec![
+                format!("Found invalid UTF-8 in the header, the first {position}"),
+                "Dropped entries with invalid UTF-8: __metadata__.bad".to_string()
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_header_blocking() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let header = r#"{"__metadata__":{"ss_network_dim":"16"}}"#;
+        let path = write_file(temp_dir.path(), "a.safetensors", header.len() as u64, header);
+        let options = ReadOptions { blocking: true, ..ReadOptions::default() };
+
+        let header = read_header(&path, &options).await?;
+        assert_eq!(header.metadata()["ss_network_dim"], "16");
+        let short = write_file(temp_dir.path(), "b.safetensors", 1000, "{}");
+        let err = read_header(&short, &options).await.unwrap_err();
+        assert!(err.downcast_ref::<Truncated>().is_some(), "{err:#}");
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_open_timeout() -> anyhow::Result<()> {
+        // Opening a FIFO for reading blocks until it has a writer, like an open on a dead mount
+        let temp_dir = tempfile::tempdir()?;
+        let fifo = temp_dir.path().join("hung.safetensors");
+        let c_path = std::ffi::CString::new(fifo.to_str().unwrap())?;
+        // SAFETY: `mkfifo` only reads the NUL-terminated path it is given.
+        assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) }, 0);
+        let options = ReadOptions { open_timeout: Some(Duration::from_millis(50)), ..ReadOptions::default() };
+
+        let err = read_raw_header(&fifo, &options).await.unwrap_err();
+        let unreadable = err.downcast_ref::<Unreadable>().expect("an unreadable file");
+        assert_eq!(unreadable.source.kind(), io::ErrorKind::TimedOut);
+        // Let the abandoned open return, so the runtime can shut down
+        drop(std::fs::OpenOptions::new().write(true).open(&fifo)?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_header_lenient_bom() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let header = "\u{FEFF} {\"__metadata__\":{\"ss_network_dim\":\"16\"}}  ";
+        let path = write_file(temp_dir.path(), "a.safetensors", header.len() as u64, header);
+
+        assert!(read_header(&path, &ReadOptions::default()).await.is_err());
+
+        let header = read_header(&path, &ReadOptions { lenient: true, ..ReadOptions::default() }).await?;
+        assert_eq!(header.metadata()["ss_network_dim"], "16");
+        assert_eq!(
+            header.warnings,
+            vec!["Ignored a byte order mark before the header JSON", "Ignored whitespace before the header JSON"]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fail_on_duplicate_keys() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let header = r#"{"__metadata__":{"ss_network_dim":"16","ss_network_dim":"32","a":[{"b":1,"b":2}]},"t":{"dtype":"F16","shape":[1],"data_offsets":[0,0]}}"#;
+        let path = write_file(temp_dir.path(), "a.safetensors", header.len() as u64, header);
+
+        // Most parsers silently keep the last value
+        let header = read_header(&path, &ReadOptions::default()).await?;
+        assert_eq!(header.metadata()["ss_network_dim"], "32");
+
+        let options = ReadOptions { fail_on_duplicate_keys: true, ..ReadOptions::default() };
+        let err = read_header(&path, &options).await.unwrap_err();
+        assert_eq!(err.to_string(), "Duplicate keys in the header: __metadata__.ss_network_dim, __metadata__.a.0.b");
+
+        let header = r#"{"__metadata__":{"a":"1"},"t":{"dtype":"F16","shape":[1],"data_offsets":[0,0]}}"#;
+        let path = write_file(temp_dir.path(), "b.safetensors", header.len() as u64, header);
+        assert!(read_header(&path, &options).await.is_ok());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_report_duplicate_tensors() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let tensor = r#"{"dtype":"F16","shape":[1],"data_offsets":[0,2]}"#;
+        let header = format!(
+            r#"{{"__metadata__":{{"a.b":"1","a.b":"2"}},"lora.up.weight":{tensor},"x":{tensor},"lora.up.weight":{tensor}}}"#
+        );
+        let path = write_file(temp_dir.path(), "a.safetensors", header.len() as u64, &header);
+
+        assert!(read_header(&path, &ReadOptions::default()).await?.warnings.is_empty());
+        let options = ReadOptions { report_duplicate_tensors: true, ..ReadOptions::default() };
+        let header = read_header(&path, &options).await?;
+        // Duplicated metadata keys are left to --fail-on-duplicate-keys
+        assert_eq!(header.warnings, ["Tensors named more than once, of which only the last is read: lora.up.weight"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_max_metadata_keys() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let metadata: serde_json::Map<String, Value> = (0..1001).map(|i| (format!("key_{i}"), Value::from("x"))).collect();
+        let header = serde_json::json!({"__metadata__": metadata, "t": {"dtype": "F16", "shape": [1], "data_offsets": [0, 0]}});
+        let header = header.to_string();
+        let path = write_file(temp_dir.path(), "a.safetensors", header.len() as u64, &header);
+
+        let options = |max| ReadOptions { max_metadata_keys: Some(max), ..ReadOptions::default() };
+        let err = read_header(&path, &options(1000)).await.unwrap_err();
+        assert!(format!("{err:#}").contains("__metadata__ has more than the --max-metadata-keys limit of 1000 keys"));
+        let parsed = read_header(&path, &options(1001)).await?;
+        assert_eq!(parsed.json, serde_json::from_str::<Value>(&header)?);
+        // Only the metadata is limited
+        assert!(read_header(&path, &options(1)).await.is_err());
+        let nested = r#"{"__metadata__":{"a":{"b":1,"c":[1.5,null,true]}},"x":{"y":1,"z":2}}"#;
+        let path = write_file(temp_dir.path(), "b.safetensors", nested.len() as u64, nested);
+        assert_eq!(read_header(&path, &options(1)).await?.json, serde_json::from_str::<Value>(nested)?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_header_endian() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let header = r#"{"__metadata__":{"ss_network_dim":"16"}}"#;
+        let little = write_file(temp_dir.path(), "le.safetensors", header.len() as u64, header);
+        let big = write_file(temp_dir.path(), "be.safetensors", (header.len() as u64).swap_bytes(), header);
+        let options = |endian| ReadOptions { endian, ..ReadOptions::default() };
+
+        assert!(read_header(&little, &options(Endian::Le)).await.is_ok());
+        assert!(read_header(&little, &options(Endian::Be)).await.is_err());
+        assert!(read_header(&big, &options(Endian::Le)).await.is_err());
+        assert!(read_header(&big, &options(Endian::Be)).await.is_ok());
+
+        let header = read_header(&little, &options(Endian::Auto)).await?;
+        assert_eq!(header.endian, Endian::Le);
+        let header = read_header(&big, &options(Endian::Auto)).await?;
+        assert_eq!(header.endian, Endian::Be);
+        assert_eq!(header.metadata()["ss_network_dim"], "16");
+
+        // Neither reading fits, so the little-endian error is reported
+        let neither = write_file(temp_dir.path(), "bad.safetensors", 1000, "{}");
+        let err = read_header(&neither, &options(Endian::Auto)).await.unwrap_err();
+        assert!(err.to_string().contains("Header length 1000 exceeds the file size"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_parameter_count_exceeds_u32() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let header = r#"{"__metadata__":{},"a":{"dtype":"F16","shape":[65536,65536,2],"data_offsets":[0,0]},"b":{"dtype":"F16","shape":[3],"data_offsets":[0,0]},"c":{"dtype":"F16","shape":[],"data_offsets":[0,0]}}"#;
+        let path = write_file(temp_dir.path(), "a.safetensors", header.len() as u64, header);
+
+        let header = read_header(&path, &ReadOptions::default()).await?;
+        let count = header.parameter_count(|_| true);
+        assert!(count > u128::from(u32::MAX));
+        assert_eq!(count, 65536 * 65536 * 2 + 3 + 1);
+        assert_eq!(header.parameter_count(|name| name != "a"), 3 + 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,180 @@
+use crate::cli::HistogramMode;
+use crate::table;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Shown for the files that don't have the key.
+const NOT_SET: &str = "(not set)";
+
+/// Shown for the values of a bucketed key that aren't numbers.
+const NOT_A_NUMBER: &str = "(not a number)";
+
+/// How many ranges `--histogram-mode numeric` buckets the values into without `--buckets`.
+const DEFAULT_BUCKETS: usize = 10;
+
+/// Counts the values that metadata keys take across the scanned files, for `--histogram`:
+/// by value, most frequent first, or by numeric range.
+#[derive(Debug)]
+pub struct Histogram {
+    keys: Vec<String>,
+    mode: HistogramMode,
+    /// `--buckets`.
+    buckets: Option<usize>,
+    /// For each key, how many files have each value, which is `None` for those without it.
+    counts: Mutex<Vec<HashMap<Option<String>, usize>>>,
+}
+
+impl Histogram {
+    pub fn new(keys: Vec<String>, mode: HistogramMode, buckets: Option<usize>) -> Self {
+        let counts = Mutex::new(vec![HashMap::new(); keys.len()]);
+        Self { keys, mode, buckets, counts }
+    }
+
+    /// Count the values of one file's metadata. Strings are counted as they are, other values
+    /// as compact JSON.
+    pub fn add(&self, metadata: &Value) {
+        let mut counts = self.counts.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        for (key, counts) in self.keys.iter().zip(counts.iter_mut()) {
+            let value = metadata.get(key).map(|value| match value {
+                Value::String(s) => s.clone(),
+                value => value.to_string(),
+            });
+            *counts.entry(value).or_default() += 1;
+        }
+    }
+
+    /// A table per key of its values or ranges, with the number and share of the files in
+    /// each.
+    pub fn report(&self) -> String {
+        let counts = self.counts.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        let sections: Vec<String> = self
+            .keys
+            .iter()
+            .zip(counts.iter())
+            .map(|(key, counts)| {
+                let files = counts.values().sum::<usize>();
+                let bucketed = match self.mode {
+                    HistogramMode::Categorical => None,
+                    HistogramMode::Numeric => Some(self.buckets.unwrap_or(DEFAULT_BUCKETS)),
+                    HistogramMode::Auto => self.buckets.filter(|_| {
+                        counts.keys().flatten().all(|value| value.trim().parse::<f64>().is_ok_and(f64::is_finite))
+                    }),
+                };
+                let rows = match bucketed {
+                    Some(buckets) => bucket(counts, buckets),
+                    None => by_value(counts),
+                };
+                let rows: Vec<Vec<String>> = rows
+                    .into_iter()
+                    .map(|(label, count)| vec![count.to_string(), percent(count, files), label])
+                    .collect();
+                table::render(&["files".to_string(), "share".to_string(), key.clone()], &rows)
+            })
+            .collect();
+        sections.join("\n")
+    }
+}
+
+/// The values and their counts, most frequent first, the files without the key last of
+/// those as frequent.
+fn by_value(counts: &HashMap<Option<String>, usize>) -> Vec<(String, usize)> {
+    let mut values: Vec<(&Option<String>, &usize)> = counts.iter().collect();
+    values.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.is_none().cmp(&b.0.is_none())).then_with(|| a.0.cmp(b.0)));
+    values.into_iter().map(|(value, count)| (value.clone().unwrap_or_else(|| NOT_SET.to_string()), *count)).collect()
+}
+
+/// The counts of `buckets` ranges of equal width from the smallest value to the largest, in
+/// order, followed by the values that aren't numbers and the files without the key, if any.
+/// Each range includes its start, the last one its end too.
+fn bucket(counts: &HashMap<Option<String>, usize>, buckets: usize) -> Vec<(String, usize)> {
+    let (mut not_set, mut not_a_number) = (0, 0);
+    let mut numbers = Vec::new();
+    for (value, count) in counts {
+        match value.as_deref().map(|value| value.trim().parse::<f64>()) {
+            None => not_set += count,
+            Some(Ok(number)) if number.is_finite() => numbers.push((number, *count)),
+            Some(_) => not_a_number += count,
+        }
+    }
+    let mut rows = Vec::new();
+    let min = numbers.iter().map(|(number, _)| *number).fold(f64::INFINITY, f64::min);
+    let max = numbers.iter().map(|(number, _)| *number).fold(f64::NEG_INFINITY, f64::max);
+    if max > min {
+        #[allow(clippy::cast_precision_loss)] // a handful of buckets
+        let width = (max - min) / buckets as f64;
+        let mut counts = vec![0; buckets];
+        for (number, count) in numbers {
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)] // within 0..buckets
+            let i = (((number - min) / width) as usize).min(buckets - 1);
+            counts[i] += count;
+        }
+        #[allow(clippy::cast_precision_loss)]
+        let bound = |i: usize| if i == buckets { max } else { round(min + width * i as f64) };
+        for (i, count) in counts.into_iter().enumerate() {
+            let close = if i + 1 == buckets { ']' } else { ')' };
+            rows.push((format!("[{}, {}{close}", bound(i), bound(i + 1)), count));
+        }
+    } else if !numbers.is_empty() {
+        // All the same
+        rows.push((format!("[{min}, {max}]"), numbers.iter().map(|(_, count)| count).sum()));
+    }
+    if not_a_number > 0 {
+        rows.push((NOT_A_NUMBER.to_string(), not_a_number));
+    }
+    if not_set > 0 {
+        rows.push((NOT_SET.to_string(), not_set));
+    }
+    rows
+}
+
+/// A range bound without the noise of float arithmetic, such as `0.3` for
+/// `0.30000000000000004`.
+fn round(bound: f64) -> f64 {
+    let rounded = (bound * 1e6).round() / 1e6;
+    if rounded == 0.0 { 0.0 } else { rounded }
+}
+
+/// `count` as a share of `total`, such as `12.5%`.
+fn percent(count: usize, total: usize) -> String {
+    #[allow(clippy::cast_precision_loss)]
+    let share = count as f64 * 100.0 / total.max(1) as f64;
+    format!("{share:.1}%")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_histogram_report() {
+        let add_all = |histogram: &Histogram| {
+            for dim in ["4", "8", "8", "16", "32", "32", "32"] {
+                histogram.add(&json!({"ss_network_dim": dim}));
+            }
+            histogram.add(&json!({}));
+        };
+        let histogram = Histogram::new(vec!["ss_network_dim".to_string()], HistogramMode::Auto, None);
+        add_all(&histogram);
+        assert_eq!(
+            histogram.report(),
+            "files  share  ss_network_dim\n\
+             3      37.5%  32\n\
+             2      25.0%  8\n\
+             1      12.5%  16\n\
+             1      12.5%  4\n\
+             1      12.5%  (not set)\n"
+        );
+
+        let histogram = Histogram::new(vec!["ss_network_dim".to_string()], HistogramMode::Auto, Some(2));
+        add_all(&histogram);
+        assert_eq!(
+            histogram.report(),
+            "files  share  ss_network_dim\n\
+             4      50.0%  [4, 18)\n\
+             3      37.5%  [18, 32]\n\
+             1      12.5%  (not set)\n"
+        );
+    }
+}
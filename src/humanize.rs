@@ -0,0 +1,95 @@
+/// `6738415616` as `6,738,415,616`.
+pub fn thousands(n: u128) -> String {
+    let digits = n.to_string();
+    let mut out = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            out.push(',');
+        }
+        out.push(digit);
+    }
+    out
+}
+
+/// A count with a decimal suffix, such as `6.74B` for a parameter count; below a thousand
+/// the number itself.
+pub fn count(n: u128) -> String {
+    scaled(n, 1000.0, &["", "K", "M", "B", "T"], "")
+}
+
+/// A size in bytes with a binary unit, such as `1.50 GiB`.
+pub fn bytes(n: u64) -> String {
+    scaled(u128::from(n), 1024.0, &[" B", " KiB", " MiB", " GiB", " TiB", " PiB"], " B")
+}
+
+/// Parse a count written as [`count`] shows it, such as `7B`, `350M` or `1.5K`, or as a plain
+/// number. The suffixes are decimal and ignore case; the count must come out whole.
+pub fn parse_count(text: &str) -> Result<u128, String> {
+    let text = text.trim();
+    let split = text.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(text.len());
+    let (number, suffix) = text.split_at(split);
+    let multiplier: u128 = match suffix.to_ascii_uppercase().as_str() {
+        "" => 1,
+        "K" => 1_000,
+        "M" => 1_000_000,
+        "B" => 1_000_000_000,
+        "T" => 1_000_000_000_000,
+        _ => return Err(format!("unknown suffix `{suffix}` in `{text}`, expected K, M, B or T")),
+    };
+    let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+    if whole.is_empty() && fraction.is_empty() {
+        return Err(format!("expected a count such as 7B, got `{text}`"));
+    }
+    let parse = |digits: &str| -> Result<u128, String> {
+        if digits.is_empty() { Ok(0) } else { digits.parse().map_err(|_| format!("invalid count `{text}`")) }
+    };
+    let too_large = || format!("`{text}` is too large");
+    let scale = u32::try_from(fraction.len()).ok().and_then(|len| 10u128.checked_pow(len)).ok_or_else(too_large)?;
+    let fraction = parse(fraction)?.checked_mul(multiplier).ok_or_else(too_large)?;
+    if !fraction.is_multiple_of(scale) {
+        return Err(format!("`{text}` isn't a whole number"));
+    }
+    parse(whole)?
+        .checked_mul(multiplier)
+        .and_then(|whole| whole.checked_add(fraction / scale))
+        .ok_or_else(too_large)
+}
+
+#[allow(clippy::cast_precision_loss)] // two decimals are all that is shown
+fn scaled(n: u128, base: f64, units: &[&str], unscaled_unit: &str) -> String {
+    let mut value = n as f64;
+    let mut unit = 0;
+    while value >= base && unit + 1 < units.len() {
+        value /= base;
+        unit += 1;
+    }
+    if unit == 0 { format!("{n}{unscaled_unit}") } else { format!("{value:.2}{}", units[unit]) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_humanize() {
+        assert_eq!(thousands(0), "0");
+        assert_eq!(thousands(999), "999");
+        assert_eq!(thousands(6_738_415_616), "6,738,415,616");
+        assert_eq!(count(950), "950");
+        assert_eq!(count(6_738_415_616), "6.74B");
+        assert_eq!(count(1_500), "1.50K");
+        assert_eq!(bytes(512), "512 B");
+        assert_eq!(bytes(3 << 29), "1.50 GiB");
+    }
+
+    #[test]
+    fn test_parse_count() {
+        assert_eq!(parse_count("7B"), Ok(7_000_000_000));
+        assert_eq!(parse_count("350m"), Ok(350_000_000));
+        assert_eq!(parse_count("1.5K"), Ok(1_500));
+        assert_eq!(parse_count("6738415616"), Ok(6_738_415_616));
+        assert!(parse_count("1.5").is_err());
+        assert!(parse_count("7G").is_err());
+        assert!(parse_count("B").is_err());
+    }
+}
@@ -0,0 +1,133 @@
+use crate::output::LineEnding;
+use crate::record::Record;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+/// What a file looked like when its record was made. A file with another size or
+/// modification time is parsed again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct Fingerprint {
+    size: u64,
+    modified_ns: u64,
+}
+
+impl Fingerprint {
+    fn of(stat: &std::fs::Metadata) -> Option<Self> {
+        let modified = stat.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        Some(Self { size: stat.len(), modified_ns: u64::try_from(modified.as_nanos()).ok()? })
+    }
+}
+
+/// A file of the state, with the record it had.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    #[serde(flatten)]
+    fingerprint: Fingerprint,
+    record: Value,
+}
+
+/// The records of a collection with what each file looked like, for `--incremental`. A run
+/// parses only the files that are new or changed since the last, carries the records of the
+/// others over, and writes the state again without the files it didn't find.
+#[derive(Debug)]
+pub struct State {
+    path: PathBuf,
+    line_ending: LineEnding,
+    /// The files of the last run.
+    previous: BTreeMap<String, Entry>,
+    /// The files of this run, by path.
+    current: Mutex<BTreeMap<String, Entry>>,
+}
+
+impl State {
+    /// Load the state at `path`, which may not exist yet, as on the first run.
+    pub fn load(path: &Path, line_ending: LineEnding) -> anyhow::Result<Self> {
+        let previous = match crate::io::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text)
+                .with_context(|| format!("{} isn't the state of an --incremental run", path.display()))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        Ok(Self { path: path.to_path_buf(), line_ending, previous, current: Mutex::default() })
+    }
+
+    /// Whether `path` is the same as in the last run, in which case its record is carried over.
+    pub async fn unchanged(&self, path: &Path) -> bool {
+        let key = path.to_string_lossy();
+        let Some(entry) = self.previous.get(key.as_ref()) else {
+            return false;
+        };
+        let Ok(stat) = tokio::fs::metadata(path).await else {
+            return false;
+        };
+        if Fingerprint::of(&stat) != Some(entry.fingerprint) {
+            return false;
+        }
+        self.current.lock().unwrap_or_else(std::sync::PoisonError::into_inner).insert(key.into_owned(), entry.clone());
+        true
+    }
+
+    /// Add the new record of the file at `path`. A record that isn't of a file, such as that of
+    /// an archive member or a sharded model, has nothing to compare and is parsed every run.
+    pub fn add(&self, path: &Path, record: &Record) -> anyhow::Result<()> {
+        let Some(fingerprint) = std::fs::metadata(path).ok().as_ref().and_then(Fingerprint::of) else {
+            return Ok(());
+        };
+        let entry = Entry { fingerprint, record: serde_json::to_value(record)? };
+        let key = path.to_string_lossy().into_owned();
+        self.current.lock().unwrap_or_else(std::sync::PoisonError::into_inner).insert(key, entry);
+        Ok(())
+    }
+
+    /// Write the state of this run, with its keys sorted. The files of the last run that
+    /// weren't found again are left out.
+    pub fn write(&self) -> anyhow::Result<()> {
+        let current = self.current.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        let json = self.line_ending.apply(serde_json::to_string_pretty(&*current)?);
+        crate::io::atomic_write(&self.path, json.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_state() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let state_path = temp_dir.path().join("state.json");
+        let (a, b) = (temp_dir.path().join("a.safetensors"), temp_dir.path().join("b.safetensors"));
+        std::fs::write(&a, "a")?;
+        std::fs::write(&b, "b")?;
+        let record = |path: &Path, metadata| Record { path: path.to_path_buf(), metadata, ..Record::default() };
+
+        let state = State::load(&state_path, LineEnding::Lf)?;
+        assert!(!state.unchanged(&a).await);
+        state.add(&a, &record(&a, json!({"v": 1})))?;
+        state.add(&b, &record(&b, json!({"v": 1})))?;
+        state.write()?;
+
+        // a is the same, b has changed and is gone from the state until it is added again
+        std::fs::write(&b, "bb")?;
+        let state = State::load(&state_path, LineEnding::Lf)?;
+        assert!(state.unchanged(&a).await);
+        assert!(!state.unchanged(&b).await);
+        state.write()?;
+        let written: Value = serde_json::from_str(&std::fs::read_to_string(&state_path)?)?;
+        let written = written.as_object().unwrap();
+        assert_eq!(written.keys().collect::<Vec<_>>(), [&a.to_string_lossy()]);
+        assert_eq!(written[a.to_string_lossy().as_ref()]["record"]["metadata"], json!({"v": 1}));
+        assert_eq!(written[a.to_string_lossy().as_ref()]["size"], 1);
+
+        std::fs::write(&state_path, "[]")?;
+        assert!(State::load(&state_path, LineEnding::Lf).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,145 @@
+use crate::Scan;
+use crate::distinct::Distinct;
+use crate::filter::{Condition, parse_where_arg};
+use crate::header;
+use crate::summary::Outcome;
+use futures::StreamExt;
+use rustyline::error::ReadlineError;
+use serde_json::Value;
+use std::fmt::Write;
+use std::path::PathBuf;
+
+const HELP: &str = "\
+where KEY[=VALUE]...  list the files whose metadata satisfies every condition, as --where does
+distinct KEY          count the files with each value of KEY, as --distinct does
+show PATH             print the metadata of a file, given by its path or the end of it
+files                 list the loaded files
+help                  print this help
+quit                  leave, as do Ctrl-C and Ctrl-D
+";
+
+/// The metadata of the scanned files, queried by the lines typed at the prompt.
+#[derive(Debug)]
+struct Session {
+    files: Vec<(PathBuf, Value)>,
+}
+
+impl Session {
+    /// Run one command and return what to print, or an error message for a bad command.
+    fn execute(&self, line: &str) -> Result<String, String> {
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else { return Ok(String::new()) };
+        let args: Vec<&str> = words.collect();
+        match (command, args.as_slice()) {
+            ("where", conditions) => {
+                let conditions: Vec<Condition> = conditions.iter().map(|arg| parse_where_arg(arg)).collect::<Result<_, _>>()?;
+                let matching: Vec<&PathBuf> = self
+                    .files
+                    .iter()
+                    .filter(|(_, metadata)| conditions.iter().all(|condition| condition.matches(metadata)))
+                    .map(|(path, _)| path)
+                    .collect();
+                let mut out = String::new();
+                for path in &matching {
+                    let _ = writeln!(out, "{}", path.display());
+                }
+                let _ = writeln!(out, "{} of {} files", matching.len(), self.files.len());
+                Ok(out)
+            }
+            ("distinct", [key]) => {
+                let distinct = Distinct::new(vec![(*key).to_string()]);
+                for (_, metadata) in &self.files {
+                    distinct.add(metadata);
+                }
+                Ok(distinct.report())
+            }
+            ("show", [path]) => {
+                let mut found = self.files.iter().filter(|(file, _)| file.ends_with(path) || file.to_string_lossy() == *path);
+                match (found.next(), found.next()) {
+                    (Some((_, metadata)), None) => Ok(format!("{}\n", serde_json::to_string_pretty(metadata).unwrap_or_default())),
+                    (None, _) => Err(format!("No file matches {path}")),
+                    (Some(_), Some(_)) => Err(format!("More than one file matches {path}")),
+                }
+            }
+            ("files", []) => Ok(self.files.iter().fold(String::new(), |mut out, (path, _)| {
+                let _ = writeln!(out, "{}", path.display());
+                out
+            })),
+            ("help", []) => Ok(HELP.to_string()),
+            ("distinct" | "show" | "files" | "help", _) => Err(format!("Wrong arguments for {command}, see help")),
+            _ => Err(format!("Unknown command {command}, see help")),
+        }
+    }
+}
+
+/// Read the metadata of every file, then answer queries typed at a prompt until `quit`.
+///
+/// The queries reuse the `--where` and `--distinct` semantics on the metadata as extracted.
+/// Files that fail to read are reported and left out.
+pub async fn run(scan: &Scan, files: Vec<PathBuf>) -> anyhow::Result<()> {
+    let mut loaded: Vec<(PathBuf, Value)> = futures::stream::iter(files)
+        .map(|path| async move {
+            let _permit = scan.open_files.acquire().await.ok()?;
+            match header::read_header(&path, &scan.read_options).await {
+                Ok(header) => {
+                    scan.summary.record(Outcome::Processed);
+                    Some((path, header.metadata()))
+                }
+                Err(e) => {
+                    scan.summary.record(Outcome::Failed);
+                    scan.warn_failed(&path, &e);
+                    None
+                }
+            }
+        })
+        .buffer_unordered(scan.open_files.available_permits().max(1))
+        .filter_map(std::future::ready)
+        .collect()
+        .await;
+    loaded.sort_by(|a, b| a.0.cmp(&b.0));
+    let session = Session { files: loaded };
+
+    println!("Loaded {} files; type help for the commands", session.files.len());
+    let mut editor = rustyline::DefaultEditor::new()?;
+    loop {
+        let line = match editor.readline("> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted | ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+        let line = line.trim();
+        if matches!(line, "quit" | "exit") {
+            break;
+        }
+        let _ = editor.add_history_entry(line);
+        match session.execute(line) {
+            Ok(out) => print!("{out}"),
+            Err(message) => eprintln!("{message}"),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_session() {
+        let session = Session {
+            files: vec![
+                (PathBuf::from("/models/a.safetensors"), json!({"ss_network_dim": "128", "arch": "sdxl"})),
+                (PathBuf::from("/models/b.safetensors"), json!({"ss_network_dim": "16", "arch": "sdxl"})),
+            ],
+        };
+
+        assert_eq!(session.execute("where ss_network_dim=128"), Ok("/models/a.safetensors\n1 of 2 files\n".to_string()));
+        assert_eq!(session.execute("distinct arch"), Ok("files  arch\n2      sdxl\n".to_string()));
+        assert!(session.execute("show b.safetensors").unwrap().contains("\"16\""));
+        assert_eq!(session.execute("show c.safetensors"), Err("No file matches c.safetensors".to_string()));
+        assert_eq!(session.execute("distinct"), Err("Wrong arguments for distinct, see help".to_string()));
+        assert_eq!(session.execute("drop"), Err("Unknown command drop, see help".to_string()));
+        assert_eq!(session.execute("  "), Ok(String::new()));
+    }
+}
@@ -0,0 +1,167 @@
+use anyhow::Context;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, Write};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Tells apart the temporary files of writes to the same path from one process.
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// The UTF-8 byte order mark, which some editors put at the start of the JSON files they save.
+/// Nothing the scan writes starts with one.
+const BOM: &str = "\u{feff}";
+
+/// `text` without a leading byte order mark.
+pub fn strip_bom(text: &str) -> &str {
+    text.strip_prefix(BOM).unwrap_or(text)
+}
+
+/// [`std::fs::read_to_string`] without a leading byte order mark, for the files the scan reads
+/// back, such as a `--merge-into` catalog, which may have been saved by an editor since.
+pub fn read_to_string(path: &Path) -> io::Result<String> {
+    let text = std::fs::read_to_string(path)?;
+    Ok(strip_bom(&text).to_string())
+}
+
+/// Move `reader` past a byte order mark at its start, if it has one.
+pub fn skip_bom(reader: &mut impl BufRead) -> io::Result<()> {
+    if reader.fill_buf()?.starts_with(BOM.as_bytes()) {
+        reader.consume(BOM.len());
+    }
+    Ok(())
+}
+
+/// A file the scan writes next to the models, such as a sidecar, that couldn't be written,
+/// which is a problem with the destination rather than with the file being read.
+#[derive(Debug)]
+pub struct WriteError {
+    path: PathBuf,
+    source: anyhow::Error,
+}
+
+impl WriteError {
+    pub fn new(path: PathBuf, source: anyhow::Error) -> Self {
+        Self { path, source }
+    }
+}
+
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Failed to write {}", self.path.display())
+    }
+}
+
+impl std::error::Error for WriteError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.source.as_ref())
+    }
+}
+
+/// Write `contents` to `path` atomically, as [`atomic_write_with`] does.
+pub fn atomic_write(path: &Path, contents: &[u8]) -> anyhow::Result<()> {
+    atomic_write_with(path, |file| Ok(file.write_all(contents)?))
+}
+
+/// [`atomic_write`] on tokio's blocking pool.
+pub async fn atomic_write_async(path: PathBuf, contents: Vec<u8>) -> anyhow::Result<()> {
+    tokio::task::spawn_blocking(move || atomic_write(&path, &contents)).await?
+}
+
+/// Write `path` so that readers see either the old file or all of the new one: `write` fills
+/// a temporary file next to `path`, which is synced to disk and renamed over it. Being in the
+/// same directory, it is on the same filesystem, so the rename never crosses devices. `write`
+/// may read back what it wrote, as the file is open for reading too.
+///
+/// The new file keeps the permissions of the one it replaces, and a file that didn't exist
+/// gets the default ones. On failure the temporary file is removed and `path` is left as it
+/// was. Errors are a [`WriteError`].
+pub fn atomic_write_with(path: &Path, write: impl FnOnce(&mut File) -> anyhow::Result<()>) -> anyhow::Result<()> {
+    let temp = temp_path(path, "tmp")?;
+    let result = (|| {
+        let mut file = OpenOptions::new().read(true).write(true).create_new(true).open(&temp)?;
+        write(&mut file)?;
+        file.sync_all()?;
+        if let Ok(existing) = std::fs::metadata(path) {
+            file.set_permissions(existing.permissions())?;
+        }
+        drop(file);
+        std::fs::rename(&temp, path)?;
+        sync_parent(path);
+        Ok(())
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    result.map_err(|source| anyhow::Error::new(WriteError { path: path.to_path_buf(), source }))
+}
+
+/// A path next to `path` for a temporary file, hidden and unique to this write, such as
+/// `.model.json.1234.0.tmp`.
+pub(crate) fn temp_path(path: &Path, suffix: &str) -> anyhow::Result<PathBuf> {
+    let name = path.file_name().context("Path has no file name")?.to_string_lossy();
+    let count = TEMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+    Ok(path.with_file_name(format!(".{name}.{}.{count}.{suffix}", std::process::id())))
+}
+
+/// Sync the directory of `path`, so the rename itself survives a crash. Best effort: not
+/// every filesystem supports it.
+fn sync_parent(path: &Path) {
+    #[cfg(unix)]
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        let _ = File::open(dir).and_then(|dir| dir.sync_all());
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> =
+            std::fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned()).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_atomic_write() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("a.json");
+        atomic_write(&path, b"first")?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o640))?;
+            atomic_write(&path, b"second")?;
+            assert_eq!(std::fs::metadata(&path)?.permissions().mode() & 0o777, 0o640);
+        }
+
+        // A failed write leaves the old file and no temporary one
+        let err = atomic_write_with(&path, |file| {
+            file.write_all(b"partial")?;
+            anyhow::bail!("interrupted")
+        })
+        .unwrap_err();
+        assert_eq!(format!("{err:#}"), format!("Failed to write {}: interrupted", path.display()));
+        assert!(err.is::<WriteError>());
+        assert_ne!(std::fs::read(&path)?, b"partial");
+        assert_eq!(entries(temp_dir.path()), ["a.json"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_to_string() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("a.json");
+        std::fs::write(&path, "\u{feff}{}")?;
+        assert_eq!(read_to_string(&path)?, "{}");
+        let mut reader = io::Cursor::new(b"\xef\xbb\xbf[1]".to_vec());
+        skip_bom(&mut reader)?;
+        assert_eq!(io::read_to_string(reader)?, "[1]");
+        assert_eq!(strip_bom("{}"), "{}");
+        Ok(())
+    }
+}
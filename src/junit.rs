@@ -0,0 +1,119 @@
+use crate::summary::Outcome;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// What became of a file, as a `JUnit` test case.
+#[derive(Debug)]
+enum CaseResult {
+    Passed,
+    Failed(String),
+    Skipped(&'static str),
+}
+
+/// The outcome of each file of a run, for `--report junit`: a test case per file, named after
+/// it with its directory as the class, that fails with the file's error.
+#[derive(Debug, Default)]
+pub struct Junit {
+    cases: Mutex<Vec<(PathBuf, CaseResult)>>,
+}
+
+impl Junit {
+    /// Add the outcome of a file, as [`crate::report`] gets it.
+    pub fn add(&self, path: &Path, result: &anyhow::Result<Outcome>) {
+        let case = match result {
+            Ok(Outcome::Processed) => CaseResult::Passed,
+            Ok(Outcome::Failed) => CaseResult::Failed("Failed".to_string()),
+            Ok(Outcome::Skipped(reason)) => CaseResult::Skipped(reason.label()),
+            Err(e) => CaseResult::Failed(format!("{e:#}")),
+        };
+        self.cases.lock().unwrap_or_else(std::sync::PoisonError::into_inner).push((path.to_path_buf(), case));
+    }
+
+    /// The `JUnit` XML report of a run that took `elapsed`, with the test cases sorted by path
+    /// so that the same files always give the same report.
+    pub fn render(&self, elapsed: Duration) -> String {
+        let mut cases = self.cases.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        cases.sort_by(|a, b| a.0.cmp(&b.0));
+        let failures = cases.iter().filter(|(_, case)| matches!(case, CaseResult::Failed(_))).count();
+        let skipped = cases.iter().filter(|(_, case)| matches!(case, CaseResult::Skipped(_))).count();
+        let counts = format!(
+            r#"tests="{}" failures="{failures}" errors="0" skipped="{skipped}" time="{:.3}""#,
+            cases.len(),
+            elapsed.as_secs_f64()
+        );
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        let _ = writeln!(xml, "<testsuites {counts}>");
+        let _ = writeln!(xml, r#"  <testsuite name="extract-metadata" {counts}>"#);
+        for (path, case) in cases.iter() {
+            let name = escape(&path.file_name().unwrap_or_default().to_string_lossy());
+            let class = escape(&path.parent().unwrap_or(Path::new("")).to_string_lossy());
+            let testcase = format!(r#"    <testcase name="{name}" classname="{class}""#);
+            let _ = match case {
+                CaseResult::Passed => writeln!(xml, "{testcase}/>"),
+                CaseResult::Failed(message) => {
+                    let message = escape(message);
+                    writeln!(xml, "{testcase}>\n      <failure message=\"{message}\">{message}</failure>\n    </testcase>")
+                }
+                CaseResult::Skipped(reason) => {
+                    writeln!(xml, "{testcase}>\n      <skipped message=\"{reason}\"/>\n    </testcase>")
+                }
+            };
+        }
+        xml.push_str("  </testsuite>\n</testsuites>\n");
+        xml
+    }
+}
+
+/// `text` as XML attribute or element content. Control characters, which XML 1.0 can't
+/// contain even escaped, become U+FFFD.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\n' => escaped.push_str("&#10;"),
+            '\t' | '\r' => escaped.push(c),
+            c if c.is_control() => escaped.push('\u{FFFD}'),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::summary::SkipReason;
+
+    #[test]
+    fn test_render() {
+        let junit = Junit::default();
+        junit.add(Path::new("/models/b.safetensors"), &Err(anyhow::anyhow!("Header \"x\" is <bad> & short\0")));
+        junit.add(Path::new("/models/a.safetensors"), &Ok(Outcome::Processed));
+        junit.add(Path::new("/models/c.safetensors"), &Ok(Outcome::Skipped(SkipReason::Filter)));
+        assert_eq!(
+            junit.render(Duration::from_millis(1500)),
+            concat!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+                "<testsuites tests=\"3\" failures=\"1\" errors=\"0\" skipped=\"1\" time=\"1.500\">\n",
+                "  <testsuite name=\"extract-metadata\" tests=\"3\" failures=\"1\" errors=\"0\" skipped=\"1\" time=\"1.500\">\n",
+                "    <testcase name=\"a.safetensors\" classname=\"/models\"/>\n",
+                "    <testcase name=\"b.safetensors\" classname=\"/models\">\n",
+                "      <failure message=\"Header &quot;x&quot; is &lt;bad&gt; &amp; short\u{FFFD}\">",
+                "Header &quot;x&quot; is &lt;bad&gt; &amp; short\u{FFFD}</failure>\n",
+                "    </testcase>\n",
+                "    <testcase name=\"c.safetensors\" classname=\"/models\">\n",
+                "      <skipped message=\"filter\"/>\n",
+                "    </testcase>\n",
+                "  </testsuite>\n",
+                "</testsuites>\n",
+            )
+        );
+    }
+}
@@ -0,0 +1,106 @@
+use crate::Scan;
+use crate::header;
+use crate::summary::Outcome;
+use crate::table;
+use futures::StreamExt;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// The JSON type of a metadata value, as named in the report.
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// How many files hold each type of value under each key.
+#[derive(Debug, Default)]
+struct KeyTypes {
+    files: usize,
+    types: BTreeMap<String, BTreeMap<&'static str, usize>>,
+}
+
+impl KeyTypes {
+    fn add(&mut self, metadata: &Value) {
+        self.files += 1;
+        for (key, value) in metadata.as_object().into_iter().flatten() {
+            *self.types.entry(key.clone()).or_default().entry(type_name(value)).or_default() += 1;
+        }
+    }
+
+    /// One row per key, such as `ss_steps | number: 3, string: 1, absent: 2 | yes`. A key
+    /// is inconsistent if it holds more than one type or is missing from some files.
+    fn rows(&self) -> Vec<Vec<String>> {
+        self.types
+            .iter()
+            .map(|(key, types)| {
+                let present: usize = types.values().sum();
+                let mut counts: Vec<String> = types.iter().map(|(name, count)| format!("{name}: {count}")).collect();
+                if present < self.files {
+                    counts.push(format!("absent: {}", self.files - present));
+                }
+                let inconsistent = if counts.len() > 1 { "yes" } else { "no" };
+                vec![key.clone(), counts.join(", "), inconsistent.to_string()]
+            })
+            .collect()
+    }
+}
+
+/// Print each metadata key found in any of the files with the types of value it holds and the
+/// number of files holding each, to find keys used inconsistently before writing a schema.
+///
+/// The types are those of the metadata as extracted, after JSON in strings is decoded. Files
+/// that fail to read are reported and left out.
+pub async fn run(scan: &Scan, files: Vec<PathBuf>) -> anyhow::Result<()> {
+    let metadata: Vec<Value> = futures::stream::iter(files)
+        .map(|path| async move {
+            let _permit = scan.open_files.acquire().await.ok()?;
+            match header::read_header(&path, &scan.read_options).await {
+                Ok(header) => {
+                    scan.summary.record(Outcome::Processed);
+                    Some(header.metadata())
+                }
+                Err(e) => {
+                    scan.summary.record(Outcome::Failed);
+                    scan.warn_failed(&path, &e);
+                    None
+                }
+            }
+        })
+        .buffer_unordered(scan.open_files.available_permits().max(1))
+        .filter_map(std::future::ready)
+        .collect()
+        .await;
+
+    let mut key_types = KeyTypes::default();
+    for metadata in &metadata {
+        key_types.add(metadata);
+    }
+    let headers = ["key", "types", "inconsistent"].map(String::from);
+    scan.output.write_text(&table::render(&headers, &key_types.rows()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_key_types() {
+        let mut key_types = KeyTypes::default();
+        key_types.add(&json!({"ss_steps": 1000, "title": "a"}));
+        key_types.add(&json!({"ss_steps": "1000", "title": "b"}));
+        key_types.add(&json!({"title": "c"}));
+
+        let rows = key_types.rows();
+        assert_eq!(rows[0], ["ss_steps", "number: 1, string: 1, absent: 1", "yes"]);
+        assert_eq!(rows[1], ["title", "string: 3", "no"]);
+    }
+}
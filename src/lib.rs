@@ -0,0 +1,3292 @@
+//! Extract the training metadata of safetensors files. The `extract-metadata` binary is a thin
+//! wrapper over [`cli_main`]; [`scan`] runs the same scan from Rust and returns the records.
+#![warn(clippy::all, clippy::pedantic)]
+
+mod architectures;
+mod archive;
+mod autotune;
+mod canonical;
+mod catalog;
+mod classify;
+mod collisions;
+mod columnar;
+pub mod cli;
+mod disks;
+mod encoding;
+mod distinct;
+mod error_sample;
+mod expression;
+mod drift;
+mod duplicates;
+mod extension_counts;
+mod extractor;
+mod filename;
+mod filter;
+mod formats;
+mod groups;
+mod hash;
+mod header;
+mod histogram;
+mod humanize;
+mod incremental;
+mod inspect;
+mod io;
+mod junit;
+mod key_report;
+mod manifest;
+mod matrix;
+mod memory;
+mod merge_files;
+mod nan_inf;
+mod normalize;
+mod output;
+mod peek;
+mod percentiles;
+mod pipeline;
+mod prefetch;
+mod probe;
+mod producer;
+mod progress;
+mod readahead;
+mod repair;
+mod rewrite;
+pub mod record;
+mod shards;
+mod sidecar;
+mod signing;
+mod size_report;
+mod spec;
+mod stamp;
+mod stdin;
+mod strip;
+mod summary;
+mod tensor_names;
+mod table;
+mod tag_frequencies;
+mod template;
+mod thumbnails;
+mod trace;
+#[cfg(test)]
+mod test_util;
+mod walk;
+
+use anyhow::Context;
+use clap::Parser;
+use cli::{Args, Command, ParameterSource};
+use formats::Format;
+use glob::glob;
+use output::Output;
+use record::Record;
+use summary::{Outcome, SkipReason, Summary};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::env;
+use std::num::NonZeroUsize;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use futures::{Stream, StreamExt};
+use tokio::sync::{Semaphore, mpsc};
+
+/// Normalize a path by converting it to absolute and cleaning up any . or .. components.
+/// Relative paths are resolved against `base`.
+fn normalize_path(path: &Path, base: &Path) -> anyhow::Result<PathBuf> {
+    // First convert to absolute path if needed
+    let abs_path = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        base.join(path)
+    };
+    
+    // Try to canonicalize first (this handles symlinks too)
+    match std::fs::canonicalize(&abs_path) {
+        Ok(canonical) => Ok(canonical),
+        Err(e) => {
+            // If canonicalization fails, try to clean up the path manually
+            let mut components = Vec::new();
+            let mut had_error = false;
+            
+            for component in abs_path.components() {
+                match component {
+                    std::path::Component::Prefix(p) => components.push(std::path::Component::Prefix(p)),
+                    std::path::Component::RootDir => components.push(std::path::Component::RootDir),
+                    std::path::Component::Normal(x) => components.push(std::path::Component::Normal(x)),
+                    std::path::Component::CurDir => (), // skip
+                    std::path::Component::ParentDir => {
+                        if components.len() <= 1 {
+                            // Can't go up from root
+                            had_error = true;
+                            break;
+                        }
+                        // Only pop if we have something to pop
+                        if !components.is_empty() {
+                            components.pop();
+                        }
+                    }
+                }
+            }
+            
+            if had_error {
+                // If we had an error in manual cleanup, return the original error
+                Err(e.into())
+            } else {
+                Ok(components.iter().collect())
+            }
+        }
+    }
+}
+
+/// State shared by the per-file tasks of a run.
+struct Scan {
+    args: Args,
+    read_options: header::ReadOptions,
+    output: Output,
+    /// One permit per file a task may hold open, so large scans stay below the descriptor limit.
+    open_files: Arc<Semaphore>,
+    summary: Summary,
+    /// Set by `--normalize-metadata`.
+    normalizer: Option<normalize::Normalizer>,
+    /// Paths that already have a record in the output file (`--dedupe-paths`).
+    existing_paths: HashSet<PathBuf>,
+    /// The sidecars and `--per-file-out` files written, for `--on-duplicate-output-path`.
+    output_paths: collisions::OutputPaths,
+    /// The `--input-list` entry each file was found through, for `--with-source`.
+    sources: Mutex<HashMap<PathBuf, String>>,
+    /// Set by `--group-output-by-dir`, which collects the records instead of printing them.
+    manifests: Option<manifest::Manifests>,
+    distinct: Option<distinct::Distinct>,
+    histogram: Option<histogram::Histogram>,
+    /// Set by `--percentiles`, which is printed with the summary.
+    percentiles: Option<percentiles::Percentiles>,
+    /// Set by `--aggregate-tag-frequencies`.
+    tag_frequencies: Option<tag_frequencies::TagFrequencies>,
+    drift: Option<drift::Drift>,
+    /// Set by `--metadata-size-report`, which collects the sizes instead of printing the records.
+    size_report: Option<size_report::SizeReport>,
+    /// Set by `--by-architecture`.
+    architectures: Option<architectures::Architectures>,
+    /// Set by `--count-distinct-files-by-hash`.
+    duplicates: Option<duplicates::Duplicates>,
+    /// Set by `--dedupe-by`.
+    by_metadata: Option<duplicates::ByMetadata>,
+    /// Set by `--report junit`.
+    junit: Option<junit::Junit>,
+    /// The directory paths are written relative to, with `--manifest-relative-paths`.
+    relative_to: Option<PathBuf>,
+    /// The directories of the inputs, in their order, that `relpath` is relative to with
+    /// `--with-relpath`.
+    relpath_roots: Mutex<Vec<PathBuf>>,
+    /// The directory `{relpath}` of `--per-file-out` is relative to.
+    per_file_base: Option<PathBuf>,
+    /// Loaded from `--merge-into`.
+    catalog: Option<catalog::Catalog>,
+    /// Loaded from `--incremental`.
+    incremental: Option<incremental::State>,
+    /// Set by `--group-shards`, which combines the records of each sharded model.
+    shard_groups: Option<shards::ShardGroups>,
+    /// Loaded from `--key` for `--sign`.
+    signing_key: Option<ed25519_dalek::SigningKey>,
+    /// Set by `--workers-per-disk`.
+    disk_limits: Option<disks::DiskLimits>,
+    /// Set by `--concurrency-scope per-directory`, with a group for each directory.
+    directory_limits: Option<groups::GroupLimits<PathBuf>>,
+    /// Loaded from `--tensor-name-map`.
+    tensor_name_map: Option<tensor_names::NameMap>,
+    /// Set by `--prefetch`.
+    prefetch: Option<prefetch::Prefetch>,
+    /// Set by `--error-sample`.
+    error_sample: Option<error_sample::ErrorSample>,
+    /// Set by `--autotune`, which resizes `open_files`.
+    autotune: Option<autotune::Autotune>,
+    /// Set by the first Ctrl-C with `--abort-on-signal-second` or once `--max-runtime` has
+    /// passed, after which no more files are started.
+    interrupted: AtomicBool,
+    /// Set once `--max-runtime` has passed, along with `interrupted`.
+    deadline_hit: AtomicBool,
+    /// The size of the files started so far, checked against `--max-total-bytes`.
+    bytes_started: AtomicU64,
+    /// The error that stopped the scan: the first failed write without
+    /// `--continue-on-write-error` or going over `--max-memory`. No more files are started
+    /// after it and the scan fails with it.
+    stop_error: Mutex<Option<String>>,
+    /// Set by `--max-memory`.
+    memory_budget: Option<memory::MemoryBudget>,
+    /// Parsed from `--progress-template`.
+    progress_template: Option<progress::Template>,
+    /// The file started last, which `--progress-template` can show.
+    current_file: Mutex<Option<PathBuf>>,
+    /// How many files failed `--assert-param-count`.
+    outside_param_count: AtomicUsize,
+    /// Set by [`scan`], which keeps the results instead of printing them.
+    collected: Option<Collected>,
+}
+
+/// The results of the files, as kept for [`scan`].
+#[derive(Debug, Default)]
+struct Collected {
+    records: Mutex<Vec<Record>>,
+    errors: Mutex<Vec<(PathBuf, anyhow::Error)>>,
+}
+
+impl Collected {
+    fn take(&self) -> ScanResult {
+        let mut records = std::mem::take(&mut *self.records.lock().unwrap_or_else(std::sync::PoisonError::into_inner));
+        records.sort_by(|a, b| a.path.cmp(&b.path));
+        let mut errors = std::mem::take(&mut *self.errors.lock().unwrap_or_else(std::sync::PoisonError::into_inner));
+        errors.sort_by(|a, b| a.0.cmp(&b.0));
+        ScanResult { records, errors }
+    }
+}
+
+impl Scan {
+    fn new(args: Args) -> anyhow::Result<Self> {
+        let relative_to = relative_base(&args)?;
+        // Read before opening the output, which might truncate it
+        let mut existing_paths = match &args.output {
+            Some(path) if args.dedupe_paths => output::existing_paths(path)?,
+            _ => HashSet::new(),
+        };
+        // Paths written relative to a base are compared with the files found as absolute paths
+        if let Some(base) = &relative_to {
+            existing_paths = existing_paths.into_iter().map(|path| normalize_path(&path, base).unwrap_or(path)).collect();
+        }
+        let per_file_base = per_file_base(&args, relative_to.as_ref())?;
+        let output = open_output(&args)?;
+        let read_options = read_options(&args);
+        // Only the files read as safetensors without sniffing are read ahead
+        let prefetch = (args.prefetch > 0
+            && !args.sniff
+            && !args.print_header_raw
+            && args.input_format.is_none_or(|format| format == formats::InputFormat::Safetensors))
+        .then(|| prefetch::Prefetch::new(args.prefetch, read_options.clone()));
+        let max_open_files = args.max_open_files.map_or_else(default_max_open_files, NonZeroUsize::get);
+        let open_files = Arc::new(Semaphore::new(max_open_files));
+        let autotune = args.autotune.then(|| autotune::Autotune::new(max_open_files));
+        let error_sample = args.error_sample.map(error_sample::ErrorSample::new);
+        let output_paths = collisions::OutputPaths::new(args.on_duplicate_output_path, args.verbose > 0);
+        let normalizer = args
+            .normalize_metadata
+            .then(|| normalize::Normalizer::new(args.coerce.iter().cloned()));
+        let manifests = args.group_output_by_dir.then(|| manifest::Manifests::new(args.line_ending));
+        let distinct = (!args.distinct.is_empty()).then(|| distinct::Distinct::new(args.distinct.clone()));
+        let percentiles = Some(&args.percentiles).filter(|keys| !keys.is_empty()).cloned().map(percentiles::Percentiles::new);
+        let histogram = (!args.histogram.is_empty()).then(|| {
+            histogram::Histogram::new(args.histogram.clone(), args.histogram_mode, args.buckets.map(NonZeroUsize::get))
+        });
+        let tag_frequencies = args.aggregate_tag_frequencies.map(tag_frequencies::TagFrequencies::new);
+        let size_report = args.metadata_size_report.then(size_report::SizeReport::default);
+        let architectures = args.by_architecture.then(architectures::Architectures::default);
+        let duplicates = args.count_distinct_files_by_hash.then(duplicates::Duplicates::default);
+        let by_metadata = (!args.dedupe_by.is_empty()).then(|| duplicates::ByMetadata::new(args.dedupe_by.clone(), args.keep));
+        let junit = (args.report == Some(cli::RunReport::Junit)).then(junit::Junit::default);
+        let drift =
+            args.compare_to.as_deref().map(|path| load_baseline(&args, path, relative_to.as_deref())).transpose()?;
+        let catalog = args.merge_into.as_deref().map(|path| catalog::Catalog::load(path, args.line_ending)).transpose()?;
+        let incremental =
+            args.incremental.as_deref().map(|path| incremental::State::load(path, args.line_ending)).transpose()?;
+        let shard_groups = (args.group_shards || args.follow_index_to_shards)
+            .then(|| shards::ShardGroups::new(args.check_total_size, args.verify_against_index_weightmap));
+        let disk_limits = args.workers_per_disk.map(|n| disks::DiskLimits::new(n.get()));
+        let directory_limits = (args.concurrency_scope == cli::ConcurrencyScope::PerDirectory)
+            .then(|| groups::GroupLimits::new(args.workers_per_directory.get()));
+        let tensor_name_map = args.tensor_name_map.as_deref().map(tensor_names::NameMap::load).transpose()?;
+        let memory_budget = args.max_memory.map(memory::MemoryBudget::new);
+        let progress_template = args.progress_template.as_deref().map(progress::Template::parse_or_default);
+        let signing_key = match &args.key {
+            Some(key) if args.sign => Some(signing::load_signing_key(key)?),
+            _ => None,
+        };
+        Ok(Self {
+            args,
+            read_options,
+            output,
+            open_files,
+            summary: Summary::default(),
+            normalizer,
+            existing_paths,
+            output_paths,
+            sources: Mutex::default(),
+            manifests,
+            distinct,
+            histogram,
+            percentiles,
+            tag_frequencies,
+            drift,
+            size_report,
+            architectures,
+            duplicates,
+            by_metadata,
+            junit,
+            relative_to,
+            relpath_roots: Mutex::default(),
+            per_file_base,
+            catalog,
+            incremental,
+            shard_groups,
+            signing_key,
+            disk_limits,
+            directory_limits,
+            tensor_name_map,
+            prefetch,
+            error_sample,
+            autotune,
+            interrupted: AtomicBool::new(false),
+            deadline_hit: AtomicBool::new(false),
+            bytes_started: AtomicU64::new(0),
+            stop_error: Mutex::default(),
+            memory_budget,
+            progress_template,
+            current_file: Mutex::default(),
+            outside_param_count: AtomicUsize::new(0),
+            collected: None,
+        })
+    }
+
+    /// `path` as the warnings about a file name it: the path the scan opened, or with
+    /// `--canonical-paths-in-errors` the path of its record, relative to
+    /// `--manifest-relative-paths` where it can be and with the `--path-separator`, so that a
+    /// warning can be looked up in the output as it is.
+    fn shown(&self, path: &Path) -> String {
+        if !self.args.canonical_paths_in_errors {
+            return path.display().to_string();
+        }
+        let relative = self.relative_to.as_deref().and_then(|base| record::relative_path(path, base));
+        let shown = relative.as_deref().unwrap_or(path).display().to_string();
+        if self.args.path_separator == output::PathSeparator::Slash {
+            shown.replace(std::path::MAIN_SEPARATOR, "/")
+        } else {
+            shown
+        }
+    }
+
+    /// Warn that `path` failed with `error`, unless `--error-sample` already printed enough
+    /// warnings like it.
+    fn warn_failed(&self, path: &Path, error: &anyhow::Error) {
+        let message = format!("Warning: Failed to process file {}: {error:#}", self.shown(path));
+        if self.error_sample.as_ref().is_none_or(|sample| sample.admit(path, &message)) {
+            eprintln!("{message}");
+        }
+    }
+}
+
+/// How to read headers, from the command-line flags.
+fn read_options(args: &Args) -> header::ReadOptions {
+    header::ReadOptions {
+        on_invalid_utf8: args.on_invalid_utf8,
+        lenient: args.lenient,
+        fail_on_duplicate_keys: args.fail_on_duplicate_keys,
+        report_duplicate_tensors: args.report_duplicated_tensor_names,
+        endian: args.header_endian,
+        readahead: args.readahead,
+        blocking: args.threads_blocking.is_some(),
+        open_timeout: args.open_read_timeout,
+        trace: args.trace_reads,
+        max_metadata_keys: Some(args.max_metadata_keys),
+        max_header_bytes: Some(args.max_header_bytes),
+    }
+}
+
+/// The `--compare-to` baseline, with its paths resolved as those of the files found are.
+fn load_baseline(args: &Args, baseline: &Path, relative_to: Option<&Path>) -> anyhow::Result<drift::Drift> {
+    if args.match_by == Some(drift::MatchBy::Hash) && !args.hash {
+        anyhow::bail!("--match-by hash needs --hash to compare the files by their SHA-256");
+    }
+    let mut records = output::read_records(baseline)?;
+    if let Some(base) = relative_to {
+        for record in &mut records {
+            resolve_record_path(record, base);
+        }
+    }
+    Ok(drift::Drift::new(&records, args.numeric_epsilon).match_by(args.match_by.clone()))
+}
+
+/// The output of the records as the options describe it.
+fn open_output(args: &Args) -> anyhow::Result<Output> {
+    let compression = output::Compression::for_path(args.output.as_deref(), args.compress);
+    let (path, split) = (args.output.as_deref(), args.output_split_size);
+    let mut output = if let Some(template) = &args.output_template {
+        let mut template = template.clone();
+        template.missing = args.missing_placeholder.clone().unwrap_or_default();
+        Output::with_template(path, args.append, compression, template, split, args.buffer_size)?
+    } else {
+        Output::new(path, args.append, compression, output_format(args)?, split, args.buffer_size)?
+    };
+    output.set_path_separator(args.path_separator);
+    output.set_numbers_as_strings(args.json_numbers_as_strings);
+    output.set_compact_empty(args.compact_empty);
+    output.set_natural_key_order(args.natural_key_order);
+    output.set_flush_every(args.flush_every);
+    output.set_digest(args.output_digest);
+    output.set_explode_keys(args.explode_keys);
+    Ok(output)
+}
+
+/// The `--format` of the records, which is Parquet for an `--output` ending in `.parquet`
+/// unless another is given.
+fn output_format(args: &Args) -> anyhow::Result<output::OutputFormat> {
+    let parquet_path = args.output.as_ref().is_some_and(|path| path.extension().is_some_and(|ext| ext == "parquet"));
+    let format = match args.format {
+        _ if args.canonical_json => output::OutputFormat::CanonicalJson,
+        output::OutputFormat::Json if parquet_path => output::OutputFormat::Parquet,
+        format => format,
+    };
+    if format != output::OutputFormat::Parquet {
+        anyhow::ensure!(!args.explode_keys, "--explode-keys needs --format parquet");
+        return Ok(format);
+    }
+    anyhow::ensure!(args.output.is_some(), "--format parquet needs --output, as a Parquet file can't be streamed");
+    let conflicting = [
+        (args.append, "--append"),
+        (args.output_split_size.is_some(), "--output-split-size"),
+        (output::Compression::for_path(args.output.as_deref(), args.compress) != output::Compression::None, "gzip"),
+        (args.output_digest, "--output-digest"),
+    ];
+    if let Some((_, option)) = conflicting.iter().find(|(given, _)| *given) {
+        anyhow::bail!("--format parquet can't be combined with {option}");
+    }
+    Ok(format)
+}
+
+/// The directory `{relpath}` of `--per-file-out` is relative to: that of
+/// `--manifest-relative-paths` if given, or else the one relative inputs are resolved against.
+fn per_file_base(args: &Args, relative_to: Option<&PathBuf>) -> anyhow::Result<Option<PathBuf>> {
+    match &args.per_file_out {
+        Some(template) if template.uses_hash() && !args.hash => anyhow::bail!("--per-file-out with {{hash}} needs --hash"),
+        Some(_) => Ok(Some(relative_to.cloned().map_or_else(|| resolution_base(args), Ok)?)),
+        None => Ok(None),
+    }
+}
+
+/// Budget of simultaneously open files when `--max-open-files` isn't given: half of the soft
+/// `RLIMIT_NOFILE`, leaving the rest for stdio, the runtime and the sidecar writes.
+fn default_max_open_files() -> usize {
+    #[cfg(unix)]
+    {
+        let mut limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+        // SAFETY: `getrlimit` only writes to the struct it is given.
+        if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &raw mut limit) } == 0 {
+            let soft = usize::try_from(limit.rlim_cur).unwrap_or(usize::MAX);
+            return (soft / 2).clamp(1, 4096);
+        }
+    }
+    256
+}
+
+/// What [`scan`] found, each sorted by path. Skipped files are in neither list.
+#[derive(Debug, Default)]
+pub struct ScanResult {
+    /// The records of the files that were processed, as they would have been printed.
+    pub records: Vec<Record>,
+    /// The files that failed, with the reason.
+    pub errors: Vec<(PathBuf, anyhow::Error)>,
+}
+
+/// Run a scan as the command line would, but return the records and errors instead of
+/// printing them. `args` are the command-line options, such as from
+/// [`cli::Args::try_parse_from`].
+///
+/// The `.json` files next to the models are still written. Subcommands and the options that
+/// print a report in place of the records, such as `--distinct`, print it as usual and leave
+/// [`ScanResult::records`] empty. No summary is printed and Ctrl-C isn't handled.
+///
+/// # Errors
+///
+/// If the options can't be acted on, such as an `--output` that can't be created, or the scan
+/// fails as a whole, such as for `--fail-on-empty`. The failures of single files are in
+/// [`ScanResult::errors`].
+pub async fn scan(args: Args) -> anyhow::Result<ScanResult> {
+    let mut scan = Scan::new(args)?;
+    scan.collected = Some(Collected::default());
+    let scan = Arc::new(scan);
+    run(&scan).await?;
+    scan.output.finish()?;
+
+    Ok(scan.collected.as_ref().map(Collected::take).unwrap_or_default())
+}
+
+/// Parse the command line and run the scan it asks for, as the `extract-metadata` binary.
+///
+/// # Errors
+///
+/// If the scan fails as a whole; failures of single files are only warned about. Some
+/// outcomes, such as Ctrl-C, exit the process with their own status instead of returning.
+pub fn cli_main() -> anyhow::Result<()> {
+    // Initialize the logger to output diagnostic information.
+    env_logger::init();
+
+    let args = Args::parse();
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    runtime.enable_all();
+    if let Some(threads) = args.threads_blocking {
+        runtime.max_blocking_threads(threads.get());
+    }
+    let runtime = runtime.build()?;
+    let result = runtime.block_on(scan_main(args));
+    // Opens abandoned by --open-read-timeout may never return, and mustn't keep the process
+    runtime.shutdown_background();
+    result
+}
+
+async fn scan_main(args: Args) -> anyhow::Result<()> {
+    let started = Instant::now();
+    let scan = Arc::new(Scan::new(args)?);
+    if scan.args.print_schema {
+        scan.output.write_text(&record::schema())?;
+        scan.output.finish()?;
+        return Ok(());
+    }
+
+    if scan.args.abort_on_signal_second {
+        tokio::spawn(drain_on_interrupt(Arc::clone(&scan), started));
+    }
+    if let Some(runtime) = scan.args.max_runtime {
+        tokio::spawn(stop_at_deadline(Arc::clone(&scan), runtime));
+    }
+    let result = tokio::select! {
+        result = run(&scan) => result,
+        _ = tokio::signal::ctrl_c(), if !scan.args.abort_on_signal_second => {
+            // Don't lose the records that are still sitting in the buffer.
+            let _ = scan.output.finish();
+            eprintln!("Interrupted");
+            if let Some(path) = &scan.args.stats_out {
+                let _ = write_stats(&scan, path, started, &Err(anyhow::anyhow!("Interrupted")));
+            }
+            print_summary(&scan, started);
+            std::process::exit(130);
+        }
+    };
+    scan.output.finish()?;
+    if let (Some(junit), Some(path)) = (&scan.junit, &scan.args.report_out) {
+        io::atomic_write(path, junit.render(started.elapsed()).as_bytes())?;
+    }
+    if let Some(path) = &scan.args.stats_out {
+        write_stats(&scan, path, started, &result)?;
+    }
+    print_summary(&scan, started);
+    if scan.deadline_hit.load(Ordering::Relaxed) {
+        std::process::exit(124);
+    }
+    if scan.interrupted.load(Ordering::Relaxed) {
+        std::process::exit(130);
+    }
+    if scan.args.emit_on_match_only {
+        let code = match &result {
+            Err(e) => {
+                eprintln!("Error: {e:#}");
+                2
+            }
+            Ok(()) if scan.summary.failed() > 0 => 2,
+            Ok(()) if scan.summary.processed() == 0 => 1,
+            Ok(()) => 0,
+        };
+        std::process::exit(code);
+    }
+    if result.is_ok() && scan.args.only_errors && scan.summary.failed() > 0 {
+        std::process::exit(1);
+    }
+    result
+}
+
+/// Stop starting files once `runtime` has passed since the start, for `--max-runtime`, as the
+/// first Ctrl-C of `--abort-on-signal-second` does: the files in progress are finished and the
+/// scan ends with its output flushed.
+async fn stop_at_deadline(scan: Arc<Scan>, runtime: Duration) {
+    tokio::time::sleep(runtime).await;
+    scan.deadline_hit.store(true, Ordering::Relaxed);
+    scan.interrupted.store(true, Ordering::Relaxed);
+}
+
+/// How soon a second Ctrl-C must follow the first to quit without finishing the files in
+/// progress.
+const FORCE_QUIT_WINDOW: Duration = Duration::from_secs(5);
+
+/// Handle Ctrl-C for `--abort-on-signal-second`: the first stops new files from being started
+/// so the scan ends once those in progress are done, a second within [`FORCE_QUIT_WINDOW`]
+/// exits at once with status 131. A later one starts the window again.
+async fn drain_on_interrupt(scan: Arc<Scan>, started: Instant) {
+    let mut last: Option<Instant> = None;
+    while tokio::signal::ctrl_c().await.is_ok() {
+        if last.is_some_and(|at| at.elapsed() < FORCE_QUIT_WINDOW) {
+            let _ = scan.output.finish();
+            eprintln!("Aborted");
+            print_summary(&scan, started);
+            std::process::exit(131);
+        }
+        last = Some(Instant::now());
+        scan.interrupted.store(true, Ordering::Relaxed);
+        eprintln!(
+            "Interrupted, finishing the files in progress. Press Ctrl-C again within {}s to quit now",
+            FORCE_QUIT_WINDOW.as_secs()
+        );
+    }
+}
+
+/// Write the [`summary::Stats`] of the run to `path` for `--stats-out`, along with the error
+/// that ended it, if any.
+fn write_stats(scan: &Scan, path: &Path, started: Instant, result: &anyhow::Result<()>) -> anyhow::Result<()> {
+    let jobs = match &scan.autotune {
+        Some(autotune) => autotune.concurrency(),
+        None => scan.args.max_open_files.map_or_else(default_max_open_files, NonZeroUsize::get),
+    };
+    let bytes = scan.bytes_started.load(Ordering::Relaxed);
+    let error = result.as_ref().err().map(|e| format!("{e:#}"));
+    let stats = scan.summary.stats(started.elapsed(), bytes, jobs, error);
+    let json = scan.args.line_ending.apply(serde_json::to_string_pretty(&stats)?);
+    io::atomic_write(path, json.as_bytes())
+}
+
+fn print_summary(scan: &Scan, started: Instant) {
+    if let Some(sample) = &scan.error_sample {
+        eprint!("{}", sample.report());
+    }
+    if let Some(percentiles) = &scan.percentiles {
+        eprint!("{}", percentiles.report());
+    }
+    if !scan.args.quiet {
+        eprintln!("{}", scan.summary.line(started.elapsed(), scan.args.verbose > 0));
+        if let Some(autotune) = &scan.autotune {
+            eprintln!("Autotuned to {} files at once", autotune.concurrency());
+        }
+        if let Some(files) = scan.output.split_files() {
+            eprintln!("Wrote {} output files:", files.len());
+            for file in files {
+                eprintln!("  {}", file.display());
+            }
+        }
+    }
+    if scan.args.report_unreadable {
+        eprint!("{}", scan.summary.unreadable_report());
+    }
+    if scan.args.verbose > 0 {
+        eprint!("{}", scan.summary.directory_limit_report());
+    }
+    if let Some(digest) = scan.output.digest() {
+        eprintln!("Output SHA-256: {digest}");
+    }
+}
+
+/// The directory `--manifest-relative-paths` makes paths relative to, if given: `--relative-to`,
+/// or the one relative input paths are resolved against.
+fn relative_base(args: &Args) -> anyhow::Result<Option<PathBuf>> {
+    match &args.relative_to {
+        Some(dir) => Ok(Some(normalize_path(dir, &resolution_base(args)?)?)),
+        None if args.manifest_relative_paths => Ok(Some(resolution_base(args)?)),
+        None => Ok(None),
+    }
+}
+
+/// The directory relative input paths are resolved against: `--deref-relative-to`
+/// if given, otherwise the current directory.
+fn resolution_base(args: &Args) -> anyhow::Result<PathBuf> {
+    let cwd = env::current_dir()?;
+    match &args.deref_relative_to {
+        Some(dir) => normalize_path(dir, &cwd),
+        None => Ok(cwd),
+    }
+}
+
+/// Resolve the relative path of a record written with `--manifest-relative-paths` against the
+/// same base, as an absolute path like those of the files found.
+fn resolve_record_path(record: &mut serde_json::Value, base: &Path) {
+    if let Some(path) = record.get_mut("path")
+        && let Some(relative) = path.as_str().map(Path::new).filter(|path| path.is_relative())
+        && let Ok(resolved) = normalize_path(relative, base)
+    {
+        *path = resolved.to_string_lossy().into();
+    }
+}
+
+/// The inputs to expand into the files to process, the PATHs given or the entries of the
+/// `--input-list`, with the directory relative ones are resolved against.
+fn inputs(scan: &Scan) -> anyhow::Result<(Vec<String>, PathBuf)> {
+    let base = resolution_base(&scan.args)?;
+    let Some(list) = &scan.args.input_list else {
+        return Ok((scan.args.inputs().into_iter().map(str::to_string).collect(), base));
+    };
+    let list = normalize_path(list, &base)?;
+    let text = io::read_to_string(&list).with_context(|| format!("Failed to read {}", list.display()))?;
+    let entries = text.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#'));
+    // Entries are relative to the list, wherever it is run from
+    Ok((entries.map(str::to_string).collect(), list.parent().map(Path::to_path_buf).unwrap_or(base)))
+}
+
+/// Expand each input into the files to process, before any is processed. A file matched by
+/// more than one input, by whatever path, is only kept the first time, except by `merge-files`,
+/// which merges the files as given. With `--limit` the expansion stops once enough files were
+/// found.
+fn collect_files(scan: &Scan) -> anyhow::Result<Vec<PathBuf>> {
+    let (inputs, base) = inputs(scan)?;
+    let limit = scan.args.limit.unwrap_or(usize::MAX);
+    if let Some(Command::MergeFiles(_)) = &scan.args.command {
+        let mut files = Vec::new();
+        for input in &inputs {
+            files.extend(expand_input(scan, input, &base, limit - files.len())?);
+        }
+        return Ok(files);
+    }
+    let inputs: Vec<&str> = inputs.iter().map(String::as_str).collect();
+    if scan.args.parallel_glob {
+        return expand_in_parallel(scan, &inputs, &base, limit);
+    }
+    let mut seen = HashSet::new();
+    let mut files = Vec::new();
+    for input in inputs {
+        if files.len() == limit {
+            break;
+        }
+        for file in expand_input(scan, input, &base, limit - files.len())? {
+            if seen.insert(canonical(&file)) {
+                add_sources(scan, input, std::slice::from_ref(&file));
+                files.push(file);
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// The path a file is told apart from others by, whatever path it was found through.
+fn canonical(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Expand the inputs on a thread per CPU, for `--parallel-glob`, before any file is processed.
+/// The files are in the order of the inputs, and a file matched by more than one input, by
+/// whatever path, is only kept the first time.
+fn expand_in_parallel(scan: &Scan, entries: &[&str], base: &Path, limit: usize) -> anyhow::Result<Vec<PathBuf>> {
+    let next = AtomicUsize::new(0);
+    let expanded: Vec<Mutex<Option<anyhow::Result<Vec<PathBuf>>>>> = entries.iter().map(|_| Mutex::default()).collect();
+    let threads = std::thread::available_parallelism().map_or(4, NonZeroUsize::get).min(entries.len());
+    std::thread::scope(|threads_scope| {
+        for _ in 0..threads {
+            threads_scope.spawn(|| {
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(entry) = entries.get(i) else { break };
+                    let files = expand_input(scan, entry, base, limit);
+                    *expanded[i].lock().unwrap_or_else(std::sync::PoisonError::into_inner) = Some(files);
+                }
+            });
+        }
+    });
+    let mut seen = HashSet::new();
+    let mut files = Vec::new();
+    for (input, entry) in entries.iter().zip(expanded) {
+        let entry = entry.into_inner().unwrap_or_else(std::sync::PoisonError::into_inner).transpose()?.unwrap_or_default();
+        for file in entry {
+            if files.len() == limit {
+                return Ok(files);
+            }
+            if seen.insert(canonical(&file)) {
+                add_sources(scan, input, std::slice::from_ref(&file));
+                files.push(file);
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Expand the inputs in the background, sending each file through the returned channel as soon
+/// as it is found so the scan starts on the first while the rest are still being looked for.
+/// With `--parallel-glob` the inputs are expanded on a thread per CPU, otherwise one after the
+/// other. A file matched by more than one input, by whatever path, is only sent the first time,
+/// and at most `limit` files are. The task ends with the number of files sent, or the error
+/// the expansion failed with.
+fn find_files(
+    scan: &Arc<Scan>,
+    inputs: Vec<String>,
+    base: PathBuf,
+    limit: usize,
+) -> (mpsc::UnboundedReceiver<PathBuf>, tokio::task::JoinHandle<anyhow::Result<usize>>) {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let scan = Arc::clone(scan);
+    let finding = tokio::task::spawn_blocking(move || {
+        let next = AtomicUsize::new(0);
+        let seen = Mutex::new(HashSet::new());
+        let error = Mutex::new(None);
+        let cpus = std::thread::available_parallelism().map_or(4, NonZeroUsize::get);
+        let threads = if scan.args.parallel_glob { cpus } else { 1 };
+        std::thread::scope(|threads_scope| {
+            for _ in 0..threads.min(inputs.len()) {
+                threads_scope.spawn(|| {
+                    while let Some(input) = inputs.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        let result = for_each_file(&scan, input, &base, &mut |file| {
+                            let key = canonical(&file);
+                            let mut seen = seen.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+                            if seen.len() == limit {
+                                return false;
+                            }
+                            if seen.insert(key) {
+                                add_sources(&scan, input, std::slice::from_ref(&file));
+                                // The receiver only goes away when the scan failed
+                                if sender.send(file).is_err() {
+                                    return false;
+                                }
+                            }
+                            seen.len() < limit
+                        });
+                        if let Err(e) = result {
+                            error.lock().unwrap_or_else(std::sync::PoisonError::into_inner).get_or_insert(e);
+                            next.store(inputs.len(), Ordering::Relaxed);
+                        }
+                    }
+                });
+            }
+        });
+        match error.into_inner().unwrap_or_else(std::sync::PoisonError::into_inner) {
+            Some(e) => Err(e),
+            None => Ok(seen.into_inner().unwrap_or_else(std::sync::PoisonError::into_inner).len()),
+        }
+    });
+    (receiver, finding)
+}
+
+/// Remember that `files` were found through the input `input`, a PATH or an `--input-list`
+/// entry, for `--with-source`. A file found through more than one input keeps the first.
+fn add_sources(scan: &Scan, input: &str, files: &[PathBuf]) {
+    if !scan.args.with_source {
+        return;
+    }
+    let mut sources = scan.sources.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+    for file in files {
+        sources.entry(file.clone()).or_insert_with(|| input.to_string());
+    }
+}
+
+/// The input `path` was found through, with `--with-source`: the PATH or `--input-list` entry
+/// that matched it, or `-` for a file read from stdin.
+fn source_of(scan: &Scan, path: &Path) -> Option<String> {
+    if !scan.args.with_source {
+        return None;
+    }
+    let sources = scan.sources.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+    Some(sources.get(path).cloned().unwrap_or_else(|| scan.args.input().to_string()))
+}
+
+/// Expand one input into the files to process: the matching files of a directory
+/// (recursively), the matches of a glob pattern, or the file itself. At most `limit` files are
+/// returned.
+fn expand_input(scan: &Scan, input: &str, base: &Path, limit: usize) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    if limit > 0 {
+        for_each_file(scan, input, base, &mut |file| {
+            files.push(file);
+            files.len() < limit
+        })?;
+    }
+    Ok(files)
+}
+
+/// Pass each file `input` expands to to `found` as soon as it is found, as `expand_input` does,
+/// until `found` returns false.
+fn for_each_file(scan: &Scan, input: &str, base: &Path, found: &mut dyn FnMut(PathBuf) -> bool) -> anyhow::Result<()> {
+    let path = normalize_path(Path::new(input), base)?;
+    if scan.args.with_relpath {
+        scan.relpath_roots.lock().unwrap_or_else(std::sync::PoisonError::into_inner).push(input_root(&path));
+    }
+
+    if scan.args.follow_index_to_shards && shards::is_index(&path) {
+        // Missing shards are kept, to fail like any other file that can't be read
+        let shards = shards::read_index(&path).with_context(|| format!("Failed to read {}", path.display()))?.shards;
+        for shard in shards {
+            if !found(shard) {
+                break;
+            }
+        }
+        Ok(())
+    } else if path.is_dir() {
+        let extensions = if scan.args.count_by_extension {
+            Vec::new()
+        } else if let Some(input_format) = scan.args.input_format {
+            input_format.format().extensions().to_vec()
+        } else {
+            let mut extensions = if scan.args.sniff { formats::sniffed_extensions() } else { vec!["safetensors"] };
+            extensions.extend(scan.args.extractor.iter().map(extractor::Extractor::extension));
+            if scan.args.recurse_archives {
+                extensions.extend(["zip", "tar"]);
+            }
+            extensions
+        };
+        let options = walk::Options {
+            extensions: &extensions,
+            exclude_dirs: &scan.args.exclude_dir,
+            include_hidden: scan.args.include_hidden,
+            symlinks: scan.args.symlinks,
+            on_error: scan.args.walk_errors,
+            limit_per_directory: scan.args.limit_per_directory.map(NonZeroUsize::get),
+            report: (scan.args.verbose > 0).then_some(|message| eprintln!("{message}")),
+        };
+        for file_path in walk::find_files(&path, options, &scan.summary) {
+            let file_path = file_path?;
+            let file_path = match normalize_path(&file_path, base) {
+                Ok(p) => p,
+                Err(e) => {
+                    eprintln!("Warning: Failed to normalize path {}: {}", file_path.display(), e);
+                    file_path
+                }
+            };
+            if !found(file_path) {
+                break;
+            }
+        }
+        Ok(())
+    } else if let Some(path_str) = path.to_str() {
+        if path_str.contains('*') {
+            for entry in glob(path_str).context("Failed to read glob pattern")? {
+                match entry {
+                    Ok(path) => {
+                        if !found(normalize_path(&path, base).unwrap_or(path)) {
+                            break;
+                        }
+                    }
+                    Err(e) => eprintln!("Error processing entry: {e:?}"),
+                }
+            }
+        } else {
+            found(path);
+        }
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!("Invalid path provided"))
+    }
+}
+
+/// The directory an input's files are under, which their `relpath` is relative to: the input
+/// itself if it is a directory, the directories of a glob pattern before the first one with a
+/// wildcard, or the directory holding a file.
+fn input_root(input: &Path) -> PathBuf {
+    if input.is_dir() {
+        return input.to_path_buf();
+    }
+    let wildcard = |component: &Component| component.as_os_str().to_string_lossy().contains(['*', '?', '[']);
+    if input.components().any(|component| wildcard(&component)) {
+        return input.components().take_while(|component| !wildcard(component)).collect();
+    }
+    input.parent().map(Path::to_path_buf).unwrap_or_default()
+}
+
+/// `path` relative to the first of the `--with-relpath` roots it is under, if any is.
+fn relpath(scan: &Scan, path: &Path) -> Option<PathBuf> {
+    let roots = scan.relpath_roots.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+    roots.iter().find_map(|root| path.strip_prefix(root).ok()).map(Path::to_path_buf)
+}
+
+/// For `--fail-on-empty`: fail if the inputs matched no files, telling a path that doesn't
+/// exist apart from one that exists but contains nothing to process.
+fn ensure_matched(scan: &Scan, files: &[PathBuf]) -> anyhow::Result<()> {
+    if let Some(list) = &scan.args.input_list {
+        if files.is_empty() {
+            anyhow::bail!("No files matched the entries of {}", list.display());
+        }
+        return Ok(());
+    }
+    let inputs = scan.args.inputs();
+    for input in inputs.iter().filter(|input| !input.contains('*')) {
+        let path = normalize_path(Path::new(input), &resolution_base(&scan.args)?)?;
+        if !path.exists() {
+            anyhow::bail!("{} doesn't exist", path.display());
+        }
+    }
+    if files.is_empty() {
+        anyhow::bail!("No files matched {}", inputs.join(" "));
+    }
+    Ok(())
+}
+
+/// Whether the files can be processed as they are found, rather than once all of them are:
+/// they can by the plain scan, with or without `--parallel-hash`, unless something needs them
+/// all first, as the subcommands do, or the progress its total, `--preserve-order` their order
+/// and `--fail-on-empty` to know there are none.
+fn processed_as_found(scan: &Scan) -> bool {
+    let args = &scan.args;
+    args.command.is_none()
+        && !args.count_by_extension
+        && !args.probe_only
+        && args.extract_key_binary.is_none()
+        && args.peek_tensor.is_none()
+        && scan.progress_template.is_none()
+        && args.progress_interval.is_none()
+        && !args.preserve_order
+        && !args.fail_on_empty
+}
+
+async fn run(scan: &Arc<Scan>) -> anyhow::Result<()> {
+    let from_stdin = scan.args.command.is_none() && scan.args.paths == [stdin::PATH];
+    let as_found = !from_stdin && processed_as_found(scan);
+    let files = if from_stdin || as_found { Vec::new() } else { collect_files(scan)? };
+    if scan.args.fail_on_empty && !from_stdin {
+        ensure_matched(scan, &files)?;
+    }
+
+    match &scan.args.command {
+        Some(Command::Matrix(matrix)) => return matrix::run(scan, files, matrix.format).await,
+        Some(Command::KeyReport(_)) => return key_report::run(scan, files).await,
+        Some(Command::Inspect(_)) => return inspect::run(scan, files).await,
+        Some(Command::VerifySignature(verify)) => return signing::verify_all(scan, files, &verify.public_key).await,
+        Some(Command::Repair(repair)) => return repair::run(scan, files, repair.dry_run).await,
+        Some(Command::Set(set)) => {
+            return stamp::run(scan, files, &stamp::load_template(&set.set_from)?, set.dry_run).await;
+        }
+        Some(Command::Strip(strip)) => return strip::run(scan, files, strip.strip_values_over, strip.dry_run).await,
+        Some(Command::MergeFiles(merge)) => return merge_files::run(scan, files, merge.prefer).await,
+        None => {}
+    }
+    if scan.args.count_by_extension {
+        return extension_counts::run(scan, files).await;
+    }
+    if scan.args.probe_only {
+        return probe::run(scan, files).await;
+    }
+    if let Some(key) = &scan.args.extract_key_binary {
+        return extract_key_binary(scan, &files, key).await;
+    }
+    if let Some(peek) = &scan.args.peek_tensor {
+        return peek::run(scan, &files, peek).await;
+    }
+
+    let mut total_files = files.len();
+    let _progress = scan
+        .progress_template
+        .as_ref()
+        .and_then(|template| progress::Progress::start_bar(scan, total_files, template))
+        .or_else(|| {
+            let interval = scan.args.progress_interval?;
+            progress::Progress::start(scan, total_files, interval)
+        });
+    let _autotune = autotune::Autotune::start(scan);
+    if from_stdin {
+        stdin::run(scan).await;
+    } else if as_found {
+        total_files = process_as_found(scan).await?;
+    } else if scan.args.parallel_hash {
+        pipeline::run(scan, futures::stream::iter(files)).await?;
+    } else if scan.args.preserve_order {
+        run_in_order(scan, files).await?;
+    } else {
+        spawn_each(scan, futures::stream::iter(files)).await?;
+    }
+    if let Some(shard_groups) = &scan.shard_groups {
+        for record in shard_groups.finish() {
+            emit_combined(scan, record)?;
+        }
+    }
+    if let Some(error) = scan.stop_error.lock().unwrap_or_else(std::sync::PoisonError::into_inner).take() {
+        anyhow::bail!("Stopped the scan: {error}");
+    }
+    if scan.deadline_hit.load(Ordering::Relaxed) && !scan.args.quiet {
+        let covered = total_files - scan.summary.skipped(SkipReason::Interrupted);
+        let runtime = humantime::format_duration(scan.args.max_runtime.unwrap_or_default());
+        eprintln!("Reached --max-runtime of {runtime}, covering {covered} of {total_files} files");
+    }
+    let over_budget = scan.summary.skipped(SkipReason::Budget);
+    if over_budget > 0 && !scan.args.quiet {
+        let bytes = humanize::bytes(scan.bytes_started.load(Ordering::Relaxed));
+        eprintln!("Reached --max-total-bytes after {bytes}, covering {} of {total_files} files", total_files - over_budget);
+    }
+    if let Some(manifests) = &scan.manifests {
+        let written = manifests.write_all()?;
+        log::info!("Wrote {written} manifests");
+    }
+    if let Some(catalog) = &scan.catalog {
+        catalog.write()?;
+    }
+    if let Some(state) = &scan.incremental {
+        state.write()?;
+    }
+    write_reports(scan)
+}
+
+/// Print the reports collected in place of the records, and fail for `--fail-on-drift` and
+/// `--assert-param-count`.
+fn write_reports(scan: &Scan) -> anyhow::Result<()> {
+    if let Some(distinct) = &scan.distinct {
+        scan.output.write_text(&distinct.report())?;
+    }
+    if let Some(histogram) = &scan.histogram {
+        scan.output.write_text(&histogram.report())?;
+    }
+    if let Some(tag_frequencies) = &scan.tag_frequencies {
+        scan.output.write_text(&tag_frequencies.report())?;
+    }
+    if let Some(size_report) = &scan.size_report {
+        scan.output.write_text(&size_report.report())?;
+    }
+    if let Some(architectures) = &scan.architectures {
+        let groups = architectures.groups();
+        match scan.args.architecture_format {
+            cli::ReportFormat::Table => scan.output.write_text(&architectures::render(&groups))?,
+            cli::ReportFormat::Json => scan.output.write_record(&groups)?,
+        }
+    }
+    if let Some(duplicates) = &scan.duplicates {
+        scan.output.write_text(&duplicates.report())?;
+    }
+    if let Some(by_metadata) = &scan.by_metadata {
+        scan.output.write_text(&by_metadata.report())?;
+    }
+    if let Some(drift) = &scan.drift {
+        let diff = drift.diff();
+        match scan.args.drift_format {
+            cli::DriftFormat::Text => scan.output.write_text(&diff.text())?,
+            cli::DriftFormat::Json => scan.output.write_record(&diff)?,
+        }
+        if scan.args.fail_on_drift && !diff.is_empty() {
+            anyhow::bail!("Found drift: {} added, {} removed, {} changed", diff.added.len(), diff.removed.len(), diff.changed.len());
+        }
+    }
+    let outside = scan.outside_param_count.load(Ordering::Relaxed);
+    if let Some(bound) = scan.args.assert_param_count.as_ref().filter(|_| outside > 0) {
+        anyhow::bail!("Found {outside} models with a parameter count outside --assert-param-count {bound}");
+    }
+    Ok(())
+}
+
+/// Write the base64-decoded value of `key` in the metadata of the one file given to stdout,
+/// as raw bytes, for `--extract-key-binary`.
+async fn extract_key_binary(scan: &Scan, files: &[PathBuf], key: &str) -> anyhow::Result<()> {
+    let [path] = files else {
+        anyhow::bail!("--extract-key-binary takes a single file, but {} files matched", files.len());
+    };
+    let metadata = header::read_header(path, &scan.read_options).await?.metadata();
+    let value = metadata.get(key).with_context(|| format!("{}: Has no metadata key {key}", path.display()))?;
+    let bytes = value
+        .as_str()
+        .and_then(thumbnails::decode_base64)
+        .with_context(|| format!("{}: The value of {key} isn't base64", path.display()))?;
+    let mut stdout = std::io::stdout().lock();
+    std::io::Write::write_all(&mut stdout, &bytes)?;
+    std::io::Write::flush(&mut stdout)?;
+    Ok(())
+}
+
+/// Process the files as `find_files` finds them, returning how many it found.
+async fn process_as_found(scan: &Arc<Scan>) -> anyhow::Result<usize> {
+    let (inputs, base) = inputs(scan)?;
+    let (mut receiver, finding) = find_files(scan, inputs, base, scan.args.limit.unwrap_or(usize::MAX));
+    let found = futures::stream::poll_fn(move |cx| receiver.poll_recv(cx));
+    if scan.args.parallel_hash {
+        pipeline::run(scan, found).await?;
+    } else {
+        spawn_each(scan, found).await?;
+    }
+    finding.await?
+}
+
+/// Start processing each file as soon as it comes, all of them concurrently, and wait for them
+/// to finish.
+async fn spawn_each(scan: &Arc<Scan>, mut files: impl Stream<Item = PathBuf> + Unpin) -> anyhow::Result<()> {
+    let mut handles = Vec::new();
+    while let Some(path) = files.next().await {
+        let scan = Arc::clone(scan);
+        handles.push(tokio::spawn(async move { process_or_warn(&path, &scan).await }));
+    }
+    for handle in handles {
+        handle.await?;
+    }
+    Ok(())
+}
+
+/// Process the files concurrently but write their records in the order they were found, for
+/// `--preserve-order`. The records of a file that finishes early are held until those of the
+/// files before it are written. At most `--reorder-buffer` files are held or in progress at
+/// once, and the next file only starts when the first of them is written.
+async fn run_in_order(scan: &Arc<Scan>, files: Vec<PathBuf>) -> anyhow::Result<()> {
+    let mut pending = VecDeque::new();
+    for path in files {
+        if pending.len() >= scan.args.reorder_buffer.get()
+            && let Some(handle) = pending.pop_front()
+        {
+            scan.output.write_held(handle.await?)?;
+        }
+        let scan = Arc::clone(scan);
+        pending.push_back(tokio::spawn(async move { output::hold(process_or_warn(&path, &scan)).await.1 }));
+    }
+    for handle in pending {
+        scan.output.write_held(handle.await?)?;
+    }
+    Ok(())
+}
+
+/// Extract the metadata of one file, save it next to the file as `<stem>.json`
+/// and emit it as a record on stdout.
+async fn process(path: &Path, scan: &Scan) -> anyhow::Result<Outcome> {
+    match extract(path, scan).await? {
+        Extracted::Record(record) => hash_and_emit(*record, scan).await,
+        Extracted::Done(outcome) => Ok(outcome),
+    }
+}
+
+/// What reading a file produced: a record still to be hashed and emitted, or a final outcome
+/// for files that were skipped or fully handled already.
+enum Extracted {
+    Record(Box<Record>),
+    Done(Outcome),
+}
+
+/// The files whose extension marks them as something other than a model: shard indexes, and
+/// with --recurse-archives and --extractor archives and the files of an extractor.
+async fn extract_by_extension(path: &Path, scan: &Scan) -> Option<anyhow::Result<Extracted>> {
+    if shards::is_index(path) {
+        Some(extract_index(path, scan).await)
+    } else if scan.args.recurse_archives && archive::is_archive(path) {
+        Some(extract_archive(path, scan).await)
+    } else if let Some(extractor) = scan.args.extractor.iter().find(|extractor| extractor.matches(path)) {
+        Some(extract_external(path, scan, extractor).await)
+    } else {
+        None
+    }
+}
+
+/// Why a file whose record is already where it's going isn't read again: it's in the output
+/// being appended to, or is unchanged since the last `--incremental` run.
+async fn already_done(path: &Path, scan: &Scan) -> Option<SkipReason> {
+    if scan.existing_paths.contains(path) {
+        Some(SkipReason::Existing)
+    } else if let Some(state) = &scan.incremental
+        && state.unchanged(path).await
+    {
+        Some(SkipReason::Unchanged)
+    } else {
+        None
+    }
+}
+
+/// Everything `process` does up to emitting the record, which is left to `hash_and_emit` so
+/// the two can run in separate stages with `--parallel-hash`.
+async fn extract(path: &Path, scan: &Scan) -> anyhow::Result<Extracted> {
+    if let Some(reason) = already_done(path, scan).await {
+        return Ok(Extracted::Done(Outcome::Skipped(reason)));
+    }
+    // Before taking a slot, so a file that is being written doesn't hold up the others
+    if let Some(window) = scan.args.watch_debounce
+        && !settle(path, window).await?
+    {
+        return Ok(skip_in_progress(path));
+    }
+    // Only once it is known the file is read, and forgotten however it ends
+    let _queued = scan
+        .prefetch
+        .as_ref()
+        .filter(|_| path.extension().is_some_and(|ext| ext == "safetensors"))
+        .map(|prefetch| prefetch.queue(path));
+    // Held until the file and its sidecar are closed again
+    let _directory = directory_slot(scan, path).await?;
+    let _permit = scan.open_files.acquire().await?;
+    let _disk = disk_slot(scan, path).await?;
+    let stopped = || scan.stop_error.lock().unwrap_or_else(std::sync::PoisonError::into_inner).is_some();
+    if scan.interrupted.load(Ordering::Relaxed) || stopped() {
+        return Ok(Extracted::Done(Outcome::Skipped(SkipReason::Interrupted)));
+    }
+    if scan.args.max_total_bytes.is_some_and(|budget| scan.bytes_started.load(Ordering::Relaxed) >= budget) {
+        return Ok(Extracted::Done(Outcome::Skipped(SkipReason::Budget)));
+    }
+    if scan.progress_template.is_some() {
+        *scan.current_file.lock().unwrap_or_else(std::sync::PoisonError::into_inner) = Some(path.to_path_buf());
+    }
+    if scan.args.input_format.is_none()
+        && let Some(extracted) = extract_by_extension(path, scan).await
+    {
+        return extracted;
+    }
+    // Without --sniff every file is taken to be what the scan was looking for
+    let format: &dyn Format = if let Some(input_format) = scan.args.input_format {
+        input_format.format()
+    } else if scan.args.sniff {
+        let Some(format) = formats::detect(path).await? else {
+            if scan.args.metadata_only_if_present {
+                return record_without_header(path, scan, serde_json::json!({})).await;
+            }
+            log::info!("Skipping {}: unrecognized format", path.display());
+            return Ok(Extracted::Done(Outcome::Skipped(SkipReason::Format)));
+        };
+        log::debug!("Reading {} as {}", path.display(), format.name());
+        format
+    } else {
+        &formats::Safetensors
+    };
+    if scan.args.metadata_only_if_present && !format.has_metadata() && !scan.args.print_header_raw {
+        return record_without_header(path, scan, serde_json::json!({})).await;
+    }
+    if scan.args.print_header_raw {
+        let raw = match format.read_raw_header(path, &scan.read_options).await {
+            Err(e) if still_being_written(scan, &e) => return Ok(skip_in_progress(path)),
+            raw => raw?,
+        };
+        scan.output.write_raw(&raw.bytes)?;
+        return Ok(Extracted::Done(Outcome::Processed));
+    }
+    let header = match read_header(scan, path, format).await {
+        Err(e) if still_being_written(scan, &e) => return Ok(skip_in_progress(path)),
+        header => header?,
+    };
+    // Counted as soon as the size is known, so files started meanwhile see it
+    scan.bytes_started.fetch_add(header.stat.len(), Ordering::Relaxed);
+    if scan.args.verbose > 0 && scan.args.header_endian == header::Endian::Auto {
+        eprintln!("{}: read the header length as {}", scan.shown(path), header.endian);
+    }
+    for warning in &header.warnings {
+        eprintln!("Warning: {}: {}", scan.shown(path), warning);
+    }
+    check_spec(scan, &header, header.stat.len())?;
+    let mut metadata = header_metadata(scan, &header);
+    check_double_encoding(scan, path, &mut metadata);
+    transform_metadata(scan, path, &mut metadata);
+    let sidecar = merge_sidecar(scan, path, &mut metadata).await;
+    if !matches_where(scan, &metadata) || !matches_tensors(scan, path, || format.list_tensors(&header)) {
+        return Ok(Extracted::Done(Outcome::Skipped(SkipReason::Filter)));
+    }
+    if scan.args.assert_param_count.is_some() {
+        check_parameter_count(scan, header.parameter_count(|name| tensor_selected(scan, name)))?;
+    }
+    write_thumbnails(scan, path, &metadata).await?;
+    // A sidecar being merged is curated by hand, so it mustn't be overwritten
+    if !scan.args.merge_sidecar && scan.args.per_file_out.is_none() {
+        let pretty_json = scan.args.line_ending.apply(serde_json::to_string_pretty(&metadata)?);
+        let written = match scan.output_paths.claim(sidecar::path(path), path) {
+            Ok(out) => io::atomic_write_async(out, pretty_json.into_bytes()).await,
+            Err(e) => Err(e),
+        };
+        continue_on_write_error(scan, written)?;
+    }
+
+    // Size and times come from the one `stat` done while reading the header
+    let mut record = build_record(path, metadata, &header.stat, scan)?;
+    record.sidecar = sidecar;
+    add_header_fields(scan, &mut record, path, format, &header, header.stat.len()).await?;
+    Ok(Extracted::Record(Box::new(record)))
+}
+
+/// Fill in the fields of a record that come from its header: its `--size-report` entry, the
+/// parameters of `--count-parameters-from shapes` and the fields of its tensors. `size` is
+/// that of the whole file, which for stdin isn't in the `stat` of the header.
+async fn add_header_fields(
+    scan: &Scan,
+    record: &mut Record,
+    path: &Path,
+    format: &dyn Format,
+    header: &header::Header,
+    size: u64,
+) -> anyhow::Result<()> {
+    if let Some(size_report) = &scan.size_report {
+        let metadata_len = header.json.get("__metadata__").map_or(0, |metadata| metadata.to_string().len());
+        size_report.add(path, header.len, metadata_len as u64, size);
+    }
+    if let Some(ParameterSource::Shapes) = scan.args.count_parameters_from {
+        set_parameters(record, header.parameter_count(|name| tensor_selected(scan, name)));
+    }
+    add_tensor_fields(scan, record, path, format, header).await
+}
+
+/// Fill in the fields of a record that come from the tensors of its header.
+async fn add_tensor_fields(
+    scan: &Scan,
+    record: &mut Record,
+    path: &Path,
+    format: &dyn Format,
+    header: &header::Header,
+) -> anyhow::Result<()> {
+    if scan.shard_groups.is_some() || scan.args.count_tensors {
+        record.tensors = Some(format.list_tensors(header).len());
+    }
+    if scan.args.check_total_size {
+        let sizes = format.list_tensors(header).into_iter().filter_map(|tensor| tensor.data_offsets);
+        record.tensor_bytes = Some(sizes.map(|[begin, end]| end.saturating_sub(begin)).sum());
+    }
+    if scan.args.verify_against_index_weightmap {
+        record.tensor_names = Some(format.list_tensors(header).into_iter().map(|tensor| tensor.name).collect());
+    }
+    if scan.args.classify {
+        record.kind = Some(classify::classify(&header.metadata(), &format.list_tensors(header)));
+    }
+    if scan.args.identify_tool {
+        record.producer = Some(producer::identify(&header.metadata()));
+    }
+    if scan.args.tensors {
+        set_tensor_list(scan, record, format.list_tensors(header));
+    }
+    if scan.args.detect_nan_inf {
+        let (tensors, max_bytes) = (format.list_tensors(header), scan.args.nan_check_max_bytes);
+        let data_start = 8 + header.len;
+        record.non_finite = Some(nan_inf::check(path, data_start, header.stat.len(), &tensors, max_bytes).await?);
+    }
+    Ok(())
+}
+
+/// Read and parse the header of `path`, taking the bytes `--prefetch` read ahead if it did.
+async fn read_header(scan: &Scan, path: &Path, format: &dyn Format) -> anyhow::Result<header::Header> {
+    if let Some(prefetch) = &scan.prefetch
+        && let Some(raw) = prefetch.take(path, |next| free_slots(scan, next)).await
+    {
+        return header::parse_header(raw?, &scan.read_options);
+    }
+    format.extract_metadata(path, &scan.read_options).await
+}
+
+/// Fail a file whose header breaks a rule of the safetensors spec, with `--strict-spec`,
+/// naming every rule it breaks, that has a tensor of a dtype the tool doesn't know, with
+/// `--bail-on-unknown-dtype`, or whose tensor data has gaps, with `--require-contiguous`.
+/// `file_len` is the length of the whole file, as for [`spec::check`].
+fn check_spec(scan: &Scan, header: &header::Header, file_len: u64) -> anyhow::Result<()> {
+    if scan.args.bail_on_unknown_dtype {
+        let unknown: Vec<String> =
+            spec::unknown_dtypes(header).iter().map(|(tensor, dtype)| format!("{tensor} ({dtype})")).collect();
+        if !unknown.is_empty() {
+            anyhow::bail!("Has tensors of unknown dtypes: {}", unknown.join(", "));
+        }
+    }
+    if scan.args.require_contiguous {
+        let gaps: Vec<String> = spec::gaps(header).iter().map(ToString::to_string).collect();
+        if !gaps.is_empty() {
+            anyhow::bail!("Has gaps between its tensors' data: {}", gaps.join(", "));
+        }
+    }
+    if !scan.args.strict_spec {
+        return Ok(());
+    }
+    let violations = spec::check(header, file_len);
+    if !violations.is_empty() {
+        let violations: Vec<String> = violations.iter().map(ToString::to_string).collect();
+        anyhow::bail!("Doesn't conform to the safetensors spec: {}", violations.join("; "));
+    }
+    Ok(())
+}
+
+/// The metadata of a header. Without `__metadata__` that is the whole header, or nothing with
+/// `--metadata-only-if-present`.
+fn header_metadata(scan: &Scan, header: &header::Header) -> serde_json::Value {
+    if scan.args.metadata_only_if_present && header.json.get("__metadata__").is_none() {
+        serde_json::json!({})
+    } else {
+        header.metadata()
+    }
+}
+
+/// The record of a file read by its `--extractor` command.
+async fn extract_external(path: &Path, scan: &Scan, extractor: &extractor::Extractor) -> anyhow::Result<Extracted> {
+    record_without_header(path, scan, extractor.run(path).await?).await
+}
+
+/// The record of a file whose metadata didn't come from a header we read: from an
+/// `--extractor`, or empty with `--metadata-only-if-present` for formats without metadata.
+async fn record_without_header(path: &Path, scan: &Scan, mut metadata: serde_json::Value) -> anyhow::Result<Extracted> {
+    let producer = scan.args.identify_tool.then(|| producer::identify(&metadata));
+    check_double_encoding(scan, path, &mut metadata);
+    transform_metadata(scan, path, &mut metadata);
+    let sidecar = merge_sidecar(scan, path, &mut metadata).await;
+    if !matches_where(scan, &metadata) || !matches_tensors(scan, path, Vec::new) {
+        return Ok(Extracted::Done(Outcome::Skipped(SkipReason::Filter)));
+    }
+    write_thumbnails(scan, path, &metadata).await?;
+    let stat = tokio::fs::metadata(path).await?;
+    scan.bytes_started.fetch_add(stat.len(), Ordering::Relaxed);
+    let mut record = build_record(path, metadata, &stat, scan)?;
+    record.sidecar = sidecar;
+    if scan.args.classify {
+        // Without tensors to go on only the metadata rules apply
+        record.kind = Some(classify::classify(&record.metadata, &[]));
+    }
+    record.producer = producer;
+    Ok(Extracted::Record(Box::new(record)))
+}
+
+/// The record of a sharded model from its `*.index.json` alone: the tensor count, the shards
+/// with the sizes of those present and the size of the tensor data from the index metadata.
+/// Parameters are only counted from the shards if all of them are present.
+async fn extract_index(path: &Path, scan: &Scan) -> anyhow::Result<Extracted> {
+    let index = shards::read_index(path)?;
+    let mut metadata = index.metadata;
+    transform_metadata(scan, path, &mut metadata);
+    // The dtypes are only in the shards
+    if !matches_where(scan, &metadata) || !matches_tensors(scan, path, Vec::new) {
+        return Ok(Extracted::Done(Outcome::Skipped(SkipReason::Filter)));
+    }
+    let stat = tokio::fs::metadata(path).await?;
+    let mut record = build_record(path, metadata, &stat, scan)?;
+    // The size of the model rather than of the index file
+    record.size = index.total_size;
+    record.size_human = index.total_size.map(humanize::bytes);
+    record.tensors = Some(index.tensors);
+
+    let mut sizes = std::collections::BTreeMap::new();
+    let mut missing = Vec::new();
+    for shard in &index.shards {
+        match tokio::fs::metadata(shard).await {
+            Ok(stat) => {
+                sizes.insert(shard.clone(), stat.len());
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => missing.push(shard.clone()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to stat shard {}", shard.display())),
+        }
+    }
+    if !missing.is_empty() {
+        eprintln!("Warning: {}: {} of {} shards are missing", scan.shown(path), missing.len(), index.shards.len());
+    } else if scan.args.count_parameters_from.is_some() || scan.args.assert_param_count.is_some() {
+        let mut parameters = 0u128;
+        for shard in &index.shards {
+            let header = formats::Safetensors.extract_metadata(shard, &scan.read_options).await?;
+            parameters = parameters.saturating_add(header.parameter_count(|name| tensor_selected(scan, name)));
+        }
+        check_parameter_count(scan, parameters)?;
+        if let Some(ParameterSource::Shapes) = scan.args.count_parameters_from {
+            set_parameters(&mut record, parameters);
+        }
+    }
+    record.shards = Some(index.shards);
+    record.shard_sizes = Some(sizes);
+    record.missing_shards = (!missing.is_empty()).then_some(missing);
+    Ok(Extracted::Record(Box::new(record)))
+}
+
+/// Emit the records of the safetensors files in an archive (`--recurse-archives`), which can
+/// hold any number of them. Members get no sidecar, there being nowhere to write one, and are
+/// not hashed; their times are the archive's.
+async fn extract_archive(path: &Path, scan: &Scan) -> anyhow::Result<Extracted> {
+    let limits = archive::Limits { depth: scan.args.archive_depth, max_expansion: scan.args.max_archive_expansion };
+    let archive_path = path.to_path_buf();
+    let members = tokio::task::spawn_blocking(move || archive::members(&archive_path, limits)).await??;
+    let stat = tokio::fs::metadata(path).await?;
+    scan.bytes_started.fetch_add(stat.len(), Ordering::Relaxed);
+    // Members outside --assert-param-count fail the archive once the others are emitted
+    let mut outside = Vec::new();
+    for member in members {
+        let raw = header::RawHeader { bytes: member.header, endian: header::Endian::Le, stat: stat.clone() };
+        let header = header::parse_header(raw, &scan.read_options).with_context(|| format!("Failed to parse {}", member.path.display()))?;
+        for warning in &header.warnings {
+            eprintln!("Warning: {}: {}", scan.shown(&member.path), warning);
+        }
+        let mut metadata = header.metadata();
+        check_double_encoding(scan, &member.path, &mut metadata);
+        // The member's own name, after those of the archives it is in
+        let member_name = member.path.to_string_lossy().rsplit('!').next().map(PathBuf::from).unwrap_or_default();
+        transform_metadata(scan, &member_name, &mut metadata);
+        if !matches_where(scan, &metadata) || !matches_tensors(scan, &member.path, || formats::Safetensors.list_tensors(&header)) {
+            continue;
+        }
+        if scan.args.assert_param_count.is_some()
+            && let Err(e) = check_parameter_count(scan, header.parameter_count(|name| tensor_selected(scan, name)))
+        {
+            outside.push(format!("{}: {e}", member.path.display()));
+            continue;
+        }
+        write_thumbnails(scan, &member.path, &metadata).await?;
+        let mut record = build_record(&member.path, metadata, &stat, scan)?;
+        record.source = source_of(scan, path);
+        if scan.args.with_size || scan.args.by_architecture {
+            record.size = Some(member.size);
+            record.size_human = Some(humanize::bytes(member.size));
+        }
+        if let Some(ParameterSource::Shapes) = scan.args.count_parameters_from {
+            set_parameters(&mut record, header.parameter_count(|name| tensor_selected(scan, name)));
+        }
+        if scan.shard_groups.is_some() || scan.args.count_tensors {
+            record.tensors = Some(formats::Safetensors.list_tensors(&header).len());
+        }
+        if scan.args.classify {
+            record.kind = Some(classify::classify(&header.metadata(), &formats::Safetensors.list_tensors(&header)));
+        }
+        if scan.args.identify_tool {
+            record.producer = Some(producer::identify(&header.metadata()));
+        }
+        if scan.args.tensors {
+            set_tensor_list(scan, &mut record, formats::Safetensors.list_tensors(&header));
+        }
+        emit(scan, record)?;
+    }
+    if !outside.is_empty() {
+        anyhow::bail!("{}", outside.join("; "));
+    }
+    Ok(Extracted::Done(Outcome::Processed))
+}
+
+/// Fail a file whose parameter count is outside `--assert-param-count`.
+fn check_parameter_count(scan: &Scan, parameters: u128) -> anyhow::Result<()> {
+    match &scan.args.assert_param_count {
+        Some(bound) if !bound.contains(parameters) => {
+            scan.outside_param_count.fetch_add(1, Ordering::Relaxed);
+            let count = format!("{} ({})", humanize::thousands(parameters), humanize::count(parameters));
+            anyhow::bail!("Has {count} parameters, outside --assert-param-count {bound}")
+        }
+        _ => Ok(()),
+    }
+}
+
+fn set_parameters(record: &mut Record, parameters: u128) {
+    record.parameters = Some(parameters);
+    record.parameters_human = Some(format!("{} ({})", humanize::thousands(parameters), humanize::count(parameters)));
+}
+
+/// How recently a file must have been modified for `--partial-ok` to take a header running
+/// past its end as a write in progress rather than a truncated file.
+const PARTIAL_WRITE_WINDOW: Duration = Duration::from_mins(1);
+
+/// Whether reading a file failed because it is still being written and `--partial-ok` says to
+/// skip it. A file whose modification time can't be read is given the benefit of the doubt.
+fn still_being_written(scan: &Scan, error: &anyhow::Error) -> bool {
+    scan.args.partial_ok
+        && error.downcast_ref::<header::Truncated>().is_some_and(|truncated| {
+            truncated.modified.and_then(|modified| modified.elapsed().ok()).is_none_or(|age| age < PARTIAL_WRITE_WINDOW)
+        })
+}
+
+/// How many times `--watch-debounce` waits for a file to stop changing before skipping it.
+const SETTLE_ATTEMPTS: u32 = 10;
+
+/// Wait until `path` has kept its size and modification time for `window`, for
+/// `--watch-debounce`. Returns `false` if it was still changing after [`SETTLE_ATTEMPTS`]
+/// windows.
+async fn settle(path: &Path, window: Duration) -> anyhow::Result<bool> {
+    let stat = |path| async move {
+        let stat = tokio::fs::metadata(path).await.context("Failed to read file metadata")?;
+        anyhow::Ok((stat.len(), stat.modified().ok()))
+    };
+    let mut before = stat(path).await?;
+    for _ in 0..SETTLE_ATTEMPTS {
+        tokio::time::sleep(window).await;
+        let after = stat(path).await?;
+        if after == before {
+            return Ok(true);
+        }
+        before = after;
+    }
+    Ok(false)
+}
+
+fn skip_in_progress(path: &Path) -> Extracted {
+    log::info!("Skipping {}: still being written", path.display());
+    Extracted::Done(Outcome::Skipped(SkipReason::InProgress))
+}
+
+/// Add the `--hash` digest and `--sign` signature to a record and emit it.
+/// `--count-distinct-files-by-hash` compares the digests, so it needs them too.
+async fn hash_and_emit(mut record: Record, scan: &Scan) -> anyhow::Result<Outcome> {
+    if scan.args.hash || scan.args.count_distinct_files_by_hash {
+        let _directory = directory_slot(scan, &record.path).await?;
+        let _permit = scan.open_files.acquire().await?;
+        let _disk = disk_slot(scan, &record.path).await?;
+        record.sha256 = Some(hash::sha256_file(&record.path, scan.args.hash_chunk_size.get()).await?);
+    }
+    if let Some(key) = &scan.signing_key {
+        let signature = signing::sign(key, &record.metadata);
+        let _permit = scan.open_files.acquire().await?;
+        let written = io::atomic_write_async(signing::signature_path(&record.path), signature.clone().into_bytes()).await;
+        continue_on_write_error(scan, written)?;
+        record.signature = Some(signature);
+    }
+    if let (Some(template), Some(base)) = (&scan.args.per_file_out, &scan.per_file_base) {
+        let out = template.render(&record.path, base, record.sha256.as_deref())?;
+        let out = match scan.output_paths.claim(out, &record.path) {
+            Ok(out) => out,
+            Err(e) => return continue_on_write_error(scan, Err(e)).map(|()| Outcome::Processed),
+        };
+        if let Some(parent) = out.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            tokio::fs::create_dir_all(parent).await.with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        let pretty_json = scan.args.line_ending.apply(serde_json::to_string_pretty(&record.metadata)?);
+        continue_on_write_error(scan, io::atomic_write_async(out, pretty_json.into_bytes()).await)?;
+    }
+    emit(scan, record)?;
+    Ok(Outcome::Processed)
+}
+
+/// Print a record, or keep it for its directory's manifest with `--group-output-by-dir`.
+/// With `--group-shards` the records of shards are held back to be combined at the end.
+fn emit(scan: &Scan, record: Record) -> anyhow::Result<()> {
+    let record = match &scan.shard_groups {
+        Some(shard_groups) => {
+            let bytes = match &scan.memory_budget {
+                Some(_) => Some(serde_json::to_vec(&record)?.len() as u64),
+                None => None,
+            };
+            let Some(record) = shard_groups.add(record) else {
+                // A shard, held until the rest of its model is found
+                if let (Some(budget), Some(bytes)) = (&scan.memory_budget, bytes) {
+                    budget.charge(bytes)?;
+                }
+                return Ok(());
+            };
+            record
+        }
+        None => record,
+    };
+    emit_combined(scan, record)
+}
+
+/// Print a record that is final, whether it is of one file or of a combined sharded model.
+fn emit_combined(scan: &Scan, mut record: Record) -> anyhow::Result<()> {
+    if scan.args.only_errors {
+        return Ok(());
+    }
+    if scan.args.with_schema_version {
+        record.schema_version = Some(record::SCHEMA_VERSION);
+    }
+    if scan.args.with_id {
+        record.id = Some(hash::path_id(&record.path));
+    }
+    if scan.args.with_relpath {
+        record.relpath = relpath(scan, &record.path);
+    }
+    if scan.args.count_tensors {
+        scan.summary.record_tensors(record.tensors.unwrap_or(0));
+    }
+    if let Some(percentiles) = &scan.percentiles {
+        percentiles.add(&record.metadata);
+    }
+    let reported = add_to_reports(scan, &record);
+    charge_held(scan, &record, reported)?;
+    if reported {
+        return Ok(());
+    }
+    if let Some(collected) = &scan.collected {
+        collected.records.lock().unwrap_or_else(std::sync::PoisonError::into_inner).push(record);
+        return Ok(());
+    }
+    let dir = record.path.parent().map(Path::to_path_buf).unwrap_or_default();
+    // Keyed by the path the file was read from, before it is made relative
+    let read_from = scan.incremental.is_some().then(|| record.path.clone());
+    if let Some(base) = &scan.relative_to {
+        for path in record.relativize_paths(base) {
+            eprintln!("Warning: {}: Can't be made relative to {}, so it's written as it is", path.display(), base.display());
+        }
+    }
+    match (&scan.manifests, &scan.catalog, &scan.incremental) {
+        (Some(manifests), _, _) => manifests.add(dir, record),
+        (None, Some(catalog), _) => catalog.add(&record)?,
+        (None, None, Some(state)) => state.add(read_from.as_deref().unwrap_or(&record.path), &record)?,
+        (None, None, None) => scan.output.write_record(&record)?,
+    }
+    Ok(())
+}
+
+/// Add a record to each of the reports printed in place of the records, such as `--distinct`
+/// and `--histogram`, which may be given together. Whether there are any, in which case the
+/// record isn't printed itself. `--metadata-size-report` is fed as the headers are read.
+fn add_to_reports(scan: &Scan, record: &Record) -> bool {
+    if let Some(distinct) = &scan.distinct {
+        distinct.add(&record.metadata);
+    }
+    if let Some(histogram) = &scan.histogram {
+        histogram.add(&record.metadata);
+    }
+    if let Some(tag_frequencies) = &scan.tag_frequencies {
+        tag_frequencies.add(&record.metadata);
+    }
+    if let Some(drift) = &scan.drift {
+        drift.add(record);
+    }
+    if let Some(architectures) = &scan.architectures {
+        architectures.add(record);
+    }
+    if let Some(duplicates) = &scan.duplicates {
+        duplicates.add(record);
+    }
+    if let Some(by_metadata) = &scan.by_metadata {
+        by_metadata.add(record);
+    }
+    scan.distinct.is_some()
+        || scan.histogram.is_some()
+        || scan.tag_frequencies.is_some()
+        || scan.drift.is_some()
+        || scan.size_report.is_some()
+        || scan.architectures.is_some()
+        || scan.duplicates.is_some()
+        || scan.by_metadata.is_some()
+}
+
+/// Count a record against `--max-memory` if it is held until the end of the scan: by
+/// `--compare-to`, `--dedupe-by` and `--count-distinct-files-by-hash`, which keep an entry for
+/// each file, if it was `reported`, or else by `--merge-into`, `--incremental`,
+/// `--group-output-by-dir` or [`scan`]. The other reports only keep a count for each value, and
+/// `--percentiles` a number for each file, so they aren't counted.
+fn charge_held(scan: &Scan, record: &Record, reported: bool) -> anyhow::Result<()> {
+    let Some(budget) = &scan.memory_budget else { return Ok(()) };
+    let held = if reported {
+        scan.drift.is_some() || scan.duplicates.is_some() || scan.by_metadata.is_some()
+    } else {
+        scan.manifests.is_some() || scan.catalog.is_some() || scan.incremental.is_some() || scan.collected.is_some()
+    };
+    if held {
+        budget.charge(serde_json::to_vec(record)?.len() as u64)?;
+    }
+    Ok(())
+}
+
+/// Warn about the double-encoded values in a file's metadata with `--detect-double-encoding`,
+/// and unwrap them with `--fix-double-encoding`. Only the emitted metadata changes.
+fn check_double_encoding(scan: &Scan, path: &Path, metadata: &mut serde_json::Value) {
+    if !scan.args.detect_double_encoding && !scan.args.fix_double_encoding {
+        return;
+    }
+    let keys = encoding::double_encoded(metadata, scan.args.fix_double_encoding);
+    if !keys.is_empty() {
+        let action = if scan.args.fix_double_encoding { "Unwrapped" } else { "Found" };
+        eprintln!("Warning: {}: {action} double-encoded JSON in {}", scan.shown(path), keys.join(", "));
+    }
+}
+
+/// Write the preview images embedded in a file's metadata into the `--extract-thumbnails`
+/// directory, warning about values that look like images but aren't.
+async fn write_thumbnails(scan: &Scan, path: &Path, metadata: &serde_json::Value) -> anyhow::Result<()> {
+    let Some(dir) = &scan.args.extract_thumbnails else { return Ok(()) };
+    let (found, invalid) = thumbnails::find(metadata);
+    if !invalid.is_empty() {
+        eprintln!("Warning: {}: Skipped thumbnails that aren't base64 images: {}", scan.shown(path), invalid.join(", "));
+    }
+    if found.is_empty() {
+        return Ok(());
+    }
+    tokio::fs::create_dir_all(dir).await.with_context(|| format!("Failed to create {}", dir.display()))?;
+    for thumbnail in &found {
+        let image = dir.join(thumbnails::file_name(path, thumbnail));
+        continue_on_write_error(scan, io::atomic_write_async(image, thumbnail.bytes.clone()).await)?;
+    }
+    Ok(())
+}
+
+/// With `--continue-on-write-error`, warn about a file that couldn't be written and count it,
+/// so the record is still emitted. Other errors, and write errors without the option, are passed
+/// on.
+fn continue_on_write_error(scan: &Scan, result: anyhow::Result<()>) -> anyhow::Result<()> {
+    match result {
+        Err(e) if scan.args.continue_on_write_error && e.is::<io::WriteError>() => {
+            eprintln!("Warning: {e:#}");
+            scan.summary.record_write_error();
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+/// Apply `--canonicalize-metadata-strings`, `--normalize-metadata` and `--prefix`, if given,
+/// then add the `--parse-filename` captures of the file name of `path`.
+fn transform_metadata(scan: &Scan, path: &Path, metadata: &mut serde_json::Value) {
+    if scan.args.canonicalize_metadata_strings {
+        normalize::canonicalize_strings(metadata);
+    }
+    let serde_json::Value::Object(map) = metadata else { return };
+    if let Some(normalizer) = &scan.normalizer {
+        normalizer.normalize(map);
+    }
+    if !scan.args.prefix.is_empty() {
+        filter::filter_prefixes(map, &scan.args.prefix, scan.args.strip_prefix);
+    }
+    if let Some(regex) = &scan.args.parse_filename {
+        filename::add_captures(regex, path, map);
+    }
+}
+
+/// With `--merge-sidecar`, fold the file's sidecar into `metadata` and return the part to nest
+/// in the record. A sidecar that can't be read is left out with a warning.
+async fn merge_sidecar(scan: &Scan, path: &Path, metadata: &mut serde_json::Value) -> Option<serde_json::Value> {
+    if !scan.args.merge_sidecar {
+        return None;
+    }
+    match sidecar::read(path).await {
+        Ok(sidecar) => sidecar.and_then(|sidecar| sidecar::merge(metadata, sidecar, scan.args.sidecar_merge_mode)),
+        Err(e) => {
+            eprintln!("Warning: {}: Ignored the sidecar: {:#}", scan.shown(path), e);
+            None
+        }
+    }
+}
+
+/// Whether `metadata` satisfies every `--where` condition, has every `--has-key` and lacks
+/// every `--missing-key`.
+fn matches_where(scan: &Scan, metadata: &serde_json::Value) -> bool {
+    scan.args.r#where.iter().all(|condition| condition.matches(metadata))
+        && scan.args.has_key.iter().all(|key| metadata.get(key).is_some())
+        && scan.args.missing_key.iter().all(|key| metadata.get(key).is_none())
+        && scan.args.filter.as_ref().is_none_or(|filter| filter.matches(metadata))
+}
+
+/// Whether a file's tensors satisfy `--has-dtype`, `--only-dtype`, `--tensor-shape` and
+/// `--tensor-count`, which a file without tensors never does. Dtypes are compared ignoring
+/// case. `tensors` is only called if the options are given; with `-v` the number of tensors of
+/// each dtype asked for is printed, the shape of a tensor that doesn't have the one asked for,
+/// and the number of tensors of a file outside the `--tensor-count`.
+fn matches_tensors(scan: &Scan, path: &Path, tensors: impl FnOnce() -> Vec<formats::TensorInfo>) -> bool {
+    let args = &scan.args;
+    let by_dtype = !args.has_dtype.is_empty() || args.only_dtype.is_some();
+    if !by_dtype && args.tensor_shape.is_empty() && args.tensor_count.is_none() {
+        return true;
+    }
+    let tensors = tensors();
+    let mut matches = !tensors.is_empty();
+    if let Some(bound) = &args.tensor_count {
+        let fits = bound.contains(tensors.len() as u128);
+        if args.verbose > 0 && !fits {
+            eprintln!("{}: has {} tensors, not {bound}", scan.shown(path), tensors.len());
+        }
+        matches &= fits;
+    }
+    for dtype in scan.args.has_dtype.iter().chain(&scan.args.only_dtype) {
+        let count = tensors.iter().filter(|tensor| tensor.dtype.eq_ignore_ascii_case(dtype)).count();
+        if scan.args.verbose > 0 {
+            eprintln!("{}: {count} of {} tensors are {dtype}", scan.shown(path), tensors.len());
+        }
+        let wanted = if scan.args.only_dtype.as_ref() == Some(dtype) { tensors.len() } else { 1 };
+        matches &= count >= wanted;
+    }
+    for condition in &scan.args.tensor_shape {
+        let shape = tensors.iter().find(|tensor| tensor.name == condition.name).map(|tensor| &tensor.shape);
+        let fits = shape.is_some_and(|shape| condition.matches(shape));
+        if scan.args.verbose > 0 && !fits {
+            let name = &condition.name;
+            match shape {
+                Some(shape) => eprintln!("{}: {name} has the shape {shape:?}, not {condition}", scan.shown(path)),
+                None => eprintln!("{}: has no tensor {name}", scan.shown(path)),
+            }
+        }
+        matches &= fits;
+    }
+    matches
+}
+
+/// Whether a tensor passes `--tensor-filter`, which lets everything through when not given.
+fn tensor_selected(scan: &Scan, name: &str) -> bool {
+    scan.args.tensor_filter.is_empty() || scan.args.tensor_filter.iter().any(|pattern| pattern.matches(name))
+}
+
+/// The tensors `--tensors` lists: those passing `--tensor-filter`, with their canonical names
+/// from `--tensor-name-map`, and only the `--first-n-tensors` of them in their order, the
+/// others counted in `tensors_not_listed`.
+fn set_tensor_list(scan: &Scan, record: &mut Record, tensors: Vec<formats::TensorInfo>) {
+    let mut tensors: Vec<_> = tensors
+        .into_iter()
+        .filter(|tensor| tensor_selected(scan, &tensor.name))
+        .map(|mut tensor| {
+            tensor.canonical_name = scan.tensor_name_map.as_ref().and_then(|map| map.canonical(&tensor.name));
+            tensor
+        })
+        .collect();
+    if scan.args.sort_tensors_by == cli::TensorOrder::Offset {
+        formats::sort_by_offset(&mut tensors);
+    } else if scan.args.natural_key_order {
+        tensors.sort_by(|a, b| output::natural_cmp(&a.name, &b.name));
+    }
+    if let Some(first) = scan.args.first_n_tensors {
+        record.tensors_not_listed = Some(tensors.len().saturating_sub(first));
+        tensors.truncate(first);
+    }
+    record.tensor_list = Some(tensors);
+}
+
+/// The record for `path`, with the fields requested by `--with-size` and `--with-mtime`
+/// taken from `stat`. `--by-architecture` and `--count-distinct-files-by-hash` sum the sizes,
+/// so they need them too, as does `--dedupe-by` to pick the file to keep.
+fn build_record(path: &Path, metadata: serde_json::Value, stat: &std::fs::Metadata, scan: &Scan) -> anyhow::Result<Record> {
+    let mut record = Record { path: path.to_path_buf(), source: source_of(scan, path), metadata, ..Record::default() };
+    if !scan.args.pointer.is_empty() {
+        record.pointers = Some(filter::evaluate_pointers(&record.metadata, &scan.args.pointer));
+    }
+    let dedupe = !scan.args.dedupe_by.is_empty();
+    if scan.args.with_size || scan.args.by_architecture || scan.args.count_distinct_files_by_hash || dedupe {
+        record.size = Some(stat.len());
+        record.size_human = Some(humanize::bytes(stat.len()));
+    }
+    if scan.args.with_mtime || dedupe {
+        let modified = stat.modified().context("Failed to read modification time")?;
+        record.modified = Some(humantime::format_rfc3339_seconds(modified).to_string());
+        record.modified_epoch = modified
+            .duration_since(std::time::UNIX_EPOCH)
+            .ok()
+            .map(|since| since.as_secs());
+    }
+    Ok(record)
+}
+
+/// Wait for a `--workers-per-disk` slot on the disk holding `path`, if that option is given.
+async fn disk_slot(scan: &Scan, path: &Path) -> anyhow::Result<Option<tokio::sync::OwnedSemaphorePermit>> {
+    match &scan.disk_limits {
+        Some(limits) => Ok(Some(limits.acquire(path).await?)),
+        None => Ok(None),
+    }
+}
+
+/// The slots reading `path` takes, as [`extract`] waits for them, if they are all free now:
+/// for `--prefetch`, which reads ahead only what it may without holding up the scan.
+fn free_slots(scan: &Scan, path: &Path) -> Option<Vec<tokio::sync::OwnedSemaphorePermit>> {
+    let mut slots = Vec::new();
+    if let Some(limits) = &scan.directory_limits {
+        slots.push(limits.try_acquire(path.parent().unwrap_or(path).to_path_buf())?);
+    }
+    slots.push(Arc::clone(&scan.open_files).try_acquire_owned().ok()?);
+    if let Some(limits) = &scan.disk_limits {
+        slots.push(limits.try_acquire(path)?);
+    }
+    Some(slots)
+}
+
+/// With `--concurrency-scope per-directory`, wait for a slot in the directory holding `path`.
+/// Taken before the `--max-open-files` permit, so a large directory can't hold them all.
+async fn directory_slot(scan: &Scan, path: &Path) -> anyhow::Result<Option<tokio::sync::OwnedSemaphorePermit>> {
+    match &scan.directory_limits {
+        Some(limits) => Ok(Some(limits.acquire(path.parent().unwrap_or(path).to_path_buf()).await?)),
+        None => Ok(None),
+    }
+}
+
+/// Run `future` under `--timeout`, if given.
+async fn with_timeout<T>(scan: &Scan, future: impl Future<Output = anyhow::Result<T>>) -> anyhow::Result<T> {
+    match scan.args.timeout {
+        Some(timeout) => tokio::time::timeout(timeout, future)
+            .await
+            .unwrap_or_else(|_| Err(anyhow::anyhow!("Timed out after {}", humantime::format_duration(timeout)))),
+        None => future.await,
+    }
+}
+
+/// Process a file, reporting failure as a warning so the remaining files are still processed.
+async fn process_or_warn(path: &Path, scan: &Scan) {
+    report(path, scan, with_timeout(scan, process(path, scan)).await);
+}
+
+/// Record the outcome of a file in the summary, printing a warning if it failed.
+/// With `--only-errors` the failure is written to the output as an [`record::ErrorRecord`]
+/// instead, and for [`scan`] it is kept. With `--emit-empty-record-for-skipped` a skipped
+/// file is written as a [`record::SkippedRecord`].
+fn report(path: &Path, scan: &Scan, result: anyhow::Result<Outcome>) {
+    if let Some(junit) = &scan.junit {
+        junit.add(path, &result);
+    }
+    let outcome = result.unwrap_or_else(|e| {
+        let stop_error = if e.is::<io::WriteError>() {
+            scan.summary.record_write_error();
+            Some(format!("{e:#}. Pass --continue-on-write-error to scan the rest regardless"))
+        } else {
+            e.is::<memory::OverBudget>().then(|| format!("{e:#}"))
+        };
+        if let Some(error) = stop_error {
+            scan.stop_error.lock().unwrap_or_else(std::sync::PoisonError::into_inner).get_or_insert(error);
+        }
+        let unreadable = e.downcast_ref::<header::Unreadable>().map(|unreadable| unreadable.source.kind());
+        if let Some(kind) = unreadable {
+            scan.summary.record_unreadable(path, kind);
+        }
+        if let Some(collected) = &scan.collected {
+            collected.errors.lock().unwrap_or_else(std::sync::PoisonError::into_inner).push((path.to_path_buf(), e));
+        } else if scan.args.only_errors {
+            let category = match unreadable {
+                Some(_) => record::FailureCategory::Unreadable,
+                None => record::FailureCategory::Unparseable,
+            };
+            let os_error = unreadable.map(|kind| format!("{kind:?}"));
+            let record = record::ErrorRecord { path, error: format!("{e:#}"), category, os_error };
+            if let Err(e) = scan.output.write_record(&record) {
+                eprintln!("Warning: Failed to write the error of {}: {:#}", scan.shown(path), e);
+            }
+        } else {
+            scan.warn_failed(path, &e);
+        }
+        Outcome::Failed
+    });
+    if let Outcome::Skipped(skipped) = outcome
+        && scan.args.emit_empty_record_for_skipped
+        && scan.collected.is_none()
+        && let Err(e) = scan.output.write_record(&record::SkippedRecord { path, skipped })
+    {
+        eprintln!("Warning: Failed to write the record of skipped {}: {:#}", scan.shown(path), e);
+    }
+    scan.summary.record(outcome);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dset::{process_safetensors_file, xio::walk_directory};
+    use tokio::fs::{self, File};
+    use tokio::io::AsyncWriteExt;
+    use tokio::time::{sleep, Duration};
+
+    async fn create_dummy_safetensors(path: &Path) -> anyhow::Result<()> {
+        let dir = path.parent().unwrap();
+        fs::create_dir_all(dir).await?;
+        let mut file = File::create(path).await?;
+        // Write a minimal valid safetensors header
+        let header = r#"{"__metadata__":{"foo":"bar"}}"#;
+        let header_len = header.len() as u64;
+        let mut header_bytes = header_len.to_le_bytes().to_vec();
+        header_bytes.extend(header.as_bytes());
+        file.write_all(&header_bytes).await?;
+        file.flush().await?;
+        // Drop the file handle explicitly
+        drop(file);
+        // Small delay to ensure filesystem operations complete
+        sleep(Duration::from_millis(50)).await;
+        Ok(())
+    }
+
+    async fn create_dummy_file(path: &Path, content: &str) -> anyhow::Result<()> {
+        let dir = path.parent().unwrap();
+        fs::create_dir_all(dir).await?;
+        let mut file = File::create(path).await?;
+        file.write_all(content.as_bytes()).await?;
+        file.flush().await?;
+        drop(file);
+        sleep(Duration::from_millis(50)).await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_single_file() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let file_path = temp_dir.path().join("test.safetensors");
+        create_dummy_safetensors(&file_path).await?;
+        
+        let result = process_safetensors_file(&file_path).await;
+        if result.is_err() {
+            eprintln!("Error processing file: {result:?}");
+        }
+        assert!(result.is_ok());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_directory() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let file1 = temp_dir.path().join("test1.safetensors");
+        let file2 = temp_dir.path().join("subdir").join("test2.safetensors");
+        
+        create_dummy_safetensors(&file1).await?;
+        create_dummy_safetensors(&file2).await?;
+
+        let result = walk_directory(temp_dir.path(), "safetensors", |file_path| {
+            let path_buf = file_path.to_path_buf();
+            async move { process_safetensors_file(&path_buf).await }
+        }).await;
+        
+        assert!(result.is_ok());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_invalid_path() -> anyhow::Result<()> {
+        let invalid_path = Path::new("nonexistent.safetensors");
+        let result = process_safetensors_file(invalid_path).await;
+        assert!(result.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_glob_pattern() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let file1 = temp_dir.path().join("test1.safetensors");
+        let file2 = temp_dir.path().join("test2.safetensors");
+        
+        create_dummy_safetensors(&file1).await?;
+        create_dummy_safetensors(&file2).await?;
+
+        let pattern = temp_dir.path().join("*.safetensors");
+        let pattern_str = pattern.to_str().unwrap();
+
+        for entry in glob(pattern_str)? {
+            match entry {
+                Ok(path) => {
+                    let result = process_safetensors_file(&path).await;
+                    assert!(result.is_ok());
+                }
+                Err(e) => panic!("Failed to process glob entry: {e:?}"),
+            }
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_only_process_safetensors() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        
+        // Create various file types
+        let safetensors_file = temp_dir.path().join("model.safetensors");
+        let toml_file = temp_dir.path().join("config.toml");
+        let txt_file = temp_dir.path().join("readme.txt");
+        let fake_safetensors = temp_dir.path().join("fake.safetensors.txt");
+
+        create_dummy_safetensors(&safetensors_file).await?;
+        create_dummy_file(&toml_file, "[config]\nkey = 'value'").await?;
+        create_dummy_file(&txt_file, "This is a text file").await?;
+        create_dummy_file(&fake_safetensors, "Not a real safetensors file").await?;
+
+        // Test directory walking
+        let processed_files = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let processed_files_clone = processed_files.clone();
+
+        let result = walk_directory(temp_dir.path(), "safetensors", move |file_path| {
+            let processed_files = processed_files_clone.clone();
+            let path_buf = file_path.to_path_buf();
+            async move {
+                processed_files.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                process_safetensors_file(&path_buf).await
+            }
+        }).await;
+
+        assert!(result.is_ok());
+        // Only one file should have been processed (the real safetensors file)
+        assert_eq!(processed_files.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        // Test glob pattern
+        let pattern = temp_dir.path().join("*.safetensors");
+        let pattern_str = pattern.to_str().unwrap();
+        let mut glob_processed = 0;
+
+        for entry in glob(pattern_str)? {
+            match entry {
+                Ok(path) => {
+                    let result = process_safetensors_file(&path).await;
+                    assert!(result.is_ok());
+                    glob_processed += 1;
+                }
+                Err(e) => panic!("Failed to process glob entry: {e:?}"),
+            }
+        }
+
+        // Only one file should match the glob pattern
+        assert_eq!(glob_processed, 1);
+        
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_relative_paths() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let subdir = temp_dir.path().join("subdir");
+        let file_path = subdir.join("test.safetensors");
+        
+        create_dummy_safetensors(&file_path).await?;
+        
+        // Change to the temp directory to test relative paths
+        let original_dir = env::current_dir()?;
+        env::set_current_dir(temp_dir.path())?;
+
+        // Test with relative path
+        let result = walk_directory(Path::new("."), "safetensors", move |file_path| {
+            let path_buf = std::fs::canonicalize(file_path).unwrap_or_else(|_| file_path.to_path_buf());
+            async move { process_safetensors_file(&path_buf).await }
+        }).await;
+        assert!(result.is_ok());
+
+        // Test with absolute path
+        let result = walk_directory(temp_dir.path(), "safetensors", move |file_path| {
+            let path_buf = std::fs::canonicalize(file_path).unwrap_or_else(|_| file_path.to_path_buf());
+            async move { process_safetensors_file(&path_buf).await }
+        }).await;
+        assert!(result.is_ok());
+
+        // Test with mixed paths (some relative, some absolute)
+        let mixed_dir = temp_dir.path().join("mixed");
+        fs::create_dir_all(&mixed_dir).await?;
+        let abs_file = mixed_dir.join("abs.safetensors");
+        let rel_file = mixed_dir.join("rel.safetensors");
+        
+        create_dummy_safetensors(&abs_file).await?;
+        create_dummy_safetensors(&rel_file).await?;
+
+        let processed_files = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let processed_files_clone = processed_files.clone();
+
+        let result = walk_directory(&mixed_dir, "safetensors", move |file_path| {
+            let processed_files = processed_files_clone.clone();
+            let path_buf = std::fs::canonicalize(file_path).unwrap_or_else(|_| file_path.to_path_buf());
+            async move {
+                processed_files.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                process_safetensors_file(&path_buf).await
+            }
+        }).await;
+
+        assert!(result.is_ok());
+        assert_eq!(processed_files.load(std::sync::atomic::Ordering::SeqCst), 2);
+
+        // Restore original directory
+        env::set_current_dir(original_dir)?;
+        
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_print_header_raw() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let file_path = temp_dir.path().join("test.safetensors");
+        let out_path = temp_dir.path().join("headers.txt");
+        create_dummy_safetensors(&file_path).await?;
+
+        let args = Args::parse_from([
+            "extract-metadata",
+            "--print-header-raw",
+            "--output",
+            out_path.to_str().unwrap(),
+            file_path.to_str().unwrap(),
+        ]);
+        let scan = Scan::new(args)?;
+        process(&file_path, &scan).await?;
+        scan.output.finish()?;
+
+        assert_eq!(fs::read_to_string(&out_path).await?, "{\"__metadata__\":{\"foo\":\"bar\"}}\n");
+        // The raw view doesn't save a sidecar
+        assert!(!file_path.with_extension("json").exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_limit_stops_collecting() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        for i in 0..5 {
+            create_dummy_safetensors(&temp_dir.path().join(format!("{i}.safetensors"))).await?;
+        }
+        let dir = temp_dir.path().to_str().unwrap();
+        let pattern = temp_dir.path().join("*.safetensors");
+
+        let scan = test_util::scan(&["--limit", "3", dir]);
+        assert_eq!(collect_files(&scan)?.len(), 3);
+        let scan = test_util::scan(&["--limit", "2", pattern.to_str().unwrap()]);
+        assert_eq!(collect_files(&scan)?.len(), 2);
+        let scan = test_util::scan(&[dir]);
+        assert_eq!(collect_files(&scan)?.len(), 5);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_include_hidden() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        std::fs::create_dir(temp_dir.path().join(".cache"))?;
+        for name in ["a.safetensors", ".b.safetensors", ".cache/c.safetensors"] {
+            create_dummy_safetensors(&temp_dir.path().join(name)).await?;
+        }
+        let dir = temp_dir.path().to_str().unwrap();
+        let names = |scan: &Scan| -> anyhow::Result<Vec<PathBuf>> {
+            let mut files = collect_files(scan)?;
+            files.sort();
+            Ok(files.iter().map(|file| file.strip_prefix(temp_dir.path()).unwrap().to_path_buf()).collect())
+        };
+
+        // Skipped, and with -v reported, unless --include-hidden
+        assert_eq!(names(&test_util::scan(&["-v", dir]))?, [PathBuf::from("a.safetensors")]);
+        let hidden = names(&test_util::scan(&["--include-hidden", dir]))?;
+        assert_eq!(hidden, [".b.safetensors", ".cache/c.safetensors", "a.safetensors"].map(PathBuf::from));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_with_mtime_and_size() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let file_path = temp_dir.path().join("test.safetensors");
+        let out_path = temp_dir.path().join("out.json");
+        create_dummy_safetensors(&file_path).await?;
+
+        let scan = test_util::scan(&["--with-mtime", "--with-size", "-o", out_path.to_str().unwrap(), "x"]);
+        process(&file_path, &scan).await?;
+        scan.output.finish()?;
+
+        let record: serde_json::Value = serde_json::from_str(&fs::read_to_string(&out_path).await?)?;
+        let modified = std::fs::metadata(&file_path)?.modified()?;
+        let epoch = modified.duration_since(std::time::UNIX_EPOCH)?.as_secs();
+        assert_eq!(record["modified_epoch"], epoch);
+        assert_eq!(record["modified"], humantime::format_rfc3339_seconds(modified).to_string());
+        assert_eq!(record["size"], std::fs::metadata(&file_path)?.len());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_append_dedupe_paths() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let a = temp_dir.path().join("a.safetensors");
+        let b = temp_dir.path().join("b.safetensors");
+        create_dummy_safetensors(&a).await?;
+        create_dummy_safetensors(&b).await?;
+        let out = temp_dir.path().join("catalog.ndjson");
+        let out = out.to_str().unwrap();
+
+        let scan = test_util::scan(&["--format", "ndjson", "-o", out, "x"]);
+        process(&a, &scan).await?;
+        scan.output.finish()?;
+        drop(scan);
+
+        let scan = test_util::scan(&["--format", "ndjson", "-o", out, "--append", "--dedupe-paths", "x"]);
+        assert_eq!(process(&a, &scan).await?, Outcome::Skipped(SkipReason::Existing));
+        assert_eq!(process(&b, &scan).await?, Outcome::Processed);
+        scan.output.finish()?;
+
+        let lines: Vec<serde_json::Value> = fs::read_to_string(out)
+            .await?
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?;
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["path"], a.to_str().unwrap());
+        assert_eq!(lines[1]["path"], b.to_str().unwrap());
+        Ok(())
+    }
+
+    #[test]
+    fn test_fail_on_empty() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        std::fs::write(temp_dir.path().join("notes.txt"), "")?;
+        let dir = temp_dir.path().to_str().unwrap();
+
+        let scan = test_util::scan(&["--fail-on-empty", dir]);
+        let err = ensure_matched(&scan, &collect_files(&scan)?).unwrap_err();
+        assert!(err.to_string().starts_with("No files matched"));
+
+        let missing = temp_dir.path().join("missing");
+        let scan = test_util::scan(&["--fail-on-empty", missing.to_str().unwrap()]);
+        let err = ensure_matched(&scan, &collect_files(&scan)?).unwrap_err();
+        assert!(err.to_string().ends_with("doesn't exist"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_only_errors() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let good = temp_dir.path().join("good.safetensors");
+        let bad = temp_dir.path().join("bad.safetensors");
+        let gone = temp_dir.path().join("gone.safetensors");
+        create_dummy_safetensors(&good).await?;
+        create_dummy_file(&bad, "not a safetensors file").await?;
+        let out = temp_dir.path().join("errors.ndjson");
+
+        let scan = test_util::scan(&["--only-errors", "--format", "ndjson", "-o", out.to_str().unwrap(), "x"]);
+        process_or_warn(&good, &scan).await;
+        process_or_warn(&bad, &scan).await;
+        process_or_warn(&gone, &scan).await;
+        scan.output.finish()?;
+
+        let output = fs::read_to_string(&out).await?;
+        let records: Vec<serde_json::Value> = output.lines().map(serde_json::from_str).collect::<Result<_, _>>()?;
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["path"], bad.to_str().unwrap());
+        assert!(records[0]["error"].as_str().unwrap().starts_with("Header length"));
+        assert_eq!(records[0]["category"], "unparseable");
+        assert!(records[0].get("os_error").is_none());
+        assert_eq!(records[1]["path"], gone.to_str().unwrap());
+        assert!(records[1]["error"].as_str().unwrap().starts_with("Failed to open file: "));
+        assert_eq!(records[1]["category"], "unreadable");
+        assert_eq!(records[1]["os_error"], "NotFound");
+        assert_eq!(scan.summary.failed(), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_emit_empty_record_for_skipped() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let a = test_util::write_safetensors(temp_dir.path(), "a.safetensors", &serde_json::json!({"__metadata__": {"x": "1"}}));
+        let b = test_util::write_safetensors(temp_dir.path(), "b.safetensors", &serde_json::json!({"__metadata__": {"x": "2"}}));
+        let out = temp_dir.path().join("records.ndjson");
+
+        let args = ["--emit-empty-record-for-skipped", "--where", "x=1", "--format", "ndjson", "-o", out.to_str().unwrap(), "x"];
+        let scan = test_util::scan(&args);
+        process_or_warn(&a, &scan).await;
+        process_or_warn(&b, &scan).await;
+        scan.output.finish()?;
+
+        let output = fs::read_to_string(&out).await?;
+        let records: Vec<serde_json::Value> = output.lines().map(serde_json::from_str).collect::<Result<_, _>>()?;
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["path"], a.to_str().unwrap());
+        assert_eq!(records[1], serde_json::json!({"path": b.to_str().unwrap(), "skipped": "filter"}));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_canonical_json_is_stable() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = test_util::write_safetensors(
+            temp_dir.path(),
+            "a.safetensors",
+            &serde_json::json!({"__metadata__": {"z": "1", "ss_learning_rate": "1e-4", "a": "\u{1}é"}}),
+        );
+        let mut outputs = Vec::new();
+        for run in 0..2 {
+            let out = temp_dir.path().join(format!("run{run}.json"));
+            let scan = test_util::scan(&["--canonical-json", "--normalize-metadata", "-o", out.to_str().unwrap(), "x"]);
+            process(&path, &scan).await?;
+            scan.output.finish()?;
+            outputs.push(fs::read(&out).await?);
+        }
+        assert_eq!(outputs[0], outputs[1]);
+        let expected = format!(r#"{{"metadata":{{"a":"\u0001é","ss_learning_rate":0.0001,"z":"1"}},"path":"{}"}}"#, path.display());
+        assert_eq!(String::from_utf8(outputs.swap_remove(0))?, expected + "\n");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_where_skips_non_matching() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let dim = |n: &str| serde_json::json!({"__metadata__": {"ss_network_dim": n}});
+        let a = test_util::write_safetensors(temp_dir.path(), "a.safetensors", &dim("16"));
+        let b = test_util::write_safetensors(temp_dir.path(), "b.safetensors", &dim("32"));
+
+        let scan = test_util::scan(&["--where", "ss_network_dim=16", "-o", temp_dir.path().join("out").to_str().unwrap(), "x"]);
+        assert_eq!(process(&a, &scan).await?, Outcome::Processed);
+        assert_eq!(process(&b, &scan).await?, Outcome::Skipped(SkipReason::Filter));
+        // Filtered files don't get a sidecar either
+        assert!(!b.with_extension("json").exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_has_key_and_missing_key() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let metadata = |metadata| serde_json::json!({"__metadata__": metadata});
+        let licensed = serde_json::json!({"modelspec.title": "a", "modelspec.license": "mit"});
+        let a = test_util::write_safetensors(temp_dir.path(), "a.safetensors", &metadata(licensed));
+        let unlicensed = serde_json::json!({"modelspec.title": "b"});
+        let b = test_util::write_safetensors(temp_dir.path(), "b.safetensors", &metadata(unlicensed));
+        let c = test_util::write_safetensors(temp_dir.path(), "c.safetensors", &metadata(serde_json::json!({})));
+
+        let out = temp_dir.path().join("out");
+        let filters = ["--has-key", "modelspec.title", "--missing-key", "modelspec.license"];
+        let scan = test_util::scan(&[&filters[..], &["-o", out.to_str().unwrap(), "x"]].concat());
+        assert_eq!(process(&a, &scan).await?, Outcome::Skipped(SkipReason::Filter));
+        assert_eq!(process(&b, &scan).await?, Outcome::Processed);
+        assert_eq!(process(&c, &scan).await?, Outcome::Skipped(SkipReason::Filter));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_watch_debounce() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let header = serde_json::json!({"__metadata__": {"a": "1"}});
+        let path = test_util::write_safetensors(temp_dir.path(), "a.safetensors", &header);
+        let out = temp_dir.path().join("out");
+        let scan = test_util::scan(&["--watch-debounce", "20ms", "-o", out.to_str().unwrap(), "x"]);
+        assert_eq!(process(&path, &scan).await?, Outcome::Processed);
+
+        // A file that keeps growing is left for later
+        let writer = {
+            let path = path.clone();
+            tokio::spawn(async move {
+                let mut file = fs::OpenOptions::new().append(true).open(&path).await?;
+                for _ in 0..200 {
+                    file.write_all(b"data").await?;
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                }
+                std::io::Result::Ok(())
+            })
+        };
+        assert_eq!(process(&path, &scan).await?, Outcome::Skipped(SkipReason::InProgress));
+        writer.abort();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_metadata_only_if_present() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let dir = temp_dir.path();
+        let tensor = serde_json::json!({"dtype": "F16", "shape": [1], "data_offsets": [0, 0]});
+        test_util::write_safetensors(dir, "a.safetensors", &serde_json::json!({"w": tensor}));
+        std::fs::write(dir.join("b.pt"), b"PK\x03\x04rest")?;
+        std::fs::write(dir.join("c.bin"), b"raw weights")?;
+        let out = dir.join("out");
+
+        let scan = test_util::scan(&["--sniff", "--metadata-only-if-present", "-o", out.to_str().unwrap(), "x"]);
+        for name in ["a.safetensors", "b.pt", "c.bin"] {
+            assert_eq!(process(&dir.join(name), &scan).await?, Outcome::Processed);
+        }
+        scan.output.finish()?;
+        let records = output::read_records(&out)?;
+        assert_eq!(records.len(), 3);
+        assert!(records.iter().all(|record| record["metadata"] == serde_json::json!({})));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_input_format_override() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let dir = temp_dir.path();
+        let header = serde_json::json!({"__metadata__": {"ss_epoch": "3"}});
+        let misnamed = test_util::write_safetensors(dir, "weights.zip", &header);
+        let zip = dir.join("model.safetensors");
+        std::fs::write(&zip, b"PK\x03\x04rest")?;
+        let out = dir.join("out");
+
+        // Neither the archive extension nor the sniffed zip signature decides
+        let args = ["--as", "safetensors", "--sniff", "--recurse-archives", "-o", out.to_str().unwrap(), "x"];
+        let scan = test_util::scan(&args);
+        assert_eq!(process(&misnamed, &scan).await?, Outcome::Processed);
+        assert!(process(&zip, &scan).await.is_err());
+        scan.output.finish()?;
+        assert_eq!(output::read_records(&out)?[0]["metadata"]["ss_epoch"], "3");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_parse_filename_in_archive() -> anyhow::Result<()> {
+        use std::io::Write;
+        let temp_dir = tempfile::tempdir()?;
+        let header = serde_json::json!({"__metadata__": {}});
+        let member = std::fs::read(test_util::write_safetensors(temp_dir.path(), "m.safetensors", &header))?;
+        let archive = temp_dir.path().join("bundle-fp32.zip");
+        let mut writer = zip::ZipWriter::new(std::fs::File::create(&archive)?);
+        writer.start_file("run-fp16.safetensors", zip::write::SimpleFileOptions::default())?;
+        writer.write_all(&member)?;
+        writer.finish()?;
+        let out = temp_dir.path().join("out");
+
+        // The captures are those of the member's name, not the archive's
+        let regex = r"^\w+-(?<precision>fp\d+)\.";
+        let scan = test_util::scan(&["--recurse-archives", "--parse-filename", regex, "-o", out.to_str().unwrap(), "x"]);
+        assert_eq!(process(&archive, &scan).await?, Outcome::Processed);
+        scan.output.finish()?;
+        assert_eq!(output::read_records(&out)?[0]["metadata"]["fname.precision"], "fp16");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_partial_ok() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("a.safetensors");
+        let mut bytes = 1000u64.to_le_bytes().to_vec();
+        bytes.extend(b"{}");
+        std::fs::write(&path, bytes)?;
+        let out = temp_dir.path().join("out");
+
+        let scan = test_util::scan(&["-o", out.to_str().unwrap(), "x"]);
+        assert!(process(&path, &scan).await.is_err());
+        let scan = test_util::scan(&["--partial-ok", "-o", out.to_str().unwrap(), "x"]);
+        assert_eq!(process(&path, &scan).await?, Outcome::Skipped(SkipReason::InProgress));
+
+        // A file that hasn't changed in a while isn't going to grow any more
+        let an_hour_ago = std::time::SystemTime::now() - Duration::from_hours(1);
+        std::fs::File::options().write(true).open(&path)?.set_modified(an_hour_ago)?;
+        let err = process(&path, &scan).await.unwrap_err();
+        assert!(err.to_string().contains("exceeds the file size"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_max_memory_reports() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let header = serde_json::json!({"__metadata__": {"k": "x"}});
+        let path = test_util::write_safetensors(temp_dir.path(), "a.safetensors", &header);
+        // --dedupe-by keeps an entry for each file, --distinct only a count for each value
+        let scan = test_util::scan(&["--dedupe-by", "k", "--max-memory", "16", "x"]);
+        assert!(process(&path, &scan).await.unwrap_err().is::<memory::OverBudget>());
+        let scan = test_util::scan(&["--distinct", "k", "--max-memory", "16", "x"]);
+        assert_eq!(process(&path, &scan).await?, Outcome::Processed);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reports_together() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let out = temp_dir.path().join("out.txt");
+        let scan = test_util::scan(&["--distinct", "k", "--histogram", "k", "-o", out.to_str().unwrap(), "x"]);
+        for (name, value) in [("a.safetensors", "x"), ("b.safetensors", "x"), ("c.safetensors", "y")] {
+            let header = serde_json::json!({"__metadata__": {"k": value}});
+            process(&test_util::write_safetensors(temp_dir.path(), name, &header), &scan).await?;
+        }
+        write_reports(&scan)?;
+        scan.output.finish()?;
+        // Each report sees every record, not only the first one given
+        let text = fs::read_to_string(&out).await?;
+        assert_eq!(text, "files  k\n2      x\n1      y\nfiles  share  k\n2      66.7%  x\n1      33.3%  y\n");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tensor_filter() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let tensor = |shape: &[u64]| serde_json::json!({"dtype": "F16", "shape": shape, "data_offsets": [0, 0]});
+        let path = test_util::write_safetensors(
+            temp_dir.path(),
+            "a.safetensors",
+            &serde_json::json!({
+                "__metadata__": {},
+                "blocks.0.attn.qkv.weight": tensor(&[96, 32]),
+                "blocks.0.mlp.fc1.weight": tensor(&[128, 32]),
+                "blocks.1.attn.qkv.weight": tensor(&[96, 32]),
+            }),
+        );
+        let out = temp_dir.path().join("out.ndjson");
+        let args = ["--tensors", "--tensor-filter", "*.attn.*", "--count-parameters-from", "shapes"];
+        let scan = test_util::scan(&[&args[..], &["--format", "ndjson", "-o", out.to_str().unwrap(), "x"]].concat());
+        process(&path, &scan).await?;
+        scan.output.finish()?;
+
+        let record: serde_json::Value = serde_json::from_slice(&fs::read(&out).await?)?;
+        let names: Vec<&str> = record["tensor_list"].as_array().unwrap().iter().map(|t| t["name"].as_str().unwrap()).collect();
+        assert_eq!(names, ["blocks.0.attn.qkv.weight", "blocks.1.attn.qkv.weight"]);
+        assert_eq!(record["tensor_list"][0]["shape"], serde_json::json!([96, 32]));
+        assert_eq!(record["parameters"], 2 * 96 * 32);
+
+        // --count-tensors counts them all, whatever --tensor-filter lists
+        let args = ["--count-tensors", "--tensor-filter", "*.attn.*"];
+        let scan = test_util::scan(&[&args[..], &["--format", "ndjson", "-o", out.to_str().unwrap(), "x"]].concat());
+        process(&path, &scan).await?;
+        scan.output.finish()?;
+        let record: serde_json::Value = serde_json::from_slice(&fs::read(&out).await?)?;
+        assert_eq!(record["tensors"], 3);
+        assert!(scan.summary.line(Duration::ZERO, false).contains(", 3 tensors"));
+
+        let args = ["--tensors", "--first-n-tensors", "2"];
+        let scan = test_util::scan(&[&args[..], &["--format", "ndjson", "-o", out.to_str().unwrap(), "x"]].concat());
+        process(&path, &scan).await?;
+        scan.output.finish()?;
+        let record: serde_json::Value = serde_json::from_slice(&fs::read(&out).await?)?;
+        let names: Vec<&str> = record["tensor_list"].as_array().unwrap().iter().map(|t| t["name"].as_str().unwrap()).collect();
+        assert_eq!(names, ["blocks.0.attn.qkv.weight", "blocks.0.mlp.fc1.weight"]);
+        assert_eq!(record["tensors_not_listed"], 1);
+
+        let map = temp_dir.path().join("names.json");
+        fs::write(&map, r#"{"blocks.0.*": "layers.0.*"}"#).await?;
+        let args = ["--tensors", "--tensor-name-map", map.to_str().unwrap()];
+        let scan = test_util::scan(&[&args[..], &["--format", "ndjson", "-o", out.to_str().unwrap(), "x"]].concat());
+        process(&path, &scan).await?;
+        scan.output.finish()?;
+        let record: serde_json::Value = serde_json::from_slice(&fs::read(&out).await?)?;
+        assert_eq!(record["tensor_list"][0]["canonical_name"], "layers.0.attn.qkv.weight");
+        assert!(record["tensor_list"][2].get("canonical_name").is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_prefetch() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        for name in ["a", "b", "c", "d"] {
+            create_dummy_safetensors(&temp_dir.path().join(format!("{name}.safetensors"))).await?;
+        }
+        let out = temp_dir.path().join("out.json");
+        let (dir, out_arg) = (temp_dir.path().to_str().unwrap(), out.to_str().unwrap());
+
+        // With the one slot held by the read of the scan, nothing is read ahead of it
+        let args = ["-q", "--prefetch", "2", "--max-open-files", "1", "--preserve-order", "-o", out_arg, dir];
+        let scan = Arc::new(test_util::scan(&args));
+        run(&scan).await?;
+        scan.output.finish()?;
+        assert_eq!(output::read_records(&out)?.len(), 4);
+        assert_eq!(scan.open_files.available_permits(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_first_n_tensors() -> anyhow::Result<()> {
+        /// The names in the `tensor_list` of the record of `path`, and its `tensors_not_listed`.
+        async fn listed(path: &Path, out: &Path, args: &[&str]) -> anyhow::Result<(Vec<String>, serde_json::Value)> {
+            let common = ["--tensors", "--format", "ndjson", "-o", out.to_str().unwrap(), "x"];
+            let scan = test_util::scan(&[args, &common[..]].concat());
+            process(path, &scan).await?;
+            scan.output.finish()?;
+            let record: serde_json::Value = serde_json::from_slice(&fs::read(out).await?)?;
+            let tensors = record["tensor_list"].as_array().unwrap();
+            let names = tensors.iter().map(|tensor| tensor["name"].as_str().unwrap().to_string()).collect();
+            Ok((names, record["tensors_not_listed"].clone()))
+        }
+
+        let temp_dir = tempfile::tempdir()?;
+        let tensor = |begin: u64| serde_json::json!({"dtype": "U8", "shape": [4], "data_offsets": [begin, begin + 4]});
+        let header = serde_json::json!({"a": tensor(8), "b": tensor(4), "c": tensor(0)});
+        let path = test_util::write_safetensors(temp_dir.path(), "a.safetensors", &header);
+        let out = temp_dir.path().join("out.ndjson");
+
+        // The first N in the order they are listed, the rest counted
+        let (names, not_listed) = listed(&path, &out, &["--first-n-tensors", "2", "--sort-tensors-by", "offset"]).await?;
+        assert_eq!((names, not_listed), (vec!["c".to_string(), "b".to_string()], serde_json::json!(1)));
+        let (names, not_listed) = listed(&path, &out, &["--first-n-tensors", "5"]).await?;
+        assert_eq!((names.len(), not_listed), (3, serde_json::json!(0)));
+        // Of the tensors --tensor-filter lets through
+        let (names, not_listed) = listed(&path, &out, &["--first-n-tensors", "1", "--tensor-filter", "[ab]"]).await?;
+        assert_eq!((names, not_listed), (vec!["a".to_string()], serde_json::json!(1)));
+        // Without it there is no count
+        let (names, not_listed) = listed(&path, &out, &[]).await?;
+        assert_eq!((names.len(), not_listed), (3, serde_json::Value::Null));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_assert_param_count() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let tensor = serde_json::json!({"dtype": "F16", "shape": [2, 3], "data_offsets": [0, 0]});
+        let path = test_util::write_safetensors(temp_dir.path(), "a.safetensors", &serde_json::json!({"w": tensor}));
+
+        let scan = test_util::scan(&["--assert-param-count", "<=6", "x"]);
+        assert_eq!(process(&path, &scan).await?, Outcome::Processed);
+        let scan = test_util::scan(&["--assert-param-count", "<=5", "x"]);
+        let error = process(&path, &scan).await.unwrap_err();
+        assert_eq!(error.to_string(), "Has 6 (6) parameters, outside --assert-param-count <=5");
+        assert_eq!(scan.outside_param_count.load(Ordering::Relaxed), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_line_ending() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let metadata = serde_json::json!({"__metadata__": {"a": "1", "b": "two\nlines"}});
+        let path = test_util::write_safetensors(temp_dir.path(), "a.safetensors", &metadata);
+        let out = temp_dir.path().join("out.json");
+        for (line_ending, expected) in [
+            ("lf", "{\n  \"a\": \"1\",\n  \"b\": \"two\\nlines\"\n}"),
+            ("crlf", "{\r\n  \"a\": \"1\",\r\n  \"b\": \"two\\nlines\"\r\n}"),
+        ] {
+            let scan = test_util::scan(&["--line-ending", line_ending, "-o", out.to_str().unwrap(), "x"]);
+            process(&path, &scan).await?;
+            assert_eq!(fs::read(sidecar::path(&path)).await?, expected.as_bytes());
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_extract_thumbnails() -> anyhow::Result<()> {
+        use base64::Engine;
+        let temp_dir = tempfile::tempdir()?;
+        let png = base64::engine::general_purpose::STANDARD.encode(b"\x89PNG\r\n\x1a\npixels");
+        let metadata = serde_json::json!({"__metadata__": {"modelspec.thumbnail": format!("data:image/png;base64,{png}")}});
+        let path = test_util::write_safetensors(temp_dir.path(), "a.safetensors", &metadata);
+        let thumbnails = temp_dir.path().join("thumbnails");
+        let scan = test_util::scan(&["--extract-thumbnails", thumbnails.to_str().unwrap(), "-o", temp_dir.path().join("out.json").to_str().unwrap(), "x"]);
+        process(&path, &scan).await?;
+
+        let image = fs::read(thumbnails.join("a.modelspec.thumbnail.png")).await?;
+        assert_eq!(image, b"\x89PNG\r\n\x1a\npixels");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_scan_api() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let dir = temp_dir.path();
+        test_util::write_safetensors(dir, "b.safetensors", &serde_json::json!({"__metadata__": {"ss_network_dim": "16"}}));
+        test_util::write_safetensors(dir, "a.safetensors", &serde_json::json!({}));
+        create_dummy_file(&dir.join("bad.safetensors"), "not a safetensors file").await?;
+
+        let args = Args::try_parse_from(["extract-metadata", "--with-size", dir.to_str().unwrap()])?;
+        let result = scan(args).await?;
+        let names: Vec<_> = result.records.iter().map(|record| record.path.file_name().unwrap()).collect();
+        assert_eq!(names, ["a.safetensors", "b.safetensors"]);
+        assert_eq!(result.records[1].metadata["ss_network_dim"], "16");
+        assert!(result.records[1].size.is_some());
+        assert_eq!(result.errors.len(), 1);
+        assert_eq!(result.errors[0].0, dir.join("bad.safetensors"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_max_total_bytes() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let mut files = Vec::new();
+        for name in ["a.safetensors", "b.safetensors", "c.safetensors"] {
+            files.push(test_util::write_safetensors(temp_dir.path(), name, &serde_json::json!({})));
+        }
+        let size = std::fs::metadata(&files[0])?.len();
+        let budget = (size + 1).to_string();
+        let scan = test_util::scan(&["-q", "--max-total-bytes", &budget, "-o", temp_dir.path().join("out.json").to_str().unwrap(), "x"]);
+
+        let mut outcomes = Vec::new();
+        for path in &files {
+            outcomes.push(process(path, &scan).await?);
+        }
+        // The second file starts before the budget is spent and is finished
+        let skipped = Outcome::Skipped(SkipReason::Budget);
+        assert_eq!(outcomes, [Outcome::Processed, Outcome::Processed, skipped]);
+        assert_eq!(scan.bytes_started.load(Ordering::Relaxed), 2 * size);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dtype_filters() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let tensor = |dtype: &str| serde_json::json!({"dtype": dtype, "shape": [1], "data_offsets": [0, 0]});
+        let mixed = test_util::write_safetensors(
+            temp_dir.path(),
+            "mixed.safetensors",
+            &serde_json::json!({"a": tensor("BF16"), "b": tensor("F16")}),
+        );
+        let pure = test_util::write_safetensors(temp_dir.path(), "pure.safetensors", &serde_json::json!({"a": tensor("F16")}));
+        let empty = test_util::write_safetensors(temp_dir.path(), "empty.safetensors", &serde_json::json!({}));
+
+        let outcomes = async |args: &[&str]| -> anyhow::Result<Vec<Outcome>> {
+            let scan = test_util::scan(&[args, &["-q", "-o", temp_dir.path().join("out.json").to_str().unwrap(), "x"]].concat());
+            let mut outcomes = Vec::new();
+            for path in [&mixed, &pure, &empty] {
+                outcomes.push(process(path, &scan).await?);
+            }
+            Ok(outcomes)
+        };
+        let (yes, no) = (Outcome::Processed, Outcome::Skipped(SkipReason::Filter));
+        assert_eq!(outcomes(&["--has-dtype", "bf16"]).await?, [yes, no, no]);
+        assert_eq!(outcomes(&["--only-dtype", "F16"]).await?, [no, yes, no]);
+        assert_eq!(outcomes(&["--has-dtype", "F16", "--has-dtype", "BF16"]).await?, [yes, no, no]);
+        assert_eq!(outcomes(&["--tensor-shape", "b=1"]).await?, [yes, no, no]);
+        assert_eq!(outcomes(&["--tensor-shape", "a=*"]).await?, [yes, yes, no]);
+        assert_eq!(outcomes(&["--tensor-shape", "a=1,*"]).await?, [no, no, no]);
+        assert_eq!(outcomes(&["--tensor-count", "<2"]).await?, [no, yes, no]);
+        assert_eq!(outcomes(&["--tensor-count", "2..500", "--has-dtype", "F16"]).await?, [yes, no, no]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_shown() {
+        let root = std::env::temp_dir();
+        let path = root.join("models").join("a.safetensors");
+        let base = ["--manifest-relative-paths", "--relative-to", root.to_str().unwrap(), "-q", "x"];
+        assert_eq!(test_util::scan(&base).shown(&path), path.display().to_string());
+        let scan = test_util::scan(&[&["--canonical-paths-in-errors", "--path-separator", "slash"][..], &base].concat());
+        assert_eq!(scan.shown(&path), "models/a.safetensors");
+    }
+
+    #[tokio::test]
+    async fn test_merge_sidecar() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let metadata = serde_json::json!({"__metadata__": {"ss_network_dim": "16"}});
+        let a = test_util::write_safetensors(temp_dir.path(), "a.safetensors", &metadata);
+        let b = test_util::write_safetensors(temp_dir.path(), "b.safetensors", &metadata);
+        std::fs::write(temp_dir.path().join("a.json"), r#"{"rating": 5, "ss_network_dim": "32"}"#)?;
+        std::fs::write(temp_dir.path().join("b.json"), "{not json")?;
+        let out = temp_dir.path().join("out.ndjson");
+
+        let scan = test_util::scan(&["--merge-sidecar", "--sidecar-merge-mode", "prefer-sidecar", "--format", "ndjson", "-o", out.to_str().unwrap(), "x"]);
+        process(&a, &scan).await?;
+        // A malformed sidecar doesn't fail the file
+        assert_eq!(process(&b, &scan).await?, Outcome::Processed);
+        scan.output.finish()?;
+
+        let text = fs::read_to_string(&out).await?;
+        let records: Vec<serde_json::Value> = text.lines().map(serde_json::from_str).collect::<Result<_, _>>()?;
+        assert_eq!(records[0]["metadata"], serde_json::json!({"rating": 5, "ss_network_dim": "32"}));
+        assert_eq!(records[1]["metadata"], serde_json::json!({"ss_network_dim": "16"}));
+        // The curated sidecars are left as they were
+        assert_eq!(fs::read_to_string(temp_dir.path().join("b.json")).await?, "{not json");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_shard_index() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let dir = temp_dir.path();
+        let index = serde_json::json!({
+            "metadata": {"total_size": 3_000_000_000u64},
+            "weight_map": {"a": "model-00001-of-00002.safetensors", "b": "model-00001-of-00002.safetensors", "c": "model-00002-of-00002.safetensors"},
+        });
+        let index_path = dir.join("model.safetensors.index.json");
+        std::fs::write(&index_path, index.to_string())?;
+        let tensor = serde_json::json!({"dtype": "F16", "shape": [2, 3], "data_offsets": [0, 0]});
+        test_util::write_safetensors(dir, "model-00001-of-00002.safetensors", &serde_json::json!({"a": tensor, "b": tensor}));
+        let out = dir.join("out.ndjson");
+        let scan = test_util::scan(&["--count-parameters-from", "shapes", "--format", "ndjson", "-o", out.to_str().unwrap(), "x"]);
+        process(&index_path, &scan).await?;
+
+        // Until the second shard arrives, it is reported missing and parameters aren't counted
+        test_util::write_safetensors(dir, "model-00002-of-00002.safetensors", &serde_json::json!({"c": tensor}));
+        process(&index_path, &scan).await?;
+        scan.output.finish()?;
+
+        let text = fs::read_to_string(&out).await?;
+        let records: Vec<serde_json::Value> = text.lines().map(serde_json::from_str).collect::<Result<_, _>>()?;
+        assert_eq!(records[0]["size"], 3_000_000_000u64);
+        assert_eq!(records[0]["tensors"], 3);
+        assert_eq!(records[0]["shards"].as_array().unwrap().len(), 2);
+        assert_eq!(records[0]["missing_shards"], serde_json::json!([dir.join("model-00002-of-00002.safetensors")]));
+        assert!(records[0].get("parameters").is_none());
+        assert!(records[1].get("missing_shards").is_none());
+        assert_eq!(records[1]["shard_sizes"].as_object().unwrap().len(), 2);
+        assert_eq!(records[1]["parameters"], 18);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_follow_index_to_shards() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let dir = temp_dir.path();
+        let index = serde_json::json!({"weight_map": {"a": "part-a.safetensors", "b": "part-b.safetensors", "c": "part-c.safetensors"}});
+        let index_path = dir.join("model.safetensors.index.json");
+        std::fs::write(&index_path, index.to_string())?;
+        let tensor = serde_json::json!({"dtype": "F16", "shape": [2, 3], "data_offsets": [0, 0]});
+        for (name, tensor_name) in [("part-a.safetensors", "a"), ("part-b.safetensors", "b")] {
+            let header = serde_json::json!({"__metadata__": {"format": "pt"}, tensor_name: tensor});
+            test_util::write_safetensors(dir, name, &header);
+        }
+        let out = dir.join("out.ndjson");
+        let args = ["--follow-index-to-shards", "--with-size", "--format", "ndjson", "-o", out.to_str().unwrap(), index_path.to_str().unwrap()];
+        let scan = Arc::new(test_util::scan(&args));
+        run(&scan).await?;
+        scan.output.finish()?;
+
+        let records: Vec<serde_json::Value> =
+            fs::read_to_string(&out).await?.lines().map(serde_json::from_str).collect::<Result<_, _>>()?;
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0]["path"], dir.join("model.safetensors").to_str().unwrap());
+        assert_eq!(records[0]["shards"].as_array().unwrap().len(), 2);
+        assert_eq!(records[0]["tensors"], 2);
+        assert_eq!(records[0]["metadata"], serde_json::json!({"format": "pt"}));
+        // The missing shard failed
+        assert_eq!((scan.summary.processed(), scan.summary.failed()), (2, 1));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_input_list() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let dir = temp_dir.path();
+        for name in ["a.safetensors", "models/b.safetensors", "models/c.safetensors", "d.safetensors"] {
+            create_dummy_safetensors(&dir.join(name)).await?;
+        }
+        let list = dir.join("files.txt");
+        std::fs::write(&list, "# checked-in model list\na.safetensors\n\n  models  \n")?;
+
+        let mut files = collect_files(&test_util::scan(&["--input-list", list.to_str().unwrap()]))?;
+        files.sort();
+        let expected = ["a.safetensors", "models/b.safetensors", "models/c.safetensors"].map(|name| dir.join(name));
+        assert_eq!(files, expected);
+        let files = collect_files(&test_util::scan(&["--input-list", list.to_str().unwrap(), "--limit", "2"]))?;
+        assert_eq!(files.len(), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_with_source() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let dir = temp_dir.path();
+        for name in ["a.safetensors", "models/b.safetensors"] {
+            create_dummy_safetensors(&dir.join(name)).await?;
+        }
+        let list = dir.join("files.txt");
+        std::fs::write(&list, "models\n*.safetensors\n")?;
+        let scan = test_util::scan(&["--input-list", list.to_str().unwrap(), "--with-source"]);
+        collect_files(&scan)?;
+        assert_eq!(source_of(&scan, &dir.join("models/b.safetensors")).as_deref(), Some("models"));
+        assert_eq!(source_of(&scan, &dir.join("a.safetensors")).as_deref(), Some("*.safetensors"));
+
+        let scan = test_util::scan(&[dir.to_str().unwrap(), "--with-source"]);
+        assert_eq!(source_of(&scan, &dir.join("a.safetensors")).as_deref(), dir.to_str());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_parallel_glob() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let dir = temp_dir.path();
+        for name in ["a.safetensors", "models/b.safetensors", "models/c.safetensors"] {
+            create_dummy_safetensors(&dir.join(name)).await?;
+        }
+        let list = dir.join("files.txt");
+        std::fs::write(&list, "models/c.safetensors\n*.safetensors\nmodels/*.safetensors\nmodels/../a.safetensors\n")?;
+
+        let files = collect_files(&test_util::scan(&["--input-list", list.to_str().unwrap(), "--parallel-glob"]))?;
+        let expected = ["models/c.safetensors", "a.safetensors", "models/b.safetensors"].map(|name| dir.join(name));
+        assert_eq!(files, expected);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_as_found() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let dir = temp_dir.path();
+        for name in ["a.safetensors", "models/b.safetensors", "models/c.safetensors"] {
+            create_dummy_safetensors(&dir.join(name)).await?;
+        }
+        let out = dir.join("out.ndjson");
+        let inputs = ["models/c.safetensors", "*.safetensors", "models/*.safetensors", "models/../a.safetensors", "models"]
+            .map(|input| dir.join(input).to_string_lossy().into_owned());
+        let common = ["-q", "--format", "ndjson", "-o", out.to_str().unwrap()];
+
+        for (args, expected) in [(&["--parallel-glob"][..], 3), (&[], 3), (&["--parallel-glob", "--limit", "2"], 2)] {
+            let scan = Arc::new(test_util::scan(&[&common[..], args, &inputs.each_ref().map(String::as_str)].concat()));
+            assert!(processed_as_found(&scan));
+            run(&scan).await?;
+            scan.output.finish()?;
+            // Each file once, whichever inputs matched it
+            let records = output::read_records(&out)?;
+            let paths: HashSet<_> = records.iter().map(|record| record["path"].to_string()).collect();
+            assert_eq!(records.len(), expected, "{args:?}");
+            assert_eq!(paths.len(), expected, "{args:?}");
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_interrupted() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = test_util::write_safetensors(temp_dir.path(), "a.safetensors", &serde_json::json!({}));
+        let scan = test_util::scan(&["--abort-on-signal-second", path.to_str().unwrap()]);
+        scan.interrupted.store(true, Ordering::Relaxed);
+        assert_eq!(process(&path, &scan).await?, Outcome::Skipped(SkipReason::Interrupted));
+        assert!(!sidecar::path(&path).exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_max_runtime() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = test_util::write_safetensors(temp_dir.path(), "a.safetensors", &serde_json::json!({}));
+        let scan = Arc::new(test_util::scan(&["--max-runtime", "1ms", "-q", path.to_str().unwrap()]));
+        stop_at_deadline(Arc::clone(&scan), Duration::from_millis(1)).await;
+        assert!(scan.deadline_hit.load(Ordering::Relaxed));
+        assert_eq!(process(&path, &scan).await?, Outcome::Skipped(SkipReason::Interrupted));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_continue_on_write_error() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let dir = temp_dir.path();
+        let a = test_util::write_safetensors(dir, "a.safetensors", &serde_json::json!({"__metadata__": {"k": "v"}}));
+        let b = test_util::write_safetensors(dir, "b.safetensors", &serde_json::json!({}));
+        // A directory in the way of the sidecar can't be replaced
+        std::fs::create_dir_all(sidecar::path(&a).join("in-the-way"))?;
+        let out = dir.join("out");
+
+        let scan = test_util::scan(&["--continue-on-write-error", "-o", out.to_str().unwrap(), "x"]);
+        process_or_warn(&a, &scan).await;
+        scan.output.finish()?;
+        assert_eq!(output::read_records(&out)?[0]["metadata"]["k"], "v");
+        assert!(scan.summary.line(Duration::ZERO, false).contains("Processed 1 file, 0 failed, 0 skipped, 1 write error"));
+
+        let scan = test_util::scan(&["-o", out.to_str().unwrap(), "x"]);
+        process_or_warn(&a, &scan).await;
+        assert_eq!(process(&b, &scan).await?, Outcome::Skipped(SkipReason::Interrupted));
+        assert_eq!(scan.summary.failed(), 1);
+        let failure = scan.stop_error.lock().unwrap().clone().unwrap();
+        assert!(failure.starts_with(&format!("Failed to write {}: ", sidecar::path(&a).display())), "{failure}");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_compare_to() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let dir = temp_dir.path().join("models");
+        let dim = |n: &str| serde_json::json!({"__metadata__": {"ss_network_dim": n}});
+        let a = test_util::write_safetensors(&dir, "a.safetensors", &dim("16"));
+        test_util::write_safetensors(&dir, "b.safetensors", &dim("16"));
+        let baseline = temp_dir.path().join("baseline.json");
+        let scan = Arc::new(test_util::scan(&["-q", "-o", baseline.to_str().unwrap(), dir.to_str().unwrap()]));
+        run(&scan).await?;
+        scan.output.finish()?;
+
+        let report = temp_dir.path().join("drift.json");
+        let args = ["--compare-to", baseline.to_str().unwrap(), "--drift-format", "json", "--fail-on-drift"];
+        let compare = || test_util::scan(&[&args[..], &["-o", report.to_str().unwrap(), dir.to_str().unwrap()]].concat());
+        let scan = Arc::new(compare());
+        run(&scan).await?;
+        scan.output.finish()?;
+        let unchanged = fs::read(&report).await?;
+        // Nor once an editor has saved the baseline with a byte order mark
+        let mut saved = b"\xef\xbb\xbf".to_vec();
+        saved.extend(fs::read(&baseline).await?);
+        fs::write(&baseline, saved).await?;
+        let scan = Arc::new(compare());
+        run(&scan).await?;
+        scan.output.finish()?;
+        assert_eq!(fs::read(&report).await?, unchanged);
+
+        test_util::write_safetensors(&dir, "a.safetensors", &dim("32"));
+        test_util::write_safetensors(&dir, "c.safetensors", &dim("16"));
+        let scan = Arc::new(compare());
+        let err = run(&scan).await.unwrap_err();
+        assert_eq!(err.to_string(), "Found drift: 1 added, 0 removed, 1 changed");
+        scan.output.finish()?;
+        let diff: serde_json::Value = serde_json::from_slice(&fs::read(&report).await?)?;
+        assert_eq!(diff["changed"][0]["path"], a.to_str().unwrap());
+        assert_eq!(diff["changed"][0]["keys"], serde_json::json!(["ss_network_dim"]));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_manifest_relative_paths() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let root = temp_dir.path();
+        let dir = root.join("models");
+        test_util::write_safetensors(&dir.join("sdxl"), "a.safetensors", &serde_json::json!({"__metadata__": {}}));
+        let catalog = root.join("catalog.json");
+        let relative = ["--manifest-relative-paths", "--relative-to", dir.to_str().unwrap()];
+        let output = ["-q", "-o", catalog.to_str().unwrap(), dir.to_str().unwrap()];
+        let scan = Arc::new(test_util::scan(&[&relative[..], &output[..]].concat()));
+        run(&scan).await?;
+        scan.output.finish()?;
+        let records = output::read_records(&catalog)?;
+        assert_eq!(records[0]["path"], Path::new("sdxl").join("a.safetensors").to_str().unwrap());
+
+        // The relative paths of the baseline are those of the same files
+        let report = root.join("drift");
+        let compare = ["--compare-to", catalog.to_str().unwrap(), "--fail-on-drift", "-o", report.to_str().unwrap()];
+        let scan = Arc::new(test_util::scan(&[&relative[..], &compare[..], &[dir.to_str().unwrap()]].concat()));
+        run(&scan).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_with_relpath() -> anyhow::Result<()> {
+        /// The `path` and `relpath` of the record of `input`, resolved against `cwd` as if the
+        /// scan were run from there.
+        async fn paths(cwd: &Path, input: &str) -> anyhow::Result<(serde_json::Value, serde_json::Value)> {
+            let out = cwd.join("out.json");
+            let (cwd, out_arg) = (cwd.to_str().unwrap(), out.to_str().unwrap());
+            let args = ["-q", "--with-relpath", "--deref-relative-to", cwd, "-o", out_arg, input];
+            let scan = Arc::new(test_util::scan(&args));
+            run(&scan).await?;
+            scan.output.finish()?;
+            let mut records = output::read_records(&out)?;
+            let record = records.remove(0);
+            Ok((record["path"].clone(), record["relpath"].clone()))
+        }
+
+        let temp_dir = tempfile::tempdir()?;
+        let metadata = serde_json::json!({"__metadata__": {}});
+        let file = test_util::write_safetensors(&temp_dir.path().join("rv/models/a"), "y.safetensors", &metadata);
+        let file = serde_json::json!(std::fs::canonicalize(file)?);
+
+        // Relative to the directory, the directories before a wildcard or the file's directory
+        let under_models = serde_json::json!(Path::new("a").join("y.safetensors"));
+        assert_eq!(paths(temp_dir.path(), "rv/models").await?, (file.clone(), under_models.clone()));
+        assert_eq!(paths(temp_dir.path(), "rv/models/*/y.safetensors").await?, (file.clone(), under_models));
+        let in_file_dir = serde_json::json!("y.safetensors");
+        assert_eq!(paths(temp_dir.path(), "rv/models/a/y.safetensors").await?, (file, in_file_dir));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_with_schema_version() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        create_dummy_safetensors(&temp_dir.path().join("a.safetensors")).await?;
+        let out = temp_dir.path().join("out.ndjson");
+        let args = ["-q", "--with-schema-version", "--format", "ndjson", "-o", out.to_str().unwrap()];
+        let scan = Arc::new(test_util::scan(&[&args[..], &[temp_dir.path().to_str().unwrap()]].concat()));
+        run(&scan).await?;
+        scan.output.finish()?;
+        // First, so a reader can branch on it before reading the rest of the line
+        assert!(std::fs::read_to_string(&out)?.starts_with(&format!("{{\"schema_version\":{}", record::SCHEMA_VERSION)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_parallel_hash() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        for name in ["a", "b", "c"] {
+            create_dummy_safetensors(&temp_dir.path().join(format!("{name}.safetensors"))).await?;
+        }
+        let out = temp_dir.path().join("out.ndjson");
+        let (out_str, dir) = (out.to_str().unwrap(), temp_dir.path().to_str().unwrap());
+        let args = ["--hash", "--parallel-hash", "--pipeline-depth", "1", "--format", "ndjson", "-o", out_str, dir];
+        let scan = Arc::new(test_util::scan(&args));
+        run(&scan).await?;
+        scan.output.finish()?;
+
+        let records: Vec<serde_json::Value> =
+            fs::read_to_string(&out).await?.lines().map(serde_json::from_str).collect::<Result<_, _>>()?;
+        assert_eq!(records.len(), 3);
+        // All three files have the same contents
+        let digest = hash::sha256_file(&temp_dir.path().join("a.safetensors"), hash::DEFAULT_CHUNK_SIZE).await?;
+        assert!(records.iter().all(|record| record["sha256"] == digest.as_str()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_default_max_open_files() {
+        let max_open_files = default_max_open_files();
+        assert!((1..=4096).contains(&max_open_files));
+    }
+
+    #[tokio::test]
+    async fn test_normalize_path_against_list_file_dir() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let list_dir = temp_dir.path().join("lists");
+        let list_file = list_dir.join("files.txt");
+        let model = temp_dir.path().join("models").join("a.safetensors");
+        create_dummy_safetensors(&model).await?;
+        create_dummy_file(&list_file, "../models/a.safetensors\n").await?;
+
+        // Entries of a list file resolve against the list file's directory, not the CWD
+        let entry = fs::read_to_string(&list_file).await?;
+        let resolved = normalize_path(Path::new(entry.trim()), list_file.parent().unwrap())?;
+        assert_eq!(resolved, std::fs::canonicalize(&model)?);
+
+        // Paths that don't exist yet are still cleaned up relative to the base
+        let missing = normalize_path(Path::new("./sub/../b.safetensors"), &list_dir)?;
+        assert_eq!(missing, std::fs::canonicalize(&list_dir)?.join("b.safetensors"));
+        Ok(())
+    }
+}
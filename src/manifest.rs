@@ -0,0 +1,89 @@
+use crate::output::LineEnding;
+use crate::record::Record;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// The name of the file written into each directory by `--group-output-by-dir`.
+pub const MANIFEST_NAME: &str = "manifest.json";
+
+/// Records bucketed by the directory of their file, for `--group-output-by-dir`. Only
+/// directories that received a record get a manifest.
+#[derive(Debug, Default)]
+pub struct Manifests {
+    by_dir: Mutex<BTreeMap<PathBuf, Vec<Record>>>,
+    line_ending: LineEnding,
+}
+
+impl Manifests {
+    pub fn new(line_ending: LineEnding) -> Self {
+        Self { by_dir: Mutex::default(), line_ending }
+    }
+
+    /// Add a record to the manifest of `dir`, the directory of its file, which its path no
+    /// longer tells with `--manifest-relative-paths`.
+    pub fn add(&self, dir: PathBuf, record: Record) {
+        let mut by_dir = self.by_dir.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        by_dir.entry(dir).or_default().push(record);
+    }
+
+    /// Write a `manifest.json` with a JSON array of its records, sorted by path, into each
+    /// directory. Returns the number of manifests written.
+    pub fn write_all(&self) -> anyhow::Result<usize> {
+        let mut by_dir = self.by_dir.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        for (dir, records) in by_dir.iter_mut() {
+            records.sort_by(|a, b| a.path.cmp(&b.path));
+            let path = dir.join(MANIFEST_NAME);
+            let json = self.line_ending.apply(serde_json::to_string_pretty(records)?);
+            crate::io::atomic_write(&path, json.as_bytes())?;
+        }
+        Ok(by_dir.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{Value, json};
+
+    #[test]
+    fn test_manifests_per_dir() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let root = temp_dir.path();
+        std::fs::create_dir_all(root.join("sdxl"))?;
+        std::fs::create_dir_all(root.join("empty"))?;
+        let manifests = Manifests::default();
+        let add = |path: PathBuf| {
+            let dir = path.parent().unwrap().to_path_buf();
+            manifests.add(dir, Record { path, metadata: json!({}), ..Record::default() });
+        };
+        add(root.join("sdxl").join("b.safetensors"));
+        add(root.join("a.safetensors"));
+        add(root.join("sdxl").join("a.safetensors"));
+        assert_eq!(manifests.write_all()?, 2);
+
+        let sdxl: Value = serde_json::from_str(&std::fs::read_to_string(root.join("sdxl").join(MANIFEST_NAME))?)?;
+        let paths: Vec<&str> = sdxl.as_array().unwrap().iter().map(|r| r["path"].as_str().unwrap()).collect();
+        assert_eq!(
+            paths,
+            vec![root.join("sdxl/a.safetensors").to_str().unwrap(), root.join("sdxl/b.safetensors").to_str().unwrap()]
+        );
+        assert!(root.join(MANIFEST_NAME).exists());
+        assert!(!root.join("empty").join(MANIFEST_NAME).exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_manifest_line_ending() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let manifests = Manifests::new(LineEnding::Crlf);
+        let record = Record { path: temp_dir.path().join("a.safetensors"), metadata: json!({}), ..Record::default() };
+        manifests.add(temp_dir.path().to_path_buf(), record);
+        manifests.write_all()?;
+
+        let bytes = std::fs::read(temp_dir.path().join(MANIFEST_NAME))?;
+        assert!(bytes.starts_with(b"[\r\n  {\r\n"));
+        assert_eq!(bytes.windows(2).filter(|pair| pair[1] == b'\n' && pair[0] != b'\r').count(), 0);
+        Ok(())
+    }
+}
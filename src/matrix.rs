@@ -0,0 +1,84 @@
+use crate::Scan;
+use crate::cli::MatrixFormat;
+use crate::header;
+use crate::summary::Outcome;
+use crate::table;
+use futures::StreamExt;
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+
+/// Print which metadata keys are present in which files: one row per file and one column per
+/// key found in any of them.
+///
+/// The first pass reads every file's keys and builds their union, the second renders the rows
+/// against it. Files that fail to read are reported and left out.
+pub async fn run(scan: &Scan, files: Vec<PathBuf>, format: MatrixFormat) -> anyhow::Result<()> {
+    let mut present: Vec<(PathBuf, BTreeSet<String>)> = futures::stream::iter(files)
+        .map(|path| async move {
+            let _permit = scan.open_files.acquire().await.ok()?;
+            match header::read_header(&path, &scan.read_options).await {
+                Ok(header) => {
+                    scan.summary.record(Outcome::Processed);
+                    let keys = header.metadata().as_object().map(|m| m.keys().cloned().collect());
+                    Some((path, keys.unwrap_or_default()))
+                }
+                Err(e) => {
+                    scan.summary.record(Outcome::Failed);
+                    scan.warn_failed(&path, &e);
+                    None
+                }
+            }
+        })
+        .buffer_unordered(scan.open_files.available_permits().max(1))
+        .filter_map(std::future::ready)
+        .collect()
+        .await;
+    present.sort();
+
+    let union: BTreeSet<&String> = present.iter().flat_map(|(_, keys)| keys).collect();
+    let headers: Vec<String> = std::iter::once("file".to_string())
+        .chain(union.iter().map(|key| (*key).clone()))
+        .collect();
+    let (yes, no) = match format {
+        MatrixFormat::Table => ("✓", "✗"),
+        MatrixFormat::Csv => ("1", "0"),
+    };
+    let rows: Vec<Vec<String>> = present
+        .iter()
+        .map(|(path, keys)| {
+            std::iter::once(path.display().to_string())
+                .chain(union.iter().map(|key| if keys.contains(*key) { yes } else { no }.to_string()))
+                .collect()
+        })
+        .collect();
+
+    let rendered = match format {
+        MatrixFormat::Table => table::render(&headers, &rows),
+        MatrixFormat::Csv => table::render_csv(&headers, &rows),
+    };
+    scan.output.write_text(&rendered)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::{scan, write_safetensors};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_matrix_csv() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let dir = temp_dir.path();
+        let a = write_safetensors(dir, "a.safetensors", &json!({"__metadata__": {"x": "1", "y": "2"}}));
+        let b = write_safetensors(dir, "b.safetensors", &json!({"__metadata__": {"y": "3"}}));
+        let out = dir.join("matrix.csv");
+
+        let scan = scan(&["matrix", "--format", "csv", "--output", out.to_str().unwrap(), dir.to_str().unwrap()]);
+        super::run(&scan, vec![b.clone(), a.clone()], crate::cli::MatrixFormat::Csv).await?;
+        scan.output.finish()?;
+
+        let expected = format!("file,x,y\n{},1,1\n{},0,1\n", a.display(), b.display());
+        assert_eq!(std::fs::read_to_string(out)?, expected);
+        Ok(())
+    }
+}
@@ -0,0 +1,59 @@
+use crate::humanize;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// The memory that records held until the end of the scan may take (`--max-memory`), as
+/// estimated from the size of their JSON.
+#[derive(Debug)]
+pub struct MemoryBudget {
+    limit: u64,
+    used: AtomicU64,
+}
+
+/// The records kept for the end took up more than `--max-memory`.
+#[derive(Debug)]
+pub struct OverBudget {
+    limit: u64,
+}
+
+impl fmt::Display for OverBudget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "The records held until the end of the scan take more than --max-memory {}; \
+             stream them to --output instead or raise the limit",
+            humanize::bytes(self.limit)
+        )
+    }
+}
+
+impl std::error::Error for OverBudget {}
+
+impl MemoryBudget {
+    pub fn new(limit: u64) -> Self {
+        Self { limit, used: AtomicU64::new(0) }
+    }
+
+    /// Count `bytes` more as held, failing once that goes over the limit.
+    pub fn charge(&self, bytes: u64) -> Result<(), OverBudget> {
+        let used = self.used.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        if used > self.limit {
+            return Err(OverBudget { limit: self.limit });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_charge() {
+        let budget = MemoryBudget::new(100);
+        assert!(budget.charge(60).is_ok());
+        assert!(budget.charge(40).is_ok());
+        let err = budget.charge(1).unwrap_err();
+        assert!(err.to_string().contains("--max-memory 100 B"), "{err}");
+    }
+}
@@ -0,0 +1,127 @@
+use crate::Scan;
+use crate::cli::Prefer;
+use crate::header;
+use crate::summary::Outcome;
+use futures::StreamExt;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// How the files of a merge agree on a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Agreement {
+    /// Every file has the same value.
+    Agreed,
+    /// The files that have the key have the same value, but some don't have it.
+    Partial,
+    /// The files have different values.
+    Conflicted,
+}
+
+/// The one record of `merge-files`: the metadata of all the files in one object, how they
+/// agree on each key, and the value of each file for the keys they conflict on.
+#[derive(Debug, Serialize)]
+struct Merged {
+    paths: Vec<PathBuf>,
+    metadata: Map<String, Value>,
+    keys: BTreeMap<String, Agreement>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    conflicts: BTreeMap<String, Map<String, Value>>,
+}
+
+/// Merge the metadata of the files, in the order given, into one record (`merge-files`), such
+/// as to describe an ensemble. Where the files conflict, `prefer` picks the value of the first
+/// or the last file that has the key. A file that can't be read fails the merge.
+pub async fn run(scan: &Scan, files: Vec<PathBuf>, prefer: Prefer) -> anyhow::Result<()> {
+    let total = files.len();
+    let read: Vec<(PathBuf, Option<Value>)> = futures::stream::iter(files)
+        .map(|path| async move {
+            let Ok(_permit) = scan.open_files.acquire().await else {
+                return (path, None);
+            };
+            match header::read_header(&path, &scan.read_options).await {
+                Ok(header) => {
+                    scan.summary.record(Outcome::Processed);
+                    (path, Some(header.metadata()))
+                }
+                Err(e) => {
+                    scan.summary.record(Outcome::Failed);
+                    scan.warn_failed(&path, &e);
+                    (path, None)
+                }
+            }
+        })
+        .buffered(scan.open_files.available_permits().max(1))
+        .collect()
+        .await;
+    let failed = read.iter().filter(|(_, metadata)| metadata.is_none()).count();
+    if failed > 0 {
+        anyhow::bail!("{failed} of {total} files couldn't be read, so they weren't merged");
+    }
+    let files: Vec<(PathBuf, Value)> = read.into_iter().filter_map(|(path, metadata)| Some((path, metadata?))).collect();
+    scan.output.write_record(&merge(&files, prefer))?;
+    Ok(())
+}
+
+fn merge(files: &[(PathBuf, Value)], prefer: Prefer) -> Merged {
+    // The value of each file that has the key, in the order of the files
+    let mut values: BTreeMap<&str, Vec<(&PathBuf, &Value)>> = BTreeMap::new();
+    for (path, metadata) in files {
+        for (key, value) in metadata.as_object().into_iter().flatten() {
+            values.entry(key).or_default().push((path, value));
+        }
+    }
+    let mut merged = Merged {
+        paths: files.iter().map(|(path, _)| path.clone()).collect(),
+        metadata: Map::new(),
+        keys: BTreeMap::new(),
+        conflicts: BTreeMap::new(),
+    };
+    for (key, values) in values {
+        let agreement = if values.iter().any(|(_, value)| *value != values[0].1) {
+            let by_file = values.iter().map(|(path, value)| (path.display().to_string(), (*value).clone()));
+            merged.conflicts.insert(key.to_string(), by_file.collect());
+            Agreement::Conflicted
+        } else if values.len() < files.len() {
+            Agreement::Partial
+        } else {
+            Agreement::Agreed
+        };
+        let kept = match prefer {
+            Prefer::First => values.first(),
+            Prefer::Last => values.last(),
+        };
+        if let Some((_, value)) = kept {
+            merged.metadata.insert(key.to_string(), (*value).clone());
+        }
+        merged.keys.insert(key.to_string(), agreement);
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_merge() {
+        let files = [
+            (PathBuf::from("a"), json!({"arch": "sdxl", "title": "a", "license": "mit"})),
+            (PathBuf::from("b"), json!({"arch": "sdxl", "title": "b"})),
+        ];
+        let merged = serde_json::to_value(merge(&files, Prefer::Last)).unwrap();
+        assert_eq!(
+            merged,
+            json!({
+                "paths": ["a", "b"],
+                "metadata": {"arch": "sdxl", "title": "b", "license": "mit"},
+                "keys": {"arch": "agreed", "license": "partial", "title": "conflicted"},
+                "conflicts": {"title": {"a": "a", "b": "b"}},
+            })
+        );
+        assert_eq!(merge(&files, Prefer::First).metadata["title"], "a");
+    }
+}
@@ -0,0 +1,124 @@
+use crate::formats::TensorInfo;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::io::SeekFrom;
+use std::path::Path;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+/// The largest tensor `--detect-nan-inf` reads when `--nan-check-max-bytes` isn't given.
+pub const DEFAULT_MAX_BYTES: u64 = 1 << 20;
+
+/// A tensor with values that aren't finite, found by `--detect-nan-inf`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct NonFinite {
+    pub tensor: String,
+    pub nan: u64,
+    pub inf: u64,
+}
+
+/// The bytes, exponent bits and mantissa bits of the float dtypes that can be checked.
+fn layout(dtype: &str) -> Option<(usize, u32, u32)> {
+    Some(match dtype {
+        "F16" => (2, 5, 10),
+        "BF16" => (2, 8, 7),
+        "F32" => (4, 8, 23),
+        "F64" => (8, 11, 52),
+        _ => return None,
+    })
+}
+
+/// Read the data of each float tensor of at most `max_bytes` and count its NaN and infinite
+/// values, returning the tensors that have any. `data_start` is where the data of the file
+/// begins, right after its header. Bigger tensors and other dtypes aren't read.
+pub async fn check(
+    path: &Path,
+    data_start: u64,
+    file_len: u64,
+    tensors: &[TensorInfo],
+    max_bytes: u64,
+) -> anyhow::Result<Vec<NonFinite>> {
+    let mut file = None;
+    let mut found = Vec::new();
+    for tensor in tensors {
+        let (Some((size, exponent, mantissa)), Some([begin, end])) = (layout(&tensor.dtype), tensor.data_offsets) else {
+            continue;
+        };
+        if end < begin || end - begin > max_bytes {
+            continue;
+        }
+        if data_start + end > file_len {
+            anyhow::bail!("The data of tensor {} ends past the end of the file", tensor.name);
+        }
+        let file = match &mut file {
+            Some(file) => file,
+            None => file.insert(tokio::fs::File::open(path).await?),
+        };
+        let mut bytes = vec![0; usize::try_from(end - begin)?];
+        file.seek(SeekFrom::Start(data_start + begin)).await?;
+        file.read_exact(&mut bytes).await?;
+        let (nan, inf) = count(&bytes, size, exponent, mantissa);
+        if nan > 0 || inf > 0 {
+            found.push(NonFinite { tensor: tensor.name.clone(), nan, inf });
+        }
+    }
+    Ok(found)
+}
+
+/// The NaN and infinite values of little-endian floats of `size` bytes: those whose exponent
+/// bits are all set, infinite when the mantissa is zero.
+fn count(bytes: &[u8], size: usize, exponent: u32, mantissa: u32) -> (u64, u64) {
+    let (mut nan, mut inf) = (0, 0);
+    for element in bytes.chunks_exact(size) {
+        let mut array = [0; 8];
+        array[..size].copy_from_slice(element);
+        let bits = u64::from_le_bytes(array);
+        let max_exponent = (1 << exponent) - 1;
+        if (bits >> mantissa) & max_exponent == max_exponent {
+            if bits & ((1 << mantissa) - 1) == 0 {
+                inf += 1;
+            } else {
+                nan += 1;
+            }
+        }
+    }
+    (nan, inf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_check() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let header = json!({
+            "f32": {"dtype": "F32", "shape": [3], "data_offsets": [0, 12]},
+            "f16": {"dtype": "F16", "shape": [2], "data_offsets": [12, 16]},
+            "bf16": {"dtype": "BF16", "shape": [2], "data_offsets": [16, 20]},
+            "i16": {"dtype": "I16", "shape": [1], "data_offsets": [20, 22]},
+            "big": {"dtype": "F32", "shape": [4], "data_offsets": [22, 38]},
+        });
+        let header = serde_json::to_vec(&header)?;
+        let mut bytes = (header.len() as u64).to_le_bytes().to_vec();
+        bytes.extend(&header);
+        for value in [1.0f32, f32::NAN, f32::NEG_INFINITY] {
+            bytes.extend(value.to_le_bytes());
+        }
+        bytes.extend([0x00, 0x3c, 0x00, 0x7c]); // 1.0, inf
+        bytes.extend([0x80, 0x3f, 0x80, 0x3f]); // 1.0, 1.0
+        bytes.extend([0xff, 0xff]);
+        for value in [f32::NAN; 4] {
+            bytes.extend(value.to_le_bytes());
+        }
+        let path = temp_dir.path().join("a.safetensors");
+        std::fs::write(&path, &bytes)?;
+
+        let header = crate::header::read_header(&path, &crate::header::ReadOptions::default()).await?;
+        let tensors = crate::formats::Format::list_tensors(&crate::formats::Safetensors, &header);
+        let non_finite = check(&path, 8 + header.len, header.stat.len(), &tensors, 12).await?;
+        let tensor = |tensor: &str, nan, inf| NonFinite { tensor: tensor.to_string(), nan, inf };
+        assert_eq!(non_finite, [tensor("f16", 0, 1), tensor("f32", 1, 1)]);
+        Ok(())
+    }
+}
@@ -0,0 +1,229 @@
+use serde_json::{Map, Number, Value};
+use std::collections::HashMap;
+
+/// The JSON type a metadata value is coerced to by `--normalize-metadata`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Coercion {
+    /// An integer if the value parses as one, otherwise a float
+    Number,
+    /// `true` or `false`, accepting any capitalization
+    Bool,
+    /// Left as it is stored
+    String,
+}
+
+/// Numeric fields written by kohya-ss sd-scripts, which stores every value as a string.
+const NUMBER_FIELDS: &[&str] = &[
+    "ss_adaptive_noise_scale",
+    "ss_batch_size_per_device",
+    "ss_bucket_reso_steps",
+    "ss_caption_dropout_every_n_epochs",
+    "ss_caption_dropout_rate",
+    "ss_caption_tag_dropout_rate",
+    "ss_clip_skip",
+    "ss_epoch",
+    "ss_gradient_accumulation_steps",
+    "ss_ip_noise_gamma",
+    "ss_keep_tokens",
+    "ss_learning_rate",
+    "ss_lr_warmup_steps",
+    "ss_max_bucket_reso",
+    "ss_max_grad_norm",
+    "ss_max_token_length",
+    "ss_max_train_steps",
+    "ss_min_bucket_reso",
+    "ss_min_snr_gamma",
+    "ss_multires_noise_discount",
+    "ss_multires_noise_iterations",
+    "ss_network_alpha",
+    "ss_network_dim",
+    "ss_noise_offset",
+    "ss_num_batches_per_epoch",
+    "ss_num_epochs",
+    "ss_num_reg_images",
+    "ss_num_train_images",
+    "ss_prior_loss_weight",
+    "ss_scale_weight_norms",
+    "ss_seed",
+    "ss_session_id",
+    "ss_steps",
+    "ss_text_encoder_lr",
+    "ss_training_finished_at",
+    "ss_training_started_at",
+    "ss_unet_lr",
+];
+
+/// Boolean fields written by kohya-ss sd-scripts as `"True"`/`"False"`.
+const BOOL_FIELDS: &[&str] = &[
+    "ss_bucket_no_upscale",
+    "ss_cache_latents",
+    "ss_color_aug",
+    "ss_debiased_estimation",
+    "ss_enable_bucket",
+    "ss_flip_aug",
+    "ss_full_fp16",
+    "ss_gradient_checkpointing",
+    "ss_random_crop",
+    "ss_shuffle_caption",
+    "ss_v2",
+    "ss_zero_terminal_snr",
+];
+
+/// Coerces the string values of known metadata fields to JSON numbers and booleans.
+#[derive(Debug, Clone, Default)]
+pub struct Normalizer {
+    /// Per-key coercions from `--coerce`, taking precedence over the built-in table.
+    overrides: HashMap<String, Coercion>,
+}
+
+impl Normalizer {
+    pub fn new(overrides: impl IntoIterator<Item = (String, Coercion)>) -> Self {
+        Self { overrides: overrides.into_iter().collect() }
+    }
+
+    fn coercion(&self, key: &str) -> Option<Coercion> {
+        if let Some(coercion) = self.overrides.get(key) {
+            return Some(*coercion);
+        }
+        if NUMBER_FIELDS.contains(&key) {
+            Some(Coercion::Number)
+        } else if BOOL_FIELDS.contains(&key) {
+            Some(Coercion::Bool)
+        } else {
+            None
+        }
+    }
+
+    /// Coerce the known top-level fields of `metadata`. Unknown fields, and known fields whose
+    /// value doesn't parse as the expected type, are left untouched.
+    pub fn normalize(&self, metadata: &mut Map<String, Value>) {
+        for (key, value) in metadata.iter_mut() {
+            let Value::String(s) = value else { continue };
+            let coerced = match self.coercion(key) {
+                Some(Coercion::Number) => parse_number(s.trim()).map(Value::Number),
+                Some(Coercion::Bool) => parse_bool(s.trim()).map(Value::Bool),
+                Some(Coercion::String) | None => None,
+            };
+            if let Some(coerced) = coerced {
+                *value = coerced;
+            }
+        }
+    }
+}
+
+fn parse_number(s: &str) -> Option<Number> {
+    if let Ok(i) = s.parse::<i64>() {
+        return Some(i.into());
+    }
+    if let Ok(u) = s.parse::<u64>() {
+        return Some(u.into());
+    }
+    s.parse::<f64>().ok().and_then(Number::from_f64)
+}
+
+fn parse_bool(s: &str) -> Option<bool> {
+    if s.eq_ignore_ascii_case("true") {
+        Some(true)
+    } else if s.eq_ignore_ascii_case("false") {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// Trim the string values anywhere in `value` and normalize the whitespace in them, for
+/// `--canonicalize-metadata-strings`: line endings become `\n`, each run of other whitespace
+/// a single space, and each line is trimmed.
+pub fn canonicalize_strings(value: &mut Value) {
+    match value {
+        Value::String(s) => *s = canonical_string(s),
+        Value::Array(values) => values.iter_mut().for_each(canonicalize_strings),
+        Value::Object(map) => map.values_mut().for_each(canonicalize_strings),
+        _ => {}
+    }
+}
+
+fn canonical_string(s: &str) -> String {
+    let lines: Vec<String> = s
+        .replace("\r\n", "\n")
+        .split(['\n', '\r'])
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .collect();
+    lines.join("\n").trim().to_string()
+}
+
+/// Parse a `--coerce KEY=TYPE` argument.
+pub fn parse_coerce_arg(arg: &str) -> Result<(String, Coercion), String> {
+    let (key, kind) = arg.split_once('=').ok_or_else(|| format!("expected KEY=TYPE, got `{arg}`"))?;
+    let coercion = <Coercion as clap::ValueEnum>::from_str(kind, true)?;
+    Ok((key.to_string(), coercion))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn normalized(normalizer: &Normalizer, metadata: Value) -> Value {
+        let Value::Object(mut map) = metadata else { unreachable!() };
+        normalizer.normalize(&mut map);
+        Value::Object(map)
+    }
+
+    #[test]
+    fn test_normalize_known_fields() {
+        let metadata = json!({
+            "ss_network_dim": "16",
+            "ss_learning_rate": "1e-4",
+            "ss_network_alpha": "8.0",
+            "ss_cache_latents": "True",
+            "ss_flip_aug": "false",
+            "ss_seed": "not a number",
+            "ss_output_name": "123",
+        });
+        assert_eq!(
+            normalized(&Normalizer::default(), metadata),
+            json!({
+                "ss_network_dim": 16,
+                "ss_learning_rate": 0.0001,
+                "ss_network_alpha": 8.0,
+                "ss_cache_latents": true,
+                "ss_flip_aug": false,
+                "ss_seed": "not a number",
+                "ss_output_name": "123",
+            })
+        );
+    }
+
+    #[test]
+    fn test_normalize_overrides() {
+        let normalizer = Normalizer::new([
+            parse_coerce_arg("ss_output_name=number").unwrap(),
+            parse_coerce_arg("ss_network_dim=string").unwrap(),
+        ]);
+        let metadata = json!({"ss_output_name": "123", "ss_network_dim": "16"});
+        assert_eq!(normalized(&normalizer, metadata), json!({"ss_output_name": 123, "ss_network_dim": "16"}));
+        assert!(parse_coerce_arg("ss_network_dim").is_err());
+        assert!(parse_coerce_arg("ss_network_dim=date").is_err());
+    }
+
+    #[test]
+    fn test_canonicalize_strings() {
+        let mut metadata = json!({
+            "ss_output_name": "  my\tlora  ",
+            "description": "\r\nFirst  line \r\n\r\n  second\rthird\n",
+            "ss_tag_frequency": {" a ": {"x": " 1 "}},
+            "ss_epoch": 3,
+        });
+        canonicalize_strings(&mut metadata);
+        assert_eq!(
+            metadata,
+            json!({
+                "ss_output_name": "my lora",
+                "description": "First line\n\nsecond\nthird",
+                "ss_tag_frequency": {" a ": {"x": "1"}},
+                "ss_epoch": 3,
+            })
+        );
+    }
+}
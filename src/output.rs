@@ -0,0 +1,965 @@
+use crate::template::Template;
+use anyhow::Context;
+use flate2::write::GzEncoder;
+use serde::Serialize;
+use serde::ser::{SerializeMap, Serializer};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::num::NonZeroU64;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+
+/// How records are laid out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Pretty-printed JSON, one object per file
+    #[default]
+    Json,
+    /// One compact JSON object per line
+    Ndjson,
+    /// A TOML document with a `[[files]]` table for each record
+    Toml,
+    /// A YAML document in block style for each record, with multiline strings as literal blocks
+    Yaml,
+    /// An Apache Parquet file with a row per record, for data frames and warehouses
+    Parquet,
+    /// One canonical JSON object per line (`--canonical-json`)
+    #[value(skip)]
+    CanonicalJson,
+    /// One line per record formatted by a template (`--output-template`)
+    #[value(skip)]
+    Template,
+}
+
+/// How paths are written in records (`--path-separator`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum PathSeparator {
+    /// As the platform writes them, with backslashes on Windows
+    #[default]
+    Native,
+    /// With forward slashes on every platform
+    Slash,
+}
+
+/// The fields of [`crate::record::Record`] and [`crate::record::ErrorRecord`] that hold paths.
+const PATH_FIELDS: &[&str] = &["path", "relpath", "shards", "missing_shards"];
+
+/// Rewrite the paths in a serialized record to use forward slashes. The platform's separator
+/// is replaced, so this does nothing where that is already `/`, leaving the backslashes that
+/// are ordinary file name characters there alone.
+fn slash_paths(record: &mut Value, separator: char) {
+    fn slash(path: &mut String, separator: char) {
+        if path.contains(separator) {
+            *path = path.replace(separator, "/");
+        }
+    }
+    if separator == '/' {
+        return;
+    }
+    let Value::Object(fields) = record else { return };
+    for field in PATH_FIELDS {
+        match fields.get_mut(*field) {
+            Some(Value::String(path)) => slash(path, separator),
+            Some(Value::Array(paths)) => {
+                for path in paths {
+                    if let Value::String(path) = path {
+                        slash(path, separator);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    if let Some(Value::Object(sizes)) = fields.get_mut("shard_sizes") {
+        *sizes = std::mem::take(sizes)
+            .into_iter()
+            .map(|(mut path, size)| {
+                slash(&mut path, separator);
+                (path, size)
+            })
+            .collect();
+    }
+}
+
+/// The computed numeric fields of [`crate::record::Record`], quoted by `--json-numbers-as-strings`.
+/// The values of `shard_sizes` are too. Numbers in the metadata are the file's own and left alone.
+const NUMBER_FIELDS: &[&str] = &["size", "modified_epoch", "parameters", "tensors"];
+
+/// Replace the computed numbers in a serialized record by their decimal strings, which
+/// consumers parsing JSON numbers as doubles can't round.
+fn quote_numbers(record: &mut Value) {
+    fn quote(value: &mut Value) {
+        if let Value::Number(number) = value {
+            *value = Value::String(number.to_string());
+        }
+    }
+    let Value::Object(fields) = record else { return };
+    for field in NUMBER_FIELDS {
+        if let Some(value) = fields.get_mut(*field) {
+            quote(value);
+        }
+    }
+    if let Some(Value::Object(sizes)) = fields.get_mut("shard_sizes") {
+        sizes.values_mut().for_each(quote);
+    }
+}
+
+/// Compare two keys in natural order, where runs of digits compare by their value, so
+/// `block_2` comes before `block_10`. Keys that only differ in leading zeros are ordered by
+/// their text, so that only equal keys are equal.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    fn runs(text: &str) -> impl Iterator<Item = &str> {
+        let mut rest = text;
+        std::iter::from_fn(move || {
+            let first = rest.chars().next()?;
+            let end = rest.find(|c: char| c.is_ascii_digit() != first.is_ascii_digit()).unwrap_or(rest.len());
+            let (run, tail) = rest.split_at(end);
+            rest = tail;
+            Some(run)
+        })
+    }
+    let is_number = |run: &str| run.starts_with(|c: char| c.is_ascii_digit());
+    let (mut a_runs, mut b_runs) = (runs(a), runs(b));
+    loop {
+        let ordering = match (a_runs.next(), b_runs.next()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(a), Some(b)) if is_number(a) && is_number(b) => {
+                let (a, b) = (a.trim_start_matches('0'), b.trim_start_matches('0'));
+                a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+            }
+            (Some(a), Some(b)) => a.cmp(b),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+/// Serializes a value with the keys of its objects in [`natural_cmp`] order
+/// (`--natural-key-order`), since a [`Value`] keeps them in plain string order.
+struct NaturalOrder<'a>(&'a Value);
+
+impl Serialize for NaturalOrder<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            Value::Object(fields) => {
+                let mut fields: Vec<_> = fields.iter().collect();
+                fields.sort_by(|(a, _), (b, _)| natural_cmp(a, b));
+                let mut map = serializer.serialize_map(Some(fields.len()))?;
+                for (key, value) in fields {
+                    map.serialize_entry(key, &NaturalOrder(value))?;
+                }
+                map.end()
+            }
+            Value::Array(values) => serializer.collect_seq(values.iter().map(NaturalOrder)),
+            value => value.serialize(serializer),
+        }
+    }
+}
+
+/// Remove the null values, empty strings, empty arrays and empty objects from the objects
+/// in a serialized record, at any depth (`--compact-empty`). An object left empty by this is
+/// removed too. Array elements are kept, so their positions don't shift.
+fn drop_empty(record: &mut Value) {
+    let is_empty = |value: &Value| match value {
+        Value::Null => true,
+        Value::String(text) => text.is_empty(),
+        Value::Array(values) => values.is_empty(),
+        Value::Object(fields) => fields.is_empty(),
+        _ => false,
+    };
+    match record {
+        Value::Object(fields) => {
+            fields.values_mut().for_each(drop_empty);
+            fields.retain(|_, value| !is_empty(value));
+        }
+        Value::Array(values) => values.iter_mut().for_each(drop_empty),
+        _ => {}
+    }
+}
+
+/// A record as a `[[files]]` table, so that the records written one after another make up a
+/// single TOML document. TOML has no null and no integers beyond `i64`, so those values are
+/// written as strings, with a warning naming them.
+fn toml_record(record: &Value) -> anyhow::Result<String> {
+    fn convert(value: &Value, key: &str, stringified: &mut Vec<String>) -> toml::Value {
+        let mut stringify = |text: String| {
+            stringified.push(key.to_string());
+            toml::Value::String(text)
+        };
+        match value {
+            Value::Null => stringify("null".to_string()),
+            Value::Bool(bool) => toml::Value::Boolean(*bool),
+            Value::Number(number) => match (number.as_i64(), number.as_f64()) {
+                (Some(integer), _) => toml::Value::Integer(integer),
+                (None, Some(float)) if number.is_f64() => toml::Value::Float(float),
+                _ => stringify(number.to_string()),
+            },
+            Value::String(text) => toml::Value::String(text.clone()),
+            Value::Array(values) => {
+                let element = |(i, value)| convert(value, &format!("{key}[{i}]"), stringified);
+                toml::Value::Array(values.iter().enumerate().map(element).collect())
+            }
+            Value::Object(fields) => toml::Value::Table(
+                fields
+                    .iter()
+                    .map(|(name, value)| {
+                        let key = if key.is_empty() { name.clone() } else { format!("{key}.{name}") };
+                        (name.clone(), convert(value, &key, stringified))
+                    })
+                    .collect(),
+            ),
+        }
+    }
+    let mut stringified = Vec::new();
+    let files = toml::Value::Array(vec![convert(record, "", &mut stringified)]);
+    if !stringified.is_empty() {
+        let path = record.get("path").and_then(Value::as_str).unwrap_or("record");
+        eprintln!("Warning: {path}: TOML has no type for {}; wrote them as strings", stringified.join(", "));
+    }
+    let document: toml::Table = [("files".to_string(), files)].into_iter().collect();
+    Ok(toml::to_string(&document)?)
+}
+
+/// A record as a YAML document of its own, started by `---` so that the records written one
+/// after another make up a stream of documents. Strings with line breaks, such as captions or
+/// embedded configs, are written as literal `|` blocks rather than escaped on one line.
+fn yaml_record(record: &impl Serialize) -> anyhow::Result<String> {
+    Ok(format!("---\n{}", serde_yaml::to_string(record)?))
+}
+
+/// The line ending of the JSON files written next to the models (`--line-ending`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum LineEnding {
+    /// `\n` on every platform
+    #[default]
+    Lf,
+    /// `\r\n` on every platform
+    Crlf,
+    /// `\r\n` on Windows, `\n` elsewhere
+    Native,
+}
+
+impl LineEnding {
+    /// `text`, written with `\n` line endings, with this line ending instead. JSON escapes
+    /// the newlines in strings, so only the line breaks between values change.
+    pub fn apply(self, text: String) -> String {
+        if self == Self::Crlf || (self == Self::Native && cfg!(windows)) {
+            text.replace('\n', "\r\n")
+        } else {
+            text
+        }
+    }
+}
+
+/// How the `--output` file is compressed (`--compress`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Compression {
+    None,
+    Gzip,
+}
+
+impl Compression {
+    /// `explicit` if given, otherwise gzip for a file ending in `.gz`.
+    pub fn for_path(path: Option<&Path>, explicit: Option<Self>) -> Self {
+        explicit.unwrap_or_else(|| {
+            let gz = path.and_then(Path::extension).is_some_and(|extension| extension.eq_ignore_ascii_case("gz"));
+            if gz { Self::Gzip } else { Self::None }
+        })
+    }
+}
+
+/// Where the buffered output ends up.
+enum Sink {
+    Stdout(io::Stdout),
+    File(File),
+    Gzip(GzEncoder<File>),
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Stdout(stdout) => stdout.write(buf),
+            Self::File(file) => file.write(buf),
+            Self::Gzip(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Stdout(stdout) => stdout.flush(),
+            Self::File(file) => file.flush(),
+            Self::Gzip(encoder) => encoder.flush(),
+        }
+    }
+}
+
+type Writer = BufWriter<Sink>;
+
+tokio::task_local! {
+    /// What the current task wrote inside [`hold`], one entry per record.
+    static HELD: RefCell<Vec<Vec<u8>>>;
+}
+
+/// The records a task wrote inside [`hold`], not yet in the output.
+#[derive(Debug, Default)]
+pub struct Held(Vec<Vec<u8>>);
+
+/// Run `future`, holding back the records it writes instead of writing them, for
+/// `--preserve-order`. [`Output::write_held`] writes them later.
+pub async fn hold<T>(future: impl Future<Output = T>) -> (T, Held) {
+    HELD.scope(RefCell::default(), async {
+        let value = future.await;
+        (value, Held(HELD.with(RefCell::take)))
+    })
+    .await
+}
+
+/// When `--output-split-size` moves on to the next output file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SplitSize {
+    Records(u64),
+    /// Bytes before compression.
+    Bytes(u64),
+}
+
+/// Parse `--output-split-size`: a number of records such as `10000`, or of bytes with a unit
+/// such as `64MiB` or `500MB`.
+pub fn parse_split_size(arg: &str) -> Result<SplitSize, String> {
+    let split = arg.find(|c: char| !c.is_ascii_digit()).unwrap_or(arg.len());
+    let (number, unit) = arg.split_at(split);
+    let number: u64 = number.parse().map_err(|_| format!("expected a number of records or bytes, got `{arg}`"))?;
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" => return if number > 0 { Ok(SplitSize::Records(number)) } else { Err("must be at least 1".to_string()) },
+        "B" => 1,
+        "KB" => 1000,
+        "MB" => 1000 * 1000,
+        "GB" => 1000 * 1000 * 1000,
+        "KIB" => 1 << 10,
+        "MIB" => 1 << 20,
+        "GIB" => 1 << 30,
+        _ => return Err(format!("unknown unit `{unit}`, expected B, KB, MB, GB, KiB, MiB or GiB")),
+    };
+    match number.checked_mul(multiplier) {
+        Some(0) => Err("must be at least 1".to_string()),
+        Some(bytes) => Ok(SplitSize::Bytes(bytes)),
+        None => Err(format!("`{arg}` is too large")),
+    }
+}
+
+/// The name of the `index`th of the files `path` is split into: `manifest.jsonl.gz` becomes
+/// `manifest.0001.jsonl.gz`.
+fn split_path(path: &Path, index: usize) -> PathBuf {
+    let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let name = match name.split_once('.') {
+        Some((stem, extensions)) => format!("{stem}.{index:04}.{extensions}"),
+        None => format!("{name}.{index:04}"),
+    };
+    path.with_file_name(name)
+}
+
+/// The state of `--output-split-size`: the files written so far and how much went into the
+/// last one.
+struct Split {
+    path: PathBuf,
+    size: SplitSize,
+    compression: Compression,
+    files: Vec<PathBuf>,
+    records: u64,
+    bytes: u64,
+}
+
+impl Split {
+    fn is_full(&self) -> bool {
+        match self.size {
+            SplitSize::Records(records) => self.records >= records,
+            SplitSize::Bytes(bytes) => self.bytes >= bytes,
+        }
+    }
+}
+
+/// Counts the bytes written through it, and with `--output-digest` hashes them.
+struct Counting<'a, W> {
+    inner: W,
+    bytes: u64,
+    digest: Option<&'a mut Sha256>,
+}
+
+impl<W: Write> Write for Counting<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.bytes += written as u64;
+        if let Some(digest) = &mut self.digest {
+            digest.update(&buf[..written]);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// The output stream (stdout or `--output`) shared between the concurrent per-file tasks.
+///
+/// Records are serialized straight into a `BufWriter`, so a scan of many small
+/// files costs one `write` syscall per buffer instead of one per record.
+/// Callers must `finish` before exiting; `main` does so on completion, on
+/// error and on Ctrl-C.
+pub struct Output {
+    writer: Mutex<Writer>,
+    /// Locked after `writer`, which it swaps for the next file.
+    split: Option<Mutex<Split>>,
+    buffer_size: usize,
+    format: OutputFormat,
+    template: Option<Template>,
+    path_separator: PathSeparator,
+    numbers_as_strings: bool,
+    compact_empty: bool,
+    natural_key_order: bool,
+    /// `--flush-every`: flush after this many records, rather than only when the buffer is full.
+    flush_every: Option<NonZeroU64>,
+    /// The records written since the last flush, counted while holding `writer`.
+    unflushed: AtomicU64,
+    /// `--output-digest`: the SHA-256 of everything written, locked after `writer`.
+    digest: Option<Mutex<Sha256>>,
+    /// With `--format parquet`, the file the spooled records are turned into when finished.
+    parquet: Mutex<Option<crate::columnar::Parquet>>,
+}
+
+impl Output {
+    /// Write to `path`, or to stdout if it is `None`. With `append` an existing file is
+    /// extended rather than replaced; a gzip file is extended with another gzip member.
+    /// With `split` the records go into numbered files named after `path` instead, a new one
+    /// started whenever the last one is full.
+    pub fn new(
+        path: Option<&Path>,
+        append: bool,
+        compression: Compression,
+        format: OutputFormat,
+        split: Option<SplitSize>,
+        buffer_size: usize,
+    ) -> anyhow::Result<Self> {
+        Self::open(path, append, compression, format, None, split, buffer_size)
+    }
+
+    /// Write records as lines formatted by `template`.
+    pub fn with_template(
+        path: Option<&Path>,
+        append: bool,
+        compression: Compression,
+        template: Template,
+        split: Option<SplitSize>,
+        buffer_size: usize,
+    ) -> anyhow::Result<Self> {
+        Self::open(path, append, compression, OutputFormat::Template, Some(template), split, buffer_size)
+    }
+
+    /// Write the paths in records with `separator`. Only their display changes; the paths
+    /// the scan works with stay native.
+    pub fn set_path_separator(&mut self, separator: PathSeparator) {
+        self.path_separator = separator;
+    }
+
+    /// Write the computed numbers in records as strings (`--json-numbers-as-strings`).
+    pub fn set_numbers_as_strings(&mut self, numbers_as_strings: bool) {
+        self.numbers_as_strings = numbers_as_strings;
+    }
+
+    /// Leave the empty and null values out of records (`--compact-empty`).
+    pub fn set_compact_empty(&mut self, compact_empty: bool) {
+        self.compact_empty = compact_empty;
+    }
+
+    /// Write the keys of records in natural order (`--natural-key-order`).
+    pub fn set_natural_key_order(&mut self, natural_key_order: bool) {
+        self.natural_key_order = natural_key_order;
+    }
+
+    /// Hash everything written, for [`Output::digest`] (`--output-digest`).
+    pub fn set_digest(&mut self, digest: bool) {
+        self.digest = digest.then(|| Mutex::new(Sha256::new()));
+    }
+
+    /// The SHA-256 as lowercase hex of the bytes written so far, before any compression and
+    /// across all the files of `--output-split-size`.
+    pub fn digest(&self) -> Option<String> {
+        let digest = self.digest.as_ref()?.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        Some(format!("{:x}", digest.clone().finalize()))
+    }
+
+    /// Write a column for each metadata key to a Parquet output (`--explode-keys`).
+    pub fn set_explode_keys(&mut self, explode_keys: bool) {
+        if let Some(parquet) = self.parquet.get_mut().unwrap_or_else(std::sync::PoisonError::into_inner) {
+            parquet.explode_keys = explode_keys;
+        }
+    }
+
+    /// Flush the output after every `records` records (`--flush-every`).
+    pub fn set_flush_every(&mut self, records: Option<NonZeroU64>) {
+        self.flush_every = records;
+    }
+
+    fn open(
+        path: Option<&Path>,
+        append: bool,
+        compression: Compression,
+        format: OutputFormat,
+        template: Option<Template>,
+        split: Option<SplitSize>,
+        buffer_size: usize,
+    ) -> anyhow::Result<Self> {
+        let split = match (path, split) {
+            (Some(path), Some(size)) => {
+                let files = vec![split_path(path, 1)];
+                Some(Split { path: path.to_path_buf(), size, compression, files, records: 0, bytes: 0 })
+            }
+            _ => None,
+        };
+        let parquet = match path {
+            Some(path) if format == OutputFormat::Parquet => {
+                let spool = crate::io::temp_path(path, "ndjson")?;
+                Some(crate::columnar::Parquet { spool, path: path.to_path_buf(), explode_keys: false })
+            }
+            _ => None,
+        };
+        let sink = match (&split, &parquet, path) {
+            (Some(split), _, _) => Self::open_file(&split.files[0], append, compression)?,
+            (None, Some(parquet), _) => Self::open_file(&parquet.spool, false, Compression::None)?,
+            (None, None, Some(path)) => Self::open_file(path, append, compression)?,
+            (None, None, None) => Sink::Stdout(io::stdout()),
+        };
+        Ok(Self {
+            writer: Mutex::new(BufWriter::with_capacity(buffer_size, sink)),
+            split: split.map(Mutex::new),
+            buffer_size,
+            format,
+            template,
+            path_separator: PathSeparator::Native,
+            numbers_as_strings: false,
+            compact_empty: false,
+            natural_key_order: false,
+            flush_every: None,
+            unflushed: AtomicU64::new(0),
+            digest: None,
+            parquet: Mutex::new(parquet),
+        })
+    }
+
+    fn open_file(path: &Path, append: bool, compression: Compression) -> anyhow::Result<Sink> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .append(append)
+            .truncate(!append)
+            .open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        Ok(match compression {
+            Compression::None => Sink::File(file),
+            Compression::Gzip => Sink::Gzip(GzEncoder::new(file, flate2::Compression::default())),
+        })
+    }
+
+    /// The files written with `--output-split-size`, in order.
+    pub fn split_files(&self) -> Option<Vec<PathBuf>> {
+        let split = self.split.as_ref()?;
+        Some(split.lock().unwrap_or_else(std::sync::PoisonError::into_inner).files.clone())
+    }
+
+    /// Write one record, or header, with `write`, first moving on to the next file if the last
+    /// one is full, so that no file is left empty and each holds whole records.
+    fn write_counted(&self, write: impl FnOnce(&mut dyn Write) -> anyhow::Result<()>) -> anyhow::Result<()> {
+        if HELD.try_with(|_| ()).is_ok() {
+            let mut record = Vec::new();
+            write(&mut record)?;
+            HELD.with(|held| held.borrow_mut().push(record));
+            return Ok(());
+        }
+        let mut writer = self.lock();
+        let mut digest =
+            self.digest.as_ref().map(|digest| digest.lock().unwrap_or_else(std::sync::PoisonError::into_inner));
+        let Some(split) = &self.split else {
+            write(&mut Counting { inner: &mut *writer, bytes: 0, digest: digest.as_deref_mut() })?;
+            return self.flush_if_due(&mut writer);
+        };
+        let mut split = split.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        if split.is_full() {
+            finish_writer(&mut writer)?;
+            let path = split_path(&split.path, split.files.len() + 1);
+            *writer = BufWriter::with_capacity(self.buffer_size, Self::open_file(&path, false, split.compression)?);
+            split.files.push(path);
+            split.records = 0;
+            split.bytes = 0;
+        }
+        let mut counting = Counting { inner: &mut *writer, bytes: 0, digest: digest.as_deref_mut() };
+        write(&mut counting)?;
+        split.records += 1;
+        split.bytes += counting.bytes;
+        self.flush_if_due(&mut writer)
+    }
+
+    /// Flush `writer` if this record is the last of a `--flush-every` batch.
+    fn flush_if_due(&self, writer: &mut Writer) -> anyhow::Result<()> {
+        let Some(every) = self.flush_every else {
+            return Ok(());
+        };
+        if self.unflushed.fetch_add(1, AtomicOrdering::Relaxed) + 1 >= every.get() {
+            self.unflushed.store(0, AtomicOrdering::Relaxed);
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Write a [`crate::record::Record`] or [`crate::record::ErrorRecord`] in the chosen format.
+    pub fn write_record(&self, record: &impl Serialize) -> anyhow::Result<()> {
+        let rewrite = self.numbers_as_strings || self.compact_empty || self.natural_key_order;
+        if self.path_separator == PathSeparator::Slash || rewrite {
+            let mut record = serde_json::to_value(record)?;
+            if self.path_separator == PathSeparator::Slash {
+                slash_paths(&mut record, std::path::MAIN_SEPARATOR);
+            }
+            if self.numbers_as_strings {
+                quote_numbers(&mut record);
+            }
+            if self.compact_empty {
+                drop_empty(&mut record);
+            }
+            if self.natural_key_order {
+                return self.write_value(&NaturalOrder(&record));
+            }
+            return self.write_value(&record);
+        }
+        self.write_value(record)
+    }
+
+    fn write_value(&self, record: &impl Serialize) -> anyhow::Result<()> {
+        self.write_counted(|writer| {
+            match self.format {
+                OutputFormat::Json => serde_json::to_writer_pretty(&mut *writer, record)?,
+                OutputFormat::Ndjson | OutputFormat::Parquet => serde_json::to_writer(&mut *writer, record)?,
+                OutputFormat::CanonicalJson => {
+                    writer.write_all(crate::canonical::to_string(&serde_json::to_value(record)?).as_bytes())?;
+                }
+                OutputFormat::Template => {
+                    let template = self.template.as_ref().expect("template output has a template");
+                    writer.write_all(template.render(&serde_json::to_value(record)?).as_bytes())?;
+                }
+                OutputFormat::Toml => writer.write_all(toml_record(&serde_json::to_value(record)?)?.as_bytes())?,
+                OutputFormat::Yaml => writer.write_all(yaml_record(record)?.as_bytes())?,
+            }
+            writer.write_all(b"\n")?;
+            Ok(())
+        })
+    }
+
+    /// Write the records held back by [`hold`].
+    pub fn write_held(&self, held: Held) -> anyhow::Result<()> {
+        for record in held.0 {
+            self.write_counted(|writer| Ok(writer.write_all(&record)?))?;
+        }
+        Ok(())
+    }
+
+    /// Write a file's header bytes verbatim, terminated by a newline.
+    pub fn write_raw(&self, bytes: &[u8]) -> anyhow::Result<()> {
+        self.write_counted(|writer| {
+            writer.write_all(bytes)?;
+            writer.write_all(b"\n")?;
+            Ok(())
+        })
+    }
+
+    /// Write preformatted text such as a report.
+    pub fn write_text(&self, text: &str) -> io::Result<()> {
+        if self.format == OutputFormat::Parquet {
+            return Err(io::Error::other("A Parquet output holds only records, not reports"));
+        }
+        let mut writer = self.lock();
+        if let Some(digest) = &self.digest {
+            digest.lock().unwrap_or_else(std::sync::PoisonError::into_inner).update(text.as_bytes());
+        }
+        writer.write_all(text.as_bytes())
+    }
+
+    /// Flush, and end gzip output with its trailer, without which the file reads as truncated.
+    /// A Parquet output is written from its spooled records. Nothing may be written afterwards.
+    pub fn finish(&self) -> io::Result<()> {
+        finish_writer(&mut self.lock())?;
+        let parquet = self.parquet.lock().unwrap_or_else(std::sync::PoisonError::into_inner).take();
+        match parquet {
+            Some(parquet) => parquet.write().map_err(|e| io::Error::other(format!("{e:#}"))),
+            None => Ok(()),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Writer> {
+        // A panic while holding the lock leaves at worst a partial record behind,
+        // which is still worth flushing.
+        self.writer.lock().unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+fn finish_writer(writer: &mut Writer) -> io::Result<()> {
+    writer.flush()?;
+    if let Sink::Gzip(encoder) = writer.get_mut() {
+        encoder.try_finish()?;
+    }
+    Ok(())
+}
+
+/// The paths of the records already in an output file, for `--dedupe-paths`. A missing file
+/// has no paths. The file may be gzip-compressed.
+pub fn existing_paths(path: &Path) -> anyhow::Result<HashSet<PathBuf>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashSet::new()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to open {}", path.display())),
+    };
+    let records = parse_records(file, path)?;
+    Ok(records.iter().filter_map(|record| record.get("path")?.as_str()).map(PathBuf::from).collect())
+}
+
+/// The records in a file written by an earlier run, for `--compare-to`, which may be
+/// gzip-compressed.
+pub fn read_records(path: &Path) -> anyhow::Result<Vec<Value>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    parse_records(file, path)
+}
+
+/// Works for all of the JSON output formats, since each is a sequence of JSON objects, and for
+/// the arrays of records in a `manifest.json`. Gzip is recognized by its magic bytes rather
+/// than the file name, and may have several members, as `--append` writes.
+fn parse_records(file: File, path: &Path) -> anyhow::Result<Vec<Value>> {
+    let mut file = BufReader::new(file);
+    let gzip = file.fill_buf().with_context(|| format!("Failed to read {}", path.display()))?.starts_with(&[0x1f, 0x8b]);
+    let mut reader: Box<dyn BufRead> =
+        if gzip { Box::new(BufReader::new(flate2::read::MultiGzDecoder::new(file))) } else { Box::new(file) };
+    crate::io::skip_bom(&mut reader).with_context(|| format!("Failed to read {}", path.display()))?;
+    let mut records = Vec::new();
+    for record in serde_json::Deserializer::from_reader(reader).into_iter::<Value>() {
+        match record.with_context(|| format!("Failed to parse the records in {}", path.display()))? {
+            Value::Array(array) => records.extend(array),
+            record => records.push(record),
+        }
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_slash_paths() {
+        let mut record = json!({
+            "path": r"C:\models\a.safetensors.index.json",
+            "shards": [r"C:\models\a-1.safetensors"],
+            "shard_sizes": {r"C:\models\a-1.safetensors": 10},
+            "metadata": {"note": r"a\b"},
+        });
+        slash_paths(&mut record, '\\');
+        assert_eq!(
+            record,
+            json!({
+                "path": "C:/models/a.safetensors.index.json",
+                "shards": ["C:/models/a-1.safetensors"],
+                "shard_sizes": {"C:/models/a-1.safetensors": 10},
+                "metadata": {"note": r"a\b"},
+            })
+        );
+
+        // Where `/` is the separator a backslash is part of the name
+        let mut record = json!({"path": r"/models/odd\name.safetensors"});
+        slash_paths(&mut record, '/');
+        assert_eq!(record["path"], r"/models/odd\name.safetensors");
+    }
+
+    #[test]
+    fn test_quote_numbers() {
+        let mut record = json!({
+            "path": "a.safetensors",
+            "size": 10,
+            "parameters": 6_738_415_616_u64,
+            "shard_sizes": {"a-1.safetensors": 10},
+            "metadata": {"ss_network_dim": 32},
+        });
+        quote_numbers(&mut record);
+        assert_eq!(
+            record,
+            json!({
+                "path": "a.safetensors",
+                "size": "10",
+                "parameters": "6738415616",
+                "shard_sizes": {"a-1.safetensors": "10"},
+                "metadata": {"ss_network_dim": 32},
+            })
+        );
+    }
+
+    #[test]
+    fn test_natural_cmp() {
+        let mut keys = ["block_10", "block_2", "block_1", "block_02", "block", "block_1a", "a10b2", "a10b10", "a9"];
+        keys.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(keys, ["a9", "a10b2", "a10b10", "block", "block_1", "block_1a", "block_02", "block_2", "block_10"]);
+
+        let metadata = json!({"block_10": 1, "block_2": {"x_11": 0, "x_3": 0}, "block_1": 1});
+        assert_eq!(
+            serde_json::to_string(&NaturalOrder(&metadata)).unwrap(),
+            r#"{"block_1":1,"block_2":{"x_3":0,"x_11":0},"block_10":1}"#
+        );
+    }
+
+    #[test]
+    fn test_drop_empty() {
+        let mut record = json!({
+            "path": "a.safetensors",
+            "pointers": {"/ss_tag_frequency": null},
+            "metadata": {
+                "ss_epoch": 0,
+                "ss_comment": "",
+                "ss_tags": [],
+                "ss_dirs": [{"name": "a", "note": null}],
+                "x": {"y": {}},
+            },
+        });
+        drop_empty(&mut record);
+        assert_eq!(record, json!({"path": "a.safetensors", "metadata": {"ss_epoch": 0, "ss_dirs": [{"name": "a"}]}}));
+    }
+
+    #[test]
+    fn test_toml_record() -> anyhow::Result<()> {
+        let record = |path: &str| {
+            json!({
+                "path": path,
+                "parameters": 18_446_744_073_709_551_615_u64,
+                "metadata": {"ss_epoch": 2, "ss_lr": 0.5, "ss_tags": ["a"], "ss_dirs": {"img": {"n": 1}}, "note": null},
+            })
+        };
+        let document = toml_record(&record("a.safetensors"))? + &toml_record(&record("b.safetensors"))?;
+        let parsed: toml::Table = document.parse()?;
+        let files = parsed["files"].as_array().unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[1]["path"].as_str(), Some("b.safetensors"));
+        assert_eq!(files[0]["parameters"].as_str(), Some("18446744073709551615"));
+        let metadata = &files[0]["metadata"];
+        assert_eq!(metadata["ss_epoch"].as_integer(), Some(2));
+        assert_eq!(metadata["ss_lr"].as_float(), Some(0.5));
+        assert_eq!(metadata["ss_dirs"]["img"]["n"].as_integer(), Some(1));
+        assert_eq!(metadata["note"].as_str(), Some("null"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_yaml_record() -> anyhow::Result<()> {
+        let record = json!({
+            "path": "a.safetensors",
+            "metadata": {"ss_epoch": 2, "ss_caption": "a cat\non a mat", "ss_dirs": {"img": {"n": 1}}, "note": null},
+        });
+        let yaml = yaml_record(&record)?;
+        assert!(yaml.starts_with("---\n"));
+        assert!(yaml.contains("  ss_caption: |-\n    a cat\n    on a mat\n"), "{yaml}");
+        let parsed: Value = serde_yaml::from_str(&yaml)?;
+        assert_eq!(parsed, record);
+        Ok(())
+    }
+
+    #[test]
+    fn test_gzip_round_trip() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("records.jsonl.gz");
+        let compression = Compression::for_path(Some(&path), None);
+        assert_eq!(compression, Compression::Gzip);
+        for (append, name) in [(false, "a"), (true, "b")] {
+            let output = Output::new(Some(&path), append, compression, OutputFormat::Ndjson, None, 64)?;
+            output.write_record(&json!({"path": name}))?;
+            output.finish()?;
+        }
+
+        assert!(std::fs::read(&path)?.starts_with(&[0x1f, 0x8b]));
+        assert_eq!(read_records(&path)?, [json!({"path": "a"}), json!({"path": "b"})]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_digest() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("out.jsonl.gz");
+        let split = Some(SplitSize::Records(1));
+        let mut output = Output::new(Some(&path), false, Compression::Gzip, OutputFormat::Ndjson, split, 64)?;
+        output.set_digest(true);
+        output.write_record(&json!({"path": "a"}))?;
+        output.write_record(&json!({"path": "b"}))?;
+        output.write_text("2 files\n")?;
+        output.finish()?;
+        let written = "{\"path\":\"a\"}\n{\"path\":\"b\"}\n2 files\n";
+        assert_eq!(output.digest(), Some(format!("{:x}", Sha256::digest(written))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_flush_every() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("out.jsonl");
+        let mut output = Output::new(Some(&path), false, Compression::None, OutputFormat::Ndjson, None, 1 << 20)?;
+        output.set_flush_every(NonZeroU64::new(2));
+        for name in ["a", "b", "c"] {
+            output.write_record(&json!({"path": name}))?;
+        }
+        // The third record is still in the buffer
+        assert_eq!(read_records(&path)?, [json!({"path": "a"}), json!({"path": "b"})]);
+        output.finish()?;
+        assert_eq!(read_records(&path)?.len(), 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_hold() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("out.jsonl");
+        let output = Output::new(Some(&path), false, Compression::None, OutputFormat::Ndjson, None, 64)?;
+        let ((), first) = hold(async { output.write_record(&json!({"path": "a"})).unwrap() }).await;
+        let ((), second) = hold(async { output.write_record(&json!({"path": "b"})).unwrap() }).await;
+        output.write_record(&json!({"path": "not held"}))?;
+        output.write_held(second)?;
+        output.write_held(first)?;
+        output.finish()?;
+        assert_eq!(read_records(&path)?, [json!({"path": "not held"}), json!({"path": "b"}), json!({"path": "a"})]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_split() -> anyhow::Result<()> {
+        assert_eq!(parse_split_size("1000"), Ok(SplitSize::Records(1000)));
+        assert_eq!(parse_split_size("64MiB"), Ok(SplitSize::Bytes(64 << 20)));
+        assert_eq!(parse_split_size("2kb"), Ok(SplitSize::Bytes(2000)));
+        assert!(parse_split_size("0").is_err());
+        assert!(parse_split_size("5 parsecs").is_err());
+        assert_eq!(split_path(Path::new("/out/manifest.jsonl.gz"), 2), Path::new("/out/manifest.0002.jsonl.gz"));
+
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("manifest.jsonl.gz");
+        let split = Some(SplitSize::Records(2));
+        let output = Output::new(Some(&path), false, Compression::Gzip, OutputFormat::Ndjson, split, 64)?;
+        for name in ["a", "b", "c", "d"] {
+            output.write_record(&json!({"path": name}))?;
+        }
+        output.finish()?;
+        let files = output.split_files().unwrap();
+        assert_eq!(
+            files,
+            [temp_dir.path().join("manifest.0001.jsonl.gz"), temp_dir.path().join("manifest.0002.jsonl.gz")]
+        );
+        assert_eq!(read_records(&files[1])?, [json!({"path": "c"}), json!({"path": "d"})]);
+        assert!(!path.exists());
+        Ok(())
+    }
+}
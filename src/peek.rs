@@ -0,0 +1,219 @@
+use crate::Scan;
+use crate::header;
+use crate::summary::Outcome;
+use anyhow::Context;
+use serde_json::{Value, json};
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+/// How many elements `--peek-tensor` reads when it isn't given a count.
+const DEFAULT_ELEMENTS: usize = 8;
+
+/// The most elements `--peek-tensor` reads, so a peek stays a peek.
+pub const MAX_ELEMENTS: usize = 1024;
+
+/// A tensor to peek at and how many of its first elements to read (`--peek-tensor NAME[:K]`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Peek {
+    pub name: String,
+    pub elements: usize,
+}
+
+/// Parse `--peek-tensor`: a tensor name, optionally followed by `:` and the number of elements.
+/// A name that itself contains `:` is taken whole unless what follows the last one is a number.
+pub fn parse_peek(arg: &str) -> Result<Peek, String> {
+    let (name, elements) = match arg.rsplit_once(':') {
+        Some((name, count)) if !count.is_empty() && count.bytes().all(|b| b.is_ascii_digit()) => {
+            (name, count.parse().map_err(|_| format!("`{count}` is too large"))?)
+        }
+        _ => (arg, DEFAULT_ELEMENTS),
+    };
+    if name.is_empty() {
+        return Err("expected a tensor name".to_string());
+    }
+    if elements == 0 || elements > MAX_ELEMENTS {
+        return Err(format!("the number of elements must be from 1 to {MAX_ELEMENTS}"));
+    }
+    Ok(Peek { name: name.to_string(), elements })
+}
+
+/// Print the first elements of a tensor of the one file given, decoded by its dtype, for
+/// `--peek-tensor`. This reads tensor data, which nothing else but `--hash` does.
+pub async fn run(scan: &Scan, files: &[PathBuf], peek: &Peek) -> anyhow::Result<()> {
+    let [path] = files else {
+        anyhow::bail!("--peek-tensor takes a single file, but {} files matched", files.len());
+    };
+    let record = peek_file(path, peek, &scan.read_options)
+        .await
+        .with_context(|| format!("Failed to peek into {}", path.display()))?;
+    scan.output.write_record(&record)?;
+    scan.summary.record(Outcome::Processed);
+    Ok(())
+}
+
+/// The record of a peek: the tensor's dtype and shape, how many elements it has and the values
+/// of the first of them.
+async fn peek_file(path: &Path, peek: &Peek, options: &header::ReadOptions) -> anyhow::Result<Value> {
+    let header = header::read_header(path, options).await?;
+    let tensor = header
+        .json
+        .get(&peek.name)
+        .filter(|_| peek.name != "__metadata__")
+        .with_context(|| format!("Has no tensor {}", peek.name))?;
+    let dtype = tensor.get("dtype").and_then(Value::as_str).context("The tensor has no dtype")?;
+    let shape = tensor.get("shape").cloned().unwrap_or(Value::Null);
+    let (begin, end) = match tensor.get("data_offsets").and_then(Value::as_array).map(Vec::as_slice) {
+        Some([begin, end]) => begin.as_u64().zip(end.as_u64()).filter(|(begin, end)| begin <= end),
+        _ => None,
+    }
+    .context("The tensor has no valid data_offsets")?;
+    let size = element_size(dtype).with_context(|| format!("Can't decode the dtype {dtype}"))?;
+
+    let data_start = 8 + header.len;
+    if data_start + end > header.stat.len() {
+        anyhow::bail!("The tensor's data ends past the end of the file");
+    }
+    let wanted = ((end - begin) / size as u64).min(peek.elements as u64);
+    let mut bytes = vec![0; usize::try_from(wanted)? * size];
+    let mut file = tokio::fs::File::open(path).await?;
+    file.seek(SeekFrom::Start(data_start + begin)).await?;
+    file.read_exact(&mut bytes).await?;
+
+    Ok(json!({
+        "path": path,
+        "tensor": peek.name,
+        "dtype": dtype,
+        "shape": shape,
+        "elements": (end - begin) / size as u64,
+        "values": bytes.chunks_exact(size).map(|element| decode(dtype, element)).collect::<Vec<_>>(),
+    }))
+}
+
+/// The bytes of an element of `dtype`, for the dtypes [`decode`] can read.
+fn element_size(dtype: &str) -> Option<usize> {
+    Some(match dtype {
+        "BOOL" | "U8" | "I8" | "F8_E5M2" | "F8_E4M3" => 1,
+        "U16" | "I16" | "F16" | "BF16" => 2,
+        "U32" | "I32" | "F32" => 4,
+        "U64" | "I64" | "F64" => 8,
+        _ => return None,
+    })
+}
+
+/// One little-endian element of `dtype` as JSON. Floats that JSON has no number for are the
+/// strings `NaN`, `inf` and `-inf`.
+fn decode(dtype: &str, bytes: &[u8]) -> Value {
+    let array = |bytes: &[u8]| -> [u8; 8] {
+        let mut array = [0; 8];
+        array[..bytes.len()].copy_from_slice(bytes);
+        array
+    };
+    let unsigned = u64::from_le_bytes(array(bytes));
+    #[allow(clippy::cast_possible_truncation)] // each is as wide as its dtype
+    match dtype {
+        "BOOL" => Value::Bool(bytes[0] != 0),
+        "U8" | "U16" | "U32" | "U64" => unsigned.into(),
+        "I8" => (unsigned as u8).cast_signed().into(),
+        "I16" => (unsigned as u16).cast_signed().into(),
+        "I32" => (unsigned as u32).cast_signed().into(),
+        "I64" => unsigned.cast_signed().into(),
+        "F64" => float(f64::from_bits(unsigned)),
+        "F32" => float32(f32::from_bits(unsigned as u32)),
+        "BF16" => float32(f32::from_bits((unsigned as u32) << 16)),
+        "F16" => float32(minifloat(unsigned as u32, 5, 10, true)),
+        "F8_E5M2" => float32(minifloat(unsigned as u32, 5, 2, true)),
+        "F8_E4M3" => float32(minifloat(unsigned as u32, 4, 3, false)),
+        _ => Value::Null,
+    }
+}
+
+/// A float of `exponent` and `mantissa` bits with the usual bias. With `ieee` the highest
+/// exponent holds the infinities and NaNs, as in `F16` and `F8_E5M2`; otherwise, as in `F8_E4M3`,
+/// there are no infinities and only the bits all set are NaN.
+fn minifloat(bits: u32, exponent: u32, mantissa: u32, ieee: bool) -> f32 {
+    let sign = if (bits >> (exponent + mantissa)) & 1 == 1 { -1.0 } else { 1.0 };
+    let exp = (bits >> mantissa) & ((1 << exponent) - 1);
+    let man = bits & ((1 << mantissa) - 1);
+    let max_exp = (1 << exponent) - 1;
+    if exp == max_exp && (ieee || man == (1 << mantissa) - 1) {
+        return if ieee && man == 0 { sign * f32::INFINITY } else { f32::NAN };
+    }
+    let exponent_bias = (1 << (exponent - 1)) - 1;
+    #[allow(clippy::cast_precision_loss)] // at most 10 bits
+    let fraction = man as f32 / (1 << mantissa) as f32;
+    if exp == 0 {
+        sign * fraction * 2f32.powi(1 - exponent_bias)
+    } else {
+        sign * (1.0 + fraction) * 2f32.powi(exp.cast_signed() - exponent_bias)
+    }
+}
+
+/// A float in the shortest form that reads back as the same `f32`, such as `0.1` rather than
+/// the `0.10000000149011612` of its exact value.
+fn float32(value: f32) -> Value {
+    if value.is_finite() { value.to_string().parse().map_or(Value::Null, float) } else { float(f64::from(value)) }
+}
+
+fn float(value: f64) -> Value {
+    match serde_json::Number::from_f64(value) {
+        Some(number) => Value::Number(number),
+        None if value.is_nan() => "NaN".into(),
+        None if value > 0.0 => "inf".into(),
+        None => "-inf".into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_peek() {
+        assert_eq!(parse_peek("model.embed.weight"), Ok(Peek { name: "model.embed.weight".to_string(), elements: 8 }));
+        assert_eq!(parse_peek("w:3"), Ok(Peek { name: "w".to_string(), elements: 3 }));
+        assert_eq!(parse_peek("a:b"), Ok(Peek { name: "a:b".to_string(), elements: 8 }));
+        assert!(parse_peek("w:0").is_err());
+        assert!(parse_peek("w:5000").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_peek_file() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let header = json!({
+            "__metadata__": {},
+            "f32": {"dtype": "F32", "shape": [4], "data_offsets": [0, 16]},
+            "f16": {"dtype": "F16", "shape": [2], "data_offsets": [16, 20]},
+            "bf16": {"dtype": "BF16", "shape": [1], "data_offsets": [20, 22]},
+            "e4m3": {"dtype": "F8_E4M3", "shape": [2], "data_offsets": [22, 24]},
+            "i16": {"dtype": "I16", "shape": [1], "data_offsets": [24, 26]},
+        });
+        let header = serde_json::to_vec(&header)?;
+        let mut bytes = (header.len() as u64).to_le_bytes().to_vec();
+        bytes.extend(&header);
+        for value in [0.1f32, -2.0, f32::NAN, f32::INFINITY] {
+            bytes.extend(value.to_le_bytes());
+        }
+        bytes.extend([0x00, 0x3c, 0x00, 0xfc]); // 1.0, -inf
+        bytes.extend([0x40, 0xc0]); // -3.0
+        bytes.extend([0x38, 0x7f]); // 1.0, NaN
+        bytes.extend((-7i16).to_le_bytes());
+        let path = temp_dir.path().join("a.safetensors");
+        std::fs::write(&path, bytes)?;
+
+        let peek = |arg| {
+            let path = path.clone();
+            async move { peek_file(&path, &parse_peek(arg).unwrap(), &header::ReadOptions::default()).await }
+        };
+        let record = peek("f32").await?;
+        assert_eq!(record["values"], json!([0.1, -2.0, "NaN", "inf"]));
+        assert_eq!(record["elements"], 4);
+        assert_eq!(peek("f32:2").await?["values"], json!([0.1, -2.0]));
+        assert_eq!(peek("f16").await?["values"], json!([1.0, "-inf"]));
+        assert_eq!(peek("bf16").await?["values"], json!([-3.0]));
+        assert_eq!(peek("e4m3").await?["values"], json!([1.0, "NaN"]));
+        assert_eq!(peek("i16").await?["values"], json!([-7]));
+        assert_eq!(peek("missing").await.unwrap_err().to_string(), "Has no tensor missing");
+        Ok(())
+    }
+}
@@ -0,0 +1,109 @@
+use crate::table;
+use serde_json::Value;
+use std::sync::Mutex;
+
+/// The statistics of each key, in the order of the columns after its name and sample size.
+const COLUMNS: &[&str] = &["min", "p50", "p90", "p99", "max", "mean"];
+
+/// Collects the numeric values of metadata keys across the scanned files, for
+/// `--percentiles`, to profile a collection's training parameters once the scan is done.
+#[derive(Debug)]
+pub struct Percentiles {
+    keys: Vec<String>,
+    /// The files seen, and for each key the numbers of those that have one.
+    values: Mutex<(usize, Vec<Vec<f64>>)>,
+}
+
+impl Percentiles {
+    pub fn new(keys: Vec<String>) -> Self {
+        let values = Mutex::new((0, vec![Vec::new(); keys.len()]));
+        Self { keys, values }
+    }
+
+    /// Add the values of one file's metadata: numbers, and strings that parse as a finite
+    /// number such as `1e-4`. Files without the key or with another value are left out.
+    pub fn add(&self, metadata: &Value) {
+        let mut values = self.values.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        let (files, values) = &mut *values;
+        *files += 1;
+        for (key, values) in self.keys.iter().zip(values.iter_mut()) {
+            let number = match metadata.get(key) {
+                Some(Value::Number(number)) => number.as_f64(),
+                Some(Value::String(text)) => text.trim().parse::<f64>().ok(),
+                _ => None,
+            };
+            values.extend(number.filter(|number| number.is_finite()));
+        }
+    }
+
+    /// A table with a row per key: how many of the files it was taken from, the minimum, the
+    /// 50th, 90th and 99th percentile by nearest rank, the maximum and the mean. A key no file
+    /// has a number for shows `-` for each.
+    pub fn report(&self) -> String {
+        let mut values = self.values.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        let (files, values) = &mut *values;
+        let rows: Vec<Vec<String>> = self
+            .keys
+            .iter()
+            .zip(values.iter_mut())
+            .map(|(key, values)| {
+                values.sort_by(f64::total_cmp);
+                let mut row = vec![key.clone(), format!("{} of {files}", values.len())];
+                row.extend(stats(values).map_or_else(
+                    || vec!["-".to_string(); COLUMNS.len()],
+                    |stats| stats.iter().map(|stat| round(*stat).to_string()).collect(),
+                ));
+                row
+            })
+            .collect();
+        let headers: Vec<String> = ["key", "files"].iter().chain(COLUMNS).map(ToString::to_string).collect();
+        table::render(&headers, &rows)
+    }
+}
+
+/// The [`COLUMNS`] of `sorted`, or `None` if it is empty.
+fn stats(sorted: &[f64]) -> Option<[f64; 6]> {
+    let (min, max) = (*sorted.first()?, *sorted.last()?);
+    #[allow(clippy::cast_precision_loss)] // as many values as files
+    let mean = sorted.iter().sum::<f64>() / sorted.len() as f64;
+    let percentile = |p: usize| sorted[(sorted.len() * p).div_ceil(100).max(1) - 1];
+    Some([min, percentile(50), percentile(90), percentile(99), max, mean])
+}
+
+/// `number` to 10 significant digits, so a mean shows as `0.3` rather than
+/// `0.30000000000000004` while a learning rate such as `1e-7` keeps its digits.
+fn round(number: f64) -> f64 {
+    if number == 0.0 {
+        return 0.0;
+    }
+    #[allow(clippy::cast_possible_truncation)] // the exponent of a finite f64
+    let scale = 10f64.powi(9 - number.abs().log10().floor() as i32);
+    (number * scale).round() / scale
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_percentiles_report() {
+        let percentiles = Percentiles::new(vec!["ss_steps".to_string(), "ss_learning_rate".to_string()]);
+        for steps in 1..=10 {
+            percentiles.add(&json!({"ss_steps": steps.to_string(), "ss_learning_rate": 1e-4}));
+        }
+        percentiles.add(&json!({"ss_steps": "many"}));
+        percentiles.add(&json!({}));
+        assert_eq!(
+            percentiles.report(),
+            "key               files     min     p50     p90     p99     max     mean\n\
+             ss_steps          10 of 12  1       5       9       10      10      5.5\n\
+             ss_learning_rate  10 of 12  0.0001  0.0001  0.0001  0.0001  0.0001  0.0001\n"
+        );
+        assert_eq!(
+            Percentiles::new(vec!["x".to_string()]).report(),
+            "key  files   min  p50  p90  p99  max  mean\n\
+             x    0 of 0  -    -    -    -    -    -\n"
+        );
+    }
+}
@@ -0,0 +1,57 @@
+use crate::record::Record;
+use crate::{Extracted, Scan};
+use futures::{Stream, StreamExt};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::{Semaphore, mpsc};
+
+/// Process `files`, as they come, in two stages for `--parallel-hash`: readers extract the headers and pass
+/// the records through a channel of `--pipeline-depth` slots to a separate set of hashing
+/// workers, one per CPU, so that reading one file overlaps with hashing another.
+///
+/// At most `--max-open-files` readers run at once, and a full channel pauses them, so the
+/// number of records held in memory is bounded by the sum of the two.
+pub async fn run(scan: &Arc<Scan>, files: impl Stream<Item = PathBuf> + Unpin) -> anyhow::Result<()> {
+    let (sender, mut receiver) = mpsc::channel::<Record>(scan.args.pipeline_depth.get());
+
+    let read = async move {
+        let mut extracted = files
+            .map(|path| async move {
+                let result = crate::with_timeout(scan, crate::extract(&path, scan)).await;
+                (path, result)
+            })
+            .buffer_unordered(scan.open_files.available_permits().max(1));
+        while let Some((path, result)) = extracted.next().await {
+            match result {
+                Ok(Extracted::Record(record)) => {
+                    // The receiver only goes away once every record was hashed
+                    let _ = sender.send(*record).await;
+                }
+                Ok(Extracted::Done(outcome)) => crate::report(&path, scan, Ok(outcome)),
+                Err(e) => crate::report(&path, scan, Err(e)),
+            }
+        }
+    };
+
+    let hash = async {
+        let workers = Arc::new(Semaphore::new(std::thread::available_parallelism().map_or(4, std::num::NonZeroUsize::get)));
+        let mut handles = Vec::new();
+        while let Some(record) = receiver.recv().await {
+            let worker = Arc::clone(&workers).acquire_owned().await?;
+            let scan = Arc::clone(scan);
+            handles.push(tokio::spawn(async move {
+                let path = record.path.clone();
+                let result = crate::with_timeout(&scan, crate::hash_and_emit(record, &scan)).await;
+                crate::report(&path, &scan, result);
+                drop(worker);
+            }));
+        }
+        for handle in handles {
+            handle.await?;
+        }
+        anyhow::Ok(())
+    };
+
+    let ((), hashed) = tokio::join!(read, hash);
+    hashed
+}
@@ -0,0 +1,156 @@
+use crate::header::{self, RawHeader, ReadOptions};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tokio::sync::OwnedSemaphorePermit;
+use tokio::task::JoinHandle;
+
+/// How many headers `--prefetch` reads ahead when it isn't told.
+pub const DEFAULT_AHEAD: usize = 2;
+
+/// Reads the headers of the next files while the current one is processed (`--prefetch`),
+/// so the latency of the storage is paid while the scan has other work to do. At most
+/// `ahead` headers are read or held at a time, each read holding the slots a read of the
+/// scan would, and the files they are read from are closed as soon as that is done.
+#[derive(Debug)]
+pub struct Prefetch {
+    ahead: usize,
+    options: ReadOptions,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// The files to read ahead, in the order they were queued.
+    files: Vec<PathBuf>,
+    /// The position of each file in `files`.
+    positions: HashMap<PathBuf, usize>,
+    /// The positions of the files taken or forgotten, which aren't read ahead anymore.
+    done: HashSet<usize>,
+    /// The position of the next file to read ahead.
+    next: usize,
+    /// The reads in flight or finished but not yet taken, by position.
+    pending: HashMap<usize, JoinHandle<anyhow::Result<RawHeader>>>,
+}
+
+/// A file queued to be read ahead, which is forgotten when dropped, so that a file the scan
+/// skips or gives up on isn't read or held for nothing.
+pub struct Queued<'a> {
+    prefetch: &'a Prefetch,
+    path: PathBuf,
+}
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.prefetch.forget(&self.path);
+    }
+}
+
+impl Prefetch {
+    pub fn new(ahead: usize, options: ReadOptions) -> Self {
+        Self { ahead, options, state: Mutex::default() }
+    }
+
+    /// Read `path` ahead once the scan takes the files queued before it, until the returned
+    /// guard is dropped. Queue a file only once it is known that its header will be read.
+    pub fn queue(&self, path: &Path) -> Queued<'_> {
+        let mut state = self.state.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        let position = state.files.len();
+        state.positions.insert(path.to_path_buf(), position);
+        state.files.push(path.to_path_buf());
+        Queued { prefetch: self, path: path.to_path_buf() }
+    }
+
+    /// The header of `path` as read ahead, which may be the error reading it failed with,
+    /// and start reading the files queued after it, each once `slots` gives it the slots to
+    /// be read in. `None` if it wasn't read ahead, for the caller to read it. The files the
+    /// scan takes out of order are left to be taken in turn.
+    pub async fn take(
+        &self,
+        path: &Path,
+        slots: impl Fn(&Path) -> Option<Vec<OwnedSemaphorePermit>>,
+    ) -> Option<anyhow::Result<RawHeader>> {
+        let read = {
+            let mut state = self.state.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+            let position = *state.positions.get(path)?;
+            state.done.insert(position);
+            let read = state.pending.remove(&position);
+            while state.pending.len() < self.ahead && state.next < state.files.len() {
+                let position = state.next;
+                if state.done.contains(&position) {
+                    state.next += 1;
+                    continue;
+                }
+                // Tried again on the next take, as the scan frees its slots
+                let Some(slots) = slots(&state.files[position]) else { break };
+                let (next, options) = (state.files[position].clone(), self.options.clone());
+                let read = tokio::spawn(async move {
+                    let _slots = slots;
+                    header::read_raw_header(&next, &options).await
+                });
+                state.pending.insert(position, read);
+                state.next += 1;
+            }
+            read
+        };
+        Some(read?.await.map_err(anyhow::Error::from).and_then(|read| read))
+    }
+
+    /// Stop reading `path` ahead, abandoning its read if one was started.
+    fn forget(&self, path: &Path) {
+        let mut state = self.state.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        let Some(&position) = state.positions.get(path) else { return };
+        state.done.insert(position);
+        if let Some(abandoned) = state.pending.remove(&position) {
+            abandoned.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use tokio::sync::Semaphore;
+
+    #[tokio::test]
+    async fn test_take() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let header = br#"{"__metadata__":{"a":"1"}}"#;
+        let mut bytes = (header.len() as u64).to_le_bytes().to_vec();
+        bytes.extend(header);
+        let files: Vec<PathBuf> =
+            ["a", "b", "c", "d", "e"].iter().map(|name| temp_dir.path().join(format!("{name}.safetensors"))).collect();
+        for path in &files {
+            std::fs::write(path, &bytes)?;
+        }
+        std::fs::write(&files[2], b"short")?;
+        let free = |_: &Path| Some(Vec::new());
+
+        let prefetch = Prefetch::new(2, ReadOptions::default());
+        let queued: Vec<Queued> = files.iter().map(|path| prefetch.queue(path)).collect();
+        // Nothing is read ahead of the first file, which starts the reads of the next two
+        assert!(prefetch.take(&files[0], free).await.is_none());
+        // Taking a later file first leaves the read of the one before it
+        assert!(prefetch.take(&files[3], free).await.is_none());
+        assert_eq!(prefetch.take(&files[1], free).await.unwrap()?.bytes, header);
+        // The error is that of the file it belongs to
+        let err = prefetch.take(&files[2], free).await.unwrap().unwrap_err();
+        assert!(format!("{err:#}").contains("too small"), "{err:#}");
+        // The file taken already isn't read again, but the one after it is
+        assert!(prefetch.state.lock().unwrap().pending.contains_key(&4));
+        drop(queued);
+        assert!(prefetch.state.lock().unwrap().pending.is_empty());
+        assert!(prefetch.take(&temp_dir.path().join("other.safetensors"), free).await.is_none());
+
+        // Nothing is read ahead without a free slot
+        let prefetch = Prefetch::new(2, ReadOptions::default());
+        let _queued: Vec<Queued> = files.iter().map(|path| prefetch.queue(path)).collect();
+        let open_files = Arc::new(Semaphore::new(1));
+        let _held = Arc::clone(&open_files).acquire_owned().await?;
+        let slots = |_: &Path| Some(vec![Arc::clone(&open_files).try_acquire_owned().ok()?]);
+        assert!(prefetch.take(&files[0], slots).await.is_none());
+        assert!(prefetch.take(&files[1], slots).await.is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,73 @@
+use crate::Scan;
+use crate::header;
+use crate::summary::Outcome;
+use futures::StreamExt;
+use std::path::PathBuf;
+
+/// Check that each file opens and starts like a safetensors file, printing `OK` or `NOT-OK`
+/// with the path (`--probe-only`). Fails if any file doesn't pass.
+///
+/// Only the length prefix and the first byte of the header are read, so this is much faster
+/// than extracting the metadata but can't find a header that is broken further in.
+pub async fn run(scan: &Scan, files: Vec<PathBuf>) -> anyhow::Result<()> {
+    let total = files.len();
+    let mut results = futures::stream::iter(files)
+        .map(|path| async move {
+            let result = async {
+                let _permit = scan.open_files.acquire().await?;
+                header::probe(&path, &scan.read_options).await
+            }
+            .await;
+            (path, result)
+        })
+        .buffer_unordered(scan.open_files.available_permits().max(1));
+
+    let mut failed = 0;
+    while let Some((path, result)) = results.next().await {
+        match result {
+            Ok(()) => {
+                scan.output.write_text(&format!("OK      {}\n", path.display()))?;
+                scan.summary.record(Outcome::Processed);
+            }
+            Err(e) => {
+                scan.output.write_text(&format!("NOT-OK  {}: {:#}\n", path.display(), e))?;
+                scan.summary.record(Outcome::Failed);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        anyhow::bail!("{failed} of {total} files failed the probe");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::{scan, write_safetensors};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_probe() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let dir = temp_dir.path();
+        let good = write_safetensors(dir, "good.safetensors", &json!({"__metadata__": {}}));
+        let array = write_safetensors(dir, "array.safetensors", &json!([1]));
+        let short = dir.join("short.safetensors");
+        std::fs::write(&short, 1000u64.to_le_bytes())?;
+        let out = dir.join("probe.txt");
+
+        let scan = scan(&["--probe-only", "-o", out.to_str().unwrap(), dir.to_str().unwrap()]);
+        super::run(&scan, vec![good.clone()]).await?;
+        let err = super::run(&scan, vec![array.clone(), short.clone()]).await.unwrap_err();
+        assert_eq!(err.to_string(), "2 of 2 files failed the probe");
+        scan.output.finish()?;
+
+        let mut lines: Vec<String> = std::fs::read_to_string(out)?.lines().map(String::from).collect();
+        lines.sort();
+        assert_eq!(lines[0], format!("NOT-OK  {}: Header doesn't start with {{", array.display()));
+        assert!(lines[1].starts_with(&format!("NOT-OK  {}: Header length 1000 exceeds", short.display())), "{lines:?}");
+        assert_eq!(lines[2], format!("OK      {}", good.display()));
+        Ok(())
+    }
+}
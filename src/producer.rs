@@ -0,0 +1,89 @@
+use schemars::JsonSchema;
+use serde::Serialize;
+use serde_json::Value;
+
+/// A sign in the metadata that a tool wrote the file.
+struct Rule {
+    tool: &'static str,
+    /// Whether the metadata carries the sign.
+    matches: fn(&serde_json::Map<String, Value>) -> bool,
+    /// The tools of the later rules whose signs this tool writes too, which then aren't a conflict.
+    also_writes: &'static [&'static str],
+}
+
+/// The rules of [`identify`], strongest first:
+///
+/// | tool         | sign                                                        | also writes |
+/// |--------------|-------------------------------------------------------------|-------------|
+/// | `sd-scripts` | keys starting with `ss_`, as kohya-ss sd-scripts writes     | `modelspec` |
+/// | `modelspec`  | keys starting with `modelspec.`, as ComfyUI and A1111 write |             |
+/// | `diffusers`  | `"format": "pt"`, as the Hugging Face libraries write       |             |
+const RULES: &[Rule] = &[
+    Rule { tool: "sd-scripts", matches: |metadata| has_prefix(metadata, "ss_"), also_writes: &["modelspec"] },
+    Rule { tool: "modelspec", matches: |metadata| has_prefix(metadata, "modelspec."), also_writes: &[] },
+    Rule {
+        tool: "diffusers",
+        matches: |metadata| metadata.get("format").and_then(Value::as_str) == Some("pt"),
+        also_writes: &[],
+    },
+];
+
+/// How sure [`identify`] is of its guess.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Confidence {
+    /// The signs agree on one tool.
+    High,
+    /// There were signs of other tools too, or none at all.
+    Low,
+}
+
+/// The tool that most likely wrote a file, for `--identify-tool`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct Producer {
+    /// The tool of the first rule that matched, or `unknown`.
+    pub tool: &'static str,
+    pub confidence: Confidence,
+    /// The tools of the other rules that matched, which the guess contradicts.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub conflicting: Vec<&'static str>,
+}
+
+/// Guess which tool wrote a file from the keys of its metadata, going by the first of
+/// [`RULES`] that matches.
+pub fn identify(metadata: &Value) -> Producer {
+    let empty = serde_json::Map::new();
+    let metadata = metadata.as_object().unwrap_or(&empty);
+    let mut matched = RULES.iter().filter(|rule| (rule.matches)(metadata));
+    let Some(first) = matched.next() else {
+        return Producer { tool: "unknown", confidence: Confidence::Low, conflicting: Vec::new() };
+    };
+    let conflicting: Vec<_> = matched.map(|rule| rule.tool).filter(|tool| !first.also_writes.contains(tool)).collect();
+    let confidence = if conflicting.is_empty() { Confidence::High } else { Confidence::Low };
+    Producer { tool: first.tool, confidence, conflicting }
+}
+
+fn has_prefix(metadata: &serde_json::Map<String, Value>, prefix: &str) -> bool {
+    metadata.keys().any(|key| key.starts_with(prefix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_identify() {
+        let tool = |metadata| {
+            let producer = identify(&metadata);
+            (producer.tool, producer.confidence, producer.conflicting)
+        };
+        let kohya = json!({"ss_network_dim": "16", "modelspec.title": "x"});
+        assert_eq!(tool(kohya), ("sd-scripts", Confidence::High, vec![]));
+        assert_eq!(tool(json!({"modelspec.architecture": "sdxl"})), ("modelspec", Confidence::High, vec![]));
+        assert_eq!(tool(json!({"format": "pt"})), ("diffusers", Confidence::High, vec![]));
+        let mixed = json!({"ss_epoch": "2", "format": "pt"});
+        assert_eq!(tool(mixed), ("sd-scripts", Confidence::Low, vec!["diffusers"]));
+        assert_eq!(tool(json!({"format": "np"})), ("unknown", Confidence::Low, vec![]));
+    }
+}
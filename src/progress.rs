@@ -0,0 +1,162 @@
+use crate::{Scan, humanize};
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressState, ProgressStyle};
+use std::fmt;
+use std::io::IsTerminal;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+/// The `--progress-template` used when the one given is invalid.
+pub const DEFAULT_TEMPLATE: &str = "[{bar}] {pos}/{len} files, {per_sec}, ETA {eta}";
+
+/// How often the progress bar is updated, which `indicatif` redraws.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Prints a line such as `Progress: 4200/50000 files, 310/s` to stderr at a fixed interval
+/// (`--progress-interval`), so long scans in CI logs don't look hung, or on a terminal
+/// redraws a bar of `--progress-template` in place. Stops when dropped.
+pub struct Progress {
+    task: JoinHandle<()>,
+    /// The bar drawn, which is cleared when dropped.
+    bar: Option<ProgressBar>,
+}
+
+impl Progress {
+    /// Start reporting on the `total` files of `scan`, unless stderr is a terminal, where
+    /// repeated lines would only clutter the screen.
+    pub fn start(scan: &Arc<Scan>, total: usize, interval: Duration) -> Option<Self> {
+        if std::io::stderr().is_terminal() {
+            return None;
+        }
+        let scan = Arc::clone(scan);
+        // Files skipped by the directory walk are already counted, but aren't part of `total`
+        let before = scan.summary.total();
+        let started = Instant::now();
+        let task = tokio::spawn(async move {
+            let mut ticks = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+            loop {
+                ticks.tick().await;
+                eprintln!("{}", line(scan.summary.total() - before, total, started.elapsed()));
+            }
+        });
+        Some(Self { task, bar: None })
+    }
+
+    /// Draw `template` for the `total` files of `scan` on the last line of stderr, but only if
+    /// it is a terminal.
+    pub fn start_bar(scan: &Arc<Scan>, total: usize, template: &Template) -> Option<Self> {
+        if !std::io::stderr().is_terminal() {
+            return None;
+        }
+        let scan = Arc::clone(scan);
+        let style = template.style(&scan);
+        let bar = ProgressBar::with_draw_target(Some(total as u64), ProgressDrawTarget::stderr()).with_style(style);
+        let before = scan.summary.total();
+        let drawn = bar.clone();
+        let task = tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(REDRAW_INTERVAL);
+            loop {
+                ticks.tick().await;
+                let current = scan.current_file.lock().unwrap_or_else(std::sync::PoisonError::into_inner).clone();
+                drawn.set_message(current.map(|path| path.display().to_string()).unwrap_or_default());
+                drawn.set_position((scan.summary.total() - before) as u64);
+            }
+        });
+        Some(Self { task, bar: Some(bar) })
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        self.task.abort();
+        if let Some(bar) = &self.bar {
+            bar.finish_and_clear();
+        }
+    }
+}
+
+/// A `--progress-template`: an `indicatif` template such as `{bar:30.cyan/blue} {pos}/{len}
+/// {msg}`, drawn with the files done as the position, the file started last as `{msg}` and
+/// the size of the files started as `{bytes}`.
+#[derive(Clone)]
+pub struct Template(ProgressStyle);
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Self, String> {
+        ProgressStyle::with_template(template).map(Self).map_err(|e| e.to_string())
+    }
+
+    /// Parse `--progress-template`, warning and falling back to [`DEFAULT_TEMPLATE`] if it's
+    /// invalid, since a scan shouldn't fail over how its progress looks.
+    pub fn parse_or_default(template: &str) -> Self {
+        Self::parse(template).unwrap_or_else(|e| {
+            eprintln!("Warning: Invalid --progress-template: {e}; using the default");
+            Self::parse(DEFAULT_TEMPLATE).expect("the default template is valid")
+        })
+    }
+
+    /// The style the bar is drawn in, with `{bytes}` read from `scan`.
+    fn style(&self, scan: &Arc<Scan>) -> ProgressStyle {
+        let scan = Arc::clone(scan);
+        let bytes = move |_: &ProgressState, out: &mut dyn fmt::Write| {
+            let _ = out.write_str(&humanize::bytes(scan.bytes_started.load(Ordering::Relaxed)));
+        };
+        self.0.clone().with_key("bytes", bytes)
+    }
+}
+
+fn line(done: usize, total: usize, elapsed: Duration) -> String {
+    #[allow(clippy::cast_precision_loss)] // a rate doesn't need every digit
+    let rate = done as f64 / elapsed.as_secs_f64().max(f64::EPSILON);
+    // Slow scans of huge files need the decimal to show that anything is happening
+    let precision = usize::from(rate < 10.0);
+    format!("Progress: {done}/{total} files, {rate:.precision$}/s")
+}
+
+/// Parse a `--progress-interval` argument: a number of seconds or a duration such as `1m`.
+pub fn parse_interval(arg: &str) -> Result<Duration, String> {
+    let interval = match arg.parse::<u64>() {
+        Ok(seconds) => Duration::from_secs(seconds),
+        Err(_) => humantime::parse_duration(arg).map_err(|e| e.to_string())?,
+    };
+    if interval.is_zero() {
+        return Err("the interval must be longer than zero".to_string());
+    }
+    Ok(interval)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress_line() {
+        assert_eq!(line(4200, 50000, Duration::from_secs(14)), "Progress: 4200/50000 files, 300/s");
+        assert_eq!(line(1, 2, Duration::from_secs(2)), "Progress: 1/2 files, 0.5/s");
+        assert_eq!(parse_interval("30"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_interval("1m"), Ok(Duration::from_mins(1)));
+        assert!(parse_interval("0").is_err());
+        assert!(parse_interval("soon").is_err());
+    }
+
+    #[test]
+    fn test_template() {
+        let scan = Arc::new(crate::test_util::scan(&["x"]));
+        scan.bytes_started.store(2048, Ordering::Relaxed);
+        let draw = |template| {
+            let term = indicatif::InMemoryTerm::new(1, 80);
+            let target = ProgressDrawTarget::term_like(Box::new(term.clone()));
+            let style = Template::parse(template).unwrap().style(&scan);
+            let bar = ProgressBar::with_draw_target(Some(4), target).with_style(style);
+            bar.set_message("a.safetensors");
+            bar.set_position(1);
+            bar.tick();
+            term.contents()
+        };
+        assert_eq!(draw("[{bar:8}] {pos}/{len} {percent}%"), "[██░░░░░░] 1/4 25%");
+        assert_eq!(draw("{pos:>3.green} {bytes} {msg}"), "  1 2.00 KiB a.safetensors");
+        assert!(Template::parse("[{bar:30.cyan/blue}] {per_sec} {eta} {elapsed} {wide_msg}").is_ok());
+        assert!(Template::parse("{pos:x}").is_err());
+    }
+}
@@ -0,0 +1,55 @@
+use std::fs::File;
+use std::io;
+
+/// The access pattern announced to the kernel for each file before its header is read
+/// (`--readahead`), which matters most on HDD arrays.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Readahead {
+    /// Give no advice and leave readahead to the kernel
+    #[default]
+    Default,
+    /// Advise sequential access, which makes Linux read ahead twice as far
+    Sequential,
+    /// Advise random access, which turns readahead off so reading a header doesn't also pull
+    /// in the start of the tensor data
+    Random,
+}
+
+/// Give the kernel `advice` about the whole of `file`. Only Linux supports it; elsewhere this
+/// does nothing.
+pub fn advise(file: &File, advice: Readahead) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::fd::AsRawFd;
+        let advice = match advice {
+            Readahead::Default => return Ok(()),
+            Readahead::Sequential => libc::POSIX_FADV_SEQUENTIAL,
+            Readahead::Random => libc::POSIX_FADV_RANDOM,
+        };
+        // SAFETY: `posix_fadvise` only takes a hint about the open descriptor it is given.
+        let error = unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, advice) };
+        if error != 0 {
+            return Err(io::Error::from_raw_os_error(error));
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = (file, advice);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_advise() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("a.safetensors");
+        std::fs::write(&path, b"data")?;
+        let file = File::open(&path)?;
+        for advice in [Readahead::Default, Readahead::Sequential, Readahead::Random] {
+            advise(&file, advice)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,243 @@
+use schemars::JsonSchema;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+
+/// `path` relative to `base`, both absolute and normalized, or `None` if they are on
+/// different drives. Climbs out of `base` with `..` where needed.
+pub(crate) fn relative_path(path: &Path, base: &Path) -> Option<PathBuf> {
+    let mut path_components = path.components().peekable();
+    let mut base_components = base.components().peekable();
+    // Both start with the same root, and on Windows the same drive
+    let rooted = !matches!(path_components.peek(), Some(Component::Normal(_)) | None);
+    if !rooted || path_components.peek() != base_components.peek() {
+        return None;
+    }
+    while path_components.peek().is_some() && path_components.peek() == base_components.peek() {
+        path_components.next();
+        base_components.next();
+    }
+    let relative: PathBuf = base_components.map(|_| Component::ParentDir).chain(path_components).collect();
+    Some(if relative.as_os_str().is_empty() { PathBuf::from(".") } else { relative })
+}
+
+/// The version of the layout of [`Record`] that `--with-schema-version` writes. Bump it when a
+/// field is removed or renamed or its values change type or meaning; a new optional field
+/// doesn't need it, as readers ignore fields they don't know.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// The output emitted for each processed file.
+#[derive(Debug, Default, Serialize, JsonSchema)]
+pub struct Record {
+    /// The [`SCHEMA_VERSION`] the record follows (`--with-schema-version`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schema_version: Option<u32>,
+    pub path: PathBuf,
+    /// `path` relative to the directory of the input it was found under (`--with-relpath`), if
+    /// it is under it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub relpath: Option<PathBuf>,
+    /// SHA-256 of `path` as lowercase hex, a key that doesn't change with the contents (`--with-id`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// The input the file was found through, as given: the directory, glob pattern or file, or
+    /// the entry of `--input-list` (`--with-source`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// File size in bytes (`--with-size`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// `size` in binary units, such as `1.50 GiB`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size_human: Option<String>,
+    /// Last modification time as an RFC 3339 UTC timestamp (`--with-mtime`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modified: Option<String>,
+    /// Last modification time in seconds since the Unix epoch (`--with-mtime`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modified_epoch: Option<u64>,
+    /// SHA-256 of the file's contents as lowercase hex (`--hash`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// Ed25519 signature over the canonical JSON of `metadata`, in hex (`--sign`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    /// What the file contains, guessed from its tensor names and metadata (`--classify`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<crate::classify::Kind>,
+    /// The tool that most likely wrote the file, guessed from its metadata keys (`--identify-tool`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub producer: Option<crate::producer::Producer>,
+    /// Number of parameters, counted from the tensor shapes (`--count-parameters-from shapes`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<u128>,
+    /// `parameters` with thousands separators and in short form, such as `6,738,415,616 (6.74B)`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parameters_human: Option<String>,
+    /// Number of tensors in the header (`--count-tensors` and `--group-shards`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tensors: Option<usize>,
+    /// The tensors in the header with their dtypes and shapes (`--tensors`), limited to those
+    /// matching `--tensor-filter`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tensor_list: Option<Vec<crate::formats::TensorInfo>>,
+    /// How many tensors there are after the `--first-n-tensors` in `tensor_list`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tensors_not_listed: Option<usize>,
+    /// The tensors with NaN or infinite values, empty if none of those read has any
+    /// (`--detect-nan-inf`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub non_finite: Option<Vec<crate::nan_inf::NonFinite>>,
+    /// The file's sidecar JSON (`--merge-sidecar` with `--sidecar-merge-mode nested`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sidecar: Option<Value>,
+    /// The values at the `--pointer` JSON Pointers into `metadata`, null where there is none.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pointers: Option<BTreeMap<String, Value>>,
+    /// For a sharded model combined by `--group-shards`, the paths of its shards.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shards: Option<Vec<PathBuf>>,
+    /// For a model read from its `*.index.json`, the size in bytes of each shard that's present.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shard_sizes: Option<BTreeMap<PathBuf, u64>>,
+    /// For a model read from its `*.index.json`, the shards that aren't present.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub missing_shards: Option<Vec<PathBuf>>,
+    /// For a sharded model, the metadata keys whose values differ between shards. These are
+    /// left out of `metadata`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata_conflicts: Option<Vec<MetadataConflict>>,
+    /// For a sharded model read through its index, the index's `total_size` against the
+    /// tensor data of the shards found (`--check-total-size`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_size_check: Option<TotalSizeCheck>,
+    /// For a sharded model read through its index, the tensors of its `weight_map` that the
+    /// shards don't have and the other way around (`--verify-against-index-weightmap`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub weight_map_check: Option<WeightMapCheck>,
+    /// The bytes of tensor data in the file, kept for `--check-total-size`.
+    #[serde(skip)]
+    pub tensor_bytes: Option<u64>,
+    /// The names of the tensors in the file, kept for `--verify-against-index-weightmap`.
+    #[serde(skip)]
+    pub tensor_names: Option<Vec<String>>,
+    pub metadata: Value,
+}
+
+impl Record {
+    /// Make the paths in the record relative to `base`, for `--manifest-relative-paths`.
+    /// Returns the paths that can't be, which are left absolute.
+    pub fn relativize_paths(&mut self, base: &Path) -> Vec<PathBuf> {
+        let mut absolute = Vec::new();
+        let mut relativize = |path: &mut PathBuf| match relative_path(path, base) {
+            Some(relative) => *path = relative,
+            None => absolute.push(path.clone()),
+        };
+        relativize(&mut self.path);
+        self.shards.iter_mut().chain(&mut self.missing_shards).flatten().for_each(&mut relativize);
+        if let Some(sizes) = self.shard_sizes.take() {
+            let sizes = sizes.into_iter().map(|(mut path, size)| {
+                relativize(&mut path);
+                (path, size)
+            });
+            self.shard_sizes = Some(sizes.collect());
+        }
+        absolute
+    }
+}
+
+/// The output emitted for each file that failed, with `--only-errors`.
+#[derive(Debug, Serialize)]
+pub struct ErrorRecord<'a> {
+    pub path: &'a std::path::Path,
+    pub error: String,
+    pub category: FailureCategory,
+    /// The kind of OS error, such as `PermissionDenied`, for unreadable files.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub os_error: Option<String>,
+}
+
+/// The output emitted for each file that was skipped, with `--emit-empty-record-for-skipped`.
+#[derive(Debug, Serialize)]
+pub struct SkippedRecord<'a> {
+    pub path: &'a std::path::Path,
+    pub skipped: crate::summary::SkipReason,
+}
+
+/// Whether a file couldn't be opened at all or was opened but failed later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FailureCategory {
+    Unreadable,
+    Unparseable,
+}
+
+/// A metadata key with different values in the shards of one model.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct MetadataConflict {
+    pub key: String,
+    /// The value in each shard that has the key, by shard file name.
+    pub values: BTreeMap<String, Value>,
+}
+
+/// How the size of a sharded model's tensor data compares with what its index declares.
+#[derive(Debug, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct TotalSizeCheck {
+    /// The `metadata.total_size` of the index.
+    pub declared: u64,
+    /// The bytes of tensor data in the shards found, added up.
+    pub computed: u64,
+    pub matches: bool,
+}
+
+/// How the tensors of a sharded model's shards compare with its index's `weight_map`.
+#[derive(Debug, Default, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct WeightMapCheck {
+    /// The tensors the `weight_map` puts in a shard that doesn't have them, with that shard's
+    /// file name.
+    pub missing: BTreeMap<String, String>,
+    /// The tensors of a shard that the `weight_map` doesn't put there, with the shard's file
+    /// name.
+    pub extra: BTreeMap<String, String>,
+    pub matches: bool,
+}
+
+/// The JSON Schema of [`Record`], pretty-printed, for `--print-schema`.
+pub(crate) fn schema() -> String {
+    let schema = schemars::schema_for!(Record);
+    format!("{}\n", serde_json::to_string_pretty(&schema).expect("a schema serializes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schema_lists_fields() {
+        let schema: Value = serde_json::from_str(&schema()).unwrap();
+        let properties = schema["properties"].as_object().unwrap();
+        assert!(properties.contains_key("path"));
+        assert!(properties.contains_key("sha256"));
+        assert_eq!(schema["required"], serde_json::json!(["path", "metadata"]));
+        assert_eq!(properties["size"]["description"], "File size in bytes (`--with-size`).");
+        assert!(properties.contains_key("schema_version"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_relativize_paths() {
+        let mut record = Record {
+            path: "/models/sdxl/a.safetensors.index.json".into(),
+            shards: Some(vec!["/models/sdxl/a-1.safetensors".into()]),
+            shard_sizes: Some(BTreeMap::from([("/data/a-2.safetensors".into(), 10)])),
+            ..Record::default()
+        };
+        assert!(record.relativize_paths(Path::new("/models")).is_empty());
+        assert_eq!(record.path, Path::new("sdxl/a.safetensors.index.json"));
+        assert_eq!(record.shards, Some(vec!["sdxl/a-1.safetensors".into()]));
+        assert_eq!(record.shard_sizes, Some(BTreeMap::from([("../data/a-2.safetensors".into(), 10)])));
+        assert_eq!(relative_path(Path::new("/models"), Path::new("/models")), Some(".".into()));
+        assert_eq!(relative_path(Path::new("models"), Path::new("/models")), None);
+    }
+}
@@ -0,0 +1,116 @@
+use crate::Scan;
+use crate::header::{self, Endian};
+use crate::rewrite::{self, Change};
+use anyhow::Context;
+use serde_json::Value;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// The alignment loaders expect of the tensor data, which starts right after the header.
+pub const ALIGNMENT: usize = 8;
+
+/// Rewrite the headers of the files as strict loaders expect them (`repair`): stray padding
+/// such as NUL bytes or extra whitespace after the JSON is dropped, the header is padded with
+/// spaces so the tensor data starts at a multiple of 8 bytes, and the length prefix is
+/// written little-endian. With `dry_run` the files that would change are only listed.
+///
+/// The JSON itself is kept byte for byte and the tensor data is copied unchanged, since its
+/// offsets are relative to the end of the header. Each file is written next to the original,
+/// checked by reading it back and then renamed over it, so a failure leaves the original.
+pub async fn run(scan: &Scan, files: Vec<PathBuf>, dry_run: bool) -> anyhow::Result<()> {
+    let words = rewrite::Words { would: "would repair", did: "repaired", failed: "couldn't be repaired" };
+    rewrite::run(scan, files, dry_run, &words, |path| async move { repair(&path, &scan.read_options, dry_run).await })
+        .await
+}
+
+async fn repair(path: &Path, options: &header::ReadOptions, dry_run: bool) -> anyhow::Result<Change> {
+    let raw = header::read_raw_header(path, options).await?;
+    let (json, fixed) = fix_header(&raw.bytes)?;
+    if fixed == raw.bytes && raw.endian == Endian::Le {
+        return Ok(Change::Unchanged);
+    }
+    let repair = Change::Changed(format!("header of {} bytes becomes {}", raw.bytes.len(), fixed.len()));
+    if dry_run {
+        return Ok(repair);
+    }
+    let data_start = 8 + raw.bytes.len() as u64;
+    let data_len = raw.stat.len() - data_start;
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || rewrite(&path, &fixed, data_start, data_len, &json)).await??;
+    Ok(repair)
+}
+
+/// The parsed header and its bytes as they should be: without trailing whitespace and NUL
+/// bytes, then padded with spaces to a multiple of [`ALIGNMENT`].
+pub fn fix_header(bytes: &[u8]) -> anyhow::Result<(Value, Vec<u8>)> {
+    let end = bytes.iter().rposition(|byte| !matches!(byte, b' ' | b'\t' | b'\r' | b'\n' | 0)).map_or(0, |i| i + 1);
+    let json: Value = serde_json::from_slice(&bytes[..end]).context("Header is not valid JSON without its padding")?;
+    let mut fixed = bytes[..end].to_vec();
+    fixed.resize(end.next_multiple_of(ALIGNMENT), b' ');
+    Ok((json, fixed))
+}
+
+/// Write `header` and the tensor data of `path` to a temporary file, check it and put it in
+/// the place of `path`.
+pub fn rewrite(path: &Path, header: &[u8], data_start: u64, data_len: u64, json: &Value) -> anyhow::Result<()> {
+    let mut source = File::open(path).context("Failed to open file")?;
+    source.seek(SeekFrom::Start(data_start))?;
+    crate::io::atomic_write_with(path, |out| {
+        out.write_all(&(header.len() as u64).to_le_bytes())?;
+        out.write_all(header)?;
+        let copied = io::copy(&mut source, out).context("Failed to copy the tensor data")?;
+        if copied != data_len {
+            anyhow::bail!("Copied {copied} bytes of tensor data but expected {data_len}; is the file being written?");
+        }
+        verify(out, json, 8 + header.len() as u64 + data_len)
+    })
+}
+
+/// Read the rewritten file back: its header must parse to `json` and it must be `len` bytes.
+fn verify(file: &mut File, json: &Value, len: u64) -> anyhow::Result<()> {
+    file.seek(SeekFrom::Start(0))?;
+    let mut prefix = [0u8; 8];
+    file.read_exact(&mut prefix)?;
+    let mut header = vec![0; usize::try_from(u64::from_le_bytes(prefix))?];
+    file.read_exact(&mut header)?;
+    let reread: Value = serde_json::from_slice(&header).context("The rewritten header doesn't parse")?;
+    if reread != *json || file.metadata()?.len() != len {
+        anyhow::bail!("The rewritten file doesn't match the original");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{scan, write_with_data as write};
+
+    #[tokio::test]
+    async fn test_repair() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let dir = temp_dir.path();
+        let header = br#"{"w":{"dtype":"U8","shape":[4],"data_offsets":[0,4]}}"#;
+        let padded = dir.join("padded.safetensors");
+        write(&padded, &[&header[..], b"\0\0\0 "].concat(), b"data");
+        let aligned = dir.join("aligned.safetensors");
+        let mut aligned_header = header.to_vec();
+        aligned_header.resize(header.len().next_multiple_of(8), b' ');
+        write(&aligned, &aligned_header, b"data");
+        let out = dir.join("repair.txt");
+
+        let scan = scan(&["repair", "--dry-run", "-o", out.to_str().unwrap(), dir.to_str().unwrap()]);
+        run(&scan, vec![padded.clone()], true).await?;
+        assert_eq!(std::fs::read(&padded)?.len(), 8 + header.len() + 4 + 4);
+
+        run(&scan, vec![padded.clone(), aligned.clone()], false).await?;
+        scan.output.finish()?;
+        assert_eq!(std::fs::read(&padded)?, std::fs::read(&aligned)?);
+        let text = std::fs::read_to_string(out)?;
+        let from_to = format!("header of {} bytes becomes {}", header.len() + 4, aligned_header.len());
+        assert!(text.contains(&format!("would repair  {}: {from_to}", padded.display())), "{text}");
+        assert!(text.contains(&format!("repaired      {}: {from_to}", padded.display())), "{text}");
+        assert!(text.contains(&format!("unchanged     {}", aligned.display())), "{text}");
+        Ok(())
+    }
+}
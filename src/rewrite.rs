@@ -0,0 +1,74 @@
+use crate::Scan;
+use crate::summary::Outcome;
+use futures::StreamExt;
+use std::path::PathBuf;
+
+/// What rewriting one file did, for the subcommands that rewrite headers in place.
+pub enum Change {
+    Unchanged,
+    /// The file was, or with `--dry-run` would be, rewritten, as described after its path.
+    Changed(String),
+}
+
+/// How the lines of a rewrite read: `would` and `did` for the files it changes, with
+/// `--dry-run` and without, such as `would repair` and `repaired`, and `failed` for the error
+/// at the end, such as `couldn't be repaired`.
+pub struct Words {
+    pub would: &'static str,
+    pub did: &'static str,
+    pub failed: &'static str,
+}
+
+/// Rewrite each of `files` with `rewrite`, as many at once as there are open files to spare,
+/// and write a line for each as it finishes: `unchanged`, changed with what changed, or
+/// `failed` with the error. Fails at the end if any file did.
+pub async fn run<F, Fut>(
+    scan: &Scan,
+    files: Vec<PathBuf>,
+    dry_run: bool,
+    words: &Words,
+    rewrite: F,
+) -> anyhow::Result<()>
+where
+    F: Fn(PathBuf) -> Fut,
+    Fut: Future<Output = anyhow::Result<Change>>,
+{
+    let total = files.len();
+    let rewrite = &rewrite;
+    let mut results = futures::stream::iter(files)
+        .map(|path| async move {
+            let result = async {
+                let _permit = scan.open_files.acquire().await?;
+                rewrite(path.clone()).await
+            }
+            .await;
+            (path, result)
+        })
+        .buffer_unordered(scan.open_files.available_permits().max(1));
+
+    let changed = if dry_run { words.would } else { words.did };
+    let width = [words.would, words.did, "unchanged", "failed"].iter().map(|word| word.len()).max().unwrap_or(0);
+    let mut failed = 0;
+    while let Some((path, result)) = results.next().await {
+        let line = match result {
+            Ok(Change::Unchanged) => {
+                scan.summary.record(Outcome::Processed);
+                format!("{:width$}  {}\n", "unchanged", path.display())
+            }
+            Ok(Change::Changed(what)) => {
+                scan.summary.record(Outcome::Processed);
+                format!("{changed:width$}  {}: {what}\n", path.display())
+            }
+            Err(e) => {
+                scan.summary.record(Outcome::Failed);
+                failed += 1;
+                format!("{:width$}  {}: {:#}\n", "failed", path.display(), e)
+            }
+        };
+        scan.output.write_text(&line)?;
+    }
+    if failed > 0 {
+        anyhow::bail!("{failed} of {total} files {}", words.failed);
+    }
+    Ok(())
+}
@@ -0,0 +1,409 @@
+use crate::humanize;
+use crate::record::{MetadataConflict, Record, TotalSizeCheck, WeightMapCheck};
+use anyhow::Context;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Split a shard file name such as `model-00001-of-00003.safetensors` into the logical
+/// model's file name (`model.safetensors`) and the total number of shards.
+fn parse_shard_name(file_name: &str) -> Option<(String, usize)> {
+    let (stem, extension) = file_name.rsplit_once('.')?;
+    let (rest, total) = stem.rsplit_once("-of-")?;
+    let (base, index) = rest.rsplit_once('-')?;
+    let is_number = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    if base.is_empty() || !is_number(index) || !is_number(total) {
+        return None;
+    }
+    Some((format!("{base}.{extension}"), total.parse().ok()?))
+}
+
+/// What the `*.index.json` files of a directory say.
+#[derive(Debug, Default)]
+struct Indexes {
+    /// The logical model each file in a `weight_map` belongs to.
+    members: HashMap<PathBuf, PathBuf>,
+    /// The `metadata.total_size` of each model whose index has one.
+    total_sizes: HashMap<PathBuf, u64>,
+    /// The `weight_map` of each model: the shard file name each tensor is in.
+    weight_maps: HashMap<PathBuf, BTreeMap<String, String>>,
+}
+
+/// Read the `*.index.json` files in `dir`, mapping each file in their `weight_map` to the
+/// logical model the index describes: `model.safetensors.index.json` describes `model.safetensors`.
+fn read_indexes(dir: &Path) -> Indexes {
+    let mut indexes = Indexes::default();
+    let Ok(entries) = std::fs::read_dir(dir) else { return indexes };
+    for entry in entries.flatten() {
+        let file_name = entry.file_name();
+        let Some(model) = file_name.to_str().and_then(|name| name.strip_suffix(".index.json")) else { continue };
+        let read = || -> anyhow::Result<Value> { Ok(serde_json::from_slice(&std::fs::read(entry.path())?)?) };
+        let index = match read() {
+            Ok(index) => index,
+            Err(e) => {
+                eprintln!("Warning: Failed to read shard index {}: {e}", entry.path().display());
+                continue;
+            }
+        };
+        let Some(weight_map) = index.get("weight_map").and_then(Value::as_object) else { continue };
+        for shard in weight_map.values().filter_map(Value::as_str) {
+            indexes.members.insert(dir.join(shard), dir.join(model));
+        }
+        let tensors = weight_map
+            .iter()
+            .filter_map(|(tensor, shard)| Some((tensor.clone(), shard.as_str()?.to_string())));
+        indexes.weight_maps.insert(dir.join(model), tensors.collect());
+        if let Some(total_size) = index.pointer("/metadata/total_size").and_then(Value::as_u64) {
+            indexes.total_sizes.insert(dir.join(model), total_size);
+        }
+    }
+    indexes
+}
+
+/// A `*.index.json` read on its own, for a record of the model it describes.
+#[derive(Debug)]
+pub struct Index {
+    /// The index's `metadata`, usually just `total_size`.
+    pub metadata: Value,
+    /// The size of the model's tensor data in bytes, from `metadata.total_size`.
+    pub total_size: Option<u64>,
+    /// Number of tensors in the `weight_map`.
+    pub tensors: usize,
+    /// The shard files the `weight_map` refers to, sorted, whether present or not.
+    pub shards: Vec<PathBuf>,
+}
+
+/// Whether `path` looks like a shard index such as `model.safetensors.index.json`.
+pub fn is_index(path: &Path) -> bool {
+    path.file_name().and_then(|name| name.to_str()).is_some_and(|name| name.ends_with(".index.json"))
+}
+
+/// Read a shard index. Shard paths are relative to the index's directory.
+pub fn read_index(path: &Path) -> anyhow::Result<Index> {
+    let index: Value = serde_json::from_slice(&std::fs::read(path)?).context("Failed to parse shard index")?;
+    let Some(weight_map) = index.get("weight_map").and_then(Value::as_object) else {
+        anyhow::bail!("Shard index has no weight_map");
+    };
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let shards: BTreeSet<PathBuf> = weight_map.values().filter_map(Value::as_str).map(|shard| dir.join(shard)).collect();
+    let metadata = index.get("metadata").cloned().unwrap_or_else(|| Value::Object(Map::new()));
+    Ok(Index {
+        total_size: metadata.get("total_size").and_then(Value::as_u64),
+        metadata,
+        tensors: weight_map.len(),
+        shards: shards.into_iter().collect(),
+    })
+}
+
+/// Collects the records of sharded models for `--group-shards`, to be emitted as one record
+/// per logical model once every shard was read.
+///
+/// Membership comes from a `*.index.json` `weight_map` in the shard's directory if there is
+/// one, and from the `-NNNNN-of-NNNNN` file name pattern otherwise.
+#[derive(Debug, Default)]
+pub struct ShardGroups {
+    /// The indexes found so far, by directory.
+    indexes: Mutex<HashMap<PathBuf, Indexes>>,
+    groups: Mutex<BTreeMap<PathBuf, Group>>,
+    /// Compare the tensor data of the shards with the `total_size` of their index.
+    check_total_size: bool,
+    /// Compare the tensors of the shards with the `weight_map` of their index.
+    check_weight_map: bool,
+}
+
+#[derive(Debug, Default)]
+struct Group {
+    /// From the file name pattern, if it was used.
+    expected: Option<usize>,
+    shards: Vec<Record>,
+}
+
+impl ShardGroups {
+    /// Groups that also compare each indexed model's `total_size` with its shards, for
+    /// `--check-total-size`, which needs the shards' [`Record::tensor_bytes`], and its
+    /// `weight_map`, for `--verify-against-index-weightmap`, which needs their
+    /// [`Record::tensor_names`].
+    pub fn new(check_total_size: bool, check_weight_map: bool) -> Self {
+        Self { check_total_size, check_weight_map, ..Self::default() }
+    }
+
+    /// The logical model `path` is a shard of, and how many shards its name says there are.
+    fn model_of(&self, path: &Path) -> Option<(PathBuf, Option<usize>)> {
+        let dir = path.parent()?;
+        let mut indexes = self.indexes.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        let members = &indexes.entry(dir.to_path_buf()).or_insert_with(|| read_indexes(dir)).members;
+        if let Some(model) = members.get(path) {
+            return Some((model.clone(), None));
+        }
+        let (model, total) = parse_shard_name(path.file_name()?.to_str()?)?;
+        Some((dir.join(model), Some(total)))
+    }
+
+    /// Keep `record` if its file is a shard, handing it back otherwise.
+    pub fn add(&self, record: Record) -> Option<Record> {
+        let Some((model, expected)) = self.model_of(&record.path) else { return Some(record) };
+        let mut groups = self.groups.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        let group = groups.entry(model).or_default();
+        group.expected = group.expected.or(expected);
+        group.shards.push(record);
+        None
+    }
+
+    /// One record per logical model, sorted by path.
+    pub fn finish(&self) -> Vec<Record> {
+        let mut groups = self.groups.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        std::mem::take(&mut *groups)
+            .into_iter()
+            .map(|(model, mut group)| {
+                group.shards.sort_by(|a, b| a.path.cmp(&b.path));
+                if let Some(expected) = group.expected.filter(|expected| *expected != group.shards.len()) {
+                    eprintln!("Warning: {}: found {} of {expected} shards", model.display(), group.shards.len());
+                }
+                let mut record = combine(model, &group.shards);
+                if self.check_total_size {
+                    record.total_size_check = self.check_total_size(&record.path, &group.shards);
+                }
+                if self.check_weight_map {
+                    record.weight_map_check = self.check_weight_map(&record.path, &group.shards);
+                }
+                record
+            })
+            .collect()
+    }
+
+    /// Compare the `total_size` that the index of `model` declares with the tensor data of
+    /// its `shards`, warning with the shards found if they differ. `None` without an index
+    /// that declares one.
+    fn check_total_size(&self, model: &Path, shards: &[Record]) -> Option<TotalSizeCheck> {
+        let indexes = self.indexes.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        let declared = *indexes.get(model.parent()?)?.total_sizes.get(model)?;
+        let computed = shards.iter().filter_map(|shard| shard.tensor_bytes).sum();
+        if computed != declared {
+            let found: Vec<String> = shards
+                .iter()
+                .map(|shard| shard.path.file_name().unwrap_or_default().to_string_lossy().into_owned())
+                .collect();
+            eprintln!(
+                "Warning: {}: the index declares a total_size of {declared} bytes, but the {} shards found ({}) hold {}",
+                model.display(),
+                shards.len(),
+                found.join(", "),
+                computed
+            );
+        }
+        Some(TotalSizeCheck { declared, computed, matches: computed == declared })
+    }
+
+    /// Compare the tensors of the `shards` of `model` with where the `weight_map` of its index
+    /// puts them, warning with the tensors that are missing or extra. Only the shards found are
+    /// compared, as one that is missing fails to be read anyway. `None` without an index.
+    fn check_weight_map(&self, model: &Path, shards: &[Record]) -> Option<WeightMapCheck> {
+        let indexes = self.indexes.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        let weight_map = indexes.get(model.parent()?)?.weight_maps.get(model)?;
+        let mut check = WeightMapCheck::default();
+        for shard in shards {
+            let Some(names) = &shard.tensor_names else { continue };
+            let file_name = shard.path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+            let names: BTreeSet<&str> = names.iter().map(String::as_str).collect();
+            for (tensor, mapped) in weight_map {
+                if *mapped == file_name && !names.contains(tensor.as_str()) {
+                    check.missing.insert(tensor.clone(), file_name.clone());
+                }
+            }
+            for tensor in names.into_iter().filter(|tensor| weight_map.get(*tensor) != Some(&file_name)) {
+                check.extra.insert(tensor.to_string(), file_name.clone());
+            }
+        }
+        let list = |tensors: &BTreeMap<String, String>| -> String {
+            tensors.iter().map(|(tensor, shard)| format!("{tensor} ({shard})")).collect::<Vec<_>>().join(", ")
+        };
+        if !check.missing.is_empty() {
+            let missing = list(&check.missing);
+            eprintln!("Warning: {}: Tensors of the weight_map missing from their shards: {missing}", model.display());
+        }
+        if !check.extra.is_empty() {
+            let extra = list(&check.extra);
+            eprintln!("Warning: {}: Tensors of the shards not in the weight_map: {extra}", model.display());
+        }
+        check.matches = check.missing.is_empty() && check.extra.is_empty();
+        Some(check)
+    }
+}
+
+/// Combine the records of a model's shards: counts and sizes are summed, the latest
+/// modification time is kept, and metadata keys are merged where the shards agree.
+fn combine(model: PathBuf, shards: &[Record]) -> Record {
+    let sum = |field: fn(&Record) -> Option<u128>| shards.iter().map(field).sum::<Option<u128>>();
+    let mut record = Record {
+        path: model,
+        size: sum(|r| r.size.map(u128::from)).and_then(|size| u64::try_from(size).ok()),
+        parameters: sum(|r| r.parameters),
+        tensors: shards.iter().map(|r| r.tensors).sum(),
+        shards: Some(shards.iter().map(|r| r.path.clone()).collect()),
+        ..Record::default()
+    };
+    // The input of the shards, unless they were found through different ones
+    if let Some(first) = shards.first().filter(|first| shards.iter().all(|r| r.source == first.source)) {
+        record.source.clone_from(&first.source);
+    }
+    record.size_human = record.size.map(humanize::bytes);
+    record.parameters_human =
+        record.parameters.map(|n| format!("{} ({})", humanize::thousands(n), humanize::count(n)));
+    if let Some(latest) = shards.iter().filter(|r| r.modified_epoch.is_some()).max_by_key(|r| r.modified_epoch) {
+        record.modified.clone_from(&latest.modified);
+        record.modified_epoch = latest.modified_epoch;
+    }
+
+    let (metadata, conflicts) = reconcile(shards);
+    record.metadata = Value::Object(metadata);
+    record.metadata_conflicts = Some(conflicts);
+    record
+}
+
+/// Merge the shards' metadata. A key whose value is the same in every shard that has it is
+/// kept; a key with differing values is left out and reported with each shard's value.
+fn reconcile(shards: &[Record]) -> (Map<String, Value>, Vec<MetadataConflict>) {
+    let mut values: BTreeMap<&str, BTreeMap<String, &Value>> = BTreeMap::new();
+    for shard in shards {
+        let Some(metadata) = shard.metadata.as_object() else { continue };
+        let name = shard.path.file_name().map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+        for (key, value) in metadata {
+            values.entry(key).or_default().insert(name.clone(), value);
+        }
+    }
+
+    let mut merged = Map::new();
+    let mut conflicts = Vec::new();
+    for (key, by_shard) in values {
+        let mut distinct = by_shard.values();
+        let first = distinct.next().copied().unwrap_or(&Value::Null);
+        if distinct.all(|value| *value == first) {
+            merged.insert(key.to_string(), first.clone());
+        } else {
+            let values = by_shard.into_iter().map(|(shard, value)| (shard, value.clone())).collect();
+            conflicts.push(MetadataConflict { key: key.to_string(), values });
+        }
+    }
+    (merged, conflicts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_shard_name() {
+        assert_eq!(parse_shard_name("model-00001-of-00003.safetensors"), Some(("model.safetensors".to_string(), 3)));
+        assert_eq!(parse_shard_name("my-model-2-of-2.safetensors"), Some(("my-model.safetensors".to_string(), 2)));
+        assert_eq!(parse_shard_name("model.safetensors"), None);
+        assert_eq!(parse_shard_name("one-of-us.safetensors"), None);
+    }
+
+    #[test]
+    fn test_group_shards_reconciles_metadata() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let dir = temp_dir.path();
+        let shard = |name: &str, metadata: Value| Record {
+            path: dir.join(name),
+            size: Some(10),
+            tensors: Some(2),
+            metadata,
+            ..Record::default()
+        };
+
+        let groups = ShardGroups::default();
+        assert!(groups.add(shard("model-00002-of-00002.safetensors", json!({"format": "pt", "step": "2"}))).is_none());
+        assert!(groups.add(shard("model-00001-of-00002.safetensors", json!({"format": "pt", "step": "1"}))).is_none());
+        assert!(groups.add(shard("lora.safetensors", json!({}))).is_some());
+
+        let records = groups.finish();
+        assert_eq!(records.len(), 1);
+        let model = &records[0];
+        assert_eq!(model.path, dir.join("model.safetensors"));
+        assert_eq!((model.size, model.tensors), (Some(20), Some(4)));
+        assert_eq!(model.metadata, json!({"format": "pt"}));
+        let conflicts = model.metadata_conflicts.as_ref().unwrap();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].key, "step");
+        assert_eq!(conflicts[0].values["model-00001-of-00002.safetensors"], "1");
+        Ok(())
+    }
+
+    #[test]
+    fn test_group_shards_from_index() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let dir = temp_dir.path();
+        let index = json!({"weight_map": {"a.weight": "part-a.safetensors", "b.weight": "part-b.safetensors"}});
+        std::fs::write(dir.join("model.safetensors.index.json"), index.to_string())?;
+
+        let groups = ShardGroups::default();
+        for name in ["part-a.safetensors", "part-b.safetensors"] {
+            assert!(groups.add(Record { path: dir.join(name), ..Record::default() }).is_none());
+        }
+        let records = groups.finish();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].shards.as_ref().unwrap().len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_total_size() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let dir = temp_dir.path();
+        let index = json!({
+            "metadata": {"total_size": 300},
+            "weight_map": {"a.weight": "part-a.safetensors", "b.weight": "part-b.safetensors"},
+        });
+        std::fs::write(dir.join("model.safetensors.index.json"), index.to_string())?;
+        let shard = |name: &str, bytes| Record { path: dir.join(name), tensor_bytes: Some(bytes), ..Record::default() };
+
+        let groups = ShardGroups::new(true, false);
+        groups.add(shard("part-a.safetensors", 100));
+        groups.add(shard("part-b.safetensors", 200));
+        let check = groups.finish().remove(0).total_size_check;
+        assert_eq!(check, Some(TotalSizeCheck { declared: 300, computed: 300, matches: true }));
+
+        // One shard short
+        groups.add(shard("part-a.safetensors", 100));
+        let check = groups.finish().remove(0).total_size_check;
+        assert_eq!(check, Some(TotalSizeCheck { declared: 300, computed: 100, matches: false }));
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_weight_map() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let dir = temp_dir.path();
+        let index = json!({
+            "weight_map": {
+                "a.weight": "part-a.safetensors",
+                "a.bias": "part-a.safetensors",
+                "b.weight": "part-b.safetensors",
+            },
+        });
+        std::fs::write(dir.join("model.safetensors.index.json"), index.to_string())?;
+        let shard = |name: &str, tensors: &[&str]| Record {
+            path: dir.join(name),
+            tensor_names: Some(tensors.iter().map(ToString::to_string).collect()),
+            ..Record::default()
+        };
+
+        let groups = ShardGroups::new(false, true);
+        groups.add(shard("part-a.safetensors", &["a.weight", "a.bias"]));
+        groups.add(shard("part-b.safetensors", &["b.weight"]));
+        let check = groups.finish().remove(0).weight_map_check.unwrap();
+        assert!(check.matches);
+
+        // A tensor lost from one shard, and one moved to another
+        groups.add(shard("part-a.safetensors", &["a.weight", "b.weight"]));
+        groups.add(shard("part-b.safetensors", &[]));
+        let check = groups.finish().remove(0).weight_map_check.unwrap();
+        let missing = [("a.bias", "part-a.safetensors"), ("b.weight", "part-b.safetensors")];
+        assert_eq!(check.missing, missing.map(|(tensor, shard)| (tensor.to_string(), shard.to_string())).into());
+        assert_eq!(check.extra, [("b.weight".to_string(), "part-a.safetensors".to_string())].into());
+        assert!(!check.matches);
+        Ok(())
+    }
+}
@@ -0,0 +1,179 @@
+use anyhow::Context;
+use serde_json::{Map, Value};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// How `--merge-sidecar` combines a sidecar with the extracted metadata.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum MergeMode {
+    /// Add the sidecar to the record as `sidecar`, leaving the metadata alone
+    #[default]
+    Nested,
+    /// Add the sidecar's keys to the metadata, keeping the extracted value of keys in both
+    PreferExtracted,
+    /// Add the sidecar's keys to the metadata, replacing the extracted value of keys in both
+    PreferSidecar,
+}
+
+/// The sidecar of a model file, the `<stem>.json` next to it.
+pub fn path(path: &Path) -> PathBuf {
+    path.with_extension("json")
+}
+
+/// Where `--per-file-out` writes the metadata of each file, such as
+/// `out/{relpath}.meta.json`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathTemplate(Vec<Part>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    /// The file's path relative to the base directory, without its extension.
+    RelPath,
+    /// The file name without its extension.
+    Stem,
+    /// The extension, without the dot.
+    Ext,
+    /// The `--hash` digest.
+    Hash,
+}
+
+/// Parse a `--per-file-out` argument. Only the known placeholders are accepted, and no `..`
+/// that could lead out of the directory the template names.
+pub fn parse_path_template(arg: &str) -> Result<PathTemplate, String> {
+    if Path::new(arg).components().any(|component| component == Component::ParentDir) {
+        return Err("`..` isn't allowed".to_string());
+    }
+    let mut parts = Vec::new();
+    let mut rest = arg;
+    while let Some(open) = rest.find('{') {
+        if open > 0 {
+            parts.push(Part::Literal(rest[..open].to_string()));
+        }
+        let close = rest[open..].find('}').ok_or("unclosed `{`")? + open;
+        parts.push(match &rest[open + 1..close] {
+            "relpath" => Part::RelPath,
+            "stem" => Part::Stem,
+            "ext" => Part::Ext,
+            "hash" => Part::Hash,
+            other => {
+                return Err(format!("unknown placeholder `{{{other}}}`, expected {{relpath}}, {{stem}}, {{ext}} or {{hash}}"));
+            }
+        });
+        rest = &rest[close + 1..];
+    }
+    if !rest.is_empty() {
+        parts.push(Part::Literal(rest.to_string()));
+    }
+    if !parts.iter().any(|part| matches!(part, Part::RelPath | Part::Stem | Part::Hash)) {
+        return Err("needs {relpath}, {stem} or {hash} to give each file its own path".to_string());
+    }
+    Ok(PathTemplate(parts))
+}
+
+impl PathTemplate {
+    pub fn uses_hash(&self) -> bool {
+        self.0.contains(&Part::Hash)
+    }
+
+    /// The output path of `path`, whose `{relpath}` is relative to `base`. Fails for a file
+    /// outside `base` when `{relpath}` is used.
+    pub fn render(&self, path: &Path, base: &Path, sha256: Option<&str>) -> anyhow::Result<PathBuf> {
+        let mut rendered = String::new();
+        for part in &self.0 {
+            match part {
+                Part::Literal(text) => rendered.push_str(text),
+                Part::RelPath => {
+                    let relative = path.strip_prefix(base).ok().filter(|relative| {
+                        relative.components().all(|component| matches!(component, Component::Normal(_)))
+                    });
+                    let relative = relative
+                        .with_context(|| format!("Isn't under {}, which {{relpath}} is relative to", base.display()))?;
+                    rendered.push_str(&relative.with_extension("").to_string_lossy());
+                }
+                Part::Stem => rendered.push_str(&path.file_stem().unwrap_or_default().to_string_lossy()),
+                Part::Ext => rendered.push_str(&path.extension().unwrap_or_default().to_string_lossy()),
+                Part::Hash => rendered.push_str(sha256.context("{hash} needs --hash")?),
+            }
+        }
+        Ok(PathBuf::from(rendered))
+    }
+}
+
+/// Read and parse the sidecar of `path`, or `None` if there isn't one.
+pub async fn read(path: &Path) -> anyhow::Result<Option<Value>> {
+    let sidecar = self::path(path);
+    let text = match tokio::fs::read_to_string(&sidecar).await {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", sidecar.display())),
+    };
+    let text = crate::io::strip_bom(&text);
+    let value = serde_json::from_str(text).with_context(|| format!("Failed to parse {}", sidecar.display()))?;
+    Ok(Some(value))
+}
+
+/// Fold `sidecar` into `metadata` by `mode`, returning what is left to nest in the record.
+/// Only objects can be merged key by key, so other sidecars are always nested.
+pub fn merge(metadata: &mut Value, sidecar: Value, mode: MergeMode) -> Option<Value> {
+    let (Value::Object(metadata), Value::Object(sidecar)) = (metadata, &sidecar) else {
+        return Some(sidecar);
+    };
+    match mode {
+        MergeMode::Nested => return Some(Value::Object(sidecar.clone())),
+        MergeMode::PreferExtracted => merge_maps(metadata, sidecar, false),
+        MergeMode::PreferSidecar => merge_maps(metadata, sidecar, true),
+    }
+    None
+}
+
+fn merge_maps(metadata: &mut Map<String, Value>, sidecar: &Map<String, Value>, overwrite: bool) {
+    for (key, value) in sidecar {
+        if overwrite || !metadata.contains_key(key) {
+            metadata.insert(key.clone(), value.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_merge() {
+        let extracted = json!({"ss_network_dim": "16", "title": "extracted"});
+        let sidecar = json!({"title": "curated", "rating": 5});
+
+        let mut metadata = extracted.clone();
+        assert_eq!(merge(&mut metadata, sidecar.clone(), MergeMode::Nested), Some(sidecar.clone()));
+        assert_eq!(metadata, extracted);
+
+        let mut metadata = extracted.clone();
+        assert_eq!(merge(&mut metadata, sidecar.clone(), MergeMode::PreferExtracted), None);
+        assert_eq!(metadata, json!({"ss_network_dim": "16", "title": "extracted", "rating": 5}));
+
+        let mut metadata = extracted.clone();
+        assert_eq!(merge(&mut metadata, sidecar, MergeMode::PreferSidecar), None);
+        assert_eq!(metadata, json!({"ss_network_dim": "16", "title": "curated", "rating": 5}));
+
+        let mut metadata = extracted.clone();
+        assert_eq!(merge(&mut metadata, json!(["a"]), MergeMode::PreferSidecar), Some(json!(["a"])));
+        assert_eq!(metadata, extracted);
+    }
+
+    #[test]
+    fn test_path_template() -> anyhow::Result<()> {
+        let base = Path::new("/models");
+        let path = Path::new("/models/sdxl/lora.safetensors");
+        let render = |template| parse_path_template(template).unwrap().render(path, base, Some("ab12"));
+        assert_eq!(render("out/{relpath}.meta.json")?, Path::new("out/sdxl/lora.meta.json"));
+        assert_eq!(render("/meta/{hash}-{stem}.{ext}.json")?, Path::new("/meta/ab12-lora.safetensors.json"));
+        let template = parse_path_template("out/{relpath}.json").unwrap();
+        assert!(template.render(Path::new("/other/a.safetensors"), base, None).is_err());
+        assert!(parse_path_template("out/{name}.json").is_err());
+        assert!(parse_path_template("out/../{relpath}.json").is_err());
+        assert!(parse_path_template("out/all.json").is_err());
+        Ok(())
+    }
+}
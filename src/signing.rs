@@ -0,0 +1,150 @@
+use crate::Scan;
+use crate::header;
+use crate::summary::Outcome;
+use anyhow::Context;
+use ed25519_dalek::pkcs8::{DecodePrivateKey, DecodePublicKey};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use futures::StreamExt;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+/// Where the detached signature of a file's metadata is written: `<file>.metadata.sig`.
+pub fn signature_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".metadata.sig");
+    PathBuf::from(name)
+}
+
+/// Read the 32 bytes of a key from `path`, which holds either a PEM document, decoded by
+/// `from_pem`, or the key bytes as 64 hex digits.
+fn read_key<K>(
+    path: &Path,
+    from_pem: impl FnOnce(&str) -> anyhow::Result<K>,
+    from_bytes: impl FnOnce(&[u8; 32]) -> anyhow::Result<K>,
+) -> anyhow::Result<K> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("Failed to read key {}", path.display()))?;
+    let text = text.trim();
+    let key = if text.starts_with("-----BEGIN") {
+        from_pem(text)
+    } else {
+        let bytes: [u8; 32] = hex::decode(text)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .context("expected a PEM document or 64 hex digits")?;
+        from_bytes(&bytes)
+    };
+    key.with_context(|| format!("Invalid key {}", path.display()))
+}
+
+/// Load an Ed25519 private key for `--sign`: PKCS#8 PEM, as written by
+/// `openssl genpkey -algorithm ed25519`, or the 32-byte seed in hex.
+pub fn load_signing_key(path: &Path) -> anyhow::Result<SigningKey> {
+    read_key(
+        path,
+        |pem| Ok(SigningKey::from_pkcs8_pem(pem)?),
+        |bytes| Ok(SigningKey::from_bytes(bytes)),
+    )
+}
+
+/// Load an Ed25519 public key for `verify-signature`: SPKI PEM, as written by
+/// `openssl pkey -pubout`, or the 32 bytes in hex.
+pub fn load_verifying_key(path: &Path) -> anyhow::Result<VerifyingKey> {
+    read_key(
+        path,
+        |pem| Ok(VerifyingKey::from_public_key_pem(pem)?),
+        |bytes| Ok(VerifyingKey::from_bytes(bytes)?),
+    )
+}
+
+/// Sign the canonical JSON of `metadata`, returning the signature in hex.
+pub fn sign(key: &SigningKey, metadata: &Value) -> String {
+    hex::encode(key.sign(crate::canonical::to_string(metadata).as_bytes()).to_bytes())
+}
+
+/// Check a hex signature made by [`sign`] against `metadata`.
+pub fn verify(key: &VerifyingKey, metadata: &Value, signature: &str) -> anyhow::Result<()> {
+    let bytes: [u8; 64] = hex::decode(signature.trim())
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .context("Signature is not 128 hex digits")?;
+    key.verify(crate::canonical::to_string(metadata).as_bytes(), &Signature::from_bytes(&bytes))
+        .map_err(|_| anyhow::anyhow!("Signature doesn't match the metadata"))
+}
+
+/// Check the `<file>.metadata.sig` of each file against its metadata, printing `OK` or
+/// `FAILED` with the path. Fails if any signature is missing or doesn't verify.
+///
+/// The metadata is extracted the same way as when signing, so options that change it, such
+/// as `--normalize-metadata` or `--prefix`, must be given again.
+pub async fn verify_all(scan: &Scan, files: Vec<PathBuf>, public_key: &Path) -> anyhow::Result<()> {
+    let key = load_verifying_key(public_key)?;
+    let key = &key;
+    let total = files.len();
+    let mut results = futures::stream::iter(files)
+        .map(|path| async move {
+            let result = async {
+                let _permit = scan.open_files.acquire().await?;
+                let header = header::read_header(&path, &scan.read_options).await?;
+                let mut metadata = header.metadata();
+                crate::transform_metadata(scan, &path, &mut metadata);
+                let sig_path = signature_path(&path);
+                let signature = tokio::fs::read_to_string(&sig_path)
+                    .await
+                    .with_context(|| format!("Failed to read {}", sig_path.display()))?;
+                verify(key, &metadata, &signature)
+            }
+            .await;
+            (path, result)
+        })
+        .buffer_unordered(scan.open_files.available_permits().max(1));
+
+    let mut failed = 0;
+    while let Some((path, result)) = results.next().await {
+        match result {
+            Ok(()) => {
+                scan.output.write_text(&format!("OK      {}\n", path.display()))?;
+                scan.summary.record(Outcome::Processed);
+            }
+            Err(e) => {
+                scan.output.write_text(&format!("FAILED  {}: {:#}\n", path.display(), e))?;
+                scan.summary.record(Outcome::Failed);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        anyhow::bail!("{failed} of {total} signatures failed to verify");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::pkcs8::EncodePrivateKey;
+    use serde_json::json;
+
+    #[test]
+    fn test_sign_and_verify() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let seed_path = temp_dir.path().join("key.hex");
+        std::fs::write(&seed_path, hex::encode([7; 32]))?;
+        let pem_path = temp_dir.path().join("key.pem");
+        std::fs::write(&pem_path, key.to_pkcs8_pem(ed25519_dalek::pkcs8::spki::der::pem::LineEnding::LF)?.as_bytes())?;
+        assert_eq!(load_signing_key(&seed_path)?.to_bytes(), key.to_bytes());
+        assert_eq!(load_signing_key(&pem_path)?.to_bytes(), key.to_bytes());
+
+        let metadata = json!({"ss_network_dim": "16", "ss_output_name": "lora"});
+        let signature = sign(&key, &metadata);
+        let public = key.verifying_key();
+        verify(&public, &metadata, &signature)?;
+        // Key order doesn't matter, since the canonical form is signed
+        verify(&public, &json!({"ss_output_name": "lora", "ss_network_dim": "16"}), &signature)?;
+        assert!(verify(&public, &json!({"ss_network_dim": "32", "ss_output_name": "lora"}), &signature).is_err());
+
+        let path = Path::new("/models/a.safetensors");
+        assert_eq!(signature_path(path), Path::new("/models/a.safetensors.metadata.sig"));
+        Ok(())
+    }
+}
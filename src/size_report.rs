@@ -0,0 +1,70 @@
+use crate::humanize;
+use crate::table;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// The sizes of one file, in bytes.
+#[derive(Debug)]
+struct Sizes {
+    path: PathBuf,
+    /// The header JSON, not counting the 8-byte length before it.
+    header: u64,
+    /// The `__metadata__` object, as compact JSON.
+    metadata: u64,
+    file: u64,
+}
+
+/// Collects the header and metadata sizes of the scanned files, for `--metadata-size-report`.
+#[derive(Debug, Default)]
+pub struct SizeReport {
+    files: Mutex<Vec<Sizes>>,
+}
+
+impl SizeReport {
+    pub fn add(&self, path: &Path, header: u64, metadata: u64, file: u64) {
+        let mut files = self.files.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        files.push(Sizes { path: path.to_path_buf(), header, metadata, file });
+    }
+
+    /// A table of the files with the largest metadata first, with the share of the file it
+    /// takes up.
+    pub fn report(&self) -> String {
+        let mut files = self.files.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        files.sort_by(|a, b| b.metadata.cmp(&a.metadata).then_with(|| b.header.cmp(&a.header)).then_with(|| a.path.cmp(&b.path)));
+        let headers = ["file", "header", "metadata", "size", "metadata/size"].map(String::from);
+        let rows: Vec<Vec<String>> = files
+            .iter()
+            .map(|sizes| {
+                #[allow(clippy::cast_precision_loss)]
+                let ratio = sizes.metadata as f64 / sizes.file.max(1) as f64;
+                vec![
+                    sizes.path.display().to_string(),
+                    humanize::bytes(sizes.header),
+                    humanize::bytes(sizes.metadata),
+                    humanize::bytes(sizes.file),
+                    format!("{:.2}%", ratio * 100.0),
+                ]
+            })
+            .collect();
+        table::render(&headers, &rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_order() {
+        let report = SizeReport::default();
+        report.add(Path::new("big.safetensors"), 4096, 2048, 1 << 30);
+        report.add(Path::new("small.safetensors"), 8192, 6144, 1 << 20);
+        report.add(Path::new("bare.safetensors"), 100, 0, 1000);
+
+        let text = report.report();
+        let lines: Vec<&str> = text.lines().collect();
+        assert!(lines[1].starts_with("small.safetensors") && lines[1].ends_with("0.59%"), "{text}");
+        assert!(lines[2].starts_with("big.safetensors") && lines[2].ends_with("0.00%"), "{text}");
+        assert!(lines[3].starts_with("bare.safetensors"), "{text}");
+    }
+}
@@ -0,0 +1,273 @@
+use crate::header::{Endian, Header};
+use serde_json::Value;
+use std::fmt;
+
+/// The dtypes of the safetensors format, with their size in bits.
+const DTYPES: &[(&str, u64)] = &[
+    ("BOOL", 8),
+    ("F4", 4),
+    ("F6_E2M3", 6),
+    ("F6_E3M2", 6),
+    ("U8", 8),
+    ("I8", 8),
+    ("F8_E5M2", 8),
+    ("F8_E4M3", 8),
+    ("F8_E8M0", 8),
+    ("I16", 16),
+    ("U16", 16),
+    ("F16", 16),
+    ("BF16", 16),
+    ("I32", 32),
+    ("U32", 32),
+    ("F32", 32),
+    ("F64", 64),
+    ("I64", 64),
+    ("U64", 64),
+];
+
+/// A rule of the safetensors format that a header breaks, for `--strict-spec`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Violation {
+    /// A short name of the rule, such as `data-offsets`.
+    pub rule: &'static str,
+    /// Where in the header: a tensor name, a `__metadata__` key or `header`.
+    pub location: String,
+    pub detail: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {} [{}]", self.location, self.detail, self.rule)
+    }
+}
+
+/// The tensors of `header` whose dtype isn't one of the safetensors dtypes, such as one from a
+/// newer writer, each as its name and the dtype, `none` if it has none
+/// (`--bail-on-unknown-dtype`).
+pub fn unknown_dtypes(header: &Header) -> Vec<(String, String)> {
+    let Some(entries) = header.json.as_object() else { return Vec::new() };
+    entries
+        .iter()
+        .filter(|(name, _)| *name != "__metadata__")
+        .filter_map(|(name, tensor)| match tensor.get("dtype") {
+            Some(Value::String(dtype)) if DTYPES.iter().any(|(known, _)| known == dtype) => None,
+            Some(Value::String(dtype)) => Some((name.clone(), dtype.clone())),
+            Some(dtype) => Some((name.clone(), dtype.to_string())),
+            None => Some((name.clone(), "none".to_string())),
+        })
+        .collect()
+}
+
+/// A stretch of unused bytes between the data of two tensors (`--require-contiguous`).
+#[derive(Debug, PartialEq, Eq)]
+pub struct Gap {
+    /// The tensor whose data follows the gap.
+    pub before: String,
+    /// The offset the gap starts at in the data section.
+    pub at: u64,
+    pub bytes: u64,
+}
+
+impl fmt::Display for Gap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} bytes at {} before {}", self.bytes, self.at, self.before)
+    }
+}
+
+/// The gaps between the data of consecutive tensors of `header`, ordered by offset, such as
+/// padding a loader won't accept. Unlike the `contiguous` rule of [`check`] this ignores
+/// overlaps and the start and end of the data section, and tensors without valid offsets.
+pub fn gaps(header: &Header) -> Vec<Gap> {
+    let Some(entries) = header.json.as_object() else { return Vec::new() };
+    let mut ranges: Vec<(u64, u64, &str)> = entries
+        .iter()
+        .filter(|(name, _)| *name != "__metadata__")
+        .filter_map(|(name, tensor)| match tensor.get("data_offsets")?.as_array()?.as_slice() {
+            [begin, end] => Some((begin.as_u64()?, end.as_u64()?, name.as_str())),
+            _ => None,
+        })
+        .filter(|(begin, end, _)| end >= begin)
+        .collect();
+    ranges.sort_unstable();
+    let mut gaps = Vec::new();
+    // Where the data of the tensors so far ends
+    let mut position: Option<u64> = None;
+    for (begin, end, name) in ranges {
+        if let Some(position) = position
+            && begin > position
+        {
+            gaps.push(Gap { before: name.to_string(), at: position, bytes: begin - position });
+        }
+        position = Some(position.map_or(end, |position| position.max(end)));
+    }
+    gaps
+}
+
+/// Every way in which `header` departs from the safetensors specification: a length prefix
+/// that isn't little-endian, deviations that were tolerated while reading it, tensor entries
+/// without a known dtype, an integer shape or `[begin, end]` offsets that match the dtype and
+/// shape, data that isn't laid out contiguously in offset order to the end of the file, and
+/// `__metadata__` that isn't a map of strings to strings. `file_len` is the length of the
+/// whole file, which for stdin is only known once the stream has been read.
+pub fn check(header: &Header, file_len: u64) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    let mut violation = |rule, location: &str, detail: String| {
+        violations.push(Violation { rule, location: location.to_string(), detail });
+    };
+    if header.endian != Endian::Le {
+        violation("length-prefix", "header", format!("The length is {}, not little-endian", header.endian));
+    }
+    for warning in &header.warnings {
+        violation("tolerated", "header", warning.clone());
+    }
+    let Some(entries) = header.json.as_object() else {
+        violation("header", "header", "The header is not a JSON object".to_string());
+        return violations;
+    };
+    let mut ranges = Vec::new();
+    for (name, entry) in entries {
+        if name == "__metadata__" {
+            match entry.as_object() {
+                Some(metadata) => {
+                    for (key, value) in metadata.iter().filter(|(_, value)| !value.is_string()) {
+                        let location = format!("__metadata__.{key}");
+                        violation("metadata", &location, format!("The value {value} is not a string"));
+                    }
+                }
+                None => violation("metadata", name, "`__metadata__` is not an object".to_string()),
+            }
+            continue;
+        }
+        let Some(tensor) = entry.as_object() else {
+            violation("tensor-entry", name, "The entry is not an object".to_string());
+            continue;
+        };
+        let dtype = tensor.get("dtype").and_then(Value::as_str);
+        let bits = dtype.and_then(|dtype| DTYPES.iter().find(|(known, _)| *known == dtype)).map(|(_, bits)| *bits);
+        if bits.is_none() {
+            let dtype = tensor.get("dtype").map_or_else(|| "none".to_string(), Value::to_string);
+            violation("dtype", name, format!("The dtype {dtype} is not one of the safetensors dtypes"));
+        }
+        let shape = tensor
+            .get("shape")
+            .and_then(Value::as_array)
+            .and_then(|shape| shape.iter().map(Value::as_u64).collect::<Option<Vec<u64>>>());
+        if shape.is_none() {
+            violation("shape", name, "The shape is not an array of non-negative integers".to_string());
+        }
+        let offsets = tensor.get("data_offsets").and_then(Value::as_array).and_then(|offsets| match offsets.as_slice() {
+            [begin, end] => Some((begin.as_u64()?, end.as_u64()?)),
+            _ => None,
+        });
+        let Some((begin, end)) = offsets.filter(|(begin, end)| end >= begin) else {
+            violation("data-offsets", name, "The data offsets are not [begin, end] with end >= begin".to_string());
+            continue;
+        };
+        let elements = shape.and_then(|shape| shape.iter().try_fold(1u64, |product, dim| product.checked_mul(*dim)));
+        if let (Some(bits), Some(elements)) = (bits, elements) {
+            let expected = elements.checked_mul(bits).filter(|bits| bits % 8 == 0).map(|bits| bits / 8);
+            if expected != Some(end - begin) {
+                let expected = expected.map_or_else(|| "a fraction of a byte".to_string(), |bytes| format!("{bytes} bytes"));
+                let detail = format!("The data is {} bytes, but its dtype and shape take {expected}", end - begin);
+                violation("byte-size", name, detail);
+            }
+        }
+        ranges.push((begin, end, name.as_str()));
+    }
+
+    // The data must be one buffer with the tensors back to back, as far as the offsets tell
+    ranges.sort_unstable();
+    let mut position = 0;
+    for (begin, end, name) in ranges {
+        if begin != position {
+            let what = if begin > position { "after a gap" } else { "overlapping the previous tensor" };
+            violation("contiguous", name, format!("The data starts at {begin} {what}, which ended at {position}"));
+        }
+        position = position.max(end);
+    }
+    let data_len = file_len.saturating_sub(8 + header.len);
+    if position != data_len {
+        let detail = format!("The tensors' data ends at {position}, but the data section is {data_len} bytes");
+        violation("data-length", "header", detail);
+    }
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::{ReadOptions, read_header};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_check() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let conforming = json!({
+            "__metadata__": {"format": "pt"},
+            "a": {"dtype": "F16", "shape": [2, 2], "data_offsets": [0, 8]},
+            "b": {"dtype": "F4", "shape": [4], "data_offsets": [8, 10]},
+        });
+        let path = crate::test_util::write_safetensors(temp_dir.path(), "a.safetensors", &conforming);
+        let mut bytes = std::fs::read(&path)?;
+        bytes.extend([0; 10]);
+        std::fs::write(&path, &bytes)?;
+        let header = read_header(&path, &ReadOptions::default()).await?;
+        assert_eq!(check(&header, header.stat.len()), []);
+
+        let broken = json!({
+            "__metadata__": {"epochs": 3},
+            "a": {"dtype": "F16", "shape": [2, 2], "data_offsets": [0, 6]},
+            "b": {"dtype": "Q4", "shape": [-1], "data_offsets": [8, 10]},
+            "c": {"dtype": "F32", "shape": [], "data_offsets": [4, 2]},
+        });
+        let path = crate::test_util::write_safetensors(temp_dir.path(), "b.safetensors", &broken);
+        let header = read_header(&path, &ReadOptions::default()).await?;
+        let violations: Vec<String> = check(&header, header.stat.len()).iter().map(ToString::to_string).collect();
+        assert_eq!(
+            violations,
+            [
+                "__metadata__.epochs: The value 3 is not a string [metadata]",
+                "a: The data is 6 bytes, but its dtype and shape take 8 bytes [byte-size]",
+                "b: The dtype \"Q4\" is not one of the safetensors dtypes [dtype]",
+                "b: The shape is not an array of non-negative integers [shape]",
+                "c: The data offsets are not [begin, end] with end >= begin [data-offsets]",
+                "b: The data starts at 8 after a gap, which ended at 6 [contiguous]",
+                "header: The tensors' data ends at 10, but the data section is 0 bytes [data-length]",
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_gaps() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let tensor = |begin: u64, end: u64| json!({"dtype": "U8", "shape": [end - begin], "data_offsets": [begin, end]});
+        let contiguous = json!({"a": tensor(0, 4), "b": tensor(4, 8), "c": tensor(8, 12)});
+        let path = crate::test_util::write_safetensors(temp_dir.path(), "a.safetensors", &contiguous);
+        assert_eq!(gaps(&read_header(&path, &ReadOptions::default()).await?), []);
+
+        // Padded to 8 bytes, with an overlap that isn't a gap
+        let padded = json!({"a": tensor(0, 4), "b": tensor(8, 12), "c": tensor(10, 14), "d": tensor(16, 20)});
+        let path = crate::test_util::write_safetensors(temp_dir.path(), "b.safetensors", &padded);
+        let header = read_header(&path, &ReadOptions::default()).await?;
+        let gaps: Vec<String> = gaps(&header).iter().map(ToString::to_string).collect();
+        assert_eq!(gaps, ["4 bytes at 4 before b", "2 bytes at 14 before d"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unknown_dtypes() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let header = json!({
+            "__metadata__": {"dtype": "Q4"},
+            "a": {"dtype": "F8_E8M0", "shape": [1], "data_offsets": [0, 1]},
+            "b": {"dtype": "Q4_K", "shape": [1], "data_offsets": [1, 2]},
+            "c": {"shape": [1], "data_offsets": [2, 3]},
+        });
+        let path = crate::test_util::write_safetensors(temp_dir.path(), "a.safetensors", &header);
+        let header = read_header(&path, &ReadOptions::default()).await?;
+        let unknown = [("b".to_string(), "Q4_K".to_string()), ("c".to_string(), "none".to_string())];
+        assert_eq!(unknown_dtypes(&header), unknown);
+        Ok(())
+    }
+}
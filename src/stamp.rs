@@ -0,0 +1,128 @@
+use crate::Scan;
+use crate::header;
+use crate::repair;
+use crate::rewrite::{self, Change};
+use anyhow::Context;
+use serde_json::{Map, Value};
+use std::path::{Path, PathBuf};
+
+/// Read the `--set-from` template: a JSON object of the metadata keys to set.
+pub fn load_template(path: &Path) -> anyhow::Result<Map<String, Value>> {
+    let text = crate::io::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    match serde_json::from_str(&text).with_context(|| format!("Failed to parse {}", path.display()))? {
+        Value::Object(template) => Ok(template),
+        _ => anyhow::bail!("{} isn't a JSON object of metadata keys", path.display()),
+    }
+}
+
+/// Merge the keys of `template` into the `__metadata__` of each file (`set`), such as the
+/// license, author and version of a release. With `dry_run` the files that would change are
+/// only listed.
+///
+/// The header is written anew, as compact JSON padded for the tensor data to stay aligned,
+/// and the tensor data is copied unchanged, as `repair` does, so a failure leaves the
+/// original.
+pub async fn run(scan: &Scan, files: Vec<PathBuf>, template: &Map<String, Value>, dry_run: bool) -> anyhow::Result<()> {
+    let words = rewrite::Words { would: "would set", did: "set", failed: "couldn't be updated" };
+    let set = |path: PathBuf| async move { stamp(&path, template, &scan.read_options, dry_run).await };
+    rewrite::run(scan, files, dry_run, &words, set).await
+}
+
+async fn stamp(
+    path: &Path,
+    template: &Map<String, Value>,
+    options: &header::ReadOptions,
+    dry_run: bool,
+) -> anyhow::Result<Change> {
+    let raw = header::read_raw_header(path, options).await?;
+    let (mut json, _) = repair::fix_header(&raw.bytes)?;
+    let Value::Object(header) = &mut json else { anyhow::bail!("Header is not a JSON object") };
+    let metadata = header.entry("__metadata__").or_insert_with(|| Value::Object(Map::new()));
+    let Value::Object(metadata) = metadata else { anyhow::bail!("__metadata__ is not a JSON object") };
+    let mut keys = Vec::new();
+    for (key, value) in template {
+        let value = Value::String(render(value, path));
+        if metadata.get(key) != Some(&value) {
+            metadata.insert(key.clone(), value);
+            keys.push(key.clone());
+        }
+    }
+    if keys.is_empty() {
+        return Ok(Change::Unchanged);
+    }
+    let set = Change::Changed(keys.join(", "));
+    if dry_run {
+        return Ok(set);
+    }
+    let mut bytes = serde_json::to_vec(&json)?;
+    bytes.resize(bytes.len().next_multiple_of(repair::ALIGNMENT), b' ');
+    let data_start = 8 + raw.bytes.len() as u64;
+    let data_len = raw.stat.len() - data_start;
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || repair::rewrite(&path, &bytes, data_start, data_len, &json)).await??;
+    Ok(set)
+}
+
+/// A template value for the file at `path`, as the string safetensors metadata holds: other
+/// values become compact JSON. `{stem}`, `{name}`, `{ext}` and `{dir}` are replaced by the
+/// file name without its extension, the whole file name, the extension and the name of the
+/// directory holding it; other braces are left as they are.
+fn render(value: &Value, path: &Path) -> String {
+    let text = match value {
+        Value::String(text) => text.clone(),
+        value => return value.to_string(),
+    };
+    let part = |part: Option<&std::ffi::OsStr>| part.unwrap_or_default().to_string_lossy().into_owned();
+    [
+        ("{stem}", part(path.file_stem())),
+        ("{name}", part(path.file_name())),
+        ("{ext}", part(path.extension())),
+        ("{dir}", part(path.parent().and_then(Path::file_name))),
+    ]
+    .iter()
+    .fold(text, |text, (placeholder, value)| text.replace(placeholder, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{scan, write_with_data as write};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_stamp() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let dir = temp_dir.path().join("release");
+        std::fs::create_dir(&dir)?;
+        let header = json!({
+            "__metadata__": {"license": "mit"},
+            "w": {"dtype": "U8", "shape": [4], "data_offsets": [0, 4]},
+        });
+        let path = dir.join("model.safetensors");
+        write(&path, &serde_json::to_vec(&header)?, b"data");
+        let template = json!({"license": "mit", "author": "me", "modelspec.title": "{stem} ({dir})", "version": 2});
+        let template = template.as_object().unwrap();
+        let out = temp_dir.path().join("set.txt");
+        let scan = scan(&["set", "--set-from", "t.json", "-o", out.to_str().unwrap(), dir.to_str().unwrap()]);
+
+        run(&scan, vec![path.clone()], template, true).await?;
+        assert!(header::read_header(&path, &scan.read_options).await?.metadata().get("author").is_none());
+        run(&scan, vec![path.clone()], template, false).await?;
+        run(&scan, vec![path.clone()], template, false).await?;
+        scan.output.finish()?;
+
+        let written = header::read_header(&path, &scan.read_options).await?;
+        assert_eq!(
+            written.metadata(),
+            json!({"license": "mit", "author": "me", "modelspec.title": "model (release)", "version": "2"})
+        );
+        assert_eq!((written.len + 8) % 8, 0);
+        assert_eq!(&std::fs::read(&path)?[8 + usize::try_from(written.len)?..], b"data");
+        let text = std::fs::read_to_string(out)?;
+        let lines: Vec<&str> = text.lines().collect();
+        let set = format!("{}: author, modelspec.title, version", path.display());
+        let unchanged = format!("unchanged  {}", path.display());
+        assert_eq!(lines, [format!("would set  {set}"), format!("set        {set}"), unchanged]);
+        Ok(())
+    }
+}
@@ -0,0 +1,140 @@
+use crate::Scan;
+use crate::formats::{self, Format};
+use crate::header::{self, Endian};
+use crate::humanize;
+use crate::summary::{Outcome, SkipReason};
+use anyhow::Context;
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// The input that reads a safetensors file from stdin, as in `curl ... | extract-metadata -`,
+/// and the path of its record.
+pub const PATH: &str = "-";
+
+/// Read the safetensors file piped to stdin and emit its record, reporting it as a file of
+/// its own.
+pub async fn run(scan: &Scan) {
+    let result = async { read(scan, &mut tokio::io::stdin(), stat()?).await }.await;
+    crate::report(Path::new(PATH), scan, result);
+}
+
+/// The `stat` of stdin, which for a pipe has no size or useful times.
+fn stat() -> std::io::Result<std::fs::Metadata> {
+    #[cfg(unix)]
+    let owned = std::os::fd::AsFd::as_fd(&std::io::stdin()).try_clone_to_owned()?;
+    #[cfg(windows)]
+    let owned = std::os::windows::io::AsHandle::as_handle(&std::io::stdin()).try_clone_to_owned()?;
+    std::fs::File::from(owned).metadata()
+}
+
+/// Read the header from `reader`, then the rest of the stream, which is hashed for `--hash`
+/// and counted for `--with-size`. Reading it to the end also keeps whatever writes it from
+/// failing on a closed pipe. The header then makes the same record a file's would, but for
+/// `--detect-nan-inf`, which seeks through the tensor data a stream has already gone past.
+async fn read(scan: &Scan, reader: &mut (impl AsyncRead + Unpin), stat: std::fs::Metadata) -> anyhow::Result<Outcome> {
+    let path = Path::new(PATH);
+    if scan.args.detect_nan_inf {
+        anyhow::bail!("--detect-nan-inf needs to seek through the tensor data, so it can't read stdin");
+    }
+    let raw = header::read_raw_stream(reader, stat, &scan.read_options).await?;
+    let mut hasher = scan.args.hash.then(Sha256::new);
+    if let Some(hasher) = &mut hasher {
+        let len = raw.bytes.len() as u64;
+        hasher.update(if raw.endian == Endian::Be { len.to_be_bytes() } else { len.to_le_bytes() });
+        hasher.update(&raw.bytes);
+    }
+    let header = header::parse_header(raw, &scan.read_options)?;
+    let mut size = 8 + header.len;
+    let mut buffer = vec![0; scan.args.hash_chunk_size.get()];
+    loop {
+        let read = reader.read(&mut buffer).await.context("Failed to read stdin")?;
+        if read == 0 {
+            break;
+        }
+        size += read as u64;
+        if let Some(hasher) = &mut hasher {
+            hasher.update(&buffer[..read]);
+        }
+    }
+    scan.bytes_started.fetch_add(size, std::sync::atomic::Ordering::Relaxed);
+
+    for warning in &header.warnings {
+        eprintln!("Warning: {PATH}: {warning}");
+    }
+    crate::check_spec(scan, &header, size)?;
+    let mut metadata = crate::header_metadata(scan, &header);
+    crate::check_double_encoding(scan, path, &mut metadata);
+    crate::transform_metadata(scan, path, &mut metadata);
+    let tensors = || formats::Safetensors.list_tensors(&header);
+    if !crate::matches_where(scan, &metadata) || !crate::matches_tensors(scan, path, tensors) {
+        return Ok(Outcome::Skipped(SkipReason::Filter));
+    }
+    if scan.args.assert_param_count.is_some() {
+        crate::check_parameter_count(scan, header.parameter_count(|name| crate::tensor_selected(scan, name)))?;
+    }
+    // A pipe's `stat` has no size, so the bytes counted take its place
+    let mut record = crate::build_record(path, metadata, &header.stat, scan)?;
+    if record.size.is_some() {
+        record.size = Some(size);
+        record.size_human = Some(humanize::bytes(size));
+    }
+    crate::add_header_fields(scan, &mut record, path, &formats::Safetensors, &header, size).await?;
+    record.sha256 = hasher.map(|hasher| format!("{:x}", hasher.finalize()));
+    crate::emit_combined(scan, record)?;
+    Ok(Outcome::Processed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::scan;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_read() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let header = json!({
+            "__metadata__": {"format": "pt"},
+            "w": {"dtype": "U8", "shape": [4], "data_offsets": [0, 4]},
+        });
+        let file = crate::test_util::write_safetensors(temp_dir.path(), "a.safetensors", &header);
+        let mut bytes = std::fs::read(&file)?;
+        bytes.extend(b"data");
+        let stat = std::fs::metadata(&file)?;
+        let out = temp_dir.path().join("out.json");
+        let scan = scan(&["-q", "--hash", "--with-size", "-o", out.to_str().unwrap(), PATH]);
+        assert_eq!(read(&scan, &mut bytes.as_slice(), stat.clone()).await?, Outcome::Processed);
+        scan.output.finish()?;
+        let records = crate::output::read_records(&out)?;
+        assert_eq!(records[0]["path"], PATH);
+        assert_eq!(records[0]["metadata"], json!({"format": "pt"}));
+        assert_eq!(records[0]["size"], bytes.len());
+        assert_eq!(records[0]["sha256"], format!("{:x}", Sha256::digest(&bytes)));
+
+        // The fields that come from the tensors, as for a file
+        let out_arg = out.to_str().unwrap();
+        let args = ["-q", "--strict-spec", "--tensors", "--count-tensors", "--classify", "-o", out_arg, PATH];
+        let scan = crate::test_util::scan(&args);
+        read(&scan, &mut bytes.as_slice(), stat.clone()).await?;
+        scan.output.finish()?;
+        let records = crate::output::read_records(&out)?;
+        assert_eq!(records[0]["tensors"], 1);
+        assert_eq!(records[0]["tensor_list"][0]["name"], "w");
+        assert!(records[0]["kind"].is_string());
+
+        // --strict-spec checks the header read from stdin too
+        let header = json!({"w": {"dtype": "U8", "shape": [4], "data_offsets": [0, 3]}});
+        let file = crate::test_util::write_safetensors(temp_dir.path(), "bad.safetensors", &header);
+        let mut bad = std::fs::read(&file)?;
+        bad.extend(b"dat");
+        let err = read(&scan, &mut bad.as_slice(), std::fs::metadata(&file)?).await.unwrap_err();
+        assert!(err.to_string().starts_with("Doesn't conform to the safetensors spec"), "{err}");
+
+        // Refused before the header is read, there being no file size to check it against
+        let scan = crate::test_util::scan(&["-q", "--max-header-bytes", "16", "-o", out.to_str().unwrap(), PATH]);
+        let err = read(&scan, &mut bytes.as_slice(), stat).await.unwrap_err();
+        assert!(err.to_string().ends_with("exceeds the maximum of 16 bytes"), "{err}");
+        Ok(())
+    }
+}
@@ -0,0 +1,99 @@
+use crate::Scan;
+use crate::header;
+use crate::humanize;
+use crate::repair;
+use crate::rewrite::{self, Change};
+use serde_json::{Map, Value};
+use std::path::{Path, PathBuf};
+
+/// Remove the metadata values of more than `max_bytes` from each file (`strip
+/// --strip-values-over`), such as a huge `ss_tag_frequency`, keeping the small keys. With
+/// `dry_run` the files that would change are only listed.
+///
+/// The header is written anew as `set` writes it, and the tensor data is copied unchanged, so
+/// a failure leaves the original.
+pub async fn run(scan: &Scan, files: Vec<PathBuf>, max_bytes: u64, dry_run: bool) -> anyhow::Result<()> {
+    let words = rewrite::Words { would: "would strip", did: "stripped", failed: "couldn't be stripped" };
+    let strip_file = |path: PathBuf| async move { strip(&path, max_bytes, &scan.read_options, dry_run).await };
+    rewrite::run(scan, files, dry_run, &words, strip_file).await
+}
+
+async fn strip(path: &Path, max_bytes: u64, options: &header::ReadOptions, dry_run: bool) -> anyhow::Result<Change> {
+    let raw = header::read_raw_header(path, options).await?;
+    let (mut json, _) = repair::fix_header(&raw.bytes)?;
+    let Value::Object(header) = &mut json else { anyhow::bail!("Header is not a JSON object") };
+    let keys = match header.get_mut("__metadata__") {
+        Some(Value::Object(metadata)) => strip_values(metadata, max_bytes),
+        Some(_) => anyhow::bail!("__metadata__ is not a JSON object"),
+        None => Vec::new(),
+    };
+    if keys.is_empty() {
+        return Ok(Change::Unchanged);
+    }
+    let mut bytes = serde_json::to_vec(&json)?;
+    bytes.resize(bytes.len().next_multiple_of(repair::ALIGNMENT), b' ');
+    let reclaimed = (raw.bytes.len() as u64).saturating_sub(bytes.len() as u64);
+    let stripped = Change::Changed(format!("{} ({} reclaimed)", keys.join(", "), humanize::bytes(reclaimed)));
+    if dry_run {
+        return Ok(stripped);
+    }
+    let data_start = 8 + raw.bytes.len() as u64;
+    let data_len = raw.stat.len() - data_start;
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || repair::rewrite(&path, &bytes, data_start, data_len, &json)).await??;
+    Ok(stripped)
+}
+
+/// Remove the values of `metadata` of more than `max_bytes`, counted as the bytes of a string
+/// or of the JSON of anything else, and return their keys.
+fn strip_values(metadata: &mut Map<String, Value>, max_bytes: u64) -> Vec<String> {
+    let size = |value: &Value| match value {
+        Value::String(text) => text.len(),
+        value => value.to_string().len(),
+    };
+    let keys: Vec<String> =
+        metadata.iter().filter(|(_, value)| size(value) as u64 > max_bytes).map(|(key, _)| key.clone()).collect();
+    for key in &keys {
+        metadata.remove(key);
+    }
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{scan, write_with_data};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_strip() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let tags = "x".repeat(4000);
+        let header = json!({
+            "__metadata__": {"ss_tag_frequency": tags, "ss_output_name": "lora", "format": "pt"},
+            "w": {"dtype": "U8", "shape": [4], "data_offsets": [0, 4]},
+        });
+        let path = temp_dir.path().join("model.safetensors");
+        write_with_data(&path, &serde_json::to_vec(&header)?, b"data");
+        let out = temp_dir.path().join("strip.txt");
+        let scan = scan(&["strip", "--strip-values-over", "100", "-o", out.to_str().unwrap(), path.to_str().unwrap()]);
+        let size = std::fs::metadata(&path)?.len();
+
+        run(&scan, vec![path.clone()], 100, true).await?;
+        assert_eq!(std::fs::metadata(&path)?.len(), size);
+        run(&scan, vec![path.clone()], 100, false).await?;
+        run(&scan, vec![path.clone()], 100, false).await?;
+        scan.output.finish()?;
+
+        let written = header::read_header(&path, &scan.read_options).await?;
+        assert_eq!(written.metadata(), json!({"ss_output_name": "lora", "format": "pt"}));
+        assert_eq!(&std::fs::read(&path)?[8 + usize::try_from(written.len)?..], b"data");
+        let reclaimed = humanize::bytes(size - std::fs::metadata(&path)?.len());
+        let text = std::fs::read_to_string(out)?;
+        let lines: Vec<&str> = text.lines().collect();
+        let stripped = format!("{}: ss_tag_frequency ({reclaimed} reclaimed)", path.display());
+        let unchanged = format!("unchanged    {}", path.display());
+        assert_eq!(lines, [format!("would strip  {stripped}"), format!("stripped     {stripped}"), unchanged]);
+        Ok(())
+    }
+}
@@ -0,0 +1,315 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Why a file was passed over without being processed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SkipReason {
+    /// Found by the directory walk but doesn't have a wanted extension.
+    Extension,
+    /// `--sniff` couldn't recognize its content.
+    Format,
+    /// Already in the output file being appended to (`--dedupe-paths`).
+    Existing,
+    /// Its metadata doesn't satisfy the `--where` conditions.
+    Filter,
+    /// Still being written (`--partial-ok`).
+    InProgress,
+    /// Not started before Ctrl-C (`--abort-on-signal-second`) or after a failed write
+    /// without `--continue-on-write-error`.
+    Interrupted,
+    /// Not started once `--max-total-bytes` was spent.
+    Budget,
+    /// Past the `--limit-per-directory` files of its directory.
+    DirectoryLimit,
+    /// The same as in the last `--incremental` run, whose record is kept.
+    Unchanged,
+}
+
+impl SkipReason {
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Extension => "extension",
+            Self::Format => "format",
+            Self::Existing => "existing",
+            Self::Filter => "filter",
+            Self::InProgress => "in-progress",
+            Self::Interrupted => "interrupted",
+            Self::Budget => "budget",
+            Self::DirectoryLimit => "directory-limit",
+            Self::Unchanged => "unchanged",
+        }
+    }
+}
+
+/// What became of a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Processed,
+    Failed,
+    Skipped(SkipReason),
+}
+
+/// The machine-readable account of a run that `--stats-out` writes.
+#[derive(Debug, serde::Serialize)]
+pub struct Stats {
+    pub processed: usize,
+    pub skipped: BTreeMap<SkipReason, usize>,
+    pub errors: Errors,
+    /// The tensors of the files emitted, if `--count-tensors` counted them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tensors: Option<usize>,
+    /// The sizes of the files started, added up.
+    pub bytes: u64,
+    pub duration_secs: f64,
+    /// How many files were read at once at most (`--max-open-files`).
+    pub jobs: usize,
+    /// What stopped the scan, if it didn't finish.
+    pub error: Option<String>,
+}
+
+/// The problems of a run by kind, as the summary line counts them.
+#[derive(Debug, serde::Serialize)]
+pub struct Errors {
+    /// Files that couldn't be read or parsed, including the unreadable ones.
+    pub failed: usize,
+    /// The failed files that couldn't be opened at all.
+    pub unreadable: usize,
+    pub walk: usize,
+    pub write: usize,
+}
+
+/// Counters for the summary printed at the end of a run, updated concurrently by the per-file tasks.
+#[derive(Debug, Default)]
+pub struct Summary {
+    processed: AtomicUsize,
+    failed: AtomicUsize,
+    /// The failed files that couldn't be opened, with the kind of OS error.
+    unreadable: Mutex<Vec<(PathBuf, io::ErrorKind)>>,
+    /// Entries the directory walk couldn't read (`--walk-errors warn`).
+    walk_errors: AtomicUsize,
+    /// Sidecars, signatures and thumbnails that couldn't be written.
+    write_errors: AtomicUsize,
+    skipped: Mutex<BTreeMap<SkipReason, usize>>,
+    /// The files each directory had past `--limit-per-directory`.
+    directory_limited: Mutex<BTreeMap<PathBuf, usize>>,
+    /// The tensors of the files emitted, once `--count-tensors` counted any.
+    tensors: Mutex<Option<usize>>,
+}
+
+impl Summary {
+    pub fn record(&self, outcome: Outcome) {
+        match outcome {
+            Outcome::Processed => {
+                self.processed.fetch_add(1, Ordering::Relaxed);
+            }
+            Outcome::Failed => {
+                self.failed.fetch_add(1, Ordering::Relaxed);
+            }
+            Outcome::Skipped(reason) => {
+                let mut skipped = self.skipped.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+                *skipped.entry(reason).or_default() += 1;
+            }
+        }
+    }
+
+    /// Note that a file recorded as failed couldn't be opened.
+    pub fn record_unreadable(&self, path: &Path, kind: io::ErrorKind) {
+        self.unreadable.lock().unwrap_or_else(std::sync::PoisonError::into_inner).push((path.to_path_buf(), kind));
+    }
+
+    /// Note that a file of `dir` was skipped for being past `--limit-per-directory`.
+    pub fn record_directory_limit(&self, dir: &Path) {
+        self.record(Outcome::Skipped(SkipReason::DirectoryLimit));
+        let mut limited = self.directory_limited.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        *limited.entry(dir.to_path_buf()).or_default() += 1;
+    }
+
+    pub fn record_tensors(&self, count: usize) {
+        *self.tensors.lock().unwrap_or_else(std::sync::PoisonError::into_inner).get_or_insert(0) += count;
+    }
+
+    pub fn record_walk_error(&self) {
+        self.walk_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_write_error(&self) {
+        self.write_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn processed(&self) -> usize {
+        self.processed.load(Ordering::Relaxed)
+    }
+
+    pub fn failed(&self) -> usize {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+    pub fn skipped(&self, reason: SkipReason) -> usize {
+        let skipped = self.skipped.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        skipped.get(&reason).copied().unwrap_or_default()
+    }
+
+    /// The number of files with an outcome so far, whether processed, failed or skipped.
+    pub fn total(&self) -> usize {
+        let skipped: usize = self.skipped.lock().unwrap_or_else(std::sync::PoisonError::into_inner).values().sum();
+        self.processed() + self.failed() + skipped
+    }
+
+    /// One line such as `Processed 1234 files, 3 failed, 12 skipped in 4.2s`; `verbose` adds
+    /// the number skipped for each reason. Unreadable files, directory errors and write errors
+    /// are mentioned if there were any, the total number of tensors if they were counted.
+    pub fn line(&self, elapsed: Duration, verbose: bool) -> String {
+        let processed = self.processed.load(Ordering::Relaxed);
+        let failed = self.failed.load(Ordering::Relaxed);
+        let skipped = self.skipped.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        let total_skipped: usize = skipped.values().sum();
+
+        let files = if processed == 1 { "file" } else { "files" };
+        let mut line = format!("Processed {processed} {files}, {failed} failed");
+        let unreadable = self.unreadable.lock().unwrap_or_else(std::sync::PoisonError::into_inner).len();
+        if unreadable > 0 {
+            let _ = write!(line, " ({unreadable} unreadable)");
+        }
+        let _ = write!(line, ", {total_skipped} skipped");
+        if verbose && total_skipped > 0 {
+            let reasons: Vec<String> =
+                skipped.iter().map(|(reason, count)| format!("{}: {count}", reason.label())).collect();
+            let _ = write!(line, " ({})", reasons.join(", "));
+        }
+        let walk_errors = self.walk_errors.load(Ordering::Relaxed);
+        if walk_errors > 0 {
+            let errors = if walk_errors == 1 { "error" } else { "errors" };
+            let _ = write!(line, ", {walk_errors} directory {errors}");
+        }
+        let write_errors = self.write_errors.load(Ordering::Relaxed);
+        if write_errors > 0 {
+            let errors = if write_errors == 1 { "error" } else { "errors" };
+            let _ = write!(line, ", {write_errors} write {errors}");
+        }
+        if let Some(tensors) = *self.tensors.lock().unwrap_or_else(std::sync::PoisonError::into_inner) {
+            let _ = write!(line, ", {tensors} tensors");
+        }
+        let _ = write!(line, " in {:.1}s", elapsed.as_secs_f64());
+        line
+    }
+
+    /// The counts of the summary line as [`Stats`], with what only the caller knows.
+    pub fn stats(&self, elapsed: Duration, bytes: u64, jobs: usize, error: Option<String>) -> Stats {
+        Stats {
+            processed: self.processed(),
+            skipped: self.skipped.lock().unwrap_or_else(std::sync::PoisonError::into_inner).clone(),
+            errors: Errors {
+                failed: self.failed(),
+                unreadable: self.unreadable.lock().unwrap_or_else(std::sync::PoisonError::into_inner).len(),
+                walk: self.walk_errors.load(Ordering::Relaxed),
+                write: self.write_errors.load(Ordering::Relaxed),
+            },
+            tensors: *self.tensors.lock().unwrap_or_else(std::sync::PoisonError::into_inner),
+            bytes,
+            duration_secs: elapsed.as_secs_f64(),
+            jobs,
+            error,
+        }
+    }
+
+    /// The files that couldn't be opened, one per line with the kind of OS error, sorted by
+    /// path (`--report-unreadable`). Empty if there were none.
+    pub fn unreadable_report(&self) -> String {
+        let mut unreadable = self.unreadable.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        unreadable.sort();
+        let mut report = String::new();
+        if !unreadable.is_empty() {
+            let _ = writeln!(report, "Unreadable files:");
+        }
+        for (path, kind) in unreadable.iter() {
+            let _ = writeln!(report, "  {}: {kind:?}", path.display());
+        }
+        report
+    }
+
+    /// The directories that had files past `--limit-per-directory`, one per line with the
+    /// number skipped, sorted by path. Empty if there were none.
+    pub fn directory_limit_report(&self) -> String {
+        let limited = self.directory_limited.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        let mut report = String::new();
+        if !limited.is_empty() {
+            let _ = writeln!(report, "Skipped past --limit-per-directory:");
+        }
+        for (dir, skipped) in limited.iter() {
+            let _ = writeln!(report, "  {}: {skipped}", dir.display());
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary_line() {
+        let summary = Summary::default();
+        summary.record(Outcome::Processed);
+        summary.record(Outcome::Processed);
+        summary.record(Outcome::Failed);
+        summary.record(Outcome::Skipped(SkipReason::Format));
+        summary.record(Outcome::Skipped(SkipReason::Extension));
+        summary.record(Outcome::Skipped(SkipReason::Extension));
+
+        let elapsed = Duration::from_millis(4200);
+        assert_eq!(summary.line(elapsed, false), "Processed 2 files, 1 failed, 3 skipped in 4.2s");
+        assert_eq!(
+            summary.line(elapsed, true),
+            "Processed 2 files, 1 failed, 3 skipped (extension: 2, format: 1) in 4.2s"
+        );
+        summary.record_tensors(0);
+        assert_eq!(summary.line(elapsed, false), "Processed 2 files, 1 failed, 3 skipped, 0 tensors in 4.2s");
+        summary.record_tensors(1130);
+        assert_eq!(summary.line(elapsed, false), "Processed 2 files, 1 failed, 3 skipped, 1130 tensors in 4.2s");
+    }
+
+    #[test]
+    fn test_unreadable() {
+        let summary = Summary::default();
+        assert_eq!(summary.unreadable_report(), "");
+        for (path, kind) in [("b", io::ErrorKind::NotFound), ("a", io::ErrorKind::PermissionDenied)] {
+            summary.record(Outcome::Failed);
+            summary.record_unreadable(Path::new(path), kind);
+        }
+        summary.record(Outcome::Failed);
+
+        let elapsed = Duration::from_millis(100);
+        assert_eq!(summary.line(elapsed, false), "Processed 0 files, 3 failed (2 unreadable), 0 skipped in 0.1s");
+        assert_eq!(summary.unreadable_report(), "Unreadable files:\n  a: PermissionDenied\n  b: NotFound\n");
+    }
+
+    #[test]
+    fn test_stats() {
+        let summary = Summary::default();
+        summary.record(Outcome::Processed);
+        summary.record(Outcome::Failed);
+        summary.record_unreadable(Path::new("a"), io::ErrorKind::NotFound);
+        summary.record(Outcome::Skipped(SkipReason::Filter));
+        summary.record_walk_error();
+
+        let stats = summary.stats(Duration::from_millis(1500), 4096, 8, None);
+        assert_eq!(
+            serde_json::to_value(&stats).unwrap(),
+            serde_json::json!({
+                "processed": 1,
+                "skipped": {"filter": 1},
+                "errors": {"failed": 1, "unreadable": 1, "walk": 1, "write": 0},
+                "bytes": 4096,
+                "duration_secs": 1.5,
+                "jobs": 8,
+                "error": null,
+            })
+        );
+    }
+}
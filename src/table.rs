@@ -0,0 +1,71 @@
+use std::fmt::Write;
+
+/// Render rows as left-aligned columns separated by two spaces, with `headers` as the first line.
+pub fn render(headers: &[String], rows: &[Vec<String>]) -> String {
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.chars().count()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let mut out = String::new();
+    for row in std::iter::once(headers).chain(rows.iter().map(Vec::as_slice)) {
+        let mut line = String::new();
+        for (i, (cell, width)) in row.iter().zip(&widths).enumerate() {
+            if i > 0 {
+                line.push_str("  ");
+            }
+            let _ = write!(line, "{cell:width$}");
+        }
+        out.push_str(line.trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+/// Render rows as CSV, quoting fields that contain separators, quotes or newlines.
+pub fn render_csv(headers: &[String], rows: &[Vec<String>]) -> String {
+    let mut out = String::new();
+    for row in std::iter::once(headers).chain(rows.iter().map(Vec::as_slice)) {
+        let fields: Vec<String> = row.iter().map(|field| csv_field(field)).collect();
+        out.push_str(&fields.join(","));
+        out.push('\n');
+    }
+    out
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_render_aligns_columns() {
+        let rows = vec![strings(&["a.safetensors", "✓"]), strings(&["b", "✗"])];
+        assert_eq!(
+            render(&strings(&["file", "ss_network_dim"]), &rows),
+            "file           ss_network_dim\na.safetensors  ✓\nb              ✗\n"
+        );
+    }
+
+    #[test]
+    fn test_render_csv_quotes() {
+        let rows = vec![strings(&["a,b.safetensors", "say \"hi\""])];
+        assert_eq!(
+            render_csv(&strings(&["file", "value"]), &rows),
+            "file,value\n\"a,b.safetensors\",\"say \"\"hi\"\"\"\n"
+        );
+    }
+}
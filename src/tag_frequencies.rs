@@ -0,0 +1,122 @@
+use crate::table;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+
+/// The metadata key sd-scripts keeps the tag counts of each dataset directory in.
+const KEY: &str = "ss_tag_frequency";
+
+/// Sums the tag counts of `ss_tag_frequency` across the scanned files, for
+/// `--aggregate-tag-frequencies`: the tags a collection of models was trained on most.
+#[derive(Debug)]
+pub struct TagFrequencies {
+    top: usize,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// For each tag, its total count and the number of files that have it.
+    tags: HashMap<String, (u64, usize)>,
+    /// Files with an `ss_tag_frequency` that could be read.
+    files: usize,
+    /// Files whose `ss_tag_frequency` isn't a map of directories to tag counts.
+    malformed: usize,
+}
+
+impl TagFrequencies {
+    /// Tally for a report of the `top` most common tags.
+    pub fn new(top: usize) -> Self {
+        Self { top, state: Mutex::default() }
+    }
+
+    /// Add the tag counts of one file's metadata, in which `ss_tag_frequency` maps each dataset
+    /// directory to a map of tags to counts, usually as a JSON string. Tags are trimmed, counts
+    /// may be numbers or strings of digits. A file without the key is left out; one where it
+    /// isn't such a map is counted as malformed, and counts that aren't numbers are skipped.
+    pub fn add(&self, metadata: &Value) {
+        let Some(frequencies) = metadata.get(KEY) else { return };
+        let decoded;
+        let frequencies = match frequencies {
+            Value::String(text) => match serde_json::from_str(text) {
+                Ok(value) => {
+                    decoded = value;
+                    &decoded
+                }
+                Err(_) => &Value::Null,
+            },
+            value => value,
+        };
+        let mut state = self.state.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        let Some(directories) = frequencies.as_object() else {
+            state.malformed += 1;
+            return;
+        };
+        let mut counts: HashMap<&str, u64> = HashMap::new();
+        for tags in directories.values() {
+            let Some(tags) = tags.as_object() else { continue };
+            for (tag, count) in tags {
+                let count = match count {
+                    Value::Number(count) => count.as_u64(),
+                    Value::String(count) => count.trim().parse().ok(),
+                    _ => None,
+                };
+                if let Some(count) = count.filter(|_| !tag.trim().is_empty()) {
+                    *counts.entry(tag.trim()).or_default() += count;
+                }
+            }
+        }
+        state.files += 1;
+        for (tag, count) in counts {
+            let entry = state.tags.entry(tag.to_string()).or_default();
+            entry.0 += count;
+            entry.1 += 1;
+        }
+    }
+
+    /// The most common tags by their total count, with the number of files using each, and a
+    /// line with how many files and tags were counted.
+    pub fn report(&self) -> String {
+        let state = self.state.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        let mut tags: Vec<(&String, &(u64, usize))> = state.tags.iter().collect();
+        tags.sort_by(|a, b| b.1.0.cmp(&a.1.0).then_with(|| a.0.cmp(b.0)));
+        let rows: Vec<Vec<String>> = tags
+            .into_iter()
+            .take(self.top)
+            .map(|(tag, (count, files))| vec![count.to_string(), files.to_string(), tag.clone()])
+            .collect();
+        let mut report = table::render(&["count".to_string(), "files".to_string(), "tag".to_string()], &rows);
+        let _ = write!(report, "{} tags in {} files", state.tags.len(), state.files);
+        if state.malformed > 0 {
+            let _ = write!(report, ", {} files with a malformed {KEY}", state.malformed);
+        }
+        report.push('\n');
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_report() {
+        let frequencies = TagFrequencies::new(3);
+        frequencies.add(&json!({
+            "ss_tag_frequency": {"10_cat": {"cat": 10, " solo": 4}, "5_dog": {"cat": 2, "dog": 5}},
+        }));
+        frequencies.add(&json!({"ss_tag_frequency": r#"{"1_x": {"cat": "3", "solo": 4, "bad": "many"}}"#}));
+        frequencies.add(&json!({"ss_tag_frequency": "not json"}));
+        frequencies.add(&json!({"ss_output_name": "no tags"}));
+        assert_eq!(
+            frequencies.report(),
+            "count  files  tag\n\
+             15     2      cat\n\
+             8      2      solo\n\
+             5      1      dog\n\
+             3 tags in 2 files, 1 files with a malformed ss_tag_frequency\n"
+        );
+    }
+}
@@ -0,0 +1,125 @@
+use serde_json::Value;
+
+/// A line format for `--output-template`, such as `{path}\t{size}\t{metadata.modelspec.title}`.
+///
+/// `{FIELD}` is replaced by a record field, with dots reaching into nested objects. `{{` and
+/// `}}` stand for literal braces, `\t`, `\n` and `\\` for a tab, a newline and a backslash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    segments: Vec<Segment>,
+    /// Rendered in place of fields the record doesn't have.
+    pub missing: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Field(String),
+}
+
+impl Template {
+    /// Render the template for a record, which is given as JSON. Strings are inserted as they
+    /// are, other values as compact JSON; absent and null fields become `missing`.
+    pub fn render(&self, record: &Value) -> String {
+        let mut line = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(text) => line.push_str(text),
+                Segment::Field(field) => match lookup(record, field) {
+                    None | Some(Value::Null) => line.push_str(&self.missing),
+                    Some(Value::String(s)) => line.push_str(s),
+                    Some(value) => line.push_str(&value.to_string()),
+                },
+            }
+        }
+        line
+    }
+}
+
+/// Find `field` in `value`, where dots separate the keys of nested objects. Metadata keys
+/// often contain dots themselves (`modelspec.title`), so at each level the longest key that
+/// the remaining path starts with wins.
+fn lookup<'a>(value: &'a Value, field: &str) -> Option<&'a Value> {
+    let object = value.as_object()?;
+    if let Some(value) = object.get(field) {
+        return Some(value);
+    }
+    field
+        .rmatch_indices('.')
+        .find_map(|(i, _)| object.get(&field[..i]).and_then(|value| lookup(value, &field[i + 1..])))
+}
+
+/// Parse an `--output-template` argument.
+pub fn parse_template_arg(arg: &str) -> Result<Template, String> {
+    let mut segments = Vec::new();
+    let mut literal = String::new();
+    let mut chars = arg.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('t') => literal.push('\t'),
+                Some('n') => literal.push('\n'),
+                Some('\\') => literal.push('\\'),
+                Some(other) => return Err(format!("unknown escape `\\{other}`")),
+                None => return Err("trailing backslash".to_string()),
+            },
+            '{' if chars.as_str().starts_with('{') => {
+                chars.next();
+                literal.push('{');
+            }
+            '}' if chars.as_str().starts_with('}') => {
+                chars.next();
+                literal.push('}');
+            }
+            '{' => {
+                let (field, rest) = chars.as_str().split_once('}').ok_or("unclosed `{`")?;
+                if field.is_empty() || field.contains('{') {
+                    return Err(format!("invalid field `{{{field}}}`"));
+                }
+                if !literal.is_empty() {
+                    segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                }
+                segments.push(Segment::Field(field.to_string()));
+                chars = rest.chars();
+            }
+            '}' => return Err("unmatched `}`, write `}}` for a literal brace".to_string()),
+            c => literal.push(c),
+        }
+    }
+    if !literal.is_empty() {
+        segments.push(Segment::Literal(literal));
+    }
+    Ok(Template { segments, missing: String::new() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_render() {
+        let record = json!({
+            "path": "a.safetensors",
+            "size": 1024,
+            "metadata": {"modelspec.title": "Foo", "ss_tag_frequency": {"set": {"1girl": 3}}},
+        });
+        let template = parse_template_arg(r"{path}\t{size}\t{metadata.modelspec.title}").unwrap();
+        assert_eq!(template.render(&record), "a.safetensors\t1024\tFoo");
+
+        let template = parse_template_arg(r"{{{metadata.ss_tag_frequency.set}}}\n").unwrap();
+        assert_eq!(template.render(&record), "{{\"1girl\":3}}\n");
+
+        let mut template = parse_template_arg("{sha256}|{metadata.nope}").unwrap();
+        assert_eq!(template.render(&record), "|");
+        template.missing = "-".to_string();
+        assert_eq!(template.render(&record), "-|-");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for bad in ["{path", "path}", "{}", r"\x", "{a{b}", "\\"] {
+            assert!(parse_template_arg(bad).is_err(), "{bad}");
+        }
+    }
+}
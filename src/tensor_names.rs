@@ -0,0 +1,74 @@
+use anyhow::Context;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// The canonical tensor names of `--tensor-name-map`, read from a JSON object mapping names
+/// to canonical names. A key ending in `*` is a prefix rule: `"model.layers.*": "blocks.*"`
+/// renames `model.layers.0.attn` to `blocks.0.attn`. An exact name wins over the prefixes,
+/// and of the prefixes the longest that matches.
+#[derive(Debug, Default)]
+pub struct NameMap {
+    exact: HashMap<String, String>,
+    /// Longest first.
+    prefixes: Vec<(String, String)>,
+}
+
+impl NameMap {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = crate::io::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let json = serde_json::from_str(&text).with_context(|| format!("Failed to parse {}", path.display()))?;
+        Self::from_json(json).with_context(|| format!("Invalid tensor name map {}", path.display()))
+    }
+
+    fn from_json(json: Value) -> anyhow::Result<Self> {
+        let Value::Object(rules) = json else { anyhow::bail!("Expected a JSON object of names") };
+        let mut map = Self::default();
+        for (name, canonical) in rules {
+            let Value::String(canonical) = canonical else { anyhow::bail!("The name for `{name}` isn't a string") };
+            match name.strip_suffix('*') {
+                Some(prefix) => {
+                    let canonical = canonical.strip_suffix('*').unwrap_or(&canonical).to_string();
+                    map.prefixes.push((prefix.to_string(), canonical));
+                }
+                None => {
+                    map.exact.insert(name, canonical);
+                }
+            }
+        }
+        map.prefixes.sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+        Ok(map)
+    }
+
+    /// The canonical name of the tensor `name`, or `None` if no rule matches it.
+    pub fn canonical(&self, name: &str) -> Option<String> {
+        if let Some(canonical) = self.exact.get(name) {
+            return Some(canonical.clone());
+        }
+        self.prefixes
+            .iter()
+            .find_map(|(prefix, canonical)| Some(format!("{canonical}{}", name.strip_prefix(prefix.as_str())?)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_canonical() -> anyhow::Result<()> {
+        let map = NameMap::from_json(json!({
+            "model.*": "transformer.*",
+            "model.layers.*": "blocks.*",
+            "model.layers.0.special": "first.special",
+        }))?;
+        assert_eq!(map.canonical("model.layers.3.attn.weight").as_deref(), Some("blocks.3.attn.weight"));
+        assert_eq!(map.canonical("model.norm.weight").as_deref(), Some("transformer.norm.weight"));
+        assert_eq!(map.canonical("model.layers.0.special").as_deref(), Some("first.special"));
+        assert_eq!(map.canonical("lm_head.weight"), None);
+
+        assert!(NameMap::from_json(json!({"a": 1})).is_err());
+        Ok(())
+    }
+}
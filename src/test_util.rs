@@ -0,0 +1,29 @@
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+/// Write a safetensors file at `dir/name` with the given header and no tensor data.
+pub fn write_safetensors(dir: &Path, name: &str, header: &Value) -> PathBuf {
+    let path = dir.join(name);
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    let header = serde_json::to_vec(header).unwrap();
+    let mut bytes = (header.len() as u64).to_le_bytes().to_vec();
+    bytes.extend(header);
+    std::fs::write(&path, bytes).unwrap();
+    path
+}
+
+/// Write a safetensors file at `path` with the given header bytes, as they are, followed by
+/// `data`.
+pub fn write_with_data(path: &Path, header: &[u8], data: &[u8]) {
+    let mut bytes = (header.len() as u64).to_le_bytes().to_vec();
+    bytes.extend(header);
+    bytes.extend(data);
+    std::fs::write(path, bytes).unwrap();
+}
+
+/// Build a [`crate::Scan`] from command-line arguments, without the program name.
+pub fn scan(args: &[&str]) -> crate::Scan {
+    use clap::Parser;
+    let args = crate::cli::Args::parse_from(std::iter::once("extract-metadata").chain(args.iter().copied()));
+    crate::Scan::new(args).unwrap()
+}
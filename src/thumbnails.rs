@@ -0,0 +1,122 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD_PAD_INDIFFERENT;
+use serde_json::Value;
+use std::path::Path;
+
+/// The image formats recognized by their first bytes, with the extension they are written with.
+const IMAGE_MAGIC: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "png"),
+    (b"\xff\xd8\xff", "jpg"),
+    (b"GIF87a", "gif"),
+    (b"GIF89a", "gif"),
+];
+
+/// A preview image embedded in a metadata value (`--extract-thumbnails`).
+#[derive(Debug, PartialEq, Eq)]
+pub struct Thumbnail {
+    pub key: String,
+    pub extension: &'static str,
+    pub bytes: Vec<u8>,
+}
+
+/// The images embedded in the top-level values of `metadata`, and the keys whose value looks
+/// like one but doesn't decode to a known image format.
+///
+/// A value is taken for an image if it is a `data:image/...;base64,` URI, whatever its key,
+/// or if its key ends in `thumbnail` or `preview`, such as `modelspec.thumbnail`, in which case
+/// it may also be bare base64.
+pub fn find(metadata: &Value) -> (Vec<Thumbnail>, Vec<String>) {
+    let (mut found, mut invalid) = (Vec::new(), Vec::new());
+    for (key, value) in metadata.as_object().into_iter().flatten() {
+        let Value::String(value) = value else { continue };
+        let data = if let Some(uri) = value.strip_prefix("data:image/") {
+            uri.split_once(";base64,").map(|(_, data)| data)
+        } else if is_image_key(key) {
+            Some(value.as_str())
+        } else {
+            continue;
+        };
+        match data.and_then(decode) {
+            Some((bytes, extension)) => found.push(Thumbnail { key: key.clone(), extension, bytes }),
+            None => invalid.push(key.clone()),
+        }
+    }
+    (found, invalid)
+}
+
+fn is_image_key(key: &str) -> bool {
+    let key = key.to_ascii_lowercase();
+    key.ends_with("thumbnail") || key.ends_with("preview")
+}
+
+/// The bytes of base64 `data` and their image extension, if it decodes to an image.
+fn decode(data: &str) -> Option<(Vec<u8>, &'static str)> {
+    let bytes = decode_base64(data)?;
+    let is_webp = bytes.len() >= 12 && bytes.starts_with(b"RIFF") && &bytes[8..12] == b"WEBP";
+    let extension = if is_webp {
+        "webp"
+    } else {
+        IMAGE_MAGIC.iter().find(|(magic, _)| bytes.starts_with(magic))?.1
+    };
+    Some((bytes, extension))
+}
+
+/// The bytes of a base64 value, bare or as a `data:` URI such as `data:application/json;base64,...`,
+/// for `--extract-key-binary` and thumbnails. Whitespace, as left by line-wrapped encoders, is
+/// ignored.
+pub fn decode_base64(value: &str) -> Option<Vec<u8>> {
+    let data = match value.strip_prefix("data:") {
+        Some(uri) => uri.split_once(";base64,")?.1,
+        None => value,
+    };
+    let data: String = data.chars().filter(|c| !c.is_ascii_whitespace()).collect();
+    STANDARD_PAD_INDIFFERENT.decode(data).ok()
+}
+
+/// The file name a thumbnail of `model` is written under, such as
+/// `model.modelspec.thumbnail.png`. Characters other than letters, digits, `.`, `-` and `_`
+/// in the key are replaced, so it stays one plain file name.
+pub fn file_name(model: &Path, thumbnail: &Thumbnail) -> String {
+    let stem = model.file_stem().unwrap_or_default().to_string_lossy();
+    let key: String = thumbnail
+        .key
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') { c } else { '_' })
+        .collect();
+    format!("{stem}.{key}.{}", thumbnail.extension)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_find() {
+        let png = STANDARD_PAD_INDIFFERENT.encode(b"\x89PNG\r\n\x1a\nrest");
+        let jpeg = STANDARD_PAD_INDIFFERENT.encode(b"\xff\xd8\xff\xe0");
+        let metadata = json!({
+            "modelspec.thumbnail": format!("data:image/png;base64,{png}"),
+            "cover preview": jpeg,
+            "ss_sample": format!("data:image/jpeg;base64,{jpeg}"),
+            "broken_thumbnail": STANDARD_PAD_INDIFFERENT.encode(b"not an image"),
+            "other_preview": "data:image/png,raw",
+            "title": png,
+        });
+
+        let (found, invalid) = find(&metadata);
+        let keys: Vec<(&str, &str)> = found.iter().map(|t| (t.key.as_str(), t.extension)).collect();
+        assert_eq!(keys, [("cover preview", "jpg"), ("modelspec.thumbnail", "png"), ("ss_sample", "jpg")]);
+        assert!(found[1].bytes.starts_with(b"\x89PNG"));
+        assert_eq!(invalid, ["broken_thumbnail", "other_preview"]);
+        assert_eq!(file_name(Path::new("/models/a.safetensors"), &found[0]), "a.cover_preview.jpg");
+    }
+
+    #[test]
+    fn test_decode_base64() {
+        assert_eq!(decode_base64("eyJhIjox\nfQ"), Some(b"{\"a\":1}".to_vec()));
+        assert_eq!(decode_base64("data:application/json;base64,eyJhIjoxfQ=="), Some(b"{\"a\":1}".to_vec()));
+        assert_eq!(decode_base64("data:text/plain,eyJhIjoxfQ"), None);
+        assert_eq!(decode_base64("not base64!"), None);
+    }
+}
@@ -0,0 +1,76 @@
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, ReadBuf};
+
+/// A reader counting the read calls made on it and the bytes they returned, for
+/// `--trace-reads`. The counts are printed to stderr when it is dropped, so a file whose read
+/// failed halfway is reported too.
+#[derive(Debug)]
+pub struct Traced<R> {
+    inner: R,
+    /// The file and its size, if the reads are traced.
+    file: Option<(PathBuf, u64)>,
+    calls: u64,
+    bytes: u64,
+}
+
+impl<R> Traced<R> {
+    /// Wrap `inner`, which reads the `size` bytes of `path`, reporting on it if `trace` is set.
+    pub fn new(inner: R, path: &Path, size: u64, trace: bool) -> Self {
+        Self { inner, file: trace.then(|| (path.to_path_buf(), size)), calls: 0, bytes: 0 }
+    }
+
+    fn count(&mut self, bytes: usize) {
+        self.calls += 1;
+        self.bytes += bytes as u64;
+    }
+}
+
+impl<R> Drop for Traced<R> {
+    fn drop(&mut self) {
+        if let Some((path, size)) = &self.file {
+            let (calls, bytes) = (self.calls, self.bytes);
+            eprintln!("{}: read {bytes} of {size} bytes in {calls} read calls", path.display());
+        }
+    }
+}
+
+impl<R: Read> Read for Traced<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.count(read);
+        Ok(read)
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for Traced<R> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        // A pending poll hasn't read yet; the call is counted once it completes
+        if let Poll::Ready(Ok(())) = poll {
+            let read = buf.filled().len() - before;
+            self.count(read);
+        }
+        poll
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn test_counts() -> io::Result<()> {
+        let data = [7u8; 32];
+        let mut reader = Traced::new(&data[..], Path::new("a.safetensors"), 32, false);
+        let mut prefix = [0u8; 8];
+        Read::read_exact(&mut reader, &mut prefix)?;
+        AsyncReadExt::read_exact(&mut reader, &mut [0u8; 4]).await?;
+        assert_eq!((reader.calls, reader.bytes), (2, 12));
+        Ok(())
+    }
+}
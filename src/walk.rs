@@ -0,0 +1,312 @@
+use crate::summary::{Outcome, SkipReason, Summary};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use walkdir::{DirEntry, WalkDir};
+
+/// What to do when a directory can't be traversed, as opposed to a file that can't be parsed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum WalkErrors {
+    /// Print a warning naming the path, count it in the summary and carry on
+    #[default]
+    Warn,
+    /// Abort the scan
+    Fail,
+}
+
+/// Whether the walk should visit an entry. Hidden entries, unless `include_hidden`, and
+/// `target` directories are skipped, except for the root itself. The skipped hidden entries
+/// are told to `report`.
+fn keep_entry(entry: &DirEntry, include_hidden: bool, report: Option<fn(&str)>) -> bool {
+    if entry.depth() == 0 {
+        return true;
+    }
+    if !include_hidden && is_hidden(entry) {
+        if let Some(report) = report {
+            report(&format!("Skipping hidden {}", entry.path().display()));
+        }
+        return false;
+    }
+    entry.file_name() != "target"
+}
+
+/// Which symlinks a directory walk follows.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Symlinks {
+    /// Follow every symlink
+    #[default]
+    All,
+    /// Follow only the symlinks whose target is inside the scanned directory
+    Internal,
+    /// Follow no symlinks
+    None,
+}
+
+/// How [`find_files`] walks a directory.
+#[derive(Debug, Clone, Copy)]
+pub struct Options<'a> {
+    /// The extensions of the files to find, or all files if empty.
+    pub extensions: &'a [&'a str],
+    /// The `--exclude-dir` patterns.
+    pub exclude_dirs: &'a [glob::Pattern],
+    pub include_hidden: bool,
+    pub symlinks: Symlinks,
+    pub on_error: WalkErrors,
+    /// The most files to find in each directory (`--limit-per-directory`).
+    pub limit_per_directory: Option<usize>,
+    /// Where to report the skipped entries, which with -v is stderr.
+    pub report: Option<fn(&str)>,
+}
+
+/// Whether a symlink below the walk's root may be followed under `--symlinks internal`: its
+/// target, with all links resolved, is inside `canonical_root`. A link that can't be resolved
+/// is followed, for the walk to report it as it does with the other policies.
+fn internal_link(entry: &DirEntry, canonical_root: &Path, report: Option<fn(&str)>) -> bool {
+    let Ok(target) = entry.path().canonicalize() else { return true };
+    let internal = target.starts_with(canonical_root);
+    if !internal && let Some(report) = report {
+        let (path, target) = (entry.path().display(), target.display());
+        report(&format!("Skipping symlink {path}: points outside the scanned directory to {target}"));
+    }
+    internal
+}
+
+/// Whether an entry is hidden: its name starts with `.`, or on Windows it has the hidden
+/// attribute.
+fn is_hidden(entry: &DirEntry) -> bool {
+    entry.file_name().to_string_lossy().starts_with('.') || has_hidden_attribute(entry)
+}
+
+#[cfg(windows)]
+fn has_hidden_attribute(entry: &DirEntry) -> bool {
+    use std::os::windows::fs::MetadataExt;
+    const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+    entry.metadata().is_ok_and(|metadata| metadata.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0)
+}
+
+#[cfg(not(windows))]
+fn has_hidden_attribute(_entry: &DirEntry) -> bool {
+    false
+}
+
+/// Whether a directory below `root` matches one of the `--exclude-dir` patterns, by its name
+/// or by its path relative to `root`.
+fn excluded_dir(entry: &DirEntry, root: &Path, exclude_dirs: &[glob::Pattern]) -> bool {
+    if entry.depth() == 0 || !entry.file_type().is_dir() {
+        return false;
+    }
+    let name = entry.file_name().to_string_lossy();
+    let relative = entry.path().strip_prefix(root).unwrap_or(entry.path());
+    exclude_dirs.iter().any(|pattern| pattern.matches(&name) || pattern.matches_path(relative))
+}
+
+/// Recursively find the files under `root` with one of the given extensions, or all of them
+/// if `extensions` is empty, following the symlinks that `symlinks` allows. Directories
+/// matching `exclude_dirs` aren't entered, nor are hidden ones unless `include_hidden`, and
+/// the entries skipped for either are told to `report`. Entries that can't be read are handled
+/// as `on_error` says, files with other extensions are counted in `summary`, as are the files
+/// of a directory past `limit_per_directory`. The walk is lazy, so stopping early also stops the traversal.
+pub fn find_files<'a>(
+    root: &'a Path,
+    options: Options<'a>,
+    summary: &'a Summary,
+) -> impl Iterator<Item = anyhow::Result<PathBuf>> + 'a {
+    let Options { extensions, exclude_dirs, include_hidden, symlinks, on_error, limit_per_directory, report } = options;
+    let mut found: HashMap<PathBuf, usize> = HashMap::new();
+    let canonical_root =
+        (symlinks == Symlinks::Internal).then(|| root.canonicalize().unwrap_or_else(|_| root.to_path_buf()));
+    WalkDir::new(root)
+        .follow_links(symlinks != Symlinks::None)
+        .into_iter()
+        .filter_entry(move |entry| {
+            if excluded_dir(entry, root, exclude_dirs) {
+                if let Some(report) = report {
+                    report(&format!("Skipping directory {}: matches --exclude-dir", entry.path().display()));
+                }
+                return false;
+            }
+            if let Some(canonical_root) = &canonical_root
+                && entry.depth() > 0
+                && entry.path_is_symlink()
+                && !internal_link(entry, canonical_root, report)
+            {
+                return false;
+            }
+            keep_entry(entry, include_hidden, report)
+        })
+        .filter_map(move |entry| match entry {
+            Ok(entry) => Some(Ok(entry)),
+            Err(e) => {
+                // The io::Error alone, since walkdir's message repeats the path
+                let message = match (e.path(), e.io_error()) {
+                    (Some(path), Some(io_error)) => format!("Failed to read {}: {io_error}", path.display()),
+                    _ => format!("Failed to read directory entry: {e}"),
+                };
+                match on_error {
+                    WalkErrors::Warn => {
+                        eprintln!("Warning: {message}");
+                        summary.record_walk_error();
+                        None
+                    }
+                    WalkErrors::Fail => Some(Err(anyhow::anyhow!(message))),
+                }
+            }
+        })
+        .filter(|entry| entry.as_ref().map_or(true, |entry| entry.file_type().is_file()))
+        .filter(move |entry| {
+            let Ok(entry) = entry else { return true };
+            let wanted = extensions.is_empty()
+                || entry.path().extension().is_some_and(|ext| extensions.iter().any(|wanted| ext == *wanted));
+            if !wanted {
+                summary.record(Outcome::Skipped(SkipReason::Extension));
+            }
+            wanted
+        })
+        .filter(move |entry| {
+            let (Some(limit), Ok(entry)) = (limit_per_directory, entry) else { return true };
+            let dir = entry.path().parent().unwrap_or(Path::new(""));
+            let count = found.entry(dir.to_path_buf()).or_default();
+            *count += 1;
+            if *count > limit {
+                summary.record_directory_limit(dir);
+            }
+            *count <= limit
+        })
+        .map(|entry| entry.map(DirEntry::into_path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options<'a>(extensions: &'a [&'a str]) -> Options<'a> {
+        Options {
+            extensions,
+            exclude_dirs: &[],
+            include_hidden: false,
+            symlinks: Symlinks::All,
+            on_error: WalkErrors::Warn,
+            limit_per_directory: None,
+            report: None,
+        }
+    }
+
+    #[test]
+    fn test_find_files_skips_hidden_and_other_extensions() -> anyhow::Result<()> {
+        static REPORTED: std::sync::Mutex<Vec<String>> = std::sync::Mutex::new(Vec::new());
+        let temp_dir = tempfile::tempdir()?;
+        let root = temp_dir.path();
+        std::fs::create_dir_all(root.join("sub"))?;
+        std::fs::create_dir_all(root.join(".cache"))?;
+        std::fs::write(root.join("a.safetensors"), "")?;
+        std::fs::write(root.join("sub").join("b.safetensors"), "")?;
+        std::fs::write(root.join(".cache").join("c.safetensors"), "")?;
+        std::fs::write(root.join("d.safetensors.txt"), "")?;
+
+        let summary = Summary::default();
+        let mut files = find_files(root, options(&["safetensors"]), &summary).collect::<anyhow::Result<Vec<_>>>()?;
+        files.sort();
+        assert_eq!(files, vec![root.join("a.safetensors"), root.join("sub").join("b.safetensors")]);
+        assert!(summary.line(std::time::Duration::ZERO, false).contains("1 skipped"));
+
+        let mut files = find_files(root, Options { include_hidden: true, ..options(&["safetensors"]) }, &summary)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        files.sort();
+        assert_eq!(files[0], root.join(".cache").join("c.safetensors"));
+        assert_eq!(files.len(), 3);
+
+        // The skipped hidden directory is reported, but not the files it holds
+        let report = |message: &str| REPORTED.lock().unwrap().push(message.to_string());
+        find_files(root, Options { report: Some(report), ..options(&["safetensors"]) }, &summary).for_each(drop);
+        assert_eq!(*REPORTED.lock().unwrap(), [format!("Skipping hidden {}", root.join(".cache").display())]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_files_exclude_dir() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let root = temp_dir.path();
+        for dir in ["optimizer_states", "run/optimizer_states", "run/checkpoints", "other/checkpoints"] {
+            std::fs::create_dir_all(root.join(dir))?;
+            std::fs::write(root.join(dir).join("a.safetensors"), "")?;
+        }
+
+        let summary = Summary::default();
+        let exclude = [glob::Pattern::new("optimizer_*")?, glob::Pattern::new("run/checkpoints")?];
+        let files = find_files(root, Options { exclude_dirs: &exclude, ..options(&["safetensors"]) }, &summary)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        assert_eq!(files, vec![root.join("other/checkpoints/a.safetensors")]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_files_limit_per_directory() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let root = temp_dir.path();
+        for name in ["a", "b", "c", "sub/d", "sub/e", "other/f"] {
+            std::fs::create_dir_all(root.join(name).parent().unwrap())?;
+            std::fs::write(root.join(format!("{name}.safetensors")), "")?;
+        }
+
+        let summary = Summary::default();
+        let files = find_files(root, Options { limit_per_directory: Some(1), ..options(&["safetensors"]) }, &summary)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        assert_eq!(files.len(), 3);
+        assert_eq!(summary.skipped(SkipReason::DirectoryLimit), 3);
+        let (root_dir, sub_dir) = (root.display(), root.join("sub"));
+        assert_eq!(
+            summary.directory_limit_report(),
+            format!("Skipped past --limit-per-directory:\n  {root_dir}: 2\n  {}: 1\n", sub_dir.display())
+        );
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_find_files_walk_errors() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let root = temp_dir.path();
+        std::fs::write(root.join("a.safetensors"), "")?;
+        let dangling = root.join("gone");
+        std::os::unix::fs::symlink(root.join("nowhere"), &dangling)?;
+
+        let summary = Summary::default();
+        let files = find_files(root, options(&["safetensors"]), &summary).collect::<anyhow::Result<Vec<_>>>()?;
+        assert_eq!(files, vec![root.join("a.safetensors")]);
+        assert!(summary.line(std::time::Duration::ZERO, false).contains("1 directory error"));
+
+        let err = find_files(root, Options { on_error: WalkErrors::Fail, ..options(&["safetensors"]) }, &summary)
+            .collect::<anyhow::Result<Vec<_>>>()
+            .unwrap_err();
+        assert!(err.to_string().contains(&dangling.display().to_string()));
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_find_files_internal_symlinks() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let (root, outside) = (temp_dir.path().join("models"), temp_dir.path().join("elsewhere"));
+        std::fs::create_dir_all(root.join("sub"))?;
+        std::fs::create_dir_all(&outside)?;
+        std::fs::write(root.join("sub").join("a.safetensors"), "")?;
+        std::fs::write(outside.join("b.safetensors"), "")?;
+        std::os::unix::fs::symlink("sub/a.safetensors", root.join("link.safetensors"))?;
+        std::os::unix::fs::symlink("sub", root.join("linked_dir"))?;
+        std::os::unix::fs::symlink(&outside, root.join("escaping_dir"))?;
+        std::os::unix::fs::symlink(outside.join("b.safetensors"), root.join("escaping.safetensors"))?;
+
+        let find = |symlinks| -> anyhow::Result<Vec<PathBuf>> {
+            let summary = Summary::default();
+            let mut files = find_files(&root, Options { symlinks, ..options(&["safetensors"]) }, &summary)
+                .map(|file| Ok(file?.strip_prefix(&root)?.to_path_buf()))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            files.sort();
+            Ok(files)
+        };
+        let internal = ["link.safetensors", "linked_dir/a.safetensors", "sub/a.safetensors"];
+        assert_eq!(find(Symlinks::Internal)?, internal.map(PathBuf::from));
+        assert_eq!(find(Symlinks::None)?, [PathBuf::from("sub/a.safetensors")]);
+        assert_eq!(find(Symlinks::All)?.len(), 5);
+        Ok(())
+    }
+}